@@ -1,4 +1,9 @@
-use criterion::{Bencher, Criterion};
+use criterion::{Bencher, Criterion, Throughput};
+use detached_jws::{
+    batch::BatchVerifier,
+    openssl::{OpensslSigningKey, OpensslVerificationKey},
+    DecodeOptions, DetachedJwsRef, JwsHeader,
+};
 use openssl::{
     hash::MessageDigest,
     pkey::PKey,
@@ -7,6 +12,11 @@ use openssl::{
 };
 use rand::{Rng, SeedableRng};
 use serde_json::Map;
+use std::{
+    io::{self, Read},
+    sync::Arc,
+    thread,
+};
 
 extern crate detached_jws;
 #[macro_use]
@@ -33,6 +43,207 @@ fn seriliaze_openssl_ps256_bench(b: &mut Bencher, &size: &usize) {
     });
 }
 
+fn small_payloads() -> Vec<Vec<u8>> {
+    (0..SMALL_PAYLOAD_COUNT)
+        .map(|_| {
+            let mut payload = Vec::with_capacity(SMALL_PAYLOAD_SIZE);
+            fill(&mut payload);
+            payload
+        })
+        .collect()
+}
+
+fn serialize_shared_key_bench(b: &mut Bencher, &threads: &usize) {
+    let payloads = small_payloads();
+    let keypair = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let key = Arc::new(OpensslSigningKey::new("PS256", keypair).unwrap());
+
+    b.iter(|| {
+        thread::scope(|s| {
+            for chunk in payloads.chunks(payloads.len() / threads) {
+                let key = Arc::clone(&key);
+                s.spawn(move || {
+                    for payload in chunk {
+                        let _ = detached_jws::serialize_with_key(
+                            Map::new(),
+                            &mut payload.as_slice(),
+                            key.as_ref(),
+                        )
+                        .unwrap();
+                    }
+                });
+            }
+        });
+    });
+}
+
+fn deserialize_shared_key_bench(b: &mut Bencher, &threads: &usize) {
+    let payloads = small_payloads();
+    let keypair = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let signing_key = OpensslSigningKey::new("PS256", keypair.clone()).unwrap();
+    let key = Arc::new(OpensslVerificationKey::new("PS256", &keypair).unwrap());
+
+    let signed: Vec<_> = payloads
+        .into_iter()
+        .map(|payload| {
+            let jws =
+                detached_jws::serialize_with_key(Map::new(), &mut payload.as_slice(), &signing_key)
+                    .unwrap();
+            (jws, payload)
+        })
+        .collect();
+
+    b.iter(|| {
+        thread::scope(|s| {
+            for chunk in signed.chunks(signed.len() / threads) {
+                let key = Arc::clone(&key);
+                s.spawn(move || {
+                    for (jws, payload) in chunk {
+                        detached_jws::deserialize_with_key(
+                            jws,
+                            &mut payload.as_slice(),
+                            key.as_ref(),
+                        )
+                        .unwrap();
+                    }
+                });
+            }
+        });
+    });
+}
+
+/// Detached jws and payload pairs
+type SignedPayloads = Vec<(Vec<u8>, Vec<u8>)>;
+
+fn signed_batch() -> (SignedPayloads, Arc<OpensslVerificationKey>) {
+    let keypair = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let signing_key = OpensslSigningKey::new("PS256", keypair.clone()).unwrap();
+    let key = Arc::new(OpensslVerificationKey::new("PS256", &keypair).unwrap());
+
+    let signed = (0..BATCH_LEN)
+        .map(|_| {
+            let mut payload = Vec::with_capacity(SMALL_PAYLOAD_SIZE);
+            fill(&mut payload);
+            let jws =
+                detached_jws::serialize_with_key(Map::new(), &mut payload.as_slice(), &signing_key)
+                    .unwrap();
+            (jws, payload)
+        })
+        .collect();
+
+    (signed, key)
+}
+
+fn deserialize_loop_bench(b: &mut Bencher) {
+    let (signed, key) = signed_batch();
+
+    b.iter(|| {
+        for (jws, payload) in signed.iter() {
+            detached_jws::deserialize_with_key(jws, &mut payload.as_slice(), key.as_ref()).unwrap();
+        }
+    });
+}
+
+fn batch_verifier_bench(b: &mut Bencher) {
+    let (signed, key) = signed_batch();
+    let verifier = BatchVerifier::new(move |_: &JwsHeader| Some(Arc::clone(&key)));
+
+    b.iter(|| {
+        for result in verifier.verify(
+            signed
+                .iter()
+                .map(|(jws, payload)| (jws, payload.as_slice())),
+        ) {
+            result.unwrap();
+        }
+    });
+}
+
+fn hmac_signed_batch() -> (SignedPayloads, OpensslVerificationKey) {
+    let signing_key =
+        OpensslSigningKey::hmac("HS256", b"a benchmark secret of 32 bytes or more").unwrap();
+    let key =
+        OpensslVerificationKey::hmac("HS256", b"a benchmark secret of 32 bytes or more").unwrap();
+
+    let signed = (0..SMALL_PAYLOAD_COUNT)
+        .map(|i| {
+            let mut header = Map::new();
+            header.insert("kid".to_owned(), format!("key-{}", i).into());
+            let mut payload = Vec::with_capacity(SMALL_PAYLOAD_SIZE);
+            fill(&mut payload);
+            let jws =
+                detached_jws::serialize_with_key(header, &mut payload.as_slice(), &signing_key)
+                    .unwrap();
+            (jws, payload)
+        })
+        .collect();
+
+    (signed, key)
+}
+
+fn owned_decode_bench(b: &mut Bencher) {
+    let (signed, key) = hmac_signed_batch();
+
+    b.iter(|| {
+        for (jws, payload) in signed.iter() {
+            let header =
+                detached_jws::deserialize_with_key(jws, &mut payload.as_slice(), &key).unwrap();
+            criterion::black_box(header.get("kid"));
+        }
+    });
+}
+
+fn borrowed_decode_bench(b: &mut Bencher) {
+    #[derive(serde::Deserialize)]
+    struct KeyId<'a> {
+        kid: &'a str,
+    }
+
+    let (signed, key) = hmac_signed_batch();
+    let options = DecodeOptions::default();
+
+    b.iter(|| {
+        let mut buf = [0; 256];
+        for (jws, payload) in signed.iter() {
+            let view = DetachedJwsRef::parse(jws, &options).unwrap();
+            view.verify_with_key(&mut payload.as_slice(), &key).unwrap();
+            let KeyId { kid } = view.header_as(&mut buf).unwrap();
+            criterion::black_box(kid);
+        }
+    });
+}
+
+/// A payload of `size` bytes streamed without being kept in memory
+fn streamed_payload(size: u64) -> impl Read {
+    io::repeat(0x5a).take(size)
+}
+
+fn stream_keys() -> (OpensslSigningKey, OpensslVerificationKey) {
+    let keypair = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    (
+        OpensslSigningKey::new("PS256", keypair.clone()).unwrap(),
+        OpensslVerificationKey::new("PS256", &keypair).unwrap(),
+    )
+}
+
+fn stream_sign_bench(b: &mut Bencher, &size: &u64) {
+    let (signing_key, _) = stream_keys();
+
+    b.iter(|| {
+        detached_jws::serialize_with_key(Map::new(), &mut streamed_payload(size), &signing_key)
+            .unwrap()
+    });
+}
+
+fn stream_verify_bench(b: &mut Bencher, &size: &u64) {
+    let (signing_key, key) = stream_keys();
+    let jws =
+        detached_jws::serialize_with_key(Map::new(), &mut streamed_payload(size), &signing_key)
+            .unwrap();
+
+    b.iter(|| detached_jws::deserialize_with_key(&jws, &mut streamed_payload(size), &key).unwrap());
+}
+
 fn fill(v: &mut Vec<u8>) {
     let cap = v.capacity();
     let mut r = rand::rngs::SmallRng::from_entropy();
@@ -43,6 +254,13 @@ fn fill(v: &mut Vec<u8>) {
 
 const BYTE_SIZES: [usize; 5] = [1, 3, 100, 3 * 1024, 10 * 1024 * 1024];
 
+const STREAM_SIZES: [u64; 4] = [1 << 10, 1 << 20, 64 << 20, 1 << 30];
+
+const SMALL_PAYLOAD_COUNT: usize = 256;
+const SMALL_PAYLOAD_SIZE: usize = 128;
+const THREADS: [usize; 3] = [1, 2, 4];
+const BATCH_LEN: usize = 4096;
+
 fn seriliaze_bench(c: &mut Criterion) {
     let mut group = c.benchmark_group("seriliaze-openssl");
     for size in BYTE_SIZES.iter() {
@@ -55,10 +273,74 @@ fn seriliaze_bench(c: &mut Criterion) {
     group.finish();
 }
 
+fn stream_bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group(if cfg!(feature = "simd") {
+        "stream-openssl-simd"
+    } else {
+        "stream-openssl"
+    });
+    group.sample_size(10);
+    for size in STREAM_SIZES.iter() {
+        group.throughput(Throughput::Bytes(*size));
+        group.bench_with_input(
+            format!("PS256-sign-{}-bytes", size),
+            size,
+            stream_sign_bench,
+        );
+        group.bench_with_input(
+            format!("PS256-verify-{}-bytes", size),
+            size,
+            stream_verify_bench,
+        );
+    }
+    group.finish();
+}
+
+fn shared_key_bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("shared-key-openssl");
+    group.throughput(Throughput::Elements(SMALL_PAYLOAD_COUNT as u64));
+    for threads in THREADS.iter() {
+        group.bench_with_input(
+            format!("PS256-serialize-{}-threads", threads),
+            threads,
+            serialize_shared_key_bench,
+        );
+        group.bench_with_input(
+            format!("PS256-deserialize-{}-threads", threads),
+            threads,
+            deserialize_shared_key_bench,
+        );
+    }
+    group.finish();
+}
+
+fn batch_bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch-openssl");
+    group.throughput(Throughput::Elements(BATCH_LEN as u64));
+    group.bench_function("PS256-deserialize-loop", deserialize_loop_bench);
+    group.bench_function(
+        if cfg!(feature = "rayon") {
+            "PS256-batch-verifier-rayon"
+        } else {
+            "PS256-batch-verifier"
+        },
+        batch_verifier_bench,
+    );
+    group.finish();
+}
+
+fn borrowed_bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("borrowed-openssl");
+    group.throughput(Throughput::Elements(SMALL_PAYLOAD_COUNT as u64));
+    group.bench_function("HS256-deserialize-with-key", owned_decode_bench);
+    group.bench_function("HS256-detached-jws-ref", borrowed_decode_bench);
+    group.finish();
+}
+
 criterion_group! {
     name = benches;
     config = Criterion::default();
-    targets = seriliaze_bench
+    targets = seriliaze_bench, stream_bench, shared_key_bench, batch_bench, borrowed_bench
 }
 
 criterion_main!(benches);
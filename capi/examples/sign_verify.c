@@ -0,0 +1,69 @@
+/*
+ * Sign a payload in two pieces with an HS256 key and verify the detached jws.
+ *
+ *   cargo build --release
+ *   cc examples/sign_verify.c -Iinclude target/release/libdetached_jws_capi.a \
+ *       -lssl -lcrypto -lpthread -ldl -lm -o sign_verify
+ */
+#include <stdio.h>
+#include <string.h>
+
+#include "detached_jws.h"
+
+static int check(DjwsStatus status, const char *call) {
+    if (status != DJWS_STATUS_OK) {
+        fprintf(stderr, "%s failed (%d): %s\n", call, status, djws_last_error_message());
+        return 1;
+    }
+    return 0;
+}
+
+int main(void) {
+    const uint8_t secret[] = "a shared secret of 32 bytes or more";
+    const char *header = "{\"kid\":\"key-1\"}";
+    const char *first = "hello, ", *second = "world";
+
+    DjwsSigningKey *signing_key = NULL;
+    DjwsVerificationKey *verification_key = NULL;
+    if (check(djws_signing_key_hmac("HS256", secret, sizeof secret - 1, &signing_key),
+              "djws_signing_key_hmac") ||
+        check(djws_verification_key_hmac("HS256", secret, sizeof secret - 1, &verification_key),
+              "djws_verification_key_hmac")) {
+        return 1;
+    }
+
+    DjwsSerializer *serializer = NULL;
+    DjwsBuffer jws = {NULL, 0};
+    if (check(djws_serializer_new(signing_key, (const uint8_t *)header, strlen(header),
+                                  &serializer),
+              "djws_serializer_new") ||
+        check(djws_serializer_update(serializer, (const uint8_t *)first, strlen(first)),
+              "djws_serializer_update") ||
+        check(djws_serializer_update(serializer, (const uint8_t *)second, strlen(second)),
+              "djws_serializer_update") ||
+        check(djws_serializer_finish(serializer, &jws), "djws_serializer_finish")) {
+        return 1;
+    }
+    djws_serializer_free(serializer);
+    djws_signing_key_free(signing_key);
+    printf("%.*s\n", (int)jws.len, (const char *)jws.data);
+
+    DjwsDeserializer *deserializer = NULL;
+    DjwsBuffer header_json = {NULL, 0};
+    if (check(djws_deserializer_new(jws.data, jws.len, verification_key, &deserializer),
+              "djws_deserializer_new") ||
+        check(djws_deserializer_update(deserializer, (const uint8_t *)first, strlen(first)),
+              "djws_deserializer_update") ||
+        check(djws_deserializer_update(deserializer, (const uint8_t *)second, strlen(second)),
+              "djws_deserializer_update") ||
+        check(djws_deserializer_finish(deserializer, &header_json), "djws_deserializer_finish")) {
+        return 1;
+    }
+    printf("%.*s\n", (int)header_json.len, (const char *)header_json.data);
+
+    djws_buffer_free(header_json);
+    djws_buffer_free(jws);
+    djws_deserializer_free(deserializer);
+    djws_verification_key_free(verification_key);
+    return 0;
+}
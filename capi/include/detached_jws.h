@@ -0,0 +1,265 @@
+#ifndef DETACHED_JWS_H
+#define DETACHED_JWS_H
+
+/* Generated by cbindgen from capi/src/lib.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Outcome of a call, mirroring the error cases of the crate
+typedef enum DjwsStatus {
+  DJWS_STATUS_OK = 0,
+  // A null pointer, non UTF-8 string or header that is not a JSON object was passed
+  DJWS_STATUS_INVALID_ARGUMENT = 1,
+  // The jws is malformed or exceeds the default decode limits
+  DJWS_STATUS_MALFORMED = 2,
+  // The protected header repeats a member
+  DJWS_STATUS_DUPLICATE_HEADER_MEMBER = 3,
+  // The `alg` header of the jws is not the algorithm of the key
+  DJWS_STATUS_ALGORITHM_MISMATCH = 4,
+  // The signature does not match the header and payload
+  DJWS_STATUS_SIGNATURE_MISMATCH = 5,
+  // A key could not be loaded or the signing backend failed
+  DJWS_STATUS_KEY = 6,
+  // A callback returned a non-zero code
+  DJWS_STATUS_CALLBACK = 7,
+  // `finish` was already called on the handle
+  DJWS_STATUS_FINISHED = 8,
+  // The call panicked; the handle must not be used any further
+  DJWS_STATUS_PANIC = 9,
+} DjwsStatus;
+
+// Verifies a detached jws over a payload passed piece by piece to [`djws_deserializer_update`]
+typedef struct DjwsDeserializer DjwsDeserializer;
+
+// Serializes a payload passed piece by piece to [`djws_serializer_update`]
+typedef struct DjwsSerializer DjwsSerializer;
+
+// A private or secret key bound to a JWS algorithm
+typedef struct DjwsSigningKey DjwsSigningKey;
+
+// A public or secret key bound to a JWS algorithm
+typedef struct DjwsVerificationKey DjwsVerificationKey;
+
+// Bytes allocated by the library, released with [`djws_buffer_free`]
+typedef struct DjwsBuffer {
+  uint8_t *data;
+  size_t len;
+} DjwsBuffer;
+
+// A custom signer
+//
+// Every callback returns 0 on success; any other value fails the call with
+// `DJWS_STATUS_CALLBACK`. The library owns `context` once the callbacks are passed and calls
+// `release` exactly once, also when creating the serializer fails.
+typedef struct DjwsSignerCallbacks {
+  void *context;
+  // Receives the signing input piece by piece
+  int32_t (*update)(void *context, const uint8_t *data, size_t len);
+  // Points `signature` to the signature of everything passed to `update`; the memory must
+  // stay valid until the next callback
+  int32_t (*sign)(void *context, const uint8_t **signature, size_t *signature_len);
+  // Releases `context`, may be null
+  void (*release)(void *context);
+} DjwsSignerCallbacks;
+
+// A custom verifier
+//
+// Every callback returns 0 on success; any other value fails the call with
+// `DJWS_STATUS_CALLBACK`. The library owns `context` once the callbacks are passed and calls
+// `release` exactly once, also when creating the deserializer fails.
+typedef struct DjwsVerifierCallbacks {
+  void *context;
+  // Receives the signing input piece by piece
+  int32_t (*update)(void *context, const uint8_t *data, size_t len);
+  // Sets `valid` to whether `signature` matches everything passed to `update`
+  int32_t (*verify)(void *context, const uint8_t *signature, size_t signature_len, bool *valid);
+  // Releases `context`, may be null
+  void (*release)(void *context);
+} DjwsVerifierCallbacks;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// The message of the last failed call on this thread, or null
+//
+// The string stays valid until the next failed call on the same thread.
+const char *djws_last_error_message(void);
+
+// Release a buffer returned by the library
+//
+// # Safety
+//
+// `buffer` must come from this library and be released only once.
+void djws_buffer_free(struct DjwsBuffer buffer);
+
+// Load a PEM private key for an `RS*`, `PS*` or `ES*` algorithm
+//
+// # Safety
+//
+// `algorithm` must be a NUL-terminated string and `pem` point to `pem_len` bytes.
+enum DjwsStatus djws_signing_key_from_pem(const char *algorithm,
+                                          const uint8_t *pem,
+                                          size_t pem_len,
+                                          struct DjwsSigningKey **out);
+
+// Create an `HS*` signing key from a shared secret
+//
+// # Safety
+//
+// `algorithm` must be a NUL-terminated string and `secret` point to `secret_len` bytes.
+enum DjwsStatus djws_signing_key_hmac(const char *algorithm,
+                                      const uint8_t *secret,
+                                      size_t secret_len,
+                                      struct DjwsSigningKey **out);
+
+// Release a signing key; serializers created from it keep working
+//
+// # Safety
+//
+// `key` must be null or come from this library and be released only once.
+void djws_signing_key_free(struct DjwsSigningKey *key);
+
+// Load a PEM public (or private) key for an `RS*`, `PS*` or `ES*` algorithm
+//
+// # Safety
+//
+// `algorithm` must be a NUL-terminated string and `pem` point to `pem_len` bytes.
+enum DjwsStatus djws_verification_key_from_pem(const char *algorithm,
+                                               const uint8_t *pem,
+                                               size_t pem_len,
+                                               struct DjwsVerificationKey **out);
+
+// Create an `HS*` verification key from a shared secret
+//
+// # Safety
+//
+// `algorithm` must be a NUL-terminated string and `secret` point to `secret_len` bytes.
+enum DjwsStatus djws_verification_key_hmac(const char *algorithm,
+                                           const uint8_t *secret,
+                                           size_t secret_len,
+                                           struct DjwsVerificationKey **out);
+
+// Create a verification key from a JSON Web Key
+//
+// # Safety
+//
+// `algorithm` must be a NUL-terminated string and `jwk_json` point to `jwk_len` bytes.
+enum DjwsStatus djws_verification_key_from_jwk(const char *algorithm,
+                                               const uint8_t *jwk_json,
+                                               size_t jwk_len,
+                                               struct DjwsVerificationKey **out);
+
+// Release a verification key; deserializers created from it keep working
+//
+// # Safety
+//
+// `key` must be null or come from this library and be released only once.
+void djws_verification_key_free(struct DjwsVerificationKey *key);
+
+// Start a detached jws signed with `key`
+//
+// `header_json` holds extra protected header members as a JSON object and may be null;
+// `alg` is taken from the key.
+//
+// # Safety
+//
+// `key` must come from this library and `header_json` point to `header_len` bytes.
+enum DjwsStatus djws_serializer_new(const struct DjwsSigningKey *key,
+                                    const uint8_t *header_json,
+                                    size_t header_len,
+                                    struct DjwsSerializer **out);
+
+// Start a detached jws with `alg` set to `algorithm`, signed by `callbacks`
+//
+// # Safety
+//
+// `algorithm` must be a NUL-terminated string, `header_json` point to `header_len` bytes and
+// the callbacks must be safe to call with `context`.
+enum DjwsStatus djws_serializer_new_with_callbacks(const char *algorithm,
+                                                   const uint8_t *header_json,
+                                                   size_t header_len,
+                                                   struct DjwsSignerCallbacks callbacks,
+                                                   struct DjwsSerializer **out);
+
+// Pass the next piece of the payload
+//
+// # Safety
+//
+// `serializer` must come from this library and `data` point to `len` bytes.
+enum DjwsStatus djws_serializer_update(struct DjwsSerializer *serializer,
+                                       const uint8_t *data,
+                                       size_t len);
+
+// Sign the payload and store the jws in `jws`, to be released with [`djws_buffer_free`]
+//
+// # Safety
+//
+// `serializer` must come from this library and `jws` be writable.
+enum DjwsStatus djws_serializer_finish(struct DjwsSerializer *serializer, struct DjwsBuffer *jws);
+
+// Release a serializer, finished or not
+//
+// # Safety
+//
+// `serializer` must be null or come from this library and be released only once.
+void djws_serializer_free(struct DjwsSerializer *serializer);
+
+// Start verifying a detached jws with `key`
+//
+// The jws is rejected unless its `alg` header equals the algorithm of the key.
+//
+// # Safety
+//
+// `jws` must point to `jws_len` bytes and `key` come from this library.
+enum DjwsStatus djws_deserializer_new(const uint8_t *jws,
+                                      size_t jws_len,
+                                      const struct DjwsVerificationKey *key,
+                                      struct DjwsDeserializer **out);
+
+// Start verifying a detached jws whose `alg` header must equal `algorithm` with `callbacks`
+//
+// # Safety
+//
+// `jws` must point to `jws_len` bytes, `algorithm` be a NUL-terminated string and the
+// callbacks must be safe to call with `context`.
+enum DjwsStatus djws_deserializer_new_with_callbacks(const uint8_t *jws,
+                                                     size_t jws_len,
+                                                     const char *algorithm,
+                                                     struct DjwsVerifierCallbacks callbacks,
+                                                     struct DjwsDeserializer **out);
+
+// Pass the next piece of the payload
+//
+// # Safety
+//
+// `deserializer` must come from this library and `data` point to `len` bytes.
+enum DjwsStatus djws_deserializer_update(struct DjwsDeserializer *deserializer,
+                                         const uint8_t *data,
+                                         size_t len);
+
+// Verify the signature and store the protected header as JSON in `header_json`, to be
+// released with [`djws_buffer_free`]
+//
+// # Safety
+//
+// `deserializer` must come from this library and `header_json` be writable.
+enum DjwsStatus djws_deserializer_finish(struct DjwsDeserializer *deserializer,
+                                         struct DjwsBuffer *header_json);
+
+// Release a deserializer, finished or not
+//
+// # Safety
+//
+// `deserializer` must be null or come from this library and be released only once.
+void djws_deserializer_free(struct DjwsDeserializer *deserializer);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* DETACHED_JWS_H */
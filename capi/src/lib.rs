@@ -0,0 +1,871 @@
+//! C API of [detached-jws](https://crates.io/crates/detached-jws)
+//!
+//! The header `include/detached_jws.h` is generated by
+//! [cbindgen](https://github.com/mozilla/cbindgen) from this file. Keys, serializers and
+//! deserializers are opaque handles released with their `_free` function; every fallible call
+//! returns a [`DjwsStatus`] and leaves a message for [`djws_last_error_message`]. Signers and
+//! verifiers other than the built-in openssl keys are plugged in as C callbacks.
+
+use std::cell::{Cell, RefCell};
+use std::ffi::{c_void, CStr, CString};
+use std::fmt;
+use std::io::{self, Write};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use detached_jws::openssl::{
+    OpensslSigner, OpensslSigningKey, OpensslVerificationKey, OpensslVerifier,
+};
+use detached_jws::{
+    DecodeOptions, DeserializeJwsWriter, DuplicateHeaderMember, FailureReason, JwsHeader,
+    SerializeJwsWriter, Sign, SigningKey, VerificationError, VerificationKey, Verify,
+};
+use openssl::pkey::PKey;
+use self_cell::self_cell;
+use serde_json::Value;
+
+/// Outcome of a call, mirroring the error cases of the crate
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DjwsStatus {
+    Ok = 0,
+    /// A null pointer, non UTF-8 string or header that is not a JSON object was passed
+    InvalidArgument = 1,
+    /// The jws is malformed or exceeds the default decode limits
+    Malformed = 2,
+    /// The protected header repeats a member
+    DuplicateHeaderMember = 3,
+    /// The `alg` header of the jws is not the algorithm of the key
+    AlgorithmMismatch = 4,
+    /// The signature does not match the header and payload
+    SignatureMismatch = 5,
+    /// A key could not be loaded or the signing backend failed
+    Key = 6,
+    /// A callback returned a non-zero code
+    Callback = 7,
+    /// `finish` was already called on the handle
+    Finished = 8,
+    /// The call panicked; the handle must not be used any further
+    Panic = 9,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+struct Failure {
+    status: DjwsStatus,
+    message: String,
+}
+
+impl Failure {
+    fn new(status: DjwsStatus, message: impl fmt::Display) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+
+    /// Classify a crate error, `status` applies when it carries no typed cause
+    fn from_error(e: anyhow::Error, status: DjwsStatus) -> Self {
+        let mut status = status;
+        for cause in e.chain() {
+            if let Some(e) = cause.downcast_ref::<VerificationError>() {
+                match e.reason {
+                    FailureReason::Backend(_) => continue,
+                    _ => status = DjwsStatus::SignatureMismatch,
+                }
+            } else if cause.is::<DuplicateHeaderMember>() {
+                status = DjwsStatus::DuplicateHeaderMember
+            } else if cause.is::<CallbackError>()
+                || cause
+                    .downcast_ref::<io::Error>()
+                    .and_then(io::Error::get_ref)
+                    .is_some_and(|e| e.is::<CallbackError>())
+            {
+                status = DjwsStatus::Callback
+            } else {
+                continue;
+            }
+            break;
+        }
+
+        Self::new(status, format!("{:#}", e))
+    }
+}
+
+/// Run the body of an exported function, catching panics and recording the error message
+fn ffi(body: impl FnOnce() -> Result<(), Failure>) -> DjwsStatus {
+    let failure = match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => return DjwsStatus::Ok,
+        Ok(Err(failure)) => failure,
+        Err(_) => Failure::new(DjwsStatus::Panic, "detached-jws panicked"),
+    };
+
+    let message = CString::new(failure.message.replace('\0', " ")).ok();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    failure.status
+}
+
+fn invalid(message: &str) -> Failure {
+    Failure::new(DjwsStatus::InvalidArgument, message)
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize, name: &str) -> Result<&'a [u8], Failure> {
+    match (data.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(invalid(&format!("`{}` is null", name))),
+        (false, _) => Ok(std::slice::from_raw_parts(data, len)),
+    }
+}
+
+unsafe fn string<'a>(value: *const c_char, name: &str) -> Result<&'a str, Failure> {
+    if value.is_null() {
+        return Err(invalid(&format!("`{}` is null", name)));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| invalid(&format!("`{}` is not UTF-8", name)))
+}
+
+/// A null header is an empty one
+unsafe fn header(json: *const u8, len: usize) -> Result<JwsHeader, Failure> {
+    if json.is_null() {
+        return Ok(JwsHeader::new());
+    }
+    serde_json::from_slice(bytes(json, len, "header_json")?)
+        .map_err(|e| invalid(&format!("header is not a JSON object: {}", e)))
+}
+
+unsafe fn output<T>(out: *mut *mut T, value: T) -> Result<(), Failure> {
+    if out.is_null() {
+        return Err(invalid("`out` is null"));
+    }
+    *out = Box::into_raw(Box::new(value));
+    Ok(())
+}
+
+/// Bytes allocated by the library, released with [`djws_buffer_free`]
+#[repr(C)]
+pub struct DjwsBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl DjwsBuffer {
+    fn new(data: Vec<u8>) -> Self {
+        let data = Box::into_raw(data.into_boxed_slice());
+        Self {
+            data: data as *mut u8,
+            len: data.len(),
+        }
+    }
+}
+
+/// The message of the last failed call on this thread, or null
+///
+/// The string stays valid until the next failed call on the same thread.
+#[no_mangle]
+pub extern "C" fn djws_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+/// Release a buffer returned by the library
+///
+/// # Safety
+///
+/// `buffer` must come from this library and be released only once.
+#[no_mangle]
+pub unsafe extern "C" fn djws_buffer_free(buffer: DjwsBuffer) {
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            buffer.data,
+            buffer.len,
+        )));
+    }
+}
+
+/// A private or secret key bound to a JWS algorithm
+pub struct DjwsSigningKey(Arc<OpensslSigningKey>);
+
+/// A public or secret key bound to a JWS algorithm
+pub struct DjwsVerificationKey(Arc<OpensslVerificationKey>);
+
+/// Load a PEM private key for an `RS*`, `PS*` or `ES*` algorithm
+///
+/// # Safety
+///
+/// `algorithm` must be a NUL-terminated string and `pem` point to `pem_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn djws_signing_key_from_pem(
+    algorithm: *const c_char,
+    pem: *const u8,
+    pem_len: usize,
+    out: *mut *mut DjwsSigningKey,
+) -> DjwsStatus {
+    ffi(|| {
+        let algorithm = string(algorithm, "algorithm")?;
+        let pem = bytes(pem, pem_len, "pem")?;
+        let key = PKey::private_key_from_pem(pem)
+            .map_err(anyhow::Error::from)
+            .and_then(|key| OpensslSigningKey::new(algorithm, key))
+            .map_err(|e| Failure::from_error(e, DjwsStatus::Key))?;
+        output(out, DjwsSigningKey(Arc::new(key)))
+    })
+}
+
+/// Create an `HS*` signing key from a shared secret
+///
+/// # Safety
+///
+/// `algorithm` must be a NUL-terminated string and `secret` point to `secret_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn djws_signing_key_hmac(
+    algorithm: *const c_char,
+    secret: *const u8,
+    secret_len: usize,
+    out: *mut *mut DjwsSigningKey,
+) -> DjwsStatus {
+    ffi(|| {
+        let algorithm = string(algorithm, "algorithm")?;
+        let secret = bytes(secret, secret_len, "secret")?;
+        let key = OpensslSigningKey::hmac(algorithm, secret)
+            .map_err(|e| Failure::from_error(e, DjwsStatus::Key))?;
+        output(out, DjwsSigningKey(Arc::new(key)))
+    })
+}
+
+/// Release a signing key; serializers created from it keep working
+///
+/// # Safety
+///
+/// `key` must be null or come from this library and be released only once.
+#[no_mangle]
+pub unsafe extern "C" fn djws_signing_key_free(key: *mut DjwsSigningKey) {
+    if !key.is_null() {
+        drop(Box::from_raw(key));
+    }
+}
+
+/// Load a PEM public (or private) key for an `RS*`, `PS*` or `ES*` algorithm
+///
+/// # Safety
+///
+/// `algorithm` must be a NUL-terminated string and `pem` point to `pem_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn djws_verification_key_from_pem(
+    algorithm: *const c_char,
+    pem: *const u8,
+    pem_len: usize,
+    out: *mut *mut DjwsVerificationKey,
+) -> DjwsStatus {
+    ffi(|| {
+        let algorithm = string(algorithm, "algorithm")?;
+        let pem = bytes(pem, pem_len, "pem")?;
+        let key = match PKey::public_key_from_pem(pem) {
+            Ok(key) => OpensslVerificationKey::new(algorithm, &key),
+            Err(_) => PKey::private_key_from_pem(pem)
+                .map_err(anyhow::Error::from)
+                .and_then(|key| OpensslVerificationKey::new(algorithm, &key)),
+        }
+        .map_err(|e| Failure::from_error(e, DjwsStatus::Key))?;
+        output(out, DjwsVerificationKey(Arc::new(key)))
+    })
+}
+
+/// Create an `HS*` verification key from a shared secret
+///
+/// # Safety
+///
+/// `algorithm` must be a NUL-terminated string and `secret` point to `secret_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn djws_verification_key_hmac(
+    algorithm: *const c_char,
+    secret: *const u8,
+    secret_len: usize,
+    out: *mut *mut DjwsVerificationKey,
+) -> DjwsStatus {
+    ffi(|| {
+        let algorithm = string(algorithm, "algorithm")?;
+        let secret = bytes(secret, secret_len, "secret")?;
+        let key = OpensslVerificationKey::hmac(algorithm, secret)
+            .map_err(|e| Failure::from_error(e, DjwsStatus::Key))?;
+        output(out, DjwsVerificationKey(Arc::new(key)))
+    })
+}
+
+/// Create a verification key from a JSON Web Key
+///
+/// # Safety
+///
+/// `algorithm` must be a NUL-terminated string and `jwk_json` point to `jwk_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn djws_verification_key_from_jwk(
+    algorithm: *const c_char,
+    jwk_json: *const u8,
+    jwk_len: usize,
+    out: *mut *mut DjwsVerificationKey,
+) -> DjwsStatus {
+    ffi(|| {
+        let algorithm = string(algorithm, "algorithm")?;
+        let jwk: JwsHeader = serde_json::from_slice(bytes(jwk_json, jwk_len, "jwk_json")?)
+            .map_err(|e| invalid(&format!("JWK is not a JSON object: {}", e)))?;
+        let key = OpensslVerificationKey::from_jwk(algorithm, &jwk)
+            .map_err(|e| Failure::from_error(e, DjwsStatus::Key))?;
+        output(out, DjwsVerificationKey(Arc::new(key)))
+    })
+}
+
+/// Release a verification key; deserializers created from it keep working
+///
+/// # Safety
+///
+/// `key` must be null or come from this library and be released only once.
+#[no_mangle]
+pub unsafe extern "C" fn djws_verification_key_free(key: *mut DjwsVerificationKey) {
+    if !key.is_null() {
+        drop(Box::from_raw(key));
+    }
+}
+
+/// A non-zero code returned by a callback
+#[derive(Debug)]
+struct CallbackError {
+    callback: &'static str,
+    code: i32,
+}
+
+impl fmt::Display for CallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` callback failed with {}", self.callback, self.code)
+    }
+}
+
+impl std::error::Error for CallbackError {}
+
+fn callback(callback: &'static str, code: i32) -> Result<(), CallbackError> {
+    match code {
+        0 => Ok(()),
+        code => Err(CallbackError { callback, code }),
+    }
+}
+
+/// A custom signer
+///
+/// Every callback returns 0 on success; any other value fails the call with
+/// `DJWS_STATUS_CALLBACK`. The library owns `context` once the callbacks are passed and calls
+/// `release` exactly once, also when creating the serializer fails.
+#[repr(C)]
+pub struct DjwsSignerCallbacks {
+    pub context: *mut c_void,
+    /// Receives the signing input piece by piece
+    pub update:
+        Option<unsafe extern "C" fn(context: *mut c_void, data: *const u8, len: usize) -> i32>,
+    /// Points `signature` to the signature of everything passed to `update`; the memory must
+    /// stay valid until the next callback
+    pub sign: Option<
+        unsafe extern "C" fn(
+            context: *mut c_void,
+            signature: *mut *const u8,
+            signature_len: *mut usize,
+        ) -> i32,
+    >,
+    /// Releases `context`, may be null
+    pub release: Option<unsafe extern "C" fn(context: *mut c_void)>,
+}
+
+impl Drop for DjwsSignerCallbacks {
+    fn drop(&mut self) {
+        if let Some(release) = self.release {
+            unsafe { release(self.context) }
+        }
+    }
+}
+
+impl Write for DjwsSignerCallbacks {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let update = self.update.expect("checked on creation");
+        callback("update", unsafe {
+            update(self.context, buf.as_ptr(), buf.len())
+        })
+        .map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Sign for DjwsSignerCallbacks {
+    fn get_sign(&self) -> Result<Vec<u8>> {
+        let sign = self.sign.expect("checked on creation");
+        let (mut signature, mut len) = (ptr::null(), 0);
+        callback("sign", unsafe {
+            sign(self.context, &mut signature, &mut len)
+        })?;
+        if signature.is_null() {
+            bail!("`sign` callback returned a null signature")
+        }
+        Ok(unsafe { std::slice::from_raw_parts(signature, len) }.to_vec())
+    }
+}
+
+/// A custom verifier
+///
+/// Every callback returns 0 on success; any other value fails the call with
+/// `DJWS_STATUS_CALLBACK`. The library owns `context` once the callbacks are passed and calls
+/// `release` exactly once, also when creating the deserializer fails.
+#[repr(C)]
+pub struct DjwsVerifierCallbacks {
+    pub context: *mut c_void,
+    /// Receives the signing input piece by piece
+    pub update:
+        Option<unsafe extern "C" fn(context: *mut c_void, data: *const u8, len: usize) -> i32>,
+    /// Sets `valid` to whether `signature` matches everything passed to `update`
+    pub verify: Option<
+        unsafe extern "C" fn(
+            context: *mut c_void,
+            signature: *const u8,
+            signature_len: usize,
+            valid: *mut bool,
+        ) -> i32,
+    >,
+    /// Releases `context`, may be null
+    pub release: Option<unsafe extern "C" fn(context: *mut c_void)>,
+}
+
+impl Drop for DjwsVerifierCallbacks {
+    fn drop(&mut self) {
+        if let Some(release) = self.release {
+            unsafe { release(self.context) }
+        }
+    }
+}
+
+impl Write for DjwsVerifierCallbacks {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let update = self.update.expect("checked on creation");
+        callback("update", unsafe {
+            update(self.context, buf.as_ptr(), buf.len())
+        })
+        .map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Verify for DjwsVerifierCallbacks {
+    fn verify(&self, signature: &[u8]) -> Result<bool> {
+        let verify = self.verify.expect("checked on creation");
+        let mut valid = false;
+        callback("verify", unsafe {
+            verify(
+                self.context,
+                signature.as_ptr(),
+                signature.len(),
+                &mut valid,
+            )
+        })?;
+        Ok(valid)
+    }
+}
+
+enum AnySigner<'a> {
+    Key(OpensslSigner<'a>),
+    Callbacks(DjwsSignerCallbacks),
+}
+
+impl<'a> Write for AnySigner<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            AnySigner::Key(signer) => signer.write(buf),
+            AnySigner::Callbacks(signer) => signer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> Sign for AnySigner<'a> {
+    fn get_sign(&self) -> Result<Vec<u8>> {
+        match self {
+            AnySigner::Key(signer) => signer.get_sign(),
+            AnySigner::Callbacks(signer) => signer.get_sign(),
+        }
+    }
+}
+
+enum AnyVerifier<'a> {
+    Key(OpensslVerifier<'a>),
+    Callbacks(DjwsVerifierCallbacks),
+}
+
+impl<'a> Write for AnyVerifier<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            AnyVerifier::Key(verifier) => verifier.write(buf),
+            AnyVerifier::Callbacks(verifier) => verifier.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> Verify for AnyVerifier<'a> {
+    fn verify(&self, signature: &[u8]) -> Result<bool> {
+        match self {
+            AnyVerifier::Key(verifier) => verifier.verify(signature),
+            AnyVerifier::Callbacks(verifier) => verifier.verify(signature),
+        }
+    }
+
+    fn check(&self, signature: &[u8]) -> std::result::Result<(), FailureReason> {
+        match self {
+            AnyVerifier::Key(verifier) => verifier.check(signature),
+            AnyVerifier::Callbacks(verifier) => verifier.check(signature),
+        }
+    }
+}
+
+type SignWriter<'a> = SerializeJwsWriter<Vec<u8>, AnySigner<'a>>;
+
+self_cell!(
+    /// A writer together with the key its signer borrows
+    struct SignState {
+        owner: Option<Arc<OpensslSigningKey>>,
+
+        #[not_covariant]
+        dependent: SignWriter,
+    }
+);
+
+/// Serializes a payload passed piece by piece to [`djws_serializer_update`]
+pub struct DjwsSerializer {
+    state: SignState,
+    finished: bool,
+}
+
+fn new_serializer(
+    key: Option<Arc<OpensslSigningKey>>,
+    callbacks: Option<(String, DjwsSignerCallbacks)>,
+    header: JwsHeader,
+) -> Result<DjwsSerializer, Failure> {
+    let state = SignState::try_new(key, |key| {
+        let (algorithm, signer) = match (key, callbacks) {
+            (Some(key), _) => (key.algorithm().to_owned(), AnySigner::Key(key.signer()?)),
+            (None, Some((algorithm, callbacks))) => (algorithm, AnySigner::Callbacks(callbacks)),
+            (None, None) => bail!("no signer"),
+        };
+        SerializeJwsWriter::new(Vec::new(), algorithm, header, signer)
+    })
+    .map_err(|e| Failure::from_error(e, DjwsStatus::Key))?;
+
+    Ok(DjwsSerializer {
+        state,
+        finished: false,
+    })
+}
+
+/// Start a detached jws signed with `key`
+///
+/// `header_json` holds extra protected header members as a JSON object and may be null;
+/// `alg` is taken from the key.
+///
+/// # Safety
+///
+/// `key` must come from this library and `header_json` point to `header_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn djws_serializer_new(
+    key: *const DjwsSigningKey,
+    header_json: *const u8,
+    header_len: usize,
+    out: *mut *mut DjwsSerializer,
+) -> DjwsStatus {
+    ffi(|| {
+        let key = key.as_ref().ok_or_else(|| invalid("`key` is null"))?;
+        let header = header(header_json, header_len)?;
+        output(out, new_serializer(Some(key.0.clone()), None, header)?)
+    })
+}
+
+/// Start a detached jws with `alg` set to `algorithm`, signed by `callbacks`
+///
+/// # Safety
+///
+/// `algorithm` must be a NUL-terminated string, `header_json` point to `header_len` bytes and
+/// the callbacks must be safe to call with `context`.
+#[no_mangle]
+pub unsafe extern "C" fn djws_serializer_new_with_callbacks(
+    algorithm: *const c_char,
+    header_json: *const u8,
+    header_len: usize,
+    callbacks: DjwsSignerCallbacks,
+    out: *mut *mut DjwsSerializer,
+) -> DjwsStatus {
+    ffi(move || {
+        if callbacks.update.is_none() || callbacks.sign.is_none() {
+            return Err(invalid("`update` and `sign` callbacks are required"));
+        }
+        let algorithm = string(algorithm, "algorithm")?.to_owned();
+        let header = header(header_json, header_len)?;
+        output(
+            out,
+            new_serializer(None, Some((algorithm, callbacks)), header)?,
+        )
+    })
+}
+
+/// Pass the next piece of the payload
+///
+/// # Safety
+///
+/// `serializer` must come from this library and `data` point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn djws_serializer_update(
+    serializer: *mut DjwsSerializer,
+    data: *const u8,
+    len: usize,
+) -> DjwsStatus {
+    ffi(|| {
+        let serializer = serializer
+            .as_mut()
+            .ok_or_else(|| invalid("`serializer` is null"))?;
+        if serializer.finished {
+            return Err(Failure::new(DjwsStatus::Finished, "already finished"));
+        }
+        let data = bytes(data, len, "data")?;
+        serializer
+            .state
+            .with_dependent_mut(|_, writer| writer.write_all(data))
+            .map_err(|e| Failure::from_error(e.into(), DjwsStatus::Key))
+    })
+}
+
+/// Sign the payload and store the jws in `jws`, to be released with [`djws_buffer_free`]
+///
+/// # Safety
+///
+/// `serializer` must come from this library and `jws` be writable.
+#[no_mangle]
+pub unsafe extern "C" fn djws_serializer_finish(
+    serializer: *mut DjwsSerializer,
+    jws: *mut DjwsBuffer,
+) -> DjwsStatus {
+    ffi(|| {
+        let serializer = serializer
+            .as_mut()
+            .ok_or_else(|| invalid("`serializer` is null"))?;
+        if jws.is_null() {
+            return Err(invalid("`jws` is null"));
+        }
+        if serializer.finished {
+            return Err(Failure::new(DjwsStatus::Finished, "already finished"));
+        }
+        serializer.finished = true;
+        let output = serializer
+            .state
+            .with_dependent_mut(|_, writer| writer.finish())
+            .map_err(|e| Failure::from_error(e, DjwsStatus::Key))?;
+        *jws = DjwsBuffer::new(output);
+        Ok(())
+    })
+}
+
+/// Release a serializer, finished or not
+///
+/// # Safety
+///
+/// `serializer` must be null or come from this library and be released only once.
+#[no_mangle]
+pub unsafe extern "C" fn djws_serializer_free(serializer: *mut DjwsSerializer) {
+    if !serializer.is_null() {
+        drop(Box::from_raw(serializer));
+    }
+}
+
+type VerifyWriter<'a> = DeserializeJwsWriter<AnyVerifier<'a>>;
+
+self_cell!(
+    /// A writer together with the key its verifier borrows
+    struct VerifyState {
+        owner: Option<Arc<OpensslVerificationKey>>,
+
+        #[not_covariant]
+        dependent: VerifyWriter,
+    }
+);
+
+/// Verifies a detached jws over a payload passed piece by piece to [`djws_deserializer_update`]
+pub struct DjwsDeserializer {
+    state: VerifyState,
+    finished: bool,
+}
+
+fn new_deserializer(
+    jws: &[u8],
+    key: Option<Arc<OpensslVerificationKey>>,
+    callbacks: Option<(String, DjwsVerifierCallbacks)>,
+) -> Result<DjwsDeserializer, Failure> {
+    let mismatch = Cell::new(false);
+
+    let state = VerifyState::try_new(key, |key| {
+        let (algorithm, verifier) = match (key, callbacks) {
+            (Some(key), _) => (
+                key.algorithm().to_owned(),
+                AnyVerifier::Key(key.verifier()?),
+            ),
+            (None, Some((algorithm, callbacks))) => (algorithm, AnyVerifier::Callbacks(callbacks)),
+            (None, None) => bail!("no verifier"),
+        };
+        let selector = |header: &JwsHeader| match header.get("alg").and_then(Value::as_str) {
+            Some(alg) if alg == algorithm => Some(verifier),
+            _ => {
+                mismatch.set(true);
+                None
+            }
+        };
+        DeserializeJwsWriter::with_options(&jws, selector, &DecodeOptions::default())
+    })
+    .map_err(|e| {
+        let status = if mismatch.get() {
+            DjwsStatus::AlgorithmMismatch
+        } else {
+            DjwsStatus::Malformed
+        };
+        Failure::from_error(e, status)
+    })?;
+
+    Ok(DjwsDeserializer {
+        state,
+        finished: false,
+    })
+}
+
+/// Start verifying a detached jws with `key`
+///
+/// The jws is rejected unless its `alg` header equals the algorithm of the key.
+///
+/// # Safety
+///
+/// `jws` must point to `jws_len` bytes and `key` come from this library.
+#[no_mangle]
+pub unsafe extern "C" fn djws_deserializer_new(
+    jws: *const u8,
+    jws_len: usize,
+    key: *const DjwsVerificationKey,
+    out: *mut *mut DjwsDeserializer,
+) -> DjwsStatus {
+    ffi(|| {
+        let jws = bytes(jws, jws_len, "jws")?;
+        let key = key.as_ref().ok_or_else(|| invalid("`key` is null"))?;
+        output(out, new_deserializer(jws, Some(key.0.clone()), None)?)
+    })
+}
+
+/// Start verifying a detached jws whose `alg` header must equal `algorithm` with `callbacks`
+///
+/// # Safety
+///
+/// `jws` must point to `jws_len` bytes, `algorithm` be a NUL-terminated string and the
+/// callbacks must be safe to call with `context`.
+#[no_mangle]
+pub unsafe extern "C" fn djws_deserializer_new_with_callbacks(
+    jws: *const u8,
+    jws_len: usize,
+    algorithm: *const c_char,
+    callbacks: DjwsVerifierCallbacks,
+    out: *mut *mut DjwsDeserializer,
+) -> DjwsStatus {
+    ffi(move || {
+        if callbacks.update.is_none() || callbacks.verify.is_none() {
+            return Err(invalid("`update` and `verify` callbacks are required"));
+        }
+        let jws = bytes(jws, jws_len, "jws")?;
+        let algorithm = string(algorithm, "algorithm")?.to_owned();
+        output(
+            out,
+            new_deserializer(jws, None, Some((algorithm, callbacks)))?,
+        )
+    })
+}
+
+/// Pass the next piece of the payload
+///
+/// # Safety
+///
+/// `deserializer` must come from this library and `data` point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn djws_deserializer_update(
+    deserializer: *mut DjwsDeserializer,
+    data: *const u8,
+    len: usize,
+) -> DjwsStatus {
+    ffi(|| {
+        let deserializer = deserializer
+            .as_mut()
+            .ok_or_else(|| invalid("`deserializer` is null"))?;
+        if deserializer.finished {
+            return Err(Failure::new(DjwsStatus::Finished, "already finished"));
+        }
+        let data = bytes(data, len, "data")?;
+        deserializer
+            .state
+            .with_dependent_mut(|_, writer| writer.write_all(data))
+            .map_err(|e| Failure::from_error(e.into(), DjwsStatus::Key))
+    })
+}
+
+/// Verify the signature and store the protected header as JSON in `header_json`, to be
+/// released with [`djws_buffer_free`]
+///
+/// # Safety
+///
+/// `deserializer` must come from this library and `header_json` be writable.
+#[no_mangle]
+pub unsafe extern "C" fn djws_deserializer_finish(
+    deserializer: *mut DjwsDeserializer,
+    header_json: *mut DjwsBuffer,
+) -> DjwsStatus {
+    ffi(|| {
+        let deserializer = deserializer
+            .as_mut()
+            .ok_or_else(|| invalid("`deserializer` is null"))?;
+        if header_json.is_null() {
+            return Err(invalid("`header_json` is null"));
+        }
+        if deserializer.finished {
+            return Err(Failure::new(DjwsStatus::Finished, "already finished"));
+        }
+        deserializer.finished = true;
+        let header = deserializer
+            .state
+            .with_dependent_mut(|_, writer| writer.finish())
+            .map_err(|e| Failure::from_error(e, DjwsStatus::Key))?;
+        let json = serde_json::to_vec(&header).map_err(|e| Failure::new(DjwsStatus::Key, e))?;
+        *header_json = DjwsBuffer::new(json);
+        Ok(())
+    })
+}
+
+/// Release a deserializer, finished or not
+///
+/// # Safety
+///
+/// `deserializer` must be null or come from this library and be released only once.
+#[no_mangle]
+pub unsafe extern "C" fn djws_deserializer_free(deserializer: *mut DjwsDeserializer) {
+    if !deserializer.is_null() {
+        drop(Box::from_raw(deserializer));
+    }
+}
@@ -0,0 +1,483 @@
+use std::ffi::{c_void, CStr};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use detached_jws_capi::*;
+use openssl::hash::{hash, MessageDigest};
+use serde_json::{json, Value};
+
+const PEM: &[u8] = include_bytes!("../../python/tests/data/rsa-2048.pem");
+const PUBLIC_PEM: &[u8] = include_bytes!("../../python/tests/data/rsa-2048.pub.pem");
+const SECRET: &[u8] = &[7; 64];
+
+fn payload() -> Vec<u8> {
+    (0..100_000u32).map(|i| (i % 251) as u8).collect()
+}
+
+fn last_error() -> String {
+    let message = djws_last_error_message();
+    assert!(!message.is_null());
+    unsafe { CStr::from_ptr(message) }
+        .to_str()
+        .unwrap()
+        .to_owned()
+}
+
+fn buffer() -> DjwsBuffer {
+    DjwsBuffer {
+        data: ptr::null_mut(),
+        len: 0,
+    }
+}
+
+unsafe fn take(buffer: DjwsBuffer) -> Vec<u8> {
+    let data = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
+    djws_buffer_free(buffer);
+    data
+}
+
+fn hmac_keys() -> (*mut DjwsSigningKey, *mut DjwsVerificationKey) {
+    let (mut signing, mut verification) = (ptr::null_mut(), ptr::null_mut());
+    unsafe {
+        assert_eq!(
+            djws_signing_key_hmac(
+                b"HS256\0".as_ptr().cast(),
+                SECRET.as_ptr(),
+                SECRET.len(),
+                &mut signing
+            ),
+            DjwsStatus::Ok
+        );
+        assert_eq!(
+            djws_verification_key_hmac(
+                b"HS256\0".as_ptr().cast(),
+                SECRET.as_ptr(),
+                SECRET.len(),
+                &mut verification
+            ),
+            DjwsStatus::Ok
+        );
+    }
+    (signing, verification)
+}
+
+unsafe fn serialize(key: *const DjwsSigningKey, header: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut serializer = ptr::null_mut();
+    assert_eq!(
+        djws_serializer_new(key, header.as_ptr(), header.len(), &mut serializer),
+        DjwsStatus::Ok
+    );
+    for chunk in payload.chunks(7_000) {
+        assert_eq!(
+            djws_serializer_update(serializer, chunk.as_ptr(), chunk.len()),
+            DjwsStatus::Ok
+        );
+    }
+    let mut jws = buffer();
+    assert_eq!(djws_serializer_finish(serializer, &mut jws), DjwsStatus::Ok);
+    djws_serializer_free(serializer);
+    take(jws)
+}
+
+/// Verify `jws` over `payload`, returning the header or the failed status
+unsafe fn deserialize(
+    key: *const DjwsVerificationKey,
+    jws: &[u8],
+    payload: &[u8],
+) -> Result<Value, DjwsStatus> {
+    let mut deserializer = ptr::null_mut();
+    match djws_deserializer_new(jws.as_ptr(), jws.len(), key, &mut deserializer) {
+        DjwsStatus::Ok => {}
+        status => return Err(status),
+    }
+    let result = finish(deserializer, payload);
+    djws_deserializer_free(deserializer);
+    result
+}
+
+unsafe fn finish(deserializer: *mut DjwsDeserializer, payload: &[u8]) -> Result<Value, DjwsStatus> {
+    for chunk in payload.chunks(7_000) {
+        match djws_deserializer_update(deserializer, chunk.as_ptr(), chunk.len()) {
+            DjwsStatus::Ok => {}
+            status => return Err(status),
+        }
+    }
+    let mut header = buffer();
+    match djws_deserializer_finish(deserializer, &mut header) {
+        DjwsStatus::Ok => Ok(serde_json::from_slice(&take(header)).unwrap()),
+        status => Err(status),
+    }
+}
+
+#[test]
+fn sign_and_verify_with_keys() {
+    let payload = payload();
+
+    unsafe {
+        let (signing, verification) = hmac_keys();
+        let jws = serialize(signing, br#"{"kid":"key-1"}"#, &payload);
+        assert_eq!(
+            deserialize(verification, &jws, &payload),
+            Ok(json!({ "alg": "HS256", "kid": "key-1" }))
+        );
+        djws_signing_key_free(signing);
+        djws_verification_key_free(verification);
+
+        let (mut signing, mut verification) = (ptr::null_mut(), ptr::null_mut());
+        let alg = b"PS256\0".as_ptr().cast();
+        assert_eq!(
+            djws_signing_key_from_pem(alg, PEM.as_ptr(), PEM.len(), &mut signing),
+            DjwsStatus::Ok
+        );
+        assert_eq!(
+            djws_verification_key_from_pem(
+                alg,
+                PUBLIC_PEM.as_ptr(),
+                PUBLIC_PEM.len(),
+                &mut verification
+            ),
+            DjwsStatus::Ok
+        );
+
+        // a null header is an empty one, and the key may go before its serializer
+        let mut serializer = ptr::null_mut();
+        assert_eq!(
+            djws_serializer_new(signing, ptr::null(), 0, &mut serializer),
+            DjwsStatus::Ok
+        );
+        djws_signing_key_free(signing);
+        assert_eq!(
+            djws_serializer_update(serializer, payload.as_ptr(), payload.len()),
+            DjwsStatus::Ok
+        );
+        let mut jws = buffer();
+        assert_eq!(djws_serializer_finish(serializer, &mut jws), DjwsStatus::Ok);
+        djws_serializer_free(serializer);
+        let jws = take(jws);
+
+        assert_eq!(
+            deserialize(verification, &jws, &payload),
+            Ok(json!({ "alg": "PS256" }))
+        );
+        assert_eq!(
+            deserialize(verification, &jws, &payload[1..]),
+            Err(DjwsStatus::SignatureMismatch)
+        );
+        djws_verification_key_free(verification);
+    }
+}
+
+#[test]
+fn verify_with_jwk() {
+    let jwk = json!({
+        "kty": "oct",
+        "k": base64::encode_config(SECRET, base64::URL_SAFE_NO_PAD),
+    })
+    .to_string();
+
+    unsafe {
+        let (signing, _) = hmac_keys();
+        let jws = serialize(signing, b"{}", b"payload");
+
+        let mut verification = ptr::null_mut();
+        assert_eq!(
+            djws_verification_key_from_jwk(
+                b"HS256\0".as_ptr().cast(),
+                jwk.as_ptr(),
+                jwk.len(),
+                &mut verification
+            ),
+            DjwsStatus::Ok
+        );
+        assert!(deserialize(verification, &jws, b"payload").is_ok());
+        djws_verification_key_free(verification);
+    }
+}
+
+/// A SHA-256 "signature" computed by C callbacks
+struct Context {
+    input: Vec<u8>,
+    signature: Vec<u8>,
+    fail: bool,
+    released: Arc<AtomicUsize>,
+}
+
+impl Context {
+    fn boxed(fail: bool) -> (*mut c_void, Arc<AtomicUsize>) {
+        let released = Arc::new(AtomicUsize::new(0));
+        let context = Box::new(Context {
+            input: Vec::new(),
+            signature: Vec::new(),
+            fail,
+            released: released.clone(),
+        });
+        (Box::into_raw(context).cast(), released)
+    }
+}
+
+unsafe extern "C" fn update(context: *mut c_void, data: *const u8, len: usize) -> i32 {
+    let context = &mut *context.cast::<Context>();
+    if context.fail {
+        return 42;
+    }
+    context
+        .input
+        .extend_from_slice(std::slice::from_raw_parts(data, len));
+    0
+}
+
+unsafe extern "C" fn sign(
+    context: *mut c_void,
+    signature: *mut *const u8,
+    signature_len: *mut usize,
+) -> i32 {
+    let context = &mut *context.cast::<Context>();
+    context.signature = hash(MessageDigest::sha256(), &context.input)
+        .unwrap()
+        .to_vec();
+    *signature = context.signature.as_ptr();
+    *signature_len = context.signature.len();
+    0
+}
+
+unsafe extern "C" fn verify(
+    context: *mut c_void,
+    signature: *const u8,
+    signature_len: usize,
+    valid: *mut bool,
+) -> i32 {
+    let context = &*context.cast::<Context>();
+    let expected = hash(MessageDigest::sha256(), &context.input).unwrap();
+    *valid = *expected == *std::slice::from_raw_parts(signature, signature_len);
+    0
+}
+
+unsafe extern "C" fn release(context: *mut c_void) {
+    let context = Box::from_raw(context.cast::<Context>());
+    context.released.fetch_add(1, Ordering::SeqCst);
+}
+
+fn signer_callbacks(context: *mut c_void) -> DjwsSignerCallbacks {
+    DjwsSignerCallbacks {
+        context,
+        update: Some(update),
+        sign: Some(sign),
+        release: Some(release),
+    }
+}
+
+fn verifier_callbacks(context: *mut c_void) -> DjwsVerifierCallbacks {
+    DjwsVerifierCallbacks {
+        context,
+        update: Some(update),
+        verify: Some(verify),
+        release: Some(release),
+    }
+}
+
+unsafe fn callback_deserializer(jws: &[u8]) -> (*mut DjwsDeserializer, Arc<AtomicUsize>) {
+    let (context, released) = Context::boxed(false);
+    let mut deserializer = ptr::null_mut();
+    assert_eq!(
+        djws_deserializer_new_with_callbacks(
+            jws.as_ptr(),
+            jws.len(),
+            b"custom\0".as_ptr().cast(),
+            verifier_callbacks(context),
+            &mut deserializer
+        ),
+        DjwsStatus::Ok
+    );
+    (deserializer, released)
+}
+
+#[test]
+fn sign_and_verify_with_callbacks() {
+    let payload = payload();
+    let header = br#"{"kid":"key-1"}"#;
+
+    unsafe {
+        let (context, signer_released) = Context::boxed(false);
+        let mut serializer = ptr::null_mut();
+        assert_eq!(
+            djws_serializer_new_with_callbacks(
+                b"custom\0".as_ptr().cast(),
+                header.as_ptr(),
+                header.len(),
+                signer_callbacks(context),
+                &mut serializer
+            ),
+            DjwsStatus::Ok
+        );
+        for chunk in payload.chunks(7_000) {
+            assert_eq!(
+                djws_serializer_update(serializer, chunk.as_ptr(), chunk.len()),
+                DjwsStatus::Ok
+            );
+        }
+        let mut jws = buffer();
+        assert_eq!(djws_serializer_finish(serializer, &mut jws), DjwsStatus::Ok);
+        djws_serializer_free(serializer);
+        assert_eq!(signer_released.load(Ordering::SeqCst), 1);
+        let jws = take(jws);
+
+        let (deserializer, released) = callback_deserializer(&jws);
+        assert_eq!(
+            finish(deserializer, &payload),
+            Ok(json!({ "alg": "custom", "kid": "key-1" }))
+        );
+        djws_deserializer_free(deserializer);
+        assert_eq!(released.load(Ordering::SeqCst), 1);
+
+        let (deserializer, released) = callback_deserializer(&jws);
+        assert_eq!(
+            finish(deserializer, &payload[1..]),
+            Err(DjwsStatus::SignatureMismatch)
+        );
+        djws_deserializer_free(deserializer);
+        assert_eq!(released.load(Ordering::SeqCst), 1);
+
+        // the encoded header is passed to `update` as the deserializer is created
+        let (context, released) = Context::boxed(true);
+        let mut deserializer = ptr::null_mut();
+        assert_eq!(
+            djws_deserializer_new_with_callbacks(
+                jws.as_ptr(),
+                jws.len(),
+                b"custom\0".as_ptr().cast(),
+                verifier_callbacks(context),
+                &mut deserializer
+            ),
+            DjwsStatus::Callback
+        );
+        assert!(last_error().contains("`update` callback failed with 42"));
+        assert_eq!(released.load(Ordering::SeqCst), 1);
+
+        // the context is released when the deserializer can not be created
+        let (context, released) = Context::boxed(false);
+        let mut deserializer = ptr::null_mut();
+        assert_eq!(
+            djws_deserializer_new_with_callbacks(
+                jws.as_ptr(),
+                jws.len(),
+                b"other\0".as_ptr().cast(),
+                verifier_callbacks(context),
+                &mut deserializer
+            ),
+            DjwsStatus::AlgorithmMismatch
+        );
+        assert!(deserializer.is_null());
+        assert_eq!(released.load(Ordering::SeqCst), 1);
+
+        let (context, released) = Context::boxed(false);
+        let mut callbacks = signer_callbacks(context);
+        callbacks.sign = None;
+        assert_eq!(
+            djws_serializer_new_with_callbacks(
+                b"custom\0".as_ptr().cast(),
+                ptr::null(),
+                0,
+                callbacks,
+                &mut serializer
+            ),
+            DjwsStatus::InvalidArgument
+        );
+        assert_eq!(released.load(Ordering::SeqCst), 1);
+    }
+}
+
+#[test]
+fn error_statuses() {
+    unsafe {
+        let (signing, verification) = hmac_keys();
+        let jws = serialize(signing, b"{}", b"payload");
+
+        assert_eq!(
+            deserialize(verification, b"not-a-jws", b"payload"),
+            Err(DjwsStatus::Malformed)
+        );
+
+        let mut other = ptr::null_mut();
+        djws_verification_key_hmac(
+            b"HS384\0".as_ptr().cast(),
+            SECRET.as_ptr(),
+            SECRET.len(),
+            &mut other,
+        );
+        assert_eq!(
+            deserialize(other, &jws, b"payload"),
+            Err(DjwsStatus::AlgorithmMismatch)
+        );
+        djws_verification_key_free(other);
+
+        let header =
+            base64::encode_config(r#"{"alg":"HS256","alg":"HS256"}"#, base64::URL_SAFE_NO_PAD);
+        let duplicate = format!("{}..c2lnbmF0dXJl", header);
+        assert_eq!(
+            deserialize(verification, duplicate.as_bytes(), b"payload"),
+            Err(DjwsStatus::DuplicateHeaderMember)
+        );
+        assert!(last_error().contains("alg"));
+
+        let mut key = ptr::null_mut();
+        assert_eq!(
+            djws_signing_key_hmac(b"RS256\0".as_ptr().cast(), SECRET.as_ptr(), 6, &mut key),
+            DjwsStatus::Key
+        );
+        assert!(key.is_null());
+        assert_eq!(
+            djws_signing_key_hmac(ptr::null(), SECRET.as_ptr(), 6, &mut key),
+            DjwsStatus::InvalidArgument
+        );
+        assert!(last_error().contains("`algorithm` is null"));
+
+        let mut serializer = ptr::null_mut();
+        assert_eq!(
+            djws_serializer_new(signing, b"[1]".as_ptr(), 3, &mut serializer),
+            DjwsStatus::InvalidArgument
+        );
+        assert_eq!(
+            djws_serializer_new(signing, ptr::null(), 0, &mut serializer),
+            DjwsStatus::Ok
+        );
+        let mut jws = buffer();
+        assert_eq!(djws_serializer_finish(serializer, &mut jws), DjwsStatus::Ok);
+        take(jws);
+        assert_eq!(
+            djws_serializer_finish(serializer, &mut buffer()),
+            DjwsStatus::Finished
+        );
+        assert_eq!(
+            djws_serializer_update(serializer, b"more".as_ptr(), 4),
+            DjwsStatus::Finished
+        );
+        assert_eq!(
+            djws_serializer_update(ptr::null_mut(), b"more".as_ptr(), 4),
+            DjwsStatus::InvalidArgument
+        );
+        djws_serializer_free(serializer);
+
+        djws_signing_key_free(signing);
+        djws_verification_key_free(verification);
+    }
+}
+
+#[test]
+fn header_is_up_to_date() {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", root)).unwrap();
+    let mut header = Vec::new();
+    cbindgen::Builder::new()
+        .with_crate(root)
+        .with_config(config)
+        .generate()
+        .unwrap()
+        .write(&mut header);
+
+    assert_eq!(
+        String::from_utf8(header).unwrap(),
+        std::fs::read_to_string(format!("{}/include/detached_jws.h", root)).unwrap(),
+        "regenerate include/detached_jws.h with cbindgen"
+    );
+}
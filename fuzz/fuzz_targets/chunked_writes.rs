@@ -0,0 +1,90 @@
+//! Payload writes split at arbitrary points must not change what is signed or verified
+
+#![no_main]
+
+use std::io::Write;
+
+use arbitrary::Arbitrary;
+use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+use detached_jws::{
+    DecodeOptions, DeserializeJwsWriter, JwsHeader, SerializeJwsWriter, SigningKey,
+};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    kid: String,
+    payload: Vec<u8>,
+    /// Repeats the payload to cross the internal encoding chunks
+    repeat: u8,
+    splits: Vec<u16>,
+    tamper: Option<u16>,
+}
+
+fn write_chunked(writer: &mut impl Write, payload: &[u8], splits: &[u16]) {
+    let mut rest = payload;
+    for split in splits.iter().cycle() {
+        if rest.is_empty() {
+            break;
+        }
+        let len = (*split as usize * 7 % 70_000 + 1).min(rest.len());
+        let (chunk, tail) = rest.split_at(len);
+        writer.write_all(chunk).unwrap();
+        rest = tail;
+    }
+    writer.write_all(rest).unwrap();
+}
+
+fn sign(key: &OpensslSigningKey, kid: &str, payload: &[u8], splits: &[u16]) -> Vec<u8> {
+    let mut header = JwsHeader::new();
+    header.insert("kid".to_owned(), kid.into());
+
+    let mut writer = SerializeJwsWriter::new(
+        Vec::new(),
+        "HS256".to_owned(),
+        header,
+        key.signer().unwrap(),
+    )
+    .unwrap();
+    write_chunked(&mut writer, payload, splits);
+    writer.finish().unwrap()
+}
+
+fn verify(
+    key: &OpensslVerificationKey,
+    jws: &[u8],
+    payload: &[u8],
+    splits: &[u16],
+) -> Option<JwsHeader> {
+    let options = DecodeOptions {
+        max_token_len: usize::MAX,
+        ..DecodeOptions::default()
+    };
+    let mut writer = DeserializeJwsWriter::with_key(&jws, key, &options).ok()?;
+    write_chunked(&mut writer, payload, splits);
+    writer.finish().ok()
+}
+
+fuzz_target!(|input: Input| {
+    let signing_key = OpensslSigningKey::hmac("HS256", b"fuzz").unwrap();
+    let key = OpensslVerificationKey::hmac("HS256", b"fuzz").unwrap();
+
+    let payload = input.payload.repeat(1 + input.repeat as usize % 32);
+
+    let whole = sign(&signing_key, &input.kid, &payload, &[]);
+    let chunked = sign(&signing_key, &input.kid, &payload, &input.splits);
+    assert_eq!(whole, chunked);
+
+    let expected = verify(&key, &whole, &payload, &[]);
+    assert_eq!(verify(&key, &whole, &payload, &input.splits), expected);
+
+    if expected.is_some() {
+        assert_eq!(expected.unwrap()["kid"], input.kid.as_str());
+    }
+
+    if let (Some(at), false) = (input.tamper, payload.is_empty()) {
+        let mut tampered = payload.clone();
+        tampered[at as usize % payload.len()] ^= 1;
+        assert!(verify(&key, &whole, &tampered, &input.splits).is_none());
+    }
+});
@@ -0,0 +1,40 @@
+//! Untrusted compact jws, as received in an HTTP header, against every decode entry point
+
+#![no_main]
+
+use std::io::Write;
+
+use detached_jws::openssl::OpensslVerificationKey;
+use detached_jws::{DecodeOptions, DeserializeJwsWriter, DetachedJwsRef, JwsHeader};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let options = DecodeOptions::default();
+    let key = OpensslVerificationKey::hmac("HS256", b"fuzz").unwrap();
+
+    let header = detached_jws::decode_header(&data, &options);
+
+    let mut view_header = None;
+    if let Ok(view) = DetachedJwsRef::parse(data, &options) {
+        // the borrowed decode must agree with the owned one wherever the latter succeeds;
+        // only the owned path rejects duplicate members
+        let mut buf = vec![0; view.header_buffer_len()];
+        let borrowed = view.header_as::<JwsHeader>(&mut buf);
+        if let Ok(ref owned) = header {
+            assert_eq!(borrowed.as_ref().ok(), Some(owned));
+        }
+        view_header = Some(view.encoded_header());
+
+        let _ = view.signature();
+        let _ = view.verify_with_key(&mut &b"payload"[..], &key);
+    }
+
+    if let Ok(mut writer) = DeserializeJwsWriter::with_key(&data, &key, &options) {
+        assert!(header.is_ok());
+        if let Some(view_header) = view_header {
+            assert_eq!(writer.encoded_header(), view_header);
+        }
+        writer.write_all(b"payload").unwrap();
+        let _ = writer.finish();
+    };
+});
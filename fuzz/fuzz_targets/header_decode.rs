@@ -0,0 +1,43 @@
+//! Arbitrary bytes as the protected header JSON, for signing and for decoding
+
+#![no_main]
+
+use detached_jws::openssl::OpensslSigningKey;
+use detached_jws::{DecodeOptions, DetachedJwsRef, SerializeJwsWriter, SigningKey};
+use libfuzzer_sys::fuzz_target;
+use serde_json::Value;
+
+fuzz_target!(|data: &[u8]| {
+    let options = DecodeOptions::default();
+    let key = OpensslSigningKey::hmac("HS256", b"fuzz").unwrap();
+
+    let jws = format!(
+        "{}..c2lnbmF0dXJl",
+        base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+    );
+    let decoded = detached_jws::decode_header(&jws, &options);
+
+    if let Ok(view) = DetachedJwsRef::parse(jws.as_bytes(), &options) {
+        let mut buf = vec![0; view.header_buffer_len()];
+        let _ = view.header_as::<Value>(&mut buf);
+    }
+
+    let signed = SerializeJwsWriter::with_header_json(Vec::new(), data, key.signer().unwrap())
+        .and_then(|mut writer| writer.finish());
+
+    if let Ok(header) = &decoded {
+        // a decodable header is accepted for signing exactly when it names its algorithm
+        assert_eq!(
+            signed.is_ok(),
+            matches!(header.get("alg"), Some(Value::String(_)))
+        );
+    }
+    if let Ok(jws) = signed {
+        // signing never rewrites the header bytes
+        let segment = jws.split(|b| *b == b'.').next().unwrap();
+        assert_eq!(
+            base64::decode_config(segment, base64::URL_SAFE_NO_PAD).unwrap(),
+            data
+        );
+    }
+});
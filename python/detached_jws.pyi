@@ -0,0 +1,33 @@
+from typing import Any, Dict, Optional
+
+class JwsError(Exception): ...
+class VerificationError(JwsError): ...
+
+class SigningKey:
+    def __init__(self, algorithm: str, pem: bytes) -> None: ...
+    @staticmethod
+    def hmac(algorithm: str, secret: bytes) -> "SigningKey": ...
+    @property
+    def algorithm(self) -> str: ...
+
+class VerificationKey:
+    def __init__(self, algorithm: str, pem: bytes) -> None: ...
+    @staticmethod
+    def hmac(algorithm: str, secret: bytes) -> "VerificationKey": ...
+    @staticmethod
+    def from_jwk(algorithm: str, jwk: Dict[str, Any]) -> "VerificationKey": ...
+    @property
+    def algorithm(self) -> str: ...
+
+class SerializeJwsWriter:
+    def __init__(self, header: Optional[Dict[str, Any]], key: SigningKey) -> None: ...
+    def update(self, data: bytes) -> None: ...
+    def finish(self) -> bytes: ...
+
+class DeserializeJwsWriter:
+    def __init__(self, jws: bytes, key: VerificationKey) -> None: ...
+    def update(self, data: bytes) -> None: ...
+    def finish(self) -> Dict[str, Any]: ...
+
+def serialize(header: Optional[Dict[str, Any]], payload: bytes, key: SigningKey) -> bytes: ...
+def deserialize(jws: bytes, payload: bytes, key: VerificationKey) -> Dict[str, Any]: ...
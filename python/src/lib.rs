@@ -0,0 +1,268 @@
+//! Python bindings of [detached-jws](https://crates.io/crates/detached-jws)
+//!
+//! Built with [maturin](https://www.maturin.rs) into the `detached_jws` module. Keys are openssl
+//! keys loaded from PEM, or from JWKs for verification; headers cross the boundary as `dict`s
+//! and payloads as `bytes`.
+
+use std::io::Write;
+use std::sync::Arc;
+
+use detached_jws::openssl::{
+    OpensslSigner, OpensslSigningKey, OpensslVerificationKey, OpensslVerifier,
+};
+use detached_jws::{
+    DecodeOptions, DeserializeJwsWriter, JwsHeader, SerializeJwsWriter, SigningKey, VerificationKey,
+};
+use openssl::pkey::PKey;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use self_cell::self_cell;
+
+create_exception!(
+    detached_jws,
+    JwsError,
+    PyException,
+    "A jws could not be created, decoded or verified"
+);
+create_exception!(
+    detached_jws,
+    VerificationError,
+    JwsError,
+    "A well-formed jws whose signature does not match its payload"
+);
+
+fn to_py(e: anyhow::Error) -> PyErr {
+    let message = format!("{:#}", e);
+    if e.is::<detached_jws::VerificationError>() {
+        VerificationError::new_err(message)
+    } else {
+        JwsError::new_err(message)
+    }
+}
+
+/// Convert a `dict` through the `json` module, rejecting what JSON can not hold
+fn header_from_py(header: Option<&Bound<'_, PyDict>>) -> PyResult<JwsHeader> {
+    let header = match header {
+        Some(header) => header,
+        None => return Ok(JwsHeader::new()),
+    };
+
+    let json: String = header
+        .py()
+        .import("json")?
+        .call_method1("dumps", (header,))?
+        .extract()?;
+    serde_json::from_str(&json).map_err(|e| JwsError::new_err(e.to_string()))
+}
+
+fn header_to_py<'py>(py: Python<'py>, header: &JwsHeader) -> PyResult<Bound<'py, PyAny>> {
+    let json = serde_json::to_string(header).map_err(|e| JwsError::new_err(e.to_string()))?;
+    py.import("json")?.call_method1("loads", (json,))
+}
+
+/// A private or secret key bound to a JWS algorithm
+#[pyclass(name = "SigningKey", frozen)]
+struct PySigningKey(Arc<OpensslSigningKey>);
+
+#[pymethods]
+impl PySigningKey {
+    /// Load a PEM private key for an `RS*`, `PS*` or `ES*` algorithm
+    #[new]
+    fn new(algorithm: &str, pem: &[u8]) -> PyResult<Self> {
+        let key = PKey::private_key_from_pem(pem).map_err(|e| to_py(e.into()))?;
+        let key = OpensslSigningKey::new(algorithm, key).map_err(to_py)?;
+        Ok(Self(Arc::new(key)))
+    }
+
+    /// Create an `HS*` key from a shared secret
+    #[staticmethod]
+    fn hmac(algorithm: &str, secret: &[u8]) -> PyResult<Self> {
+        let key = OpensslSigningKey::hmac(algorithm, secret).map_err(to_py)?;
+        Ok(Self(Arc::new(key)))
+    }
+
+    #[getter]
+    fn algorithm(&self) -> &str {
+        self.0.algorithm()
+    }
+}
+
+/// A public or secret key bound to a JWS algorithm
+#[pyclass(name = "VerificationKey", frozen)]
+struct PyVerificationKey(Arc<OpensslVerificationKey>);
+
+#[pymethods]
+impl PyVerificationKey {
+    /// Load a PEM public (or private) key for an `RS*`, `PS*` or `ES*` algorithm
+    #[new]
+    fn new(algorithm: &str, pem: &[u8]) -> PyResult<Self> {
+        let key = match PKey::public_key_from_pem(pem) {
+            Ok(key) => OpensslVerificationKey::new(algorithm, &key),
+            Err(_) => PKey::private_key_from_pem(pem)
+                .map_err(anyhow::Error::from)
+                .and_then(|key| OpensslVerificationKey::new(algorithm, &key)),
+        };
+        Ok(Self(Arc::new(key.map_err(to_py)?)))
+    }
+
+    /// Create an `HS*` key from a shared secret
+    #[staticmethod]
+    fn hmac(algorithm: &str, secret: &[u8]) -> PyResult<Self> {
+        let key = OpensslVerificationKey::hmac(algorithm, secret).map_err(to_py)?;
+        Ok(Self(Arc::new(key)))
+    }
+
+    /// Create a key from a JSON Web Key `dict`
+    #[staticmethod]
+    fn from_jwk(algorithm: &str, jwk: &Bound<'_, PyDict>) -> PyResult<Self> {
+        let jwk = header_from_py(Some(jwk))?;
+        let key = OpensslVerificationKey::from_jwk(algorithm, &jwk).map_err(to_py)?;
+        Ok(Self(Arc::new(key)))
+    }
+
+    #[getter]
+    fn algorithm(&self) -> &str {
+        self.0.algorithm()
+    }
+}
+
+/// Serialize `payload` to a detached jws signed with `key`
+///
+/// `header` holds extra protected header members; `alg` is taken from the key.
+#[pyfunction]
+#[pyo3(signature = (header, payload, key))]
+fn serialize<'py>(
+    py: Python<'py>,
+    header: Option<&Bound<'py, PyDict>>,
+    payload: &[u8],
+    key: &PySigningKey,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let header = header_from_py(header)?;
+    let jws = detached_jws::serialize_with_key(header, &mut &payload[..], key.0.as_ref())
+        .map_err(to_py)?;
+    Ok(PyBytes::new(py, &jws))
+}
+
+/// Verify a detached jws over `payload` and return its protected header
+///
+/// The jws is rejected unless its `alg` header equals the algorithm of `key`.
+#[pyfunction]
+fn deserialize<'py>(
+    py: Python<'py>,
+    jws: &[u8],
+    payload: &[u8],
+    key: &PyVerificationKey,
+) -> PyResult<Bound<'py, PyAny>> {
+    let header = detached_jws::deserialize_with_key(&jws, &mut &payload[..], key.0.as_ref())
+        .map_err(to_py)?;
+    header_to_py(py, &header)
+}
+
+type SignWriter<'a> = SerializeJwsWriter<Vec<u8>, OpensslSigner<'a>>;
+
+self_cell!(
+    /// A writer together with the key its signer borrows
+    struct SignState {
+        owner: Arc<OpensslSigningKey>,
+
+        #[not_covariant]
+        dependent: SignWriter,
+    }
+);
+
+/// Serializes a payload passed piece by piece to [`update`](Self::update)
+#[pyclass(name = "SerializeJwsWriter", unsendable)]
+struct PySerializeJwsWriter(SignState);
+
+#[pymethods]
+impl PySerializeJwsWriter {
+    #[new]
+    #[pyo3(signature = (header, key))]
+    fn new(header: Option<&Bound<'_, PyDict>>, key: &PySigningKey) -> PyResult<Self> {
+        let header = header_from_py(header)?;
+        let state = SignState::try_new(key.0.clone(), |key| {
+            SerializeJwsWriter::new(
+                Vec::new(),
+                key.algorithm().to_owned(),
+                header,
+                key.signer()?,
+            )
+        })
+        .map_err(to_py)?;
+        Ok(Self(state))
+    }
+
+    fn update(&mut self, data: &[u8]) -> PyResult<()> {
+        self.0
+            .with_dependent_mut(|_, writer| writer.write_all(data))
+            .map_err(|e| to_py(e.into()))
+    }
+
+    /// Sign everything passed to `update` and return the jws
+    fn finish<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let jws = self
+            .0
+            .with_dependent_mut(|_, writer| writer.finish())
+            .map_err(to_py)?;
+        Ok(PyBytes::new(py, &jws))
+    }
+}
+
+type VerifyWriter<'a> = DeserializeJwsWriter<OpensslVerifier<'a>>;
+
+self_cell!(
+    /// A writer together with the key its verifier borrows
+    struct VerifyState {
+        owner: Arc<OpensslVerificationKey>,
+
+        #[not_covariant]
+        dependent: VerifyWriter,
+    }
+);
+
+/// Verifies a detached jws over a payload passed piece by piece to [`update`](Self::update)
+#[pyclass(name = "DeserializeJwsWriter", unsendable)]
+struct PyDeserializeJwsWriter(VerifyState);
+
+#[pymethods]
+impl PyDeserializeJwsWriter {
+    #[new]
+    fn new(jws: &[u8], key: &PyVerificationKey) -> PyResult<Self> {
+        let state = VerifyState::try_new(key.0.clone(), |key| {
+            DeserializeJwsWriter::with_key(&jws, key.as_ref(), &DecodeOptions::default())
+        })
+        .map_err(to_py)?;
+        Ok(Self(state))
+    }
+
+    fn update(&mut self, data: &[u8]) -> PyResult<()> {
+        self.0
+            .with_dependent_mut(|_, writer| writer.write_all(data))
+            .map_err(|e| to_py(e.into()))
+    }
+
+    /// Verify the signature and return the protected header
+    fn finish<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let header = self
+            .0
+            .with_dependent_mut(|_, writer| writer.finish())
+            .map_err(to_py)?;
+        header_to_py(py, &header)
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "detached_jws")]
+fn detached_jws_python(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PySigningKey>()?;
+    m.add_class::<PyVerificationKey>()?;
+    m.add_class::<PySerializeJwsWriter>()?;
+    m.add_class::<PyDeserializeJwsWriter>()?;
+    m.add_function(wrap_pyfunction!(serialize, m)?)?;
+    m.add_function(wrap_pyfunction!(deserialize, m)?)?;
+    m.add("JwsError", m.py().get_type::<JwsError>())?;
+    m.add("VerificationError", m.py().get_type::<VerificationError>())?;
+    Ok(())
+}
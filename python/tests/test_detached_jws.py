@@ -0,0 +1,122 @@
+import base64
+import json
+from pathlib import Path
+
+import pytest
+
+import detached_jws
+from detached_jws import (
+    DeserializeJwsWriter,
+    JwsError,
+    SerializeJwsWriter,
+    SigningKey,
+    VerificationError,
+    VerificationKey,
+)
+
+DATA = Path(__file__).parent / "data"
+VECTORS = Path(__file__).parents[2] / "tests" / "data" / "jws-vectors.json"
+
+PAYLOAD = bytes(i % 251 for i in range(100_000))
+
+
+def b64decode(value):
+    return base64.urlsafe_b64decode(value + "=" * (-len(value) % 4))
+
+
+def keys(alg):
+    if alg.startswith("HS"):
+        secret = bytes(range(64))
+        return SigningKey.hmac(alg, secret), VerificationKey.hmac(alg, secret)
+    if alg == "ES256":
+        # a private key stands in for its public part
+        pem = (DATA / "ec-p256.pem").read_bytes()
+        return SigningKey(alg, pem), VerificationKey(alg, pem)
+    return (
+        SigningKey(alg, (DATA / "rsa-2048.pem").read_bytes()),
+        VerificationKey(alg, (DATA / "rsa-2048.pub.pem").read_bytes()),
+    )
+
+
+@pytest.mark.parametrize(
+    "vector", json.loads(VECTORS.read_text()), ids=lambda vector: vector["name"]
+)
+def test_rfc_examples(vector):
+    header, payload, signature = vector["jws"].split(".")
+    jws = f"{header}..{signature}".encode()
+    payload = b64decode(vector.get("payload", payload))
+    key = VerificationKey.from_jwk(vector["alg"], vector["jwk"])
+
+    assert detached_jws.deserialize(jws, payload, key)["alg"] == vector["alg"]
+
+    with pytest.raises(VerificationError):
+        detached_jws.deserialize(jws, payload + b"!", key)
+
+
+@pytest.mark.parametrize("alg", ["HS256", "HS512", "RS256", "PS384", "ES256"])
+def test_serialize_deserialize(alg):
+    signing_key, verification_key = keys(alg)
+    assert signing_key.algorithm == alg
+
+    jws = detached_jws.serialize({"kid": "key-1", "n": [1, 2]}, PAYLOAD, signing_key)
+    assert b".." in jws
+
+    header = detached_jws.deserialize(jws, PAYLOAD, verification_key)
+    assert header == {"alg": alg, "kid": "key-1", "n": [1, 2]}
+
+    with pytest.raises(VerificationError):
+        detached_jws.deserialize(jws, PAYLOAD[1:], verification_key)
+
+
+@pytest.mark.parametrize("alg", ["HS256", "PS256", "ES256"])
+def test_streaming_writers(alg):
+    signing_key, verification_key = keys(alg)
+
+    writer = SerializeJwsWriter({"kid": "key-1"}, signing_key)
+    for offset in range(0, len(PAYLOAD), 7_000):
+        writer.update(PAYLOAD[offset : offset + 7_000])
+    jws = writer.finish()
+
+    if alg == "HS256":
+        assert jws == detached_jws.serialize({"kid": "key-1"}, PAYLOAD, signing_key)
+
+    writer = DeserializeJwsWriter(jws, verification_key)
+    writer.update(PAYLOAD[:3])
+    writer.update(PAYLOAD[3:])
+    assert writer.finish() == {"alg": alg, "kid": "key-1"}
+
+    writer = DeserializeJwsWriter(jws, verification_key)
+    writer.update(PAYLOAD[:-1])
+    with pytest.raises(VerificationError):
+        writer.finish()
+
+
+def test_finish_twice():
+    signing_key, _ = keys("HS256")
+    writer = SerializeJwsWriter(None, signing_key)
+    writer.update(b"payload")
+    writer.finish()
+
+    with pytest.raises(JwsError):
+        writer.finish()
+    with pytest.raises(JwsError):
+        writer.update(b"more")
+
+
+def test_rejected_input():
+    signing_key, verification_key = keys("HS256")
+    jws = detached_jws.serialize(None, b"payload", signing_key)
+
+    # a well-formed jws for another algorithm is not a signature mismatch
+    with pytest.raises(JwsError) as error:
+        detached_jws.deserialize(jws, b"payload", VerificationKey.hmac("HS384", bytes(64)))
+    assert not isinstance(error.value, VerificationError)
+
+    with pytest.raises(JwsError):
+        detached_jws.deserialize(b"not-a-jws", b"payload", verification_key)
+    with pytest.raises(JwsError):
+        DeserializeJwsWriter(jws.split(b".")[0], verification_key)
+    with pytest.raises(JwsError):
+        SigningKey.hmac("RS256", b"secret")
+    with pytest.raises(TypeError):
+        detached_jws.serialize({"key": object()}, b"payload", signing_key)
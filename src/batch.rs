@@ -0,0 +1,128 @@
+//! Verification of many detached jws against their payloads
+//!
+//! With the `rayon` feature the jobs of a chunk are verified in parallel on the rayon
+//! thread pool; without it they are verified one after another.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(feature = "openssl")] {
+//! use std::sync::Arc;
+//! use serde_json::Map;
+//! use detached_jws::batch::BatchVerifier;
+//! use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+//! use detached_jws::JwsHeader;
+//!
+//! let signing_key = OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+//! let key = Arc::new(OpensslVerificationKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap());
+//!
+//! let payloads = vec![b"first".to_vec(), b"second".to_vec()];
+//! let signatures: Vec<_> = payloads
+//!     .iter()
+//!     .map(|p| detached_jws::serialize_with_key(Map::new(), &mut p.as_slice(), &signing_key).unwrap())
+//!     .collect();
+//!
+//! let verifier = BatchVerifier::new(move |_: &JwsHeader| Some(Arc::clone(&key)));
+//! let results: Vec<_> = verifier
+//!     .verify(signatures.iter().zip(payloads.iter().map(|p| p.as_slice())))
+//!     .collect();
+//!
+//! assert!(results.iter().all(|r| r.is_ok()));
+//! # }
+//! ```
+
+use anyhow::Result;
+use std::io::Read;
+
+use crate::decode::{key_selector, DecodedJws};
+use crate::{DecodeOptions, DeserializeJwsWriter, JwsHeader, KeyResolver};
+
+/// Verifies `(jws, payload)` jobs with the keys a [`KeyResolver`] finds
+///
+/// Resolved keys are reused across jobs as far as the resolver caches them, e.g. a closure
+/// returning clones of one `Arc`.
+pub struct BatchVerifier<R> {
+    resolver: R,
+    options: DecodeOptions,
+    chunk_len: usize,
+}
+
+impl<R> BatchVerifier<R>
+where
+    R: KeyResolver,
+{
+    pub fn new(resolver: R) -> Self {
+        Self {
+            resolver,
+            options: DecodeOptions::default(),
+            chunk_len: 1024,
+        }
+    }
+
+    pub fn decode_options(mut self, options: DecodeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// How many jobs are taken from the iterator and verified at once, 1024 by default
+    pub fn chunk_len(mut self, len: usize) -> Self {
+        self.chunk_len = len.max(1);
+        self
+    }
+
+    /// Verify `jobs`, yielding the verified header or the error of each in order
+    ///
+    /// Jobs are pulled lazily a chunk at a time, so the iterator may be arbitrarily long.
+    pub fn verify<'a, I, J, P>(&'a self, jobs: I) -> impl Iterator<Item = Result<JwsHeader>> + 'a
+    where
+        I: IntoIterator<Item = (J, P)>,
+        I::IntoIter: 'a,
+        J: AsRef<[u8]> + Send,
+        P: Read + Send,
+    {
+        let mut jobs = jobs.into_iter();
+        std::iter::from_fn(move || {
+            let chunk: Vec<_> = jobs.by_ref().take(self.chunk_len).collect();
+            match chunk.is_empty() {
+                true => None,
+                false => Some(self.verify_chunk(chunk)),
+            }
+        })
+        .flatten()
+    }
+
+    #[cfg(feature = "rayon")]
+    fn verify_chunk<J, P>(&self, chunk: Vec<(J, P)>) -> Vec<Result<JwsHeader>>
+    where
+        J: AsRef<[u8]> + Send,
+        P: Read + Send,
+    {
+        use rayon::prelude::*;
+
+        chunk
+            .into_par_iter()
+            .map(|(jws, mut payload)| self.verify_one(&jws, &mut payload))
+            .collect()
+    }
+
+    #[cfg(not(feature = "rayon"))]
+    fn verify_chunk<J, P>(&self, chunk: Vec<(J, P)>) -> Vec<Result<JwsHeader>>
+    where
+        J: AsRef<[u8]> + Send,
+        P: Read + Send,
+    {
+        chunk
+            .into_iter()
+            .map(|(jws, mut payload)| self.verify_one(&jws, &mut payload))
+            .collect()
+    }
+
+    fn verify_one(&self, jws: &impl AsRef<[u8]>, payload: &mut impl Read) -> Result<JwsHeader> {
+        let decoded = DecodedJws::new(jws, &self.options)?;
+        let key = self.resolver.resolve(&decoded.header)?;
+
+        let mut writer = DeserializeJwsWriter::with_decoded(decoded, key_selector(key.as_ref())?)?;
+        std::io::copy(payload, &mut writer)?;
+        writer.finish()
+    }
+}
@@ -0,0 +1,398 @@
+//! Borrowed, allocation-free view of a detached jws
+//!
+//! [`DetachedJwsRef`] splits a compact jws in place, decodes the signature into a stack buffer
+//! and deserializes the header only on request, e.g. into a struct borrowing `kid` and `alg`
+//! from a caller provided buffer. Verification encodes the payload through fixed stack
+//! buffers, so it allocates no more than the verifier itself.
+
+use anyhow::{bail, Context, Error, Result};
+use core::{fmt, ops::Deref};
+use serde::Deserialize;
+#[cfg(feature = "std")]
+use serde::{
+    de::{IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserializer,
+};
+#[cfg(feature = "std")]
+use std::borrow::Cow;
+#[cfg(feature = "std")]
+use std::io::Read;
+
+use crate::decode::{check_token_len, decode_header_segment, decoded_len, exceeds_depth};
+#[cfg(feature = "std")]
+use crate::decode::{visit_members, BorrowedStr, DIGEST_EXTENSION};
+use crate::DecodeOptions;
+use crate::JwsHeader;
+#[cfg(feature = "std")]
+use crate::{
+    chunked::{ChunkedEncoder, StackBuffers},
+    DuplicateHeaderMember, VerificationError, VerificationKey, Verify,
+};
+
+/// Longest signature [`SignatureBuf`] holds, enough for RSA keys up to 8192 bits
+pub const MAX_SIGNATURE_LEN: usize = 1024;
+
+/// Header size decoded on the stack by [`DetachedJwsRef::verify_with_key`]
+#[cfg(feature = "std")]
+const HEADER_STACK_LEN: usize = 2048;
+
+static DOT_BYTE: u8 = b'.';
+
+/// A detached jws borrowed from the input without copying its segments
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "openssl")] {
+/// use serde::Deserialize;
+/// use serde_json::{json, Map};
+/// use detached_jws::DetachedJwsRef;
+/// use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+///
+/// #[derive(Deserialize)]
+/// struct KeyId<'a> {
+///     kid: &'a str,
+/// }
+///
+/// let mut header = Map::new();
+/// header.insert("kid".to_owned(), json!("key-1"));
+/// let key = OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+/// let jws = detached_jws::serialize_with_key(header, &mut &b"payload"[..], &key).unwrap();
+///
+/// let view = DetachedJwsRef::parse(&jws, &Default::default()).unwrap();
+///
+/// let mut buf = [0; 256];
+/// let KeyId { kid } = view.header_as(&mut buf).unwrap();
+/// assert_eq!(kid, "key-1");
+///
+/// let key = OpensslVerificationKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+/// view.verify_with_key(&mut &b"payload"[..], &key).unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetachedJwsRef<'a> {
+    encoded_header: &'a [u8],
+    encoded_signature: &'a [u8],
+    max_header_len: usize,
+    max_header_depth: usize,
+}
+
+impl<'a> DetachedJwsRef<'a> {
+    /// Split `jws` into its segments, checking them against the `options` limits
+    pub fn parse(jws: &'a [u8], options: &DecodeOptions) -> Result<Self> {
+        check_token_len(jws, options)?;
+
+        let mut splits = jws.split(|e| e == &DOT_BYTE);
+
+        let encoded_header = splits.next().context("wrong jws format")?;
+        if decoded_len(encoded_header.len()) > options.max_header_len {
+            bail!("jws header exceeds {} bytes", options.max_header_len)
+        }
+
+        let mut splits = splits.skip(1); //detached payload skip
+
+        let encoded_signature = splits.next().context("wrong jws format")?;
+        let max_signature_len = options.max_signature_len.min(MAX_SIGNATURE_LEN);
+        if decoded_len(encoded_signature.len()) > max_signature_len {
+            bail!("jws signature exceeds {} bytes", max_signature_len)
+        }
+
+        Ok(Self {
+            encoded_header,
+            encoded_signature,
+            max_header_len: options.max_header_len,
+            max_header_depth: options.max_header_depth,
+        })
+    }
+
+    /// The protected header segment as it was received
+    pub fn encoded_header(&self) -> &'a [u8] {
+        self.encoded_header
+    }
+
+    pub fn encoded_signature(&self) -> &'a [u8] {
+        self.encoded_signature
+    }
+
+    /// Size of the buffer [`decode_header_into`](Self::decode_header_into) needs
+    pub fn header_buffer_len(&self) -> usize {
+        // the base64 decoder writes whole 6 byte groups per 8 input bytes
+        self.encoded_header.len().div_ceil(8) * 6
+    }
+
+    /// Decode the protected header JSON into `buf`, returning the written part
+    pub fn decode_header_into<'b>(&self, buf: &'b mut [u8]) -> Result<&'b [u8]> {
+        if buf.len() < self.header_buffer_len() {
+            bail!(
+                "header buffer must be at least {} bytes",
+                self.header_buffer_len()
+            )
+        }
+
+        let len = base64::decode_config_slice(self.encoded_header, base64::URL_SAFE_NO_PAD, buf)
+            .map_err(Error::msg)
+            .context("wrong jws header format")?;
+        let decoded = &buf[..len];
+
+        if exceeds_depth(decoded, self.max_header_depth) {
+            bail!("jws header exceeds nesting depth {}", self.max_header_depth)
+        }
+
+        Ok(decoded)
+    }
+
+    /// Deserialize the protected header into `T`, which may borrow from `buf`
+    ///
+    /// Borrowed `&str` fields fail on JSON escapes; use `Cow<str>` where they may occur.
+    pub fn header_as<'b, T>(&self, buf: &'b mut [u8]) -> Result<T>
+    where
+        T: Deserialize<'b>,
+    {
+        let decoded = self.decode_header_into(buf)?;
+        serde_json::from_slice(decoded).context("wrong jws header format")
+    }
+
+    /// Deserialize the whole protected header, rejecting duplicate member names
+    pub fn header(&self) -> Result<JwsHeader> {
+        let options = DecodeOptions {
+            max_header_len: self.max_header_len,
+            max_header_depth: self.max_header_depth,
+            ..DecodeOptions::default()
+        };
+        decode_header_segment(self.encoded_header, &options)
+    }
+
+    /// Decode the signature into a stack buffer
+    pub fn signature(&self) -> Result<SignatureBuf> {
+        let mut signature = SignatureBuf {
+            buf: [0; MAX_SIGNATURE_LEN + 8],
+            len: 0,
+        };
+        signature.len = base64::decode_config_slice(
+            self.encoded_signature,
+            base64::URL_SAFE_NO_PAD,
+            &mut signature.buf,
+        )
+        .map_err(Error::msg)
+        .context("wrong jws signature format")?;
+
+        Ok(signature)
+    }
+
+    /// Verify the signature of `payload` with `verifier`
+    ///
+    /// A rejected signature fails with a [`VerificationError`] carrying the reason.
+    #[cfg(feature = "std")]
+    pub fn verify(&self, payload: &mut impl Read, verifier: impl Verify) -> Result<()> {
+        self.check_header(|_| Ok(()))?;
+        self.verify_checked(payload, verifier)
+    }
+
+    /// Verify the signature of `payload` with a fresh verifier of a reusable [`VerificationKey`]
+    ///
+    /// The jws is rejected unless its `alg` header equals [`VerificationKey::algorithm`].
+    #[cfg(feature = "std")]
+    pub fn verify_with_key(
+        &self,
+        payload: &mut impl Read,
+        key: &impl VerificationKey,
+    ) -> Result<()> {
+        self.check_header(|header| match header.alg {
+            Some(alg) if alg == key.algorithm() => Ok(()),
+            _ => bail!("verifier is not found"),
+        })?;
+        self.verify_checked(payload, key.verifier()?)
+    }
+
+    /// Decode the header members checked before verifying, on the stack for usual sizes
+    ///
+    /// A digest jws is rejected, see the `digest` module.
+    #[cfg(feature = "std")]
+    fn check_header(&self, check: impl FnOnce(Checked<'_>) -> Result<()>) -> Result<()> {
+        let mut stack = [0; HEADER_STACK_LEN];
+        let mut heap = Vec::new();
+        let buf = match self.header_buffer_len() {
+            len if len <= HEADER_STACK_LEN => &mut stack[..],
+            len => {
+                heap.resize(len, 0);
+                &mut heap[..]
+            }
+        };
+
+        let header: Checked = self.header_as(buf)?;
+        if let Some(name) = header.duplicate {
+            bail!(DuplicateHeaderMember(name))
+        }
+        if header.crit.digest {
+            bail!("jws signs a content digest and must be verified as such")
+        }
+        check(header)
+    }
+
+    #[cfg(feature = "std")]
+    fn verify_checked(&self, payload: &mut impl Read, mut verifier: impl Verify) -> Result<()> {
+        let signature = self.signature()?;
+
+        verifier.update(self.encoded_header)?;
+        verifier.update(&[DOT_BYTE])?;
+
+        let mut encoder = ChunkedEncoder::with_buffers(verifier, StackBuffers::new());
+        std::io::copy(payload, &mut encoder)?;
+        let verifier = encoder.finish()?;
+
+        verifier
+            .check(&signature)
+            .map_err(|reason| VerificationError::new(&self.header().unwrap_or_default(), reason))?;
+
+        Ok(())
+    }
+}
+
+/// Header members checked by [`DetachedJwsRef::verify`] and
+/// [`DetachedJwsRef::verify_with_key`], borrowed from the decoded header
+///
+/// The header is visited like [`DetachedJwsRef::header`] does, so duplicate members are
+/// rejected on both paths.
+#[cfg(feature = "std")]
+struct Checked<'h> {
+    alg: Option<Cow<'h, str>>,
+    crit: Crit,
+    duplicate: Option<String>,
+}
+
+#[cfg(feature = "std")]
+impl<'de> Deserialize<'de> for Checked<'de> {
+    fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct CheckedVisitor;
+
+        impl<'de> Visitor<'de> for CheckedVisitor {
+            type Value = Checked<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a JSON object")
+            }
+
+            fn visit_map<A>(self, map: A) -> core::result::Result<Checked<'de>, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut alg = None;
+                let mut crit = Crit::default();
+                let duplicate = visit_members(map, |name, map| {
+                    match name {
+                        "alg" => alg = map.next_value::<Option<BorrowedStr>>()?.map(|alg| alg.0),
+                        "crit" => crit = map.next_value()?,
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                    Ok(())
+                })?;
+
+                Ok(Checked {
+                    alg,
+                    crit,
+                    duplicate,
+                })
+            }
+        }
+
+        deserializer.deserialize_map(CheckedVisitor)
+    }
+}
+
+/// Whether `crit` lists the digest extension, read without allocating
+#[cfg(feature = "std")]
+#[derive(Default)]
+struct Crit {
+    digest: bool,
+}
+
+#[cfg(feature = "std")]
+impl<'de> Deserialize<'de> for Crit {
+    fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct CritVisitor;
+
+        impl<'de> Visitor<'de> for CritVisitor {
+            type Value = Crit;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a list of header names")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> core::result::Result<Crit, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut crit = Crit::default();
+                while let Some(IsDigest(digest)) = seq.next_element()? {
+                    crit.digest |= digest;
+                }
+                Ok(crit)
+            }
+        }
+
+        deserializer.deserialize_seq(CritVisitor)
+    }
+}
+
+/// A `crit` entry compared with the digest extension
+#[cfg(feature = "std")]
+struct IsDigest(bool);
+
+#[cfg(feature = "std")]
+impl<'de> Deserialize<'de> for IsDigest {
+    fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct NameVisitor;
+
+        impl<'de> Visitor<'de> for NameVisitor {
+            type Value = IsDigest;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a header name")
+            }
+
+            fn visit_str<E>(self, name: &str) -> core::result::Result<IsDigest, E> {
+                Ok(IsDigest(name == DIGEST_EXTENSION))
+            }
+        }
+
+        deserializer.deserialize_str(NameVisitor)
+    }
+}
+
+/// A decoded signature on the stack
+#[derive(Clone)]
+pub struct SignatureBuf {
+    buf: [u8; MAX_SIGNATURE_LEN + 8],
+    len: usize,
+}
+
+impl Deref for SignatureBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl AsRef<[u8]> for SignatureBuf {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl fmt::Debug for SignatureBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SignatureBuf").field(&self.deref()).finish()
+    }
+}
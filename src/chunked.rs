@@ -0,0 +1,220 @@
+//! Buffered base64url encoding of streamed payloads
+//!
+//! Input is collected into chunks of [`CHUNK_LEN`] bytes, a multiple of 3, so each chunk encodes
+//! without padding and reaches the sink in a single update. Updates of at least a chunk are
+//! encoded straight from the caller's buffer, and `flush` encodes the buffered input up to the
+//! last whole base64 quantum. With the `simd` feature chunks are encoded by
+//! [base64-simd](https://crates.io/crates/base64-simd).
+//!
+//! The buffers grow on the heap by default; [`StackBuffers`] keeps smaller chunks inline for
+//! the allocation-free [`DetachedJwsRef`](crate::DetachedJwsRef) path.
+
+use alloc::vec::Vec;
+use anyhow::{Context, Result};
+
+use crate::ByteSink;
+
+/// Input bytes encoded at once, 48 KiB encoding to 64 KiB
+const CHUNK_LEN: usize = 48 * 1024;
+
+/// Input bytes encoded at once from [`StackBuffers`], 3 KiB encoding to 4 KiB
+#[cfg(feature = "std")]
+const STACK_CHUNK_LEN: usize = 3 * 1024;
+
+/// Storage for the buffered input and the encoded output of a chunk
+pub(crate) trait ChunkBuffers {
+    /// Input bytes encoded at once, a multiple of 3
+    const CHUNK_LEN: usize;
+
+    /// The input and output buffers, at least `input_len` and `output_len` bytes long
+    fn get(&mut self, input_len: usize, output_len: usize) -> (&mut [u8], &mut [u8]);
+}
+
+/// Heap buffers growing up to a [`CHUNK_LEN`] chunk as the payload needs
+#[derive(Default)]
+pub(crate) struct HeapBuffers {
+    input: Vec<u8>,
+    output: Vec<u8>,
+}
+
+impl ChunkBuffers for HeapBuffers {
+    const CHUNK_LEN: usize = CHUNK_LEN;
+
+    fn get(&mut self, input_len: usize, output_len: usize) -> (&mut [u8], &mut [u8]) {
+        if self.input.len() < input_len {
+            self.input.resize(input_len, 0);
+        }
+        if self.output.len() < output_len {
+            self.output.resize(output_len, 0);
+        }
+        (&mut self.input, &mut self.output)
+    }
+}
+
+/// Fixed buffers for a [`STACK_CHUNK_LEN`] chunk, keeping the encoder off the heap
+#[cfg(feature = "std")]
+pub(crate) struct StackBuffers {
+    input: [u8; STACK_CHUNK_LEN],
+    output: [u8; STACK_CHUNK_LEN / 3 * 4],
+}
+
+#[cfg(feature = "std")]
+impl StackBuffers {
+    pub fn new() -> Self {
+        Self {
+            input: [0; STACK_CHUNK_LEN],
+            output: [0; STACK_CHUNK_LEN / 3 * 4],
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ChunkBuffers for StackBuffers {
+    const CHUNK_LEN: usize = STACK_CHUNK_LEN;
+
+    fn get(&mut self, _: usize, _: usize) -> (&mut [u8], &mut [u8]) {
+        (&mut self.input, &mut self.output)
+    }
+}
+
+/// Base64url encodes everything passed to [`update`](Self::update) into the sink
+pub(crate) struct ChunkedEncoder<S, B = HeapBuffers> {
+    sink: Option<S>,
+    buffers: B,
+    buffered: usize,
+}
+
+impl<S> ChunkedEncoder<S>
+where
+    S: ByteSink,
+{
+    pub fn new(sink: S) -> Self {
+        Self::with_buffers(sink, HeapBuffers::default())
+    }
+}
+
+impl<S, B> ChunkedEncoder<S, B>
+where
+    S: ByteSink,
+    B: ChunkBuffers,
+{
+    pub fn with_buffers(sink: S, buffers: B) -> Self {
+        Self {
+            sink: Some(sink),
+            buffers,
+            buffered: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) -> Result<()> {
+        let sink = self.sink.as_mut().context(FINISHED)?;
+
+        while !data.is_empty() {
+            if self.buffered == 0 && data.len() >= B::CHUNK_LEN {
+                let (chunks, rest) = data.split_at(data.len() - data.len() % B::CHUNK_LEN);
+                let (_, output) = self.buffers.get(0, encoded_len(B::CHUNK_LEN));
+                for chunk in chunks.chunks_exact(B::CHUNK_LEN) {
+                    encode_chunk(sink, chunk, output)?;
+                }
+                data = rest;
+                continue;
+            }
+
+            let (head, rest) = data.split_at(data.len().min(B::CHUNK_LEN - self.buffered));
+            let end = self.buffered + head.len();
+            let (input, _) = self.buffers.get(end, 0);
+            input[self.buffered..end].copy_from_slice(head);
+            self.buffered = end;
+            data = rest;
+
+            if self.buffered == B::CHUNK_LEN {
+                let (input, output) = self.buffers.get(B::CHUNK_LEN, encoded_len(B::CHUNK_LEN));
+                encode_chunk(sink, &input[..B::CHUNK_LEN], output)?;
+                self.buffered = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Encode the buffered input up to a multiple of 3 bytes, keeping at most two bytes back
+    #[cfg(feature = "std")]
+    pub fn flush_quanta(&mut self) -> Result<()> {
+        let sink = match self.sink.as_mut() {
+            Some(sink) => sink,
+            None => return Ok(()),
+        };
+
+        let whole = self.buffered - self.buffered % 3;
+        let (input, output) = self.buffers.get(self.buffered, encoded_len(whole));
+        encode_chunk(sink, &input[..whole], output)?;
+        input.copy_within(whole..self.buffered, 0);
+        self.buffered -= whole;
+
+        Ok(())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.sink.is_none()
+    }
+
+    /// Encode the buffered tail and return the sink
+    pub fn finish(&mut self) -> Result<S> {
+        let mut sink = self.sink.take().context(FINISHED)?;
+
+        let (input, output) = self.buffers.get(self.buffered, encoded_len(self.buffered));
+        encode_chunk(&mut sink, &input[..self.buffered], output)?;
+        self.buffered = 0;
+
+        Ok(sink)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S, B> std::io::Write for ChunkedEncoder<S, B>
+where
+    S: ByteSink,
+    B: ChunkBuffers,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf).map_err(io_error)?;
+        Ok(buf.len())
+    }
+
+    /// Encode the whole base64 quanta buffered so far into the sink
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flush_quanta().map_err(io_error)
+    }
+}
+
+/// Hand back the `std::io::Error` of a `Write` sink unchanged
+#[cfg(feature = "std")]
+fn io_error(e: anyhow::Error) -> std::io::Error {
+    match e.downcast::<std::io::Error>() {
+        Ok(e) => e,
+        Err(e) => std::io::Error::other(e),
+    }
+}
+
+const FINISHED: &str = "encoder has already had finish() called";
+
+fn encoded_len(len: usize) -> usize {
+    len.div_ceil(3) * 4
+}
+
+fn encode_chunk(sink: &mut impl ByteSink, input: &[u8], output: &mut [u8]) -> Result<()> {
+    if input.is_empty() {
+        return Ok(());
+    }
+
+    let output = &mut output[..encoded_len(input.len())];
+
+    #[cfg(feature = "simd")]
+    let len = base64_simd::URL_SAFE_NO_PAD
+        .encode(input, base64_simd::Out::from_slice(output))
+        .len();
+    #[cfg(not(feature = "simd"))]
+    let len = base64::encode_config_slice(input, base64::URL_SAFE_NO_PAD, output);
+
+    sink.update(&output[..len])
+}
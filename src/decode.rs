@@ -1,18 +1,103 @@
 //! Verify and deserialize Detached-Jws
 
-use anyhow::{bail, Context, Result};
-use base64::{read::DecoderReader, write::EncoderWriter};
+use alloc::{borrow::Cow, collections::BTreeSet, string::String, vec::Vec};
+use anyhow::{bail, Context, Error, Result};
+use core::fmt;
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
 use serde_json::{Map, Value};
+#[cfg(feature = "std")]
 use std::io::{Read, Write};
 
-use crate::Verify;
+use crate::chunked::ChunkedEncoder;
+#[cfg(feature = "std")]
+use crate::policy::VerificationPolicy;
+#[cfg(feature = "std")]
+use crate::replay::{ReplayCache, ReplayDetected, ReplayKey};
+use crate::verification::{Verification, VerificationError};
+#[cfg(feature = "std")]
+use crate::KeyResolver;
+use crate::{VerificationKey, Verify};
 
 type JwsHeader = Map<String, Value>;
 
 static DOT_BYTE: u8 = b'.';
 
+/// Limits applied to an untrusted detached jws before it is decoded
+///
+/// Every limit is checked against the encoded input before the corresponding
+/// part is decoded, so an oversized token is rejected without allocating.
+///
+/// # Examples
+///
+/// ```
+/// use detached_jws::DecodeOptions;
+///
+/// let options = DecodeOptions {
+///     max_signature_len: 512,
+///     ..DecodeOptions::default()
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Maximum length in bytes of the whole compact jws
+    pub max_token_len: usize,
+    /// Maximum length in bytes of the decoded protected header
+    pub max_header_len: usize,
+    /// Maximum nesting depth of arrays and objects in the protected header
+    pub max_header_depth: usize,
+    /// Maximum length in bytes of the decoded signature
+    pub max_signature_len: usize,
+}
+
+impl DecodeOptions {
+    /// No limits, for trusted jws exceeding the [`default`](DecodeOptions::default) limits,
+    /// e.g. with a long `x5c` certificate chain
+    ///
+    /// Untrusted jws should be decoded with [`DecodeOptions::default`] or tighter limits.
+    pub const fn unlimited() -> Self {
+        Self {
+            max_token_len: usize::MAX,
+            max_header_len: usize::MAX,
+            max_header_depth: usize::MAX,
+            max_signature_len: usize::MAX,
+        }
+    }
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            max_token_len: 16 * 1024,
+            max_header_len: 8 * 1024,
+            max_header_depth: 16,
+            max_signature_len: 1024,
+        }
+    }
+}
+
+/// The protected header contains a member name more than once
+///
+/// [RFC 7515 §5.2](https://tools.ietf.org/html/rfc7515#section-5.2) leaves it to
+/// implementations to reject or consistently handle duplicates; this crate rejects them so
+/// that no two parties can disagree on which `alg` or `kid` is in effect.
+///
+/// Returned wrapped in [`anyhow::Error`] and can be recovered with `downcast_ref`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateHeaderMember(pub String);
+
+impl fmt::Display for DuplicateHeaderMember {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "duplicate jws header member `{}`", self.0)
+    }
+}
+
+impl core::error::Error for DuplicateHeaderMember {}
+
 /// Deserialize and verify detached jws
 ///
+/// The [`DecodeOptions::default`] limits are applied; use
+/// [`deserialize_selector_with_options`] for other limits.
+///
 /// # Examples
 ///
 /// ```
@@ -60,6 +145,7 @@ static DOT_BYTE: u8 = b'.';
 ///     "custom_value"
 /// );
 /// ```
+#[cfg(feature = "std")]
 pub fn deserialize_selector<F, V>(
     jws: &impl AsRef<[u8]>,
     payload: &mut impl Read,
@@ -69,13 +155,76 @@ where
     F: Fn(&JwsHeader) -> Option<V>,
     V: Verify,
 {
-    let mut writer = DeserializeJwsWriter::new(jws, selector)?;
+    deserialize_selector_with_options(jws, payload, selector, &DecodeOptions::default())
+}
+
+/// Deserialize and verify detached jws with the given [`DecodeOptions`] limits
+///
+/// # Examples
+///
+/// ```
+/// extern crate detached_jws;
+/// extern crate anyhow;
+/// extern crate serde_json;
+///
+/// use std::io::{Write};
+/// use anyhow::Result;
+/// use detached_jws::{DecodeOptions, Verify};
+///
+///#[derive(Default)]
+/// pub struct DummyVerifier;
+///
+/// impl Verify for DummyVerifier {
+///     fn verify(&self, signature: &[u8]) -> Result<bool> {
+///         Ok(true)
+///     }
+/// }
+///
+/// impl Write for DummyVerifier {
+///     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+///         Ok(buf.len())
+///     }
+///     fn flush(&mut self) -> std::io::Result<()> {
+///        Ok(())
+///     }
+/// }
+///
+/// let jws =  "eyJhbGciOiJ0ZXN0X2FsZ29yaXRobSIsImN1c3RvbSI6ImN1c3RvbV92YWx1ZSJ9..ZXlKaGJHY2lPaUowWlhOMFgyRnNaMjl5YVhSb2JTSXNJbU4xYzNSdmJTSTZJbU4xYzNSdmJWOTJZV3gxWlNKOS5BQUVDQXdRRkJn".as_bytes();
+///
+/// let options = DecodeOptions {
+///     max_token_len: 64,
+///     ..DecodeOptions::default()
+/// };
+///
+/// let result = detached_jws::deserialize_selector_with_options(
+///     &jws,
+///     &mut vec![0, 1, 2, 3, 4, 5, 6].as_slice(),
+///     |_| Some(DummyVerifier::default()),
+///     &options);
+///
+/// assert!(result.is_err());
+/// ```
+#[cfg(feature = "std")]
+pub fn deserialize_selector_with_options<F, V>(
+    jws: &impl AsRef<[u8]>,
+    payload: &mut impl Read,
+    selector: F,
+    options: &DecodeOptions,
+) -> Result<JwsHeader>
+where
+    F: Fn(&JwsHeader) -> Option<V>,
+    V: Verify,
+{
+    let mut writer = DeserializeJwsWriter::with_options(jws, selector, options)?;
     std::io::copy(payload, &mut writer)?;
     writer.finish()
 }
 
 /// Deserialize and verify detached jws.
 ///
+/// The [`DecodeOptions::default`] limits are applied; use
+/// [`deserialize_selector_with_options`] for other limits.
+///
 /// # Examples
 ///
 /// ```
@@ -118,6 +267,7 @@ where
 ///     "custom_value"
 /// );
 /// ```
+#[cfg(feature = "std")]
 pub fn deserialize<V>(
     jws: &impl AsRef<[u8]>,
     payload: &mut impl Read,
@@ -131,6 +281,87 @@ where
     writer.finish()
 }
 
+/// Deserialize and verify detached jws with a fresh verifier of a reusable [`VerificationKey`]
+///
+/// The jws is rejected unless its `alg` header equals [`VerificationKey::algorithm`].
+#[cfg(feature = "std")]
+pub fn deserialize_with_key(
+    jws: &impl AsRef<[u8]>,
+    payload: &mut impl Read,
+    key: &impl VerificationKey,
+) -> Result<JwsHeader> {
+    let mut writer = DeserializeJwsWriter::with_key(jws, key, &DecodeOptions::default())?;
+    std::io::copy(payload, &mut writer)?;
+    writer.finish()
+}
+
+/// Deserialize and verify detached jws with the key a [`KeyResolver`] finds for its header
+#[cfg(feature = "std")]
+pub fn deserialize_with_resolver(
+    jws: &impl AsRef<[u8]>,
+    payload: &mut impl Read,
+    resolver: &impl KeyResolver,
+) -> Result<JwsHeader> {
+    let decoded = DecodedJws::new(jws, &DecodeOptions::default())?;
+    let key = resolver.resolve(&decoded.header)?;
+
+    let mut writer = DeserializeJwsWriter::with_decoded(decoded, key_selector(key.as_ref())?)?;
+    std::io::copy(payload, &mut writer)?;
+    writer.finish()
+}
+
+/// Deserialize and verify detached jws that satisfies a [`VerificationPolicy`]
+///
+/// The policy is checked against the protected header, using its size limits, before the
+/// signature is verified with a fresh verifier of `key`.
+#[cfg(feature = "std")]
+pub fn deserialize_with_policy(
+    jws: &impl AsRef<[u8]>,
+    payload: &mut impl Read,
+    key: &impl VerificationKey,
+    policy: &VerificationPolicy,
+) -> Result<JwsHeader> {
+    let mut writer = DeserializeJwsWriter::with_policy(jws, key, policy)?;
+    std::io::copy(payload, &mut writer)?;
+    writer.finish()
+}
+
+/// Like [`deserialize_with_policy`] with the key a [`KeyResolver`] finds for the header
+///
+/// The key is only resolved for a header that satisfies the policy.
+#[cfg(feature = "std")]
+pub fn deserialize_with_resolver_and_policy(
+    jws: &impl AsRef<[u8]>,
+    payload: &mut impl Read,
+    resolver: &impl KeyResolver,
+    policy: &VerificationPolicy,
+) -> Result<JwsHeader> {
+    let decoded = DecodedJws::new(jws, policy.decode_options())?;
+    policy.check(&decoded.header)?;
+
+    let key = resolver.resolve(&decoded.header)?;
+    let mut writer = DeserializeJwsWriter::with_decoded(decoded, key_selector(key.as_ref())?)?;
+    std::io::copy(payload, &mut writer)?;
+    writer.finish()
+}
+
+/// Decode the protected header of a detached jws without verifying it
+///
+/// Meant for looking up the verification key, e.g. by `kid`; the header must not be
+/// trusted before the signature is verified.
+pub fn decode_header(jws: &impl AsRef<[u8]>, options: &DecodeOptions) -> Result<JwsHeader> {
+    let input = jws.as_ref();
+
+    check_token_len(input, options)?;
+
+    let encoded_header = input
+        .split(|e| e == &DOT_BYTE)
+        .next()
+        .context("wrong jws format")?;
+
+    decode_header_segment(encoded_header, options)
+}
+
 /// A `Write` implementation deserialize and verify detached jws
 ///
 /// # Examples
@@ -178,8 +409,9 @@ where
 ///     "custom_value"
 /// );
 /// ```
-pub struct DeserializeJwsWriter<V: Write> {
-    encoder: EncoderWriter<V>,
+pub struct DeserializeJwsWriter<V> {
+    encoder: ChunkedEncoder<V>,
+    encoded_header: Vec<u8>,
     header: Option<JwsHeader>,
     signature: Vec<u8>,
 }
@@ -188,56 +420,434 @@ impl<V> DeserializeJwsWriter<V>
 where
     V: Verify,
 {
+    /// Create a writer applying the [`DecodeOptions::default`] limits
+    ///
+    /// Use [`with_options`](Self::with_options) for other limits.
     pub fn new<S>(jws: &impl AsRef<[u8]>, selector: S) -> Result<Self>
     where
         S: FnOnce(&JwsHeader) -> Option<V>,
     {
-        let input = jws.as_ref();
+        Self::with_options(jws, selector, &DecodeOptions::default())
+    }
 
-        let mut splits = input.split(|e| e == &DOT_BYTE);
+    /// Create a writer verifying with a fresh verifier of a reusable [`VerificationKey`]
+    ///
+    /// The jws is rejected unless its `alg` header equals [`VerificationKey::algorithm`].
+    pub fn with_key<'k, K>(
+        jws: &impl AsRef<[u8]>,
+        key: &'k K,
+        options: &DecodeOptions,
+    ) -> Result<Self>
+    where
+        K: VerificationKey<Verifier<'k> = V>,
+    {
+        Self::with_options(jws, key_selector(key)?, options)
+    }
 
-        let encoded_header = splits.next().context("wrong jws format")?.to_vec();
+    /// Create a writer for a jws whose protected header satisfies a [`VerificationPolicy`]
+    ///
+    /// The jws is also rejected unless its `alg` header equals [`VerificationKey::algorithm`].
+    #[cfg(feature = "std")]
+    pub fn with_policy<'k, K>(
+        jws: &impl AsRef<[u8]>,
+        key: &'k K,
+        policy: &VerificationPolicy,
+    ) -> Result<Self>
+    where
+        K: VerificationKey<Verifier<'k> = V>,
+    {
+        let decoded = DecodedJws::new(jws, policy.decode_options())?;
+        policy.check(&decoded.header)?;
+        Self::with_decoded(decoded, key_selector(key)?)
+    }
 
-        let header = {
-            let mut slice = encoded_header.as_slice();
-            let decoder = DecoderReader::new(&mut slice, base64::URL_SAFE_NO_PAD);
-            serde_json::from_reader(decoder).context("wrong jws header format")?
-        };
+    pub fn with_options<S>(
+        jws: &impl AsRef<[u8]>,
+        selector: S,
+        options: &DecodeOptions,
+    ) -> Result<Self>
+    where
+        S: FnOnce(&JwsHeader) -> Option<V>,
+    {
+        Self::decode(jws, selector, options, false)
+    }
 
-        let mut splits = splits.skip(1); //detached payload skip
+    /// Create a writer for a jws signing a payload digest, see [`digest`](crate::digest)
+    #[cfg(feature = "openssl")]
+    pub(crate) fn for_digest<S>(
+        jws: &impl AsRef<[u8]>,
+        selector: S,
+        options: &DecodeOptions,
+    ) -> Result<Self>
+    where
+        S: FnOnce(&JwsHeader) -> Option<V>,
+    {
+        Self::decode(jws, selector, options, true)
+    }
 
-        let signature = {
-            let mut part3 = splits.next().context("wrong jws format")?;
-            base64::decode_config(&mut part3, base64::URL_SAFE_NO_PAD)
-                .context("wrong jws signature format")?
-        };
+    /// Create a writer for a jws whose header was already decoded, e.g. to resolve its key
+    #[cfg(feature = "std")]
+    pub(crate) fn with_decoded<S>(decoded: DecodedJws, selector: S) -> Result<Self>
+    where
+        S: FnOnce(&JwsHeader) -> Option<V>,
+    {
+        Self::verify_decoded(decoded, selector, false)
+    }
+
+    fn decode<S>(
+        jws: &impl AsRef<[u8]>,
+        selector: S,
+        options: &DecodeOptions,
+        digest: bool,
+    ) -> Result<Self>
+    where
+        S: FnOnce(&JwsHeader) -> Option<V>,
+    {
+        Self::verify_decoded(DecodedJws::new(jws, options)?, selector, digest)
+    }
+
+    fn verify_decoded<S>(decoded: DecodedJws, selector: S, digest: bool) -> Result<Self>
+    where
+        S: FnOnce(&JwsHeader) -> Option<V>,
+    {
+        let DecodedJws {
+            encoded_header,
+            header,
+            signature,
+        } = decoded;
+        if !digest {
+            check_not_digest(&header)?;
+        }
 
         let mut verifier = selector(&header).context("verifier is not found")?;
 
-        verifier.write_all(encoded_header.as_slice())?;
-        verifier.write_all(&[DOT_BYTE])?;
+        verifier.update(encoded_header.as_slice())?;
+        verifier.update(&[DOT_BYTE])?;
 
         Ok(Self {
-            encoder: EncoderWriter::new(verifier, base64::URL_SAFE_NO_PAD),
+            encoder: ChunkedEncoder::new(verifier),
+            encoded_header,
             header: Some(header),
-            signature: signature,
+            signature,
         })
     }
 
+    /// The protected header segment exactly as it was received
+    ///
+    /// Suitable for auditing or re-transmitting the jws byte for byte; it is only
+    /// trustworthy once [`finish`](Self::finish) has succeeded.
+    pub fn encoded_header(&self) -> &[u8] {
+        &self.encoded_header
+    }
+
     pub fn finish(&mut self) -> Result<JwsHeader> {
-        if self.header.is_none() {
-            bail!("Derializer has already had finish() called")
+        self.finish_verification().map(|v| v.header)
+    }
+
+    /// Like [`finish`](Self::finish) but reports the algorithm and key identifier used
+    ///
+    /// A rejected signature fails with a [`VerificationError`] carrying the reason.
+    pub fn finish_verification(&mut self) -> Result<Verification> {
+        let header = match self.header.take() {
+            Some(header) => header,
+            None => bail!("Derializer has already had finish() called"),
         };
 
         let verifier = self.encoder.finish()?;
 
-        match verifier.verify(&self.signature)? {
-            true => Ok(self.header.take().unwrap()),
-            false => bail!("incorrect signature"),
+        match verifier.check(&self.signature) {
+            Ok(()) => Ok(Verification::new(header)),
+            Err(reason) => Err(VerificationError::new(&header, reason).into()),
+        }
+    }
+
+    /// Like [`finish`](Self::finish) but rejects a jws already recorded in `cache`
+    ///
+    /// The jws is recorded under its `jti` header or the hash of its signature once the
+    /// signature is verified (see [`ReplayKey::new`]); a replay fails with [`ReplayDetected`].
+    #[cfg(feature = "std")]
+    pub fn finish_with_replay_cache(
+        &mut self,
+        cache: &(impl ReplayCache + ?Sized),
+    ) -> Result<JwsHeader> {
+        let header = self.finish()?;
+
+        let key = ReplayKey::new(&header, &self.signature)?;
+        if !cache.insert(key.clone())? {
+            bail!(ReplayDetected(key))
         }
+        Ok(header)
     }
 }
 
+/// A detached jws split into its segments with the protected header decoded once
+pub(crate) struct DecodedJws {
+    encoded_header: Vec<u8>,
+    pub(crate) header: JwsHeader,
+    signature: Vec<u8>,
+}
+
+impl DecodedJws {
+    /// Split `jws`, checking it against the `options` limits
+    pub(crate) fn new(jws: &impl AsRef<[u8]>, options: &DecodeOptions) -> Result<Self> {
+        let input = jws.as_ref();
+
+        check_token_len(input, options)?;
+
+        let mut splits = input.split(|e| e == &DOT_BYTE);
+
+        let encoded_header = splits.next().context("wrong jws format")?.to_vec();
+
+        let header = decode_header_segment(&encoded_header, options)?;
+
+        let mut splits = splits.skip(1); //detached payload skip
+
+        let signature = {
+            let part3 = splits.next().context("wrong jws format")?;
+            if decoded_len(part3.len()) > options.max_signature_len {
+                bail!("jws signature exceeds {} bytes", options.max_signature_len)
+            }
+            base64::decode_config(part3, base64::URL_SAFE_NO_PAD)
+                .map_err(Error::msg)
+                .context("wrong jws signature format")?
+        };
+
+        Ok(Self {
+            encoded_header,
+            header,
+            signature,
+        })
+    }
+}
+
+/// Selector returning a fresh verifier of `key` for a header with its `alg`
+pub(crate) fn key_selector<'k, K>(
+    key: &'k K,
+) -> Result<impl FnOnce(&JwsHeader) -> Option<K::Verifier<'k>> + 'k>
+where
+    K: VerificationKey,
+{
+    let verifier = key.verifier()?;
+    Ok(move |h: &JwsHeader| match h.get("alg") {
+        Some(Value::String(alg)) if alg == key.algorithm() => Some(verifier),
+        _ => None,
+    })
+}
+
+/// The `crit` extension marking a jws that signs a payload digest instead of the payload
+pub(crate) const DIGEST_EXTENSION: &str = "content-digest";
+
+/// Reject a digest jws, whose empty signed payload would verify against an empty body
+pub(crate) fn check_not_digest(header: &JwsHeader) -> Result<()> {
+    if let Some(Value::Array(crit)) = header.get("crit") {
+        if crit
+            .iter()
+            .any(|name| name.as_str() == Some(DIGEST_EXTENSION))
+        {
+            bail!("jws signs a content digest and must be verified as such")
+        }
+    }
+    Ok(())
+}
+
+/// Parse a decoded protected header, rejecting duplicate member names
+pub(crate) fn parse_header(decoded: &[u8]) -> Result<JwsHeader> {
+    let (header, duplicate) = parse_object(decoded).context("wrong jws header format")?;
+    match duplicate {
+        Some(name) => Err(DuplicateHeaderMember(name).into()),
+        None => Ok(header),
+    }
+}
+
+/// Parse a JSON object along with the first member name seen twice
+pub(crate) fn parse_object(
+    decoded: &[u8],
+) -> serde_json::Result<(Map<String, Value>, Option<String>)> {
+    let checked: CheckedObject = serde_json::from_slice(decoded)?;
+    Ok((checked.object, checked.duplicate))
+}
+
+/// Object which remembers the first member name seen twice
+struct CheckedObject {
+    object: Map<String, Value>,
+    duplicate: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for CheckedObject {
+    fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct CheckedObjectVisitor;
+
+        impl<'de> Visitor<'de> for CheckedObjectVisitor {
+            type Value = CheckedObject;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a JSON object")
+            }
+
+            fn visit_map<A>(self, map: A) -> core::result::Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut object = Map::new();
+                let duplicate = visit_members(map, |name, map| {
+                    object.insert(String::from(name), map.next_value()?);
+                    Ok(())
+                })?;
+
+                Ok(CheckedObject { object, duplicate })
+            }
+        }
+
+        deserializer.deserialize_map(CheckedObjectVisitor)
+    }
+}
+
+/// Member names kept inline before spilling to the heap
+const INLINE_MEMBER_NAMES: usize = 16;
+
+/// Visit the members of a header object, reading each value with `member`
+///
+/// Returns the first member name seen twice. Names are borrowed from the input unless they
+/// hold JSON escapes, so a header of the usual handful of members is checked without
+/// allocating; past that they spill to an ordered set, so a large object such as a set of
+/// jwt claims is still checked in `O(n log n)`.
+pub(crate) fn visit_members<'de, A>(
+    mut map: A,
+    mut member: impl FnMut(&str, &mut A) -> core::result::Result<(), A::Error>,
+) -> core::result::Result<Option<String>, A::Error>
+where
+    A: MapAccess<'de>,
+{
+    let mut inline: [Cow<'de, str>; INLINE_MEMBER_NAMES] = Default::default();
+    let mut inline_len = 0;
+    let mut spilled = BTreeSet::new();
+    let mut duplicate = None;
+
+    while let Some(BorrowedStr(name)) = map.next_key()? {
+        if duplicate.is_none() && (inline[..inline_len].contains(&name) || spilled.contains(&name))
+        {
+            duplicate = Some(String::from(name.as_ref()));
+        }
+        member(&name, &mut map)?;
+
+        if inline_len < INLINE_MEMBER_NAMES {
+            inline[inline_len] = name;
+            inline_len += 1;
+        } else {
+            spilled.insert(name);
+        }
+    }
+
+    Ok(duplicate)
+}
+
+/// A JSON string borrowed from the input unless it holds escapes
+pub(crate) struct BorrowedStr<'de>(pub(crate) Cow<'de, str>);
+
+impl<'de> Deserialize<'de> for BorrowedStr<'de> {
+    fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct BorrowedStrVisitor;
+
+        impl<'de> Visitor<'de> for BorrowedStrVisitor {
+            type Value = BorrowedStr<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string")
+            }
+
+            fn visit_borrowed_str<E>(
+                self,
+                value: &'de str,
+            ) -> core::result::Result<Self::Value, E> {
+                Ok(BorrowedStr(Cow::Borrowed(value)))
+            }
+
+            fn visit_str<E>(self, value: &str) -> core::result::Result<Self::Value, E> {
+                Ok(BorrowedStr(Cow::Owned(String::from(value))))
+            }
+
+            fn visit_string<E>(self, value: String) -> core::result::Result<Self::Value, E> {
+                Ok(BorrowedStr(Cow::Owned(value)))
+            }
+        }
+
+        deserializer.deserialize_str(BorrowedStrVisitor)
+    }
+}
+
+pub(crate) fn check_token_len(input: &[u8], options: &DecodeOptions) -> Result<()> {
+    if input.len() > options.max_token_len {
+        bail!("jws exceeds {} bytes", options.max_token_len)
+    }
+    Ok(())
+}
+
+pub(crate) fn decode_header_segment(
+    encoded_header: &[u8],
+    options: &DecodeOptions,
+) -> Result<JwsHeader> {
+    if decoded_len(encoded_header.len()) > options.max_header_len {
+        bail!("jws header exceeds {} bytes", options.max_header_len)
+    }
+
+    let decoded = base64::decode_config(encoded_header, base64::URL_SAFE_NO_PAD)
+        .map_err(Error::msg)
+        .context("wrong jws header format")?;
+    if exceeds_depth(&decoded, options.max_header_depth) {
+        bail!(
+            "jws header exceeds nesting depth {}",
+            options.max_header_depth
+        )
+    }
+    parse_header(&decoded)
+}
+
+/// Length of the data encoded by `len` bytes of unpadded base64
+pub(crate) fn decoded_len(len: usize) -> usize {
+    len / 4 * 3 + (len % 4 * 3) / 4
+}
+
+/// Check whether arrays and objects in `json` are nested deeper than `max_depth`
+///
+/// Brackets inside string literals are skipped, malformed input is left to the parser.
+pub(crate) fn exceeds_depth(json: &[u8], max_depth: usize) -> bool {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for &b in json {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match b {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > max_depth {
+                    return true;
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    false
+}
+
+#[cfg(feature = "std")]
 impl<V> Write for DeserializeJwsWriter<V>
 where
     V: Verify,
@@ -250,3 +860,13 @@ where
         self.encoder.flush()
     }
 }
+
+#[cfg(not(feature = "std"))]
+impl<V> crate::ByteSink for DeserializeJwsWriter<V>
+where
+    V: Verify,
+{
+    fn update(&mut self, data: &[u8]) -> Result<()> {
+        self.encoder.update(data)
+    }
+}
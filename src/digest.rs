@@ -0,0 +1,334 @@
+//! Detached jws over a precomputed payload digest
+//!
+//! Instead of streaming the payload through the signer, the payload digest is put into the
+//! protected header as a [`CONTENT_DIGEST_MEMBER`] member, formatted like the HTTP
+//! `Content-Digest` field ([RFC 9530](https://www.rfc-editor.org/rfc/rfc9530)), and the jws
+//! signs an empty payload. Signing needs only the digest, e.g. one already stored next to a
+//! large object; verification checks the signature and then the digest of the streamed payload.
+//!
+//! The member is also listed in the `crit` header, so the plain deserialize functions and
+//! other jws implementations reject a digest jws instead of verifying its empty payload.
+//!
+//! # Examples
+//!
+//! ```
+//! use detached_jws::digest::{self, ContentDigest, DigestAlgorithm};
+//! use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+//! use serde_json::Map;
+//!
+//! let payload = b"large object";
+//! let stored = ContentDigest::compute(DigestAlgorithm::Sha256, &mut &payload[..]).unwrap();
+//!
+//! let key = OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+//! let jws = digest::serialize_digest_with_key(Map::new(), &stored, &key).unwrap();
+//!
+//! let key = OpensslVerificationKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+//! let header = digest::deserialize_digest_with_key(&jws, &mut &payload[..], &key).unwrap();
+//!
+//! assert_eq!(header["content-digest"], stored.to_string());
+//! ```
+
+use anyhow::{bail, Context, Result};
+use openssl::hash::{Hasher, MessageDigest};
+use serde_json::Value;
+use std::{
+    fmt,
+    io::{Read, Write},
+    str::FromStr,
+};
+
+use crate::{
+    decode::{key_selector, DecodeOptions, DIGEST_EXTENSION},
+    DeserializeJwsWriter, JwsHeader, SerializeJwsWriter, Sign, SigningKey, VerificationKey, Verify,
+};
+
+/// The protected header member carrying the payload digest, also listed in `crit`
+pub const CONTENT_DIGEST_MEMBER: &str = DIGEST_EXTENSION;
+
+/// A hash algorithm of the [HTTP Digest Algorithm Values](https://www.iana.org/assignments/http-digest-hash-alg/) registry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+impl DigestAlgorithm {
+    /// The registered algorithm key, e.g. `sha-256`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha-256",
+            Self::Sha512 => "sha-512",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sha-256" => Some(Self::Sha256),
+            "sha-512" => Some(Self::Sha512),
+            _ => None,
+        }
+    }
+
+    fn message_digest(&self) -> MessageDigest {
+        match self {
+            Self::Sha256 => MessageDigest::sha256(),
+            Self::Sha512 => MessageDigest::sha512(),
+        }
+    }
+}
+
+/// A payload digest, displayed and parsed as a `Content-Digest` field value
+/// such as `sha-256=:RK/0qy18MlBSVnWgjwz6lZEWjP/lF5HF9bvEF8FabDg=:`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentDigest {
+    algorithm: DigestAlgorithm,
+    value: Vec<u8>,
+}
+
+impl ContentDigest {
+    /// Wrap a precomputed digest, checking its length against `algorithm`
+    pub fn new(algorithm: DigestAlgorithm, value: impl Into<Vec<u8>>) -> Result<Self> {
+        let value = value.into();
+        if value.len() != algorithm.message_digest().size() {
+            bail!(
+                "{} digest must be {} bytes",
+                algorithm.name(),
+                algorithm.message_digest().size()
+            )
+        }
+
+        Ok(Self { algorithm, value })
+    }
+
+    /// Compute the digest of `payload`
+    pub fn compute(algorithm: DigestAlgorithm, payload: &mut impl Read) -> Result<Self> {
+        let mut hasher = Hasher::new(algorithm.message_digest())?;
+        std::io::copy(payload, &mut hasher)?;
+
+        Ok(Self {
+            algorithm,
+            value: hasher.finish()?.to_vec(),
+        })
+    }
+
+    pub fn algorithm(&self) -> DigestAlgorithm {
+        self.algorithm
+    }
+
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+}
+
+impl fmt::Display for ContentDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}=:{}:",
+            self.algorithm.name(),
+            base64::encode(&self.value)
+        )
+    }
+}
+
+/// Parses the first member with a supported algorithm, ignoring the others
+impl FromStr for ContentDigest {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        for member in s.split(',') {
+            let (name, value) = member
+                .split_once('=')
+                .context("wrong content digest format")?;
+            let algorithm = match DigestAlgorithm::from_name(name.trim()) {
+                Some(algorithm) => algorithm,
+                None => continue,
+            };
+
+            let value = value
+                .trim()
+                .strip_prefix(':')
+                .and_then(|v| v.strip_suffix(':'))
+                .context("wrong content digest format")?;
+            let value = base64::decode(value).context("wrong content digest format")?;
+
+            return Self::new(algorithm, value);
+        }
+
+        bail!("content digest has no supported algorithm")
+    }
+}
+
+/// The streamed payload does not match the signed content digest
+///
+/// Returned wrapped in [`anyhow::Error`] and can be recovered with `downcast_ref`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestMismatch {
+    pub expected: ContentDigest,
+    pub actual: ContentDigest,
+}
+
+impl fmt::Display for DigestMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "payload does not match the content digest")
+    }
+}
+
+impl std::error::Error for DigestMismatch {}
+
+/// Serialize to detached jws signing `digest` instead of the payload
+///
+/// `header` gets a [`CONTENT_DIGEST_MEMBER`] member, replacing any present, which is added
+/// to its `crit` list.
+pub fn serialize_digest(
+    algorithm: String,
+    mut header: JwsHeader,
+    digest: &ContentDigest,
+    signer: impl Sign,
+) -> Result<Vec<u8>> {
+    let mut crit = match header.remove("crit") {
+        None => Vec::new(),
+        Some(Value::Array(crit)) => crit,
+        Some(_) => bail!("wrong jws header `crit` format"),
+    };
+    if !crit.iter().any(|name| name == CONTENT_DIGEST_MEMBER) {
+        crit.push(Value::String(CONTENT_DIGEST_MEMBER.to_owned()));
+    }
+    header.insert("crit".to_owned(), Value::Array(crit));
+    header.insert(
+        CONTENT_DIGEST_MEMBER.to_owned(),
+        Value::String(digest.to_string()),
+    );
+
+    SerializeJwsWriter::new(Vec::new(), algorithm, header, signer)?.finish()
+}
+
+/// Like [`serialize_digest`] with a fresh signer of a reusable [`SigningKey`]
+pub fn serialize_digest_with_key(
+    header: JwsHeader,
+    digest: &ContentDigest,
+    key: &impl SigningKey,
+) -> Result<Vec<u8>> {
+    serialize_digest(key.algorithm().to_owned(), header, digest, key.signer()?)
+}
+
+/// Deserialize and verify a digest detached jws against the streamed payload
+pub fn deserialize_digest_selector<F, V>(
+    jws: &impl AsRef<[u8]>,
+    payload: &mut impl Read,
+    selector: F,
+) -> Result<JwsHeader>
+where
+    F: FnOnce(&JwsHeader) -> Option<V>,
+    V: Verify,
+{
+    let mut writer = DeserializeDigestWriter::new(jws, selector)?;
+    std::io::copy(payload, &mut writer)?;
+    writer.finish()
+}
+
+/// Like [`deserialize_digest_selector`] with a fresh verifier of a reusable [`VerificationKey`]
+pub fn deserialize_digest_with_key(
+    jws: &impl AsRef<[u8]>,
+    payload: &mut impl Read,
+    key: &impl VerificationKey,
+) -> Result<JwsHeader> {
+    let mut writer = DeserializeDigestWriter::with_key(jws, key)?;
+    std::io::copy(payload, &mut writer)?;
+    writer.finish()
+}
+
+/// A `Write` implementation verifying a digest detached jws
+///
+/// The signature is verified on creation; the digest of the written payload is compared
+/// with the signed one by [`finish`](Self::finish).
+pub struct DeserializeDigestWriter {
+    header: Option<JwsHeader>,
+    digest: ContentDigest,
+    hasher: Hasher,
+}
+
+impl DeserializeDigestWriter {
+    /// Create a writer applying the [`DecodeOptions::default`] limits
+    ///
+    /// Use [`with_options`](Self::with_options) for other limits.
+    pub fn new<S, V>(jws: &impl AsRef<[u8]>, selector: S) -> Result<Self>
+    where
+        S: FnOnce(&JwsHeader) -> Option<V>,
+        V: Verify,
+    {
+        Self::with_options(jws, selector, &DecodeOptions::default())
+    }
+
+    /// Create a writer rejecting jws that exceed the [`DecodeOptions`] limits
+    pub fn with_options<S, V>(
+        jws: &impl AsRef<[u8]>,
+        selector: S,
+        options: &DecodeOptions,
+    ) -> Result<Self>
+    where
+        S: FnOnce(&JwsHeader) -> Option<V>,
+        V: Verify,
+    {
+        Self::from_header(DeserializeJwsWriter::for_digest(jws, selector, options)?.finish()?)
+    }
+
+    /// Create a writer verifying with a fresh verifier of a reusable [`VerificationKey`]
+    pub fn with_key(jws: &impl AsRef<[u8]>, key: &impl VerificationKey) -> Result<Self> {
+        Self::from_header(
+            DeserializeJwsWriter::for_digest(jws, key_selector(key)?, &DecodeOptions::default())?
+                .finish()?,
+        )
+    }
+
+    fn from_header(header: JwsHeader) -> Result<Self> {
+        let digest: ContentDigest = header
+            .get(CONTENT_DIGEST_MEMBER)
+            .and_then(Value::as_str)
+            .with_context(|| format!("jws header has no `{}`", CONTENT_DIGEST_MEMBER))?
+            .parse()?;
+
+        Ok(Self {
+            header: Some(header),
+            hasher: Hasher::new(digest.algorithm.message_digest())?,
+            digest,
+        })
+    }
+
+    /// The signed digest, e.g. to compare with a stored one without reading the payload
+    pub fn digest(&self) -> &ContentDigest {
+        &self.digest
+    }
+
+    pub fn finish(&mut self) -> Result<JwsHeader> {
+        let header = match self.header.take() {
+            Some(header) => header,
+            None => bail!("Derializer has already had finish() called"),
+        };
+
+        let actual = ContentDigest {
+            algorithm: self.digest.algorithm,
+            value: self.hasher.finish()?.to_vec(),
+        };
+
+        if !openssl::memcmp::eq(&actual.value, &self.digest.value) {
+            return Err(DigestMismatch {
+                expected: self.digest.clone(),
+                actual,
+            }
+            .into());
+        }
+
+        Ok(header)
+    }
+}
+
+impl Write for DeserializeDigestWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.hasher.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.hasher.flush()
+    }
+}
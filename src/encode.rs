@@ -1,11 +1,14 @@
 //! Serialize and sign Detached-Jws
 
-use anyhow::{bail, Context, Result};
-use base64::write::EncoderWriter;
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+use anyhow::{bail, Context, Error, Result};
 use serde_json::value::Value;
+#[cfg(feature = "std")]
 use std::io::{Read, Write};
 
-use crate::{JwsHeader, Sign};
+#[cfg(feature = "std")]
+use crate::SigningKey;
+use crate::{chunked::ChunkedEncoder, decode::parse_header, ByteSink, JwsHeader, Sign};
 
 static DOT_ARRAY: &[u8] = ".".as_bytes();
 
@@ -54,6 +57,7 @@ static DOT_ARRAY: &[u8] = ".".as_bytes();
 ///        String::from_utf8(jws).unwrap(),
 ///        "eyJhbGciOiJ0ZXN0X2FsZ29yaXRobSIsImN1c3RvbSI6ImN1c3RvbV92YWx1ZSJ9..ZXlKaGJHY2lPaUowWlhOMFgyRnNaMjl5YVhSb2JTSXNJbU4xYzNSdmJTSTZJbU4xYzNSdmJWOTJZV3gxWlNKOS5BQUVDQXdRRkJn");
 /// ```
+#[cfg(feature = "std")]
 pub fn serialize(
     algorithm: String,
     header: JwsHeader,
@@ -65,6 +69,18 @@ pub fn serialize(
     writer.finish()
 }
 
+/// Serialize to detached jws with a fresh signer of a reusable [`SigningKey`]
+///
+/// The `alg` header is taken from [`SigningKey::algorithm`].
+#[cfg(feature = "std")]
+pub fn serialize_with_key(
+    header: JwsHeader,
+    payload: &mut impl Read,
+    key: &impl SigningKey,
+) -> Result<Vec<u8>> {
+    serialize(key.algorithm().to_owned(), header, payload, key.signer()?)
+}
+
 /// A `Write` implementation serialize to detached jws
 ///
 /// # Examples
@@ -113,76 +129,193 @@ pub fn serialize(
 ///        String::from_utf8(jws).unwrap(),
 ///        "eyJhbGciOiJ0ZXN0X2FsZ29yaXRobSIsImN1c3RvbSI6ImN1c3RvbV92YWx1ZSJ9..ZXlKaGJHY2lPaUowWlhOMFgyRnNaMjl5YVhSb2JTSXNJbU4xYzNSdmJTSTZJbU4xYzNSdmJWOTJZV3gxWlNKOS5BQUVDQXdRRkJn");
 /// ```
-pub struct SerializeJwsWriter<W, S: Write> {
-    delegate: Option<W>,
-    encoder: EncoderWriter<S>,
+pub struct SerializeJwsWriter<W, S> {
+    encoder: ChunkedEncoder<SigningInput<W, S>>,
+}
+
+/// The signer and the writer, which receives the encoded payload too for an attached jws
+struct SigningInput<W, S> {
+    writer: W,
+    signer: S,
+    attached: bool,
+}
+
+impl<W, S> ByteSink for SigningInput<W, S>
+where
+    W: ByteSink,
+    S: ByteSink,
+{
+    fn update(&mut self, data: &[u8]) -> Result<()> {
+        self.signer.update(data)?;
+        if self.attached {
+            self.writer.update(data)?;
+        }
+        Ok(())
+    }
 }
 
 impl<W, S> SerializeJwsWriter<W, S>
 where
-    W: Write,
+    W: ByteSink,
     S: Sign,
 {
-    pub fn new(
-        mut writer: W,
+    pub fn new(writer: W, algorithm: String, header: JwsHeader, signer: S) -> Result<Self> {
+        let encoded_header = encode_header(algorithm, header)?;
+
+        Self::from_encoded_header(writer, &encoded_header, signer, false)
+    }
+
+    /// Create a writer emitting the compact jws with the payload attached,
+    /// `header.payload.signature`
+    #[cfg(feature = "openssl")]
+    pub(crate) fn attached(
+        writer: W,
         algorithm: String,
-        mut header: JwsHeader,
-        mut signer: S,
+        header: JwsHeader,
+        signer: S,
     ) -> Result<Self> {
-        header.insert("alg".to_owned(), Value::String(algorithm));
+        let encoded_header = encode_header(algorithm, header)?;
 
-        let encoded_header = {
-            let mut encoder = EncoderWriter::new(Vec::new(), base64::URL_SAFE_NO_PAD);
-            serde_json::to_writer(&mut encoder, &header)?;
-            encoder.finish()?
-        };
+        Self::from_encoded_header(writer, &encoded_header, signer, true)
+    }
+
+    /// Create a writer with a protected header already serialized to JSON
+    ///
+    /// The bytes are base64url encoded as is, so member order and whitespace are preserved.
+    /// They must hold a JSON object with a string `alg` member.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::io::Write;
+    /// # use anyhow::Result;
+    /// # use detached_jws::{SerializeJwsWriter, Sign};
+    /// # #[derive(Default)]
+    /// # pub struct DummySigner(Vec<u8>);
+    /// # impl Sign for DummySigner {
+    /// #     fn get_sign(&self) -> Result<Vec<u8>> { Ok(self.0.clone()) }
+    /// # }
+    /// # impl Write for DummySigner {
+    /// #     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.0.write(buf) }
+    /// #     fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    /// # }
+    /// let mut writer = SerializeJwsWriter::with_header_json(Vec::new(),
+    ///        br#"{"kid":"1","alg":"test_algorithm"}"#,
+    ///        DummySigner::default()).unwrap();
+    /// writer.write_all(&[0, 1, 2, 3]);
+    ///
+    /// let jws = writer.finish().unwrap();
+    ///
+    /// assert!(jws.starts_with(b"eyJraWQiOiIxIiwiYWxnIjoidGVzdF9hbGdvcml0aG0ifQ.."));
+    /// ```
+    pub fn with_header_json(writer: W, header: &[u8], signer: S) -> Result<Self> {
+        check_header(header)?;
 
-        signer.write_all(&encoded_header)?;
-        signer.write_all(DOT_ARRAY)?;
+        let encoded_header = base64::encode_config(header, base64::URL_SAFE_NO_PAD);
 
-        writer.write_all(&encoded_header)?;
-        writer.write_all(DOT_ARRAY)?;
-        writer.write_all(DOT_ARRAY)?;
+        Self::from_encoded_header(writer, encoded_header.as_bytes(), signer, false)
+    }
+
+    /// Create a writer with a base64url encoded protected header which is emitted unchanged
+    ///
+    /// The decoded header must hold a JSON object with a string `alg` member.
+    pub fn with_encoded_header(writer: W, encoded_header: &[u8], signer: S) -> Result<Self> {
+        let header = base64::decode_config(encoded_header, base64::URL_SAFE_NO_PAD)
+            .map_err(Error::msg)
+            .context("wrong jws header format")?;
+        check_header(&header)?;
+
+        Self::from_encoded_header(writer, encoded_header, signer, false)
+    }
+
+    fn from_encoded_header(
+        mut writer: W,
+        encoded_header: &[u8],
+        mut signer: S,
+        attached: bool,
+    ) -> Result<Self> {
+        signer.update(encoded_header)?;
+        signer.update(DOT_ARRAY)?;
+
+        writer.update(encoded_header)?;
+        writer.update(DOT_ARRAY)?;
+        if !attached {
+            writer.update(DOT_ARRAY)?;
+        }
 
         Ok(Self {
-            delegate: Some(writer),
-            encoder: EncoderWriter::new(signer, base64::URL_SAFE_NO_PAD),
+            encoder: ChunkedEncoder::new(SigningInput {
+                writer,
+                signer,
+                attached,
+            }),
         })
     }
 
     pub fn finish(&mut self) -> Result<W> {
-        if self.delegate.is_none() {
+        if self.encoder.is_finished() {
             bail!("Serializer has already had finish() called")
         };
 
-        let signer = self.encoder.finish()?;
+        let SigningInput {
+            mut writer,
+            signer,
+            attached,
+        } = self.encoder.finish()?;
 
         let signature = signer.get_sign()?;
 
-        let encoded_signature = {
-            let mut encoder = EncoderWriter::new(Vec::new(), base64::URL_SAFE_NO_PAD);
-            encoder.write_all(&signature)?;
-            encoder.finish()?
-        };
+        let encoded_signature = base64::encode_config(signature, base64::URL_SAFE_NO_PAD);
 
-        self.delegate
-            .as_mut()
-            .unwrap()
-            .write_all(&encoded_signature)?;
+        if attached {
+            writer.update(DOT_ARRAY)?;
+        }
+        writer.update(encoded_signature.as_bytes())?;
 
-        Ok(self.delegate.take().context("Writer must be present")?)
+        Ok(writer)
     }
 }
 
+/// Add `alg` to the protected header and base64url encode it
+pub(crate) fn encode_header(algorithm: String, mut header: JwsHeader) -> Result<Vec<u8>> {
+    header.insert("alg".to_owned(), Value::String(algorithm));
+
+    let header = serde_json::to_vec(&header)?;
+    Ok(base64::encode_config(header, base64::URL_SAFE_NO_PAD).into_bytes())
+}
+
+/// Check that a serialized protected header is usable for signing
+fn check_header(header: &[u8]) -> Result<()> {
+    match parse_header(header)?.get("alg") {
+        Some(Value::String(_)) => Ok(()),
+        _ => bail!("jws header must contain `alg`"),
+    }
+}
+
+#[cfg(feature = "std")]
 impl<W, S> Write for SerializeJwsWriter<W, S>
 where
-    S: Write,
+    W: ByteSink,
+    S: ByteSink,
 {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         self.encoder.write(buf)
     }
 
+    /// Pass the buffered payload to the signer, except the up to two bytes that do not yet
+    /// form a whole base64 quantum
     fn flush(&mut self) -> std::io::Result<()> {
         self.encoder.flush()
     }
 }
+
+#[cfg(not(feature = "std"))]
+impl<W, S> ByteSink for SerializeJwsWriter<W, S>
+where
+    W: ByteSink,
+    S: ByteSink,
+{
+    fn update(&mut self, data: &[u8]) -> Result<()> {
+        self.encoder.update(data)
+    }
+}
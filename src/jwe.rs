@@ -0,0 +1,1156 @@
+//! Encrypt payloads to JWE with a detached ciphertext ([RFC 7516](https://tools.ietf.org/html/rfc7516))
+//!
+//! [`EncryptJweWriter`] streams the ciphertext of everything written to it into an inner writer
+//! and returns the compact jwe with an empty ciphertext segment, `header.key.iv..tag`;
+//! [`DecryptJweWriter`] takes the ciphertext and streams the plaintext into an inner writer. The
+//! protected header is a [`JwsHeader`] with `alg` and `enc` added.
+//!
+//! Supported `alg` values are `RSA-OAEP`, `ECDH-ES` (P-256, P-384 and P-521) and `A256KW`;
+//! supported `enc` values are `A256GCM` and `A128CBC-HS256`.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::Write;
+//! use serde_json::{json, Map};
+//! use detached_jws::jwe::{DecryptJweWriter, EncryptJweWriter, JweDecryptionKey, JweEncryptionKey};
+//!
+//! let secret = [7; 32];
+//!
+//! let mut header = Map::new();
+//! header.insert("kid".to_owned(), json!("key-1"));
+//!
+//! let key = JweEncryptionKey::secret("A256KW", &secret).unwrap();
+//! let mut writer = EncryptJweWriter::new(Vec::new(), "A256GCM", header, &key).unwrap();
+//! writer.write_all(b"hello, ").unwrap();
+//! writer.write_all(b"world").unwrap();
+//!
+//! let (jwe, ciphertext) = writer.finish().unwrap();
+//!
+//! let key = JweDecryptionKey::secret("A256KW", &secret).unwrap();
+//! let mut writer = DecryptJweWriter::new(&jwe, &key, Vec::new()).unwrap();
+//! writer.write_all(&ciphertext).unwrap();
+//!
+//! let (header, plaintext) = writer.finish().unwrap();
+//!
+//! assert_eq!(header["enc"], "A256GCM");
+//! assert_eq!(plaintext, b"hello, world");
+//! ```
+//!
+//! # Sign-then-encrypt:
+//! [`SignEncryptWriter`] signs the payload and encrypts the signed jws in one pass, so the
+//! signature is only readable by the recipient. [`DecryptVerifyWriter`] decrypts it and
+//! verifies the nested jws once the ciphertext is authenticated:
+//!
+//! ```
+//! use std::io::Write;
+//! use openssl::{ec::{EcGroup, EcKey}, nid::Nid, pkey::PKey};
+//! use serde_json::Map;
+//! use detached_jws::SigningKey;
+//! use detached_jws::jwe::{
+//!     DecryptVerifyWriter, JweDecryptionKey, JweEncryptionKey, SignEncryptWriter,
+//! };
+//! use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+//!
+//! let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+//! let recipient = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+//! let sender = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+//!
+//! let signing_key = OpensslSigningKey::new("ES256", sender.clone()).unwrap();
+//! let encryption_key = JweEncryptionKey::new("ECDH-ES", &recipient).unwrap();
+//!
+//! let mut writer = SignEncryptWriter::new(
+//!     Vec::new(),
+//!     "A128CBC-HS256",
+//!     Map::new(),
+//!     &encryption_key,
+//!     "ES256".to_owned(),
+//!     Map::new(),
+//!     signing_key.signer().unwrap(),
+//! )
+//! .unwrap();
+//! writer.write_all(b"payload").unwrap();
+//!
+//! let (jwe, ciphertext) = writer.finish().unwrap();
+//!
+//! let verification_key = OpensslVerificationKey::new("ES256", &sender).unwrap();
+//! let decryption_key = JweDecryptionKey::new("ECDH-ES", recipient).unwrap();
+//!
+//! let mut writer =
+//!     DecryptVerifyWriter::new(&jwe, &decryption_key, &verification_key, Vec::new()).unwrap();
+//! writer.write_all(&ciphertext).unwrap();
+//!
+//! let (header, payload) = writer.finish().unwrap();
+//! assert_eq!(header["alg"], "ES256");
+//! assert_eq!(payload, b"payload");
+//! ```
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+use openssl::{
+    aes::{self, AesKey},
+    bn::{BigNum, BigNumContext},
+    derive::Deriver,
+    ec::{EcGroup, EcGroupRef, EcKey},
+    hash::{hash, MessageDigest},
+    md::Md,
+    md_ctx::MdCtx,
+    memcmp,
+    nid::Nid,
+    pkey::{HasPrivate, HasPublic, Id, PKey, PKeyRef, Private, Public},
+    rand::rand_bytes,
+    rsa::{Padding, Rsa},
+    symm::{Cipher, Crypter, Mode},
+};
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+
+use crate::decode::{check_not_digest, check_token_len, decode_header_segment, decoded_len};
+use crate::openssl::MIN_RSA_BITS;
+use crate::{
+    DecodeOptions, JwsHeader, SerializeJwsWriter, Sign, VerificationError, VerificationKey, Verify,
+};
+
+/// Length in bytes of the authentication tag of every supported `enc`
+const TAG_LEN: usize = 16;
+
+/// A JWE `alg` value
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum KeyManagement {
+    RsaOaep,
+    EcdhEs,
+    A256Kw,
+}
+
+impl KeyManagement {
+    fn from_name(name: &str) -> Result<Self> {
+        Ok(match name {
+            "RSA-OAEP" => KeyManagement::RsaOaep,
+            "ECDH-ES" => KeyManagement::EcdhEs,
+            "A256KW" => KeyManagement::A256Kw,
+            _ => bail!("unsupported jwe algorithm `{}`", name),
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            KeyManagement::RsaOaep => "RSA-OAEP",
+            KeyManagement::EcdhEs => "ECDH-ES",
+            KeyManagement::A256Kw => "A256KW",
+        }
+    }
+
+    /// Check that an asymmetric key suits the algorithm
+    ///
+    /// RSA keys must have at least [`MIN_RSA_BITS`] bits and EC keys must be on a supported
+    /// curve, as for the [`openssl`](crate::openssl) signing keys.
+    fn check_key<T: HasPublic>(&self, key: &PKeyRef<T>) -> Result<()> {
+        match (self, key.id()) {
+            (KeyManagement::RsaOaep, Id::RSA) => {
+                if (key.bits() as usize) < MIN_RSA_BITS {
+                    bail!("RSA key is shorter than {} bits", MIN_RSA_BITS)
+                }
+                Ok(())
+            }
+            (KeyManagement::EcdhEs, Id::EC) => curve_name(key.ec_key()?.group()).map(|_| ()),
+            (KeyManagement::A256Kw, _) => bail!("use a secret for `A256KW`"),
+            _ => bail!("key type does not suit `{}`", self.name()),
+        }
+    }
+}
+
+/// A JWE `enc` value
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ContentEncryption {
+    A256Gcm,
+    A128CbcHs256,
+}
+
+impl ContentEncryption {
+    fn from_name(name: &str) -> Result<Self> {
+        Ok(match name {
+            "A256GCM" => ContentEncryption::A256Gcm,
+            "A128CBC-HS256" => ContentEncryption::A128CbcHs256,
+            _ => bail!("unsupported jwe encryption `{}`", name),
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ContentEncryption::A256Gcm => "A256GCM",
+            ContentEncryption::A128CbcHs256 => "A128CBC-HS256",
+        }
+    }
+
+    /// Length in bytes of the content encryption key, 32 for both
+    fn key_len(&self) -> usize {
+        32
+    }
+
+    fn iv_len(&self) -> usize {
+        match self {
+            ContentEncryption::A256Gcm => 12,
+            ContentEncryption::A128CbcHs256 => 16,
+        }
+    }
+}
+
+enum KeyMaterial<T> {
+    Asymmetric(PKey<T>),
+    Secret(Vec<u8>),
+}
+
+fn random(len: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; len];
+    rand_bytes(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn encode(value: &[u8]) -> String {
+    base64::encode_config(value, base64::URL_SAFE_NO_PAD)
+}
+
+/// A base64url encoded member of a JWK or header
+fn member(object: &JwsHeader, name: &str, kind: &str) -> Result<Vec<u8>> {
+    let value = object
+        .get(name)
+        .and_then(Value::as_str)
+        .with_context(|| format!("{} has no `{}`", kind, name))?;
+    base64::decode_config(value, base64::URL_SAFE_NO_PAD)
+        .with_context(|| format!("wrong {} `{}` format", kind, name))
+}
+
+/// An optional base64url encoded header member, empty when missing
+fn optional_member(header: &JwsHeader, name: &str) -> Result<Vec<u8>> {
+    match header.get(name) {
+        Some(_) => member(header, name, "jwe header"),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn check_jwk_alg(algorithm: &str, jwk: &JwsHeader) -> Result<KeyManagement> {
+    if let Some(alg) = jwk.get("alg") {
+        if alg != algorithm {
+            bail!("JWK is for `{}`, not `{}`", alg, algorithm)
+        }
+    }
+    KeyManagement::from_name(algorithm)
+}
+
+fn curve(crv: Option<&str>) -> Result<EcGroup> {
+    let nid = match crv {
+        Some("P-256") => Nid::X9_62_PRIME256V1,
+        Some("P-384") => Nid::SECP384R1,
+        Some("P-521") => Nid::SECP521R1,
+        crv => bail!("unsupported curve {:?}", crv),
+    };
+    Ok(EcGroup::from_curve_name(nid)?)
+}
+
+fn curve_name(group: &EcGroupRef) -> Result<&'static str> {
+    Ok(match group.curve_name() {
+        Some(Nid::X9_62_PRIME256V1) => "P-256",
+        Some(Nid::SECP384R1) => "P-384",
+        Some(Nid::SECP521R1) => "P-521",
+        _ => bail!("unsupported curve"),
+    })
+}
+
+/// An EC public key from the `crv`, `x` and `y` members of a JWK
+fn ec_public_key(jwk: &JwsHeader, kind: &str) -> Result<EcKey<Public>> {
+    let group = curve(jwk.get("crv").and_then(Value::as_str))?;
+    let x = BigNum::from_slice(&member(jwk, "x", kind)?)?;
+    let y = BigNum::from_slice(&member(jwk, "y", kind)?)?;
+    let key = EcKey::from_public_key_affine_coordinates(&group, &x, &y)?;
+    key.check_key()?;
+    Ok(key)
+}
+
+/// The public JWK of an EC key, used for `epk`
+fn ec_jwk<T: HasPublic>(key: &EcKey<T>) -> Result<Value> {
+    let group = key.group();
+    let len = (group.degree() as i32 + 7) / 8;
+    let (mut x, mut y) = (BigNum::new()?, BigNum::new()?);
+    let mut context = BigNumContext::new()?;
+    key.public_key()
+        .affine_coordinates(group, &mut x, &mut y, &mut context)?;
+
+    Ok(json!({
+        "kty": "EC",
+        "crv": curve_name(group)?,
+        "x": encode(&x.to_vec_padded(len)?),
+        "y": encode(&y.to_vec_padded(len)?),
+    }))
+}
+
+/// Agree on a content encryption key with `ECDH-ES`
+///
+/// The shared secret goes through the Concat KDF of
+/// [RFC 7518 §4.6.2](https://tools.ietf.org/html/rfc7518#section-4.6.2) with `enc` as the
+/// algorithm identifier, as in direct key agreement.
+fn agree<T, U>(
+    private: &PKeyRef<T>,
+    public: &PKeyRef<U>,
+    encryption: ContentEncryption,
+    header: &JwsHeader,
+) -> Result<Vec<u8>>
+where
+    T: HasPrivate,
+    U: HasPublic,
+{
+    let mut deriver = Deriver::new(private)?;
+    deriver.set_peer(public)?;
+    let secret = deriver.derive_to_vec()?;
+
+    let apu = optional_member(header, "apu")?;
+    let apv = optional_member(header, "apv")?;
+
+    let mut info = Vec::new();
+    for field in [encryption.name().as_bytes(), &apu, &apv] {
+        info.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        info.extend_from_slice(field);
+    }
+    info.extend_from_slice(&(encryption.key_len() as u32 * 8).to_be_bytes());
+
+    let mut key = Vec::new();
+    let mut counter = 1u32;
+    while key.len() < encryption.key_len() {
+        let mut round = counter.to_be_bytes().to_vec();
+        round.extend_from_slice(&secret);
+        round.extend_from_slice(&info);
+        key.extend_from_slice(&hash(MessageDigest::sha256(), &round)?);
+        counter += 1;
+    }
+    key.truncate(encryption.key_len());
+    Ok(key)
+}
+
+fn aes_key_error(_: aes::KeyError) -> Error {
+    anyhow!("wrong `A256KW` key")
+}
+
+/// A recipient's public or secret key bound to a JWE `alg`
+pub struct JweEncryptionKey {
+    algorithm: KeyManagement,
+    key: KeyMaterial<Public>,
+}
+
+impl JweEncryptionKey {
+    /// Bind the public part of an RSA key to `RSA-OAEP` or of an EC key to `ECDH-ES`
+    ///
+    /// Fails for RSA keys shorter than 2048 bits and for curves other than P-256, P-384 and
+    /// P-521.
+    pub fn new<T: HasPublic>(algorithm: &str, key: &PKeyRef<T>) -> Result<Self> {
+        let params = KeyManagement::from_name(algorithm)?;
+        params.check_key(key)?;
+
+        Ok(Self {
+            algorithm: params,
+            key: KeyMaterial::Asymmetric(PKey::public_key_from_der(&key.public_key_to_der()?)?),
+        })
+    }
+
+    /// Create an `A256KW` key from a 256-bit key encryption key
+    pub fn secret(algorithm: &str, secret: &[u8]) -> Result<Self> {
+        if KeyManagement::from_name(algorithm)? != KeyManagement::A256Kw {
+            bail!("`{}` is not a key wrapping algorithm", algorithm)
+        }
+        if secret.len() != 32 {
+            bail!("`A256KW` requires a 256-bit key")
+        }
+
+        Ok(Self {
+            algorithm: KeyManagement::A256Kw,
+            key: KeyMaterial::Secret(secret.to_vec()),
+        })
+    }
+
+    /// Create a key for `algorithm` from a JSON Web Key ([RFC 7517](https://tools.ietf.org/html/rfc7517))
+    ///
+    /// `RSA` keys suit `RSA-OAEP`, `EC` keys `ECDH-ES` and `oct` keys `A256KW`; the `alg`
+    /// member, when present, must equal `algorithm`.
+    pub fn from_jwk(algorithm: &str, jwk: &JwsHeader) -> Result<Self> {
+        let params = check_jwk_alg(algorithm, jwk)?;
+
+        match (jwk.get("kty").and_then(Value::as_str), params) {
+            (Some("oct"), KeyManagement::A256Kw) => {
+                Self::secret(algorithm, &member(jwk, "k", "JWK")?)
+            }
+            (Some("RSA"), KeyManagement::RsaOaep) => {
+                let rsa = Rsa::from_public_components(
+                    BigNum::from_slice(&member(jwk, "n", "JWK")?)?,
+                    BigNum::from_slice(&member(jwk, "e", "JWK")?)?,
+                )?;
+                Self::new(algorithm, PKey::from_rsa(rsa)?.as_ref())
+            }
+            (Some("EC"), KeyManagement::EcdhEs) => {
+                let key = PKey::from_ec_key(ec_public_key(jwk, "JWK")?)?;
+                Self::new(algorithm, &key)
+            }
+            (kty, _) => bail!("JWK type {:?} does not suit `{}`", kty, algorithm),
+        }
+    }
+
+    /// The `alg` header value of jwes encrypted to this key
+    pub fn algorithm(&self) -> &str {
+        self.algorithm.name()
+    }
+
+    /// Create a content encryption key and its encrypted form, adding `epk` for `ECDH-ES`
+    fn wrap(
+        &self,
+        encryption: ContentEncryption,
+        header: &mut JwsHeader,
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        match (&self.key, self.algorithm) {
+            (KeyMaterial::Asymmetric(key), KeyManagement::RsaOaep) => {
+                let cek = random(encryption.key_len())?;
+                let rsa = key.rsa()?;
+                let mut encrypted = vec![0; rsa.size() as usize];
+                let len = rsa.public_encrypt(&cek, &mut encrypted, Padding::PKCS1_OAEP)?;
+                encrypted.truncate(len);
+                Ok((cek, encrypted))
+            }
+            (KeyMaterial::Asymmetric(key), KeyManagement::EcdhEs) => {
+                let ephemeral = EcKey::generate(key.ec_key()?.group())?;
+                header.insert("epk".to_owned(), ec_jwk(&ephemeral)?);
+                let ephemeral = PKey::from_ec_key(ephemeral)?;
+                let cek = agree(&ephemeral, key, encryption, header)?;
+                Ok((cek, Vec::new()))
+            }
+            (KeyMaterial::Secret(secret), _) => {
+                let cek = random(encryption.key_len())?;
+                let kek = AesKey::new_encrypt(secret).map_err(aes_key_error)?;
+                let mut wrapped = vec![0; cek.len() + 8];
+                aes::wrap_key(&kek, None, &mut wrapped, &cek)
+                    .map_err(|_| anyhow!("wrapping the content encryption key failed"))?;
+                Ok((cek, wrapped))
+            }
+            _ => bail!("key type does not suit `{}`", self.algorithm.name()),
+        }
+    }
+}
+
+/// A recipient's private or secret key bound to a JWE `alg`
+pub struct JweDecryptionKey {
+    algorithm: KeyManagement,
+    key: KeyMaterial<Private>,
+}
+
+impl JweDecryptionKey {
+    /// Bind an RSA private key to `RSA-OAEP` or an EC private key to `ECDH-ES`
+    ///
+    /// Fails for RSA keys shorter than 2048 bits and for curves other than P-256, P-384 and
+    /// P-521.
+    pub fn new(algorithm: &str, key: PKey<Private>) -> Result<Self> {
+        let params = KeyManagement::from_name(algorithm)?;
+        params.check_key(&key)?;
+
+        Ok(Self {
+            algorithm: params,
+            key: KeyMaterial::Asymmetric(key),
+        })
+    }
+
+    /// Create an `A256KW` key from a 256-bit key encryption key
+    pub fn secret(algorithm: &str, secret: &[u8]) -> Result<Self> {
+        let JweEncryptionKey { algorithm, key } = JweEncryptionKey::secret(algorithm, secret)?;
+        let key = match key {
+            KeyMaterial::Secret(secret) => KeyMaterial::Secret(secret),
+            KeyMaterial::Asymmetric(_) => unreachable!(),
+        };
+        Ok(Self { algorithm, key })
+    }
+
+    /// Create a key for `algorithm` from a private JSON Web Key
+    ///
+    /// `RSA` keys need every private member (`d`, `p`, `q`, `dp`, `dq` and `qi`), `EC` keys
+    /// need `d` next to `x` and `y`.
+    pub fn from_jwk(algorithm: &str, jwk: &JwsHeader) -> Result<Self> {
+        let params = check_jwk_alg(algorithm, jwk)?;
+        let number =
+            |name: &str| -> Result<BigNum> { Ok(BigNum::from_slice(&member(jwk, name, "JWK")?)?) };
+
+        match (jwk.get("kty").and_then(Value::as_str), params) {
+            (Some("oct"), KeyManagement::A256Kw) => {
+                Self::secret(algorithm, &member(jwk, "k", "JWK")?)
+            }
+            (Some("RSA"), KeyManagement::RsaOaep) => {
+                let rsa = Rsa::from_private_components(
+                    number("n")?,
+                    number("e")?,
+                    number("d")?,
+                    number("p")?,
+                    number("q")?,
+                    number("dp")?,
+                    number("dq")?,
+                    number("qi")?,
+                )?;
+                if !rsa.check_key()? {
+                    bail!("inconsistent RSA JWK")
+                }
+                Self::new(algorithm, PKey::from_rsa(rsa)?)
+            }
+            (Some("EC"), KeyManagement::EcdhEs) => {
+                let public = ec_public_key(jwk, "JWK")?;
+                let d = number("d")?;
+                let key = EcKey::from_private_components(public.group(), &d, public.public_key())?;
+                key.check_key().context("inconsistent EC JWK")?;
+                Self::new(algorithm, PKey::from_ec_key(key)?)
+            }
+            (kty, _) => bail!("JWK type {:?} does not suit `{}`", kty, algorithm),
+        }
+    }
+
+    /// The only `alg` header value accepted by this key
+    pub fn algorithm(&self) -> &str {
+        self.algorithm.name()
+    }
+
+    /// Recover the content encryption key
+    fn unwrap(
+        &self,
+        encryption: ContentEncryption,
+        header: &JwsHeader,
+        encrypted_key: &[u8],
+    ) -> Result<Vec<u8>> {
+        match (&self.key, self.algorithm) {
+            (KeyMaterial::Asymmetric(key), KeyManagement::RsaOaep) => {
+                let rsa = key.rsa()?;
+                let mut cek = vec![0; rsa.size() as usize];
+                match rsa.private_decrypt(encrypted_key, &mut cek, Padding::PKCS1_OAEP) {
+                    Ok(len) if len == encryption.key_len() => {
+                        cek.truncate(len);
+                        Ok(cek)
+                    }
+                    // A random key fails the tag check later, in the same time as a wrong
+                    // plaintext would (RFC 7516 §11.5)
+                    _ => random(encryption.key_len()),
+                }
+            }
+            (KeyMaterial::Asymmetric(key), KeyManagement::EcdhEs) => {
+                if !encrypted_key.is_empty() {
+                    bail!("`ECDH-ES` jwe must have an empty encrypted key")
+                }
+                let epk = header
+                    .get("epk")
+                    .and_then(Value::as_object)
+                    .context("jwe header has no `epk`")?;
+                if epk.get("crv").and_then(Value::as_str)
+                    != Some(curve_name(key.ec_key()?.group())?)
+                {
+                    bail!("jwe `epk` curve does not match the key")
+                }
+                let epk = PKey::from_ec_key(ec_public_key(epk, "jwe `epk`")?)?;
+                agree(key, &epk, encryption, header)
+            }
+            (KeyMaterial::Secret(secret), _) => {
+                if encrypted_key.len() != encryption.key_len() + 8 {
+                    bail!("wrong jwe encrypted key length")
+                }
+                let kek = AesKey::new_decrypt(secret).map_err(aes_key_error)?;
+                let mut cek = vec![0; encryption.key_len()];
+                aes::unwrap_key(&kek, None, &mut cek, encrypted_key)
+                    .map_err(|_| anyhow!("unwrapping the content encryption key failed"))?;
+                Ok(cek)
+            }
+            _ => bail!("key type does not suit `{}`", self.algorithm.name()),
+        }
+    }
+}
+
+/// Streaming content encryption or decryption with its authentication
+enum ContentCipher {
+    Gcm(Crypter),
+    /// AES-CBC with HMAC-SHA-256 over the AAD, IV, ciphertext and AAD length in bits
+    CbcHmac {
+        crypter: Crypter,
+        mac: MdCtx,
+        aad_bits: u64,
+    },
+}
+
+impl ContentCipher {
+    fn new(
+        encryption: ContentEncryption,
+        mode: Mode,
+        cek: &[u8],
+        iv: &[u8],
+        aad: &[u8],
+    ) -> Result<Self> {
+        Ok(match encryption {
+            ContentEncryption::A256Gcm => {
+                let mut crypter = Crypter::new(Cipher::aes_256_gcm(), mode, cek, Some(iv))?;
+                crypter.aad_update(aad)?;
+                ContentCipher::Gcm(crypter)
+            }
+            ContentEncryption::A128CbcHs256 => {
+                let (mac_key, enc_key) = cek.split_at(16);
+                let crypter = Crypter::new(Cipher::aes_128_cbc(), mode, enc_key, Some(iv))?;
+                let mut mac = MdCtx::new()?;
+                let mac_key = PKey::hmac(mac_key)?;
+                mac.digest_sign_init(Some(Md::sha256()), &mac_key)?;
+                mac.digest_sign_update(aad)?;
+                mac.digest_sign_update(iv)?;
+                ContentCipher::CbcHmac {
+                    crypter,
+                    mac,
+                    aad_bits: aad.len() as u64 * 8,
+                }
+            }
+        })
+    }
+
+    /// Encrypt or decrypt `data` into `output`
+    fn update(&mut self, mode: Mode, data: &[u8], output: &mut Vec<u8>) -> Result<()> {
+        output.resize(data.len() + 16, 0);
+        let len = match self {
+            ContentCipher::Gcm(crypter) => crypter.update(data, output)?,
+            ContentCipher::CbcHmac { crypter, mac, .. } => {
+                if matches!(mode, Mode::Decrypt) {
+                    mac.digest_sign_update(data)?;
+                }
+                let len = crypter.update(data, output)?;
+                if matches!(mode, Mode::Encrypt) {
+                    mac.digest_sign_update(&output[..len])?;
+                }
+                len
+            }
+        };
+        output.truncate(len);
+        Ok(())
+    }
+
+    /// Encrypt the last block into `output` and return the authentication tag
+    fn finish_encrypt(&mut self, output: &mut Vec<u8>) -> Result<Vec<u8>> {
+        output.resize(16, 0);
+        let len = match self {
+            ContentCipher::Gcm(crypter) => crypter.finalize(output)?,
+            ContentCipher::CbcHmac { crypter, mac, .. } => {
+                let len = crypter.finalize(output)?;
+                mac.digest_sign_update(&output[..len])?;
+                len
+            }
+        };
+        output.truncate(len);
+        self.tag()
+    }
+
+    /// Check the authentication tag and decrypt the last block into `output`
+    ///
+    /// The tag of AES-CBC is checked before the padding is looked at.
+    fn finish_decrypt(&mut self, tag: &[u8], output: &mut Vec<u8>) -> Result<()> {
+        let mismatch = || anyhow!("jwe authentication tag mismatch");
+
+        if let ContentCipher::CbcHmac { .. } = self {
+            if !memcmp::eq(&self.tag()?, tag) {
+                return Err(mismatch());
+            }
+        }
+        let crypter = match self {
+            ContentCipher::Gcm(crypter) => {
+                crypter.set_tag(tag)?;
+                crypter
+            }
+            ContentCipher::CbcHmac { crypter, .. } => crypter,
+        };
+
+        output.resize(16, 0);
+        let len = crypter.finalize(output).map_err(|_| mismatch())?;
+        output.truncate(len);
+        Ok(())
+    }
+
+    fn tag(&mut self) -> Result<Vec<u8>> {
+        match self {
+            ContentCipher::Gcm(crypter) => {
+                let mut tag = vec![0; TAG_LEN];
+                crypter.get_tag(&mut tag)?;
+                Ok(tag)
+            }
+            ContentCipher::CbcHmac { mac, aad_bits, .. } => {
+                mac.digest_sign_update(&aad_bits.to_be_bytes())?;
+                let mut tag = vec![0; 32];
+                mac.digest_sign_final(Some(&mut tag))?;
+                tag.truncate(TAG_LEN);
+                Ok(tag)
+            }
+        }
+    }
+}
+
+/// Encrypt `payload` to a detached jwe
+///
+/// Returns the compact jwe and the ciphertext.
+pub fn encrypt(
+    encryption: &str,
+    header: JwsHeader,
+    payload: &mut impl Read,
+    key: &JweEncryptionKey,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut writer = EncryptJweWriter::new(Vec::new(), encryption, header, key)?;
+    std::io::copy(payload, &mut writer)?;
+    writer.finish()
+}
+
+/// Decrypt the ciphertext of a detached jwe
+///
+/// Returns the protected header and the plaintext.
+pub fn decrypt(
+    jwe: &impl AsRef<[u8]>,
+    ciphertext: &mut impl Read,
+    key: &JweDecryptionKey,
+) -> Result<(JwsHeader, Vec<u8>)> {
+    let mut writer = DecryptJweWriter::new(jwe, key, Vec::new())?;
+    std::io::copy(ciphertext, &mut writer)?;
+    writer.finish()
+}
+
+/// A `Write` implementation encrypting to a detached jwe
+///
+/// The ciphertext is written to the inner writer as the plaintext comes in.
+pub struct EncryptJweWriter<W> {
+    delegate: Option<W>,
+    cipher: ContentCipher,
+    /// `header.key.iv.` of the compact jwe
+    prefix: Vec<u8>,
+    output: Vec<u8>,
+}
+
+impl<W> EncryptJweWriter<W>
+where
+    W: Write,
+{
+    /// Create a writer encrypting with `encryption` (the `enc` header) to `key`
+    ///
+    /// `alg` and `enc` are added to the protected header, and `epk` for `ECDH-ES`. `apu` and
+    /// `apv` members already in the header are used for `ECDH-ES` key agreement.
+    pub fn new(
+        writer: W,
+        encryption: &str,
+        mut header: JwsHeader,
+        key: &JweEncryptionKey,
+    ) -> Result<Self> {
+        let encryption = ContentEncryption::from_name(encryption)?;
+        if header.contains_key("zip") {
+            bail!("compressed jwe content is not supported")
+        }
+
+        header.insert("alg".to_owned(), Value::from(key.algorithm()));
+        header.insert("enc".to_owned(), Value::from(encryption.name()));
+        let (cek, encrypted_key) = key.wrap(encryption, &mut header)?;
+        let iv = random(encryption.iv_len())?;
+
+        let encoded_header = encode(&serde_json::to_vec(&header)?);
+        let cipher = ContentCipher::new(
+            encryption,
+            Mode::Encrypt,
+            &cek,
+            &iv,
+            encoded_header.as_bytes(),
+        )?;
+        let prefix = format!(
+            "{}.{}.{}.",
+            encoded_header,
+            encode(&encrypted_key),
+            encode(&iv)
+        );
+
+        Ok(Self {
+            delegate: Some(writer),
+            cipher,
+            prefix: prefix.into_bytes(),
+            output: Vec::new(),
+        })
+    }
+
+    /// Encrypt the rest of the plaintext and return the compact jwe and the inner writer
+    pub fn finish(&mut self) -> Result<(Vec<u8>, W)> {
+        let mut writer = self
+            .delegate
+            .take()
+            .context("Encryptor has already had finish() called")?;
+
+        let tag = self.cipher.finish_encrypt(&mut self.output)?;
+        writer.write_all(&self.output)?;
+
+        let mut jwe = std::mem::take(&mut self.prefix);
+        jwe.push(b'.');
+        jwe.extend_from_slice(encode(&tag).as_bytes());
+        Ok((jwe, writer))
+    }
+}
+
+impl<W> Write for EncryptJweWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let writer = self.delegate.as_mut().ok_or_else(finished)?;
+        self.cipher
+            .update(Mode::Encrypt, buf, &mut self.output)
+            .map_err(std::io::Error::other)?;
+        writer.write_all(&self.output)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.delegate.as_mut() {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn finished() -> std::io::Error {
+    std::io::Error::other("jwe writer has already had finish() called")
+}
+
+/// A `Write` implementation decrypting the ciphertext of a detached jwe
+///
+/// The plaintext is written to the inner writer as the ciphertext comes in, before the
+/// authentication tag is checked by [`finish`](Self::finish); discard it when `finish` fails.
+pub struct DecryptJweWriter<W> {
+    delegate: Option<W>,
+    header: JwsHeader,
+    cipher: ContentCipher,
+    tag: Vec<u8>,
+    output: Vec<u8>,
+}
+
+impl<W> DecryptJweWriter<W>
+where
+    W: Write,
+{
+    /// Parse a detached jwe with the default [`DecodeOptions`]
+    ///
+    /// The jwe is rejected unless its `alg` header equals the algorithm of `key`.
+    pub fn new(jwe: &impl AsRef<[u8]>, key: &JweDecryptionKey, writer: W) -> Result<Self> {
+        Self::with_options(jwe, key, writer, &DecodeOptions::default())
+    }
+
+    /// Parse a detached jwe, applying `options` before anything is decoded
+    pub fn with_options(
+        jwe: &impl AsRef<[u8]>,
+        key: &JweDecryptionKey,
+        writer: W,
+        options: &DecodeOptions,
+    ) -> Result<Self> {
+        let jwe = jwe.as_ref();
+        check_token_len(jwe, options)?;
+
+        let segments: Vec<&[u8]> = jwe.split(|b| *b == b'.').collect();
+        let [encoded_header, encrypted_key, iv, ciphertext, tag] = segments[..] else {
+            bail!("wrong jwe format: expected 5 segments")
+        };
+        if !ciphertext.is_empty() {
+            bail!("jwe ciphertext must be detached")
+        }
+
+        let header = decode_header_segment(encoded_header, options)?;
+        match header.get("alg").and_then(Value::as_str) {
+            Some(alg) if alg == key.algorithm() => {}
+            alg => bail!("jwe `alg` {:?} does not match `{}`", alg, key.algorithm()),
+        }
+        let encryption = ContentEncryption::from_name(
+            header
+                .get("enc")
+                .and_then(Value::as_str)
+                .context("jwe header has no `enc`")?,
+        )?;
+        if header.contains_key("zip") {
+            bail!("compressed jwe content is not supported")
+        }
+
+        let decode = |segment: &[u8], name: &str| {
+            base64::decode_config(segment, base64::URL_SAFE_NO_PAD)
+                .map_err(Error::msg)
+                .with_context(|| format!("wrong jwe {} format", name))
+        };
+        let iv = decode(iv, "iv")?;
+        let tag = decode(tag, "tag")?;
+        if iv.len() != encryption.iv_len() || tag.len() != TAG_LEN {
+            bail!("wrong jwe iv or tag length")
+        }
+
+        let cek = key.unwrap(
+            encryption,
+            &header,
+            &decode(encrypted_key, "encrypted key")?,
+        )?;
+        let cipher = ContentCipher::new(encryption, Mode::Decrypt, &cek, &iv, encoded_header)?;
+
+        Ok(Self {
+            delegate: Some(writer),
+            header,
+            cipher,
+            tag,
+            output: Vec::new(),
+        })
+    }
+
+    /// The protected header, not authenticated before [`finish`](Self::finish) succeeds
+    pub fn header(&self) -> &JwsHeader {
+        &self.header
+    }
+
+    /// Check the authentication tag and return the protected header and the inner writer
+    pub fn finish(&mut self) -> Result<(JwsHeader, W)> {
+        let mut writer = self
+            .delegate
+            .take()
+            .context("Decryptor has already had finish() called")?;
+
+        self.cipher.finish_decrypt(&self.tag, &mut self.output)?;
+        writer.write_all(&self.output)?;
+        Ok((std::mem::take(&mut self.header), writer))
+    }
+}
+
+impl<W> Write for DecryptJweWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let writer = self.delegate.as_mut().ok_or_else(finished)?;
+        self.cipher
+            .update(Mode::Decrypt, buf, &mut self.output)
+            .map_err(std::io::Error::other)?;
+        writer.write_all(&self.output)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.delegate.as_mut() {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `cty` values marking a jwe whose plaintext is a compact jws
+const NESTED_CONTENT_TYPES: [&str; 2] = ["JOSE", "JWT"];
+
+/// Signs a payload and encrypts the signed jws in one pass
+///
+/// The plaintext of the jwe is the compact jws `header.payload.signature` with the payload
+/// attached (the nested form of RFC 7516 Appendix A.2), signed by a [`SerializeJwsWriter`]
+/// and marked by a `cty` of `JOSE` in the jwe header. Neither the payload nor the signature
+/// leaves the encryption; read it back with a [`DecryptVerifyWriter`].
+pub struct SignEncryptWriter<W, S> {
+    jws: SerializeJwsWriter<EncryptJweWriter<W>, S>,
+}
+
+impl<W, S> SignEncryptWriter<W, S>
+where
+    W: Write,
+    S: Sign,
+{
+    /// Create a writer signing with `signer` under `algorithm` and `header`, then encrypting
+    /// with `encryption` to `key` under `jwe_header`
+    ///
+    /// A `cty` member already in `jwe_header` is kept, e.g. `JWT` for a nested JWT.
+    pub fn new(
+        writer: W,
+        encryption: &str,
+        mut jwe_header: JwsHeader,
+        key: &JweEncryptionKey,
+        algorithm: String,
+        header: JwsHeader,
+        signer: S,
+    ) -> Result<Self> {
+        jwe_header
+            .entry("cty")
+            .or_insert_with(|| Value::from(NESTED_CONTENT_TYPES[0]));
+
+        let jwe = EncryptJweWriter::new(writer, encryption, jwe_header, key)?;
+
+        Ok(Self {
+            jws: SerializeJwsWriter::attached(jwe, algorithm, header, signer)?,
+        })
+    }
+
+    /// Sign and encrypt the rest of the payload and return the compact jwe and the inner writer
+    pub fn finish(&mut self) -> Result<(Vec<u8>, W)> {
+        self.jws.finish()?.finish()
+    }
+}
+
+impl<W, S> Write for SignEncryptWriter<W, S>
+where
+    W: Write,
+    S: Sign,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.jws.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.jws.flush()
+    }
+}
+
+/// A `Write` implementation decrypting a jwe of a [`SignEncryptWriter`] and verifying the
+/// nested jws
+///
+/// The decrypted jws is held back, up to the `max_token_len` of the [`DecodeOptions`], and is
+/// only parsed once [`finish`](Self::finish) has checked the authentication tag, so forged
+/// ciphertext can not probe the parser. The payload reaches the inner writer only after the
+/// signature is verified.
+pub struct DecryptVerifyWriter<W, V> {
+    jwe: DecryptJweWriter<Plaintext>,
+    receiver: Option<(W, V)>,
+    algorithm: String,
+    options: DecodeOptions,
+}
+
+impl<W, V> DecryptVerifyWriter<W, V>
+where
+    W: Write,
+    V: Verify,
+{
+    /// Parse a detached jwe with the default [`DecodeOptions`]
+    ///
+    /// The jwe is rejected unless its `alg` header equals the algorithm of `key` and its `cty`
+    /// marks a nested jws; the jws is rejected unless its `alg` header equals
+    /// [`VerificationKey::algorithm`].
+    pub fn new<'k, K>(
+        jwe: &impl AsRef<[u8]>,
+        key: &JweDecryptionKey,
+        verification_key: &'k K,
+        writer: W,
+    ) -> Result<Self>
+    where
+        K: VerificationKey<Verifier<'k> = V>,
+    {
+        Self::with_options(
+            jwe,
+            key,
+            verification_key,
+            writer,
+            &DecodeOptions::default(),
+        )
+    }
+
+    /// Parse a detached jwe, applying `options` to the jwe and to the nested jws
+    pub fn with_options<'k, K>(
+        jwe: &impl AsRef<[u8]>,
+        key: &JweDecryptionKey,
+        verification_key: &'k K,
+        writer: W,
+        options: &DecodeOptions,
+    ) -> Result<Self>
+    where
+        K: VerificationKey<Verifier<'k> = V>,
+    {
+        let plaintext = Plaintext {
+            buffer: Vec::new(),
+            max_len: options.max_token_len,
+        };
+        let jwe = DecryptJweWriter::with_options(jwe, key, plaintext, options)?;
+
+        match jwe.header().get("cty").and_then(Value::as_str) {
+            Some(cty)
+                if NESTED_CONTENT_TYPES
+                    .iter()
+                    .any(|nested| cty.eq_ignore_ascii_case(nested)) => {}
+            cty => bail!("jwe `cty` {:?} does not mark a nested jws", cty),
+        }
+
+        Ok(Self {
+            jwe,
+            receiver: Some((writer, verification_key.verifier()?)),
+            algorithm: verification_key.algorithm().to_owned(),
+            options: options.clone(),
+        })
+    }
+
+    /// Check the authentication tag and the signature, write the payload and return the
+    /// protected header of the jws and the inner writer
+    ///
+    /// A rejected signature fails with a [`VerificationError`] carrying the reason.
+    pub fn finish(&mut self) -> Result<(JwsHeader, W)> {
+        let (mut writer, mut verifier) = self
+            .receiver
+            .take()
+            .context("Decryptor has already had finish() called")?;
+        let (_, plaintext) = self.jwe.finish()?;
+        let jws = plaintext.buffer;
+
+        let segments: Vec<&[u8]> = jws.split(|b| *b == b'.').collect();
+        let [encoded_header, payload, signature] = segments[..] else {
+            bail!("wrong jws format: expected 3 segments")
+        };
+
+        let header = decode_header_segment(encoded_header, &self.options)?;
+        check_not_digest(&header)?;
+        match header.get("alg") {
+            Some(Value::String(alg)) if *alg == self.algorithm => {}
+            _ => bail!("verifier is not found"),
+        }
+        if decoded_len(signature.len()) > self.options.max_signature_len {
+            bail!(
+                "jws signature exceeds {} bytes",
+                self.options.max_signature_len
+            )
+        }
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD)
+            .map_err(Error::msg)
+            .context("wrong jws signature format")?;
+
+        verifier.update(&jws[..encoded_header.len() + 1 + payload.len()])?;
+        verifier
+            .check(&signature)
+            .map_err(|reason| VerificationError::new(&header, reason))?;
+
+        let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD)
+            .map_err(Error::msg)
+            .context("wrong jws payload format")?;
+        writer.write_all(&payload)?;
+        Ok((header, writer))
+    }
+}
+
+impl<W, V> Write for DecryptVerifyWriter<W, V>
+where
+    W: Write,
+    V: Verify,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.receiver.is_none() {
+            return Err(finished());
+        }
+        self.jwe.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.receiver.as_mut() {
+            Some((writer, _)) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The decrypted nested jws, held back until the authentication tag is checked
+///
+/// Fails only once `max_len` is exceeded, which depends on the ciphertext length alone.
+struct Plaintext {
+    buffer: Vec<u8>,
+    max_len: usize,
+}
+
+impl Write for Plaintext {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.buffer.len() + buf.len() > self.max_len {
+            return Err(std::io::Error::other(format!(
+                "jws exceeds {} bytes",
+                self.max_len
+            )));
+        }
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+//! [`KeyResolver`] backed by a remote JSON Web Key Set ([RFC 7517](https://tools.ietf.org/html/rfc7517#section-5))
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use anyhow::Result;
+//! use detached_jws::jwks::{JwksClient, JwksResolver, JwksResponse};
+//!
+//! struct Client;
+//!
+//! impl JwksClient for Client {
+//!     fn fetch(&self, url: &str) -> Result<JwksResponse> {
+//!         // issue the GET request with the HTTP client of choice
+//!         # unimplemented!()
+//!     }
+//! }
+//!
+//! let resolver = JwksResolver::new("https://aspsp.example.com/jwks.json", Client);
+//!
+//! # let jws = b"";
+//! # let payload = b"";
+//! let header = detached_jws::deserialize_with_resolver(&jws, &mut &payload[..], &resolver);
+//! ```
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{openssl::OpensslVerificationKey, JwsHeader, KeyResolver};
+
+/// A JWKS document fetched by a [`JwksClient`]
+#[derive(Debug, Clone, Default)]
+pub struct JwksResponse {
+    pub body: Vec<u8>,
+    /// The `Cache-Control` response header
+    pub cache_control: Option<String>,
+}
+
+/// Fetches JWKS documents, plugging in the HTTP client of choice
+///
+/// Called outside the resolver cache lock and by one caller at a time, but callers that
+/// need the fetched keys wait for it, so a client must apply its own timeouts.
+///
+/// The call blocks the thread [`KeyResolver::resolve`] runs on, see
+/// [`JwksResolver`](JwksResolver#blocking).
+pub trait JwksClient: Send + Sync {
+    fn fetch(&self, url: &str) -> Result<JwksResponse>;
+}
+
+/// Resolves verification keys by the `kid` and `alg` headers from a JWKS endpoint
+///
+/// Keys are cached for the `max-age` of the response (or [`default_ttl`](Self::default_ttl)
+/// without one), but never longer than [`MAX_TTL`]. An unknown `kid` triggers a refresh,
+/// so rotated keys are picked up without waiting for expiry, but never more often than
+/// [`min_refresh_interval`](Self::min_refresh_interval). When a refresh fails the
+/// previously fetched keys keep being used.
+///
+/// A jws header without `kid` resolves to the only key of the set whose `alg`, if any,
+/// matches, so a set with a single key needs no `kid` on either side.
+///
+/// # Blocking
+///
+/// A refresh fetches the set synchronously inside [`KeyResolver::resolve`], and callers
+/// that need the refreshed keys wait for it. `tower::VerifyJwsLayer` and
+/// `reqwest::JwsMiddleware` resolve keys on the async executor, so the resolver must not
+/// be used with them as-is: a slow endpoint stalls the runtime worker threads. Use it
+/// from blocking threads (e.g. inside `tokio::task::spawn_blocking`) only, or hand the
+/// async layers a resolver closure over keys that such a thread keeps up to date.
+pub struct JwksResolver<C> {
+    url: String,
+    client: C,
+    default_ttl: Duration,
+    min_refresh_interval: Duration,
+    cache: Mutex<JwksCache>,
+    refreshed: Condvar,
+}
+
+#[derive(Default)]
+struct JwksCache {
+    jwks: Vec<JwsHeader>,
+    keys: HashMap<(usize, String), Arc<OpensslVerificationKey>>,
+    fetched_at: Option<Instant>,
+    expires_at: Option<Instant>,
+    refreshing: bool,
+}
+
+impl JwksCache {
+    /// Index of the JWK a jws header with `kid` and `alg` refers to
+    fn select(&self, kid: Option<&str>, alg: &str) -> Option<usize> {
+        let mut candidates = self.jwks.iter().enumerate().filter(|(_, jwk)| match kid {
+            Some(kid) => jwk.get("kid").and_then(Value::as_str) == Some(kid),
+            None => !matches!(jwk.get("alg").and_then(Value::as_str), Some(a) if a != alg),
+        });
+
+        let (index, _) = candidates.next()?;
+        match kid.is_none() && candidates.next().is_some() {
+            true => None,
+            false => Some(index),
+        }
+    }
+}
+
+/// A fetched key set and its expiry
+struct Jwks {
+    jwks: Vec<JwsHeader>,
+    expires_at: Instant,
+}
+
+/// Ends a refresh and wakes the waiting callers, even if the client panics
+struct RefreshGuard<'a> {
+    cache: &'a Mutex<JwksCache>,
+    refreshed: &'a Condvar,
+}
+
+impl Drop for RefreshGuard<'_> {
+    fn drop(&mut self) {
+        lock(self.cache).refreshing = false;
+        self.refreshed.notify_all();
+    }
+}
+
+fn lock(cache: &Mutex<JwksCache>) -> MutexGuard<'_, JwksCache> {
+    cache.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The longest time fetched keys are cached, 24 hours
+pub const MAX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+impl<C> JwksResolver<C>
+where
+    C: JwksClient,
+{
+    pub fn new(url: impl Into<String>, client: C) -> Self {
+        Self {
+            url: url.into(),
+            client,
+            default_ttl: Duration::from_secs(300),
+            min_refresh_interval: Duration::from_secs(30),
+            cache: Mutex::new(JwksCache::default()),
+            refreshed: Condvar::new(),
+        }
+    }
+
+    /// How long to cache keys when the response has no `max-age`, 5 minutes by default
+    ///
+    /// Capped at [`MAX_TTL`].
+    pub fn default_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = ttl;
+        self
+    }
+
+    /// Minimal time between two fetches, 30 seconds by default
+    pub fn min_refresh_interval(mut self, interval: Duration) -> Self {
+        self.min_refresh_interval = interval;
+        self
+    }
+
+    fn fetch(&self, now: Instant) -> Result<Jwks> {
+        let response = self.client.fetch(&self.url)?;
+
+        let jwks: Value = serde_json::from_slice(&response.body).context("wrong JWKS format")?;
+        let keys = jwks
+            .get("keys")
+            .and_then(Value::as_array)
+            .context("wrong JWKS format")?;
+
+        let jwks = keys
+            .iter()
+            .filter_map(Value::as_object)
+            .filter(|jwk| !matches!(jwk.get("use"), Some(u) if u != "sig"))
+            .cloned()
+            .collect();
+
+        let ttl = match response.cache_control {
+            Some(ref cache_control) => max_age(cache_control).unwrap_or(self.default_ttl),
+            None => self.default_ttl,
+        };
+
+        Ok(Jwks {
+            jwks,
+            expires_at: now + ttl.min(MAX_TTL),
+        })
+    }
+}
+
+impl<C> KeyResolver for JwksResolver<C>
+where
+    C: JwksClient,
+{
+    type Key = OpensslVerificationKey;
+
+    fn resolve(&self, header: &JwsHeader) -> Result<Arc<OpensslVerificationKey>> {
+        let kid = match header.get("kid") {
+            Some(kid) => Some(kid.as_str().context("wrong jws header `kid` format")?),
+            None => None,
+        };
+        let alg = header
+            .get("alg")
+            .and_then(Value::as_str)
+            .context("jws header has no `alg`")?;
+
+        let mut cache = lock(&self.cache);
+        let now = Instant::now();
+
+        // a caller that needs a key from an ongoing refresh waits for it instead of
+        // fetching again
+        let mut waited = false;
+        while cache.refreshing && cache.select(kid, alg).is_none() {
+            cache = self
+                .refreshed
+                .wait(cache)
+                .unwrap_or_else(|e| e.into_inner());
+            waited = true;
+        }
+
+        let too_soon = match cache.fetched_at {
+            // an interval past what `Instant` can represent never elapses
+            Some(at) => at
+                .checked_add(self.min_refresh_interval)
+                .is_none_or(|next| now < next),
+            None => false,
+        };
+        let may_refresh = !waited && !cache.refreshing && !too_soon;
+        let expired = !matches!(cache.expires_at, Some(at) if now < at);
+
+        let mut refresh_error = None;
+        if may_refresh && (expired || cache.select(kid, alg).is_none()) {
+            cache.refreshing = true;
+            cache.fetched_at = Some(now);
+            drop(cache);
+
+            {
+                let _guard = RefreshGuard {
+                    cache: &self.cache,
+                    refreshed: &self.refreshed,
+                };
+                let result = self.fetch(now);
+
+                // updated before the guard wakes the waiting callers
+                let mut cache = lock(&self.cache);
+                match result {
+                    Ok(jwks) => {
+                        cache.jwks = jwks.jwks;
+                        cache.keys.clear();
+                        cache.expires_at = Some(jwks.expires_at);
+                    }
+                    Err(e) => refresh_error = Some(e),
+                }
+            }
+            cache = lock(&self.cache);
+        }
+
+        let cache = &mut *cache;
+
+        let index = match cache.select(kid, alg) {
+            Some(index) => index,
+            None => {
+                let message = match kid {
+                    Some(kid) => format!("key `{}` is not found", kid),
+                    None => format!("no single key for `{}` is found", alg),
+                };
+                match refresh_error {
+                    Some(e) => return Err(e.context(message)),
+                    None => bail!(message),
+                }
+            }
+        };
+
+        let key = match cache.keys.get(&(index, alg.to_owned())) {
+            Some(key) => Arc::clone(key),
+            None => {
+                let key = Arc::new(OpensslVerificationKey::from_jwk(alg, &cache.jwks[index])?);
+                cache.keys.insert((index, alg.to_owned()), Arc::clone(&key));
+                key
+            }
+        };
+
+        Ok(key)
+    }
+}
+
+/// The lifetime a `Cache-Control` header allows, zero for `no-cache` and `no-store`
+fn max_age(cache_control: &str) -> Option<Duration> {
+    let mut max_age = None;
+
+    for directive in cache_control.split(',').map(str::trim) {
+        let directive = directive.to_ascii_lowercase();
+        if directive == "no-cache" || directive == "no-store" {
+            return Some(Duration::from_secs(0));
+        }
+        if let Some(seconds) = directive.strip_prefix("max-age=") {
+            max_age = seconds
+                .trim_matches('"')
+                .parse()
+                .ok()
+                .map(Duration::from_secs);
+        }
+    }
+
+    max_age
+}
@@ -0,0 +1,393 @@
+//! JSON Web Tokens ([RFC 7519](https://tools.ietf.org/html/rfc7519)) signed with the jws machinery
+//!
+//! A claims struct implementing [`Serialize`] is signed as the jws payload, either attached in a
+//! compact `header.claims.signature` token or detached with the claims sent separately. On
+//! decode the registered claims are checked by a [`Validator`] before the claims are
+//! deserialized.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(feature = "openssl")] {
+//! use std::time::{Duration, SystemTime, UNIX_EPOCH};
+//! use serde::{Deserialize, Serialize};
+//! use serde_json::Map;
+//! use detached_jws::jwt::{self, Validator};
+//! use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Claims {
+//!     iss: String,
+//!     sub: String,
+//!     exp: u64,
+//!     scope: String,
+//! }
+//!
+//! let exp = SystemTime::now() + Duration::from_secs(600);
+//! let claims = Claims {
+//!     iss: "issuer".to_owned(),
+//!     sub: "user-1".to_owned(),
+//!     exp: exp.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+//!     scope: "read".to_owned(),
+//! };
+//!
+//! let key = OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+//! let token = jwt::encode(Map::new(), &claims, &key).unwrap();
+//!
+//! let key = OpensslVerificationKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+//! let validator = Validator::new().issuer("issuer").subject("user-1");
+//! let decoded = jwt::decode::<Claims>(&token, &key, &validator).unwrap();
+//!
+//! assert_eq!(decoded.header["typ"], "JWT");
+//! assert_eq!(decoded.claims.scope, "read");
+//! # }
+//! ```
+
+use anyhow::{bail, Context, Error, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+use crate::decode::{check_token_len, parse_object};
+use crate::time::{check_numeric_dates, TimeViolation};
+use crate::{DecodeOptions, DeserializeJwsWriter, JwsHeader, SigningKey, VerificationKey};
+
+/// A verified and validated token
+#[derive(Debug, Clone)]
+pub struct Token<C> {
+    pub header: JwsHeader,
+    pub claims: C,
+}
+
+/// Sign `claims` into a compact `header.claims.signature` token
+///
+/// `typ` is set to `JWT` unless the header already holds one.
+pub fn encode<C>(header: JwsHeader, claims: &C, key: &impl SigningKey) -> Result<String>
+where
+    C: Serialize + ?Sized,
+{
+    let (jws, payload) = encode_detached(header, claims, key)?;
+    let jws = String::from_utf8(jws)?;
+    let (header, signature) = jws.split_once("..").context("wrong jws format")?;
+
+    Ok(format!(
+        "{}.{}.{}",
+        header,
+        base64::encode_config(payload, base64::URL_SAFE_NO_PAD),
+        signature
+    ))
+}
+
+/// Sign `claims` into a detached jws
+///
+/// Returns the jws and the JSON claims it signs, to be sent alongside.
+pub fn encode_detached<C>(
+    mut header: JwsHeader,
+    claims: &C,
+    key: &impl SigningKey,
+) -> Result<(Vec<u8>, Vec<u8>)>
+where
+    C: Serialize + ?Sized,
+{
+    let payload = match serde_json::to_value(claims)? {
+        claims @ Value::Object(_) => serde_json::to_vec(&claims)?,
+        _ => bail!("jwt claims must serialize to a JSON object"),
+    };
+    header.entry("typ").or_insert_with(|| Value::from("JWT"));
+
+    let jws = crate::serialize_with_key(header, &mut payload.as_slice(), key)?;
+    Ok((jws, payload))
+}
+
+/// Verify a compact token with `key`, then validate and deserialize its claims
+///
+/// The token is checked against the [`options`](Validator::options) of `validator` before
+/// anything is decoded.
+pub fn decode<C>(
+    token: &impl AsRef<[u8]>,
+    key: &impl VerificationKey,
+    validator: &Validator,
+) -> Result<Token<C>>
+where
+    C: DeserializeOwned,
+{
+    let token = token.as_ref();
+    check_token_len(token, &validator.options)?;
+
+    let segments: Vec<&[u8]> = token.split(|b| *b == b'.').collect();
+    let [header, payload, signature] = segments[..] else {
+        bail!("wrong jwt format: expected 3 segments")
+    };
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD)
+        .map_err(Error::msg)
+        .context("wrong jwt claims format")?;
+
+    let mut jws = header.to_vec();
+    jws.extend_from_slice(b"..");
+    jws.extend_from_slice(signature);
+
+    decode_detached(&jws, &payload, key, validator)
+}
+
+/// Verify a detached jws over the JSON `claims`, then validate and deserialize them
+pub fn decode_detached<C>(
+    jws: &impl AsRef<[u8]>,
+    claims: &[u8],
+    key: &impl VerificationKey,
+    validator: &Validator,
+) -> Result<Token<C>>
+where
+    C: DeserializeOwned,
+{
+    let mut writer = DeserializeJwsWriter::with_key(jws, key, &validator.options)?;
+    writer.write_all(claims)?;
+    let header = writer.finish()?;
+
+    let (claims, duplicate) = parse_object(claims).context("wrong jwt claims format")?;
+    if let Some(name) = duplicate {
+        bail!(ClaimError::Duplicate(name))
+    }
+    validator.validate(&claims)?;
+
+    Ok(Token {
+        header,
+        claims: serde_json::from_value(Value::Object(claims)).context("wrong jwt claims format")?,
+    })
+}
+
+/// A registered claim that failed validation
+///
+/// Returned wrapped in [`anyhow::Error`] and can be recovered with `downcast_ref`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClaimError {
+    /// A required claim is absent
+    Missing(String),
+    /// A registered claim has the wrong JSON type
+    Malformed(String),
+    /// A claim appears more than once, so parsers could disagree on its value
+    Duplicate(String),
+    /// `exp` has passed
+    Expired,
+    /// `nbf` has not been reached
+    NotYetValid,
+    /// `iat` is in the future or older than the maximum age
+    IssuedAt,
+    Issuer,
+    Audience,
+    Subject,
+}
+
+impl fmt::Display for ClaimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClaimError::Missing(claim) => write!(f, "jwt claim `{}` is missing", claim),
+            ClaimError::Malformed(claim) => write!(f, "wrong jwt claim `{}` format", claim),
+            ClaimError::Duplicate(claim) => write!(f, "duplicate jwt claim `{}`", claim),
+            ClaimError::Expired => f.write_str("jwt has expired"),
+            ClaimError::NotYetValid => f.write_str("jwt is not valid yet"),
+            ClaimError::IssuedAt => f.write_str("jwt issue time is out of range"),
+            ClaimError::Issuer => f.write_str("jwt issuer is not accepted"),
+            ClaimError::Audience => f.write_str("jwt audience is not accepted"),
+            ClaimError::Subject => f.write_str("jwt subject is not accepted"),
+        }
+    }
+}
+
+impl std::error::Error for ClaimError {}
+
+/// Checks the registered claims of a decoded token
+///
+/// `exp`, `nbf` and `iat` are checked whenever present and `exp` is required unless
+/// [`optional`](Self::optional). A token with an `aud` claim is rejected unless an
+/// [`audience`](Self::audience) is configured.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use detached_jws::jwt::Validator;
+///
+/// let validator = Validator::new()
+///     .issuer("https://issuer.example")
+///     .audience("api")
+///     .require("jti")
+///     .leeway(Duration::from_secs(30))
+///     .max_age(Duration::from_secs(3600));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Validator {
+    issuers: Vec<String>,
+    audiences: Vec<String>,
+    subject: Option<String>,
+    required: Vec<String>,
+    leeway: Duration,
+    max_age: Option<Duration>,
+    time: Option<SystemTime>,
+    options: DecodeOptions,
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self {
+            issuers: Vec::new(),
+            audiences: Vec::new(),
+            subject: None,
+            required: vec!["exp".to_owned()],
+            leeway: Duration::ZERO,
+            max_age: None,
+            time: None,
+            options: DecodeOptions::default(),
+        }
+    }
+
+    /// Accept tokens from `issuer`; may be called for several issuers
+    pub fn issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuers.push(issuer.into());
+        self
+    }
+
+    /// Accept tokens for `audience`; may be called for several audiences
+    pub fn audience(mut self, audience: impl Into<String>) -> Self {
+        self.audiences.push(audience.into());
+        self
+    }
+
+    /// Accept only tokens about `subject`
+    pub fn subject(mut self, subject: impl Into<String>) -> Self {
+        self.subject = Some(subject.into());
+        self
+    }
+
+    /// Require `claim` to be present, e.g. `jti` or `iat`
+    pub fn require(mut self, claim: impl Into<String>) -> Self {
+        let claim = claim.into();
+        if !self.required.contains(&claim) {
+            self.required.push(claim);
+        }
+        self
+    }
+
+    /// Stop requiring `claim`, e.g. `exp`
+    pub fn optional(mut self, claim: &str) -> Self {
+        self.required.retain(|required| required != claim);
+        self
+    }
+
+    /// Tolerated clock skew for `exp`, `nbf` and `iat`
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /// Reject tokens whose `iat` is older than `max_age`
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Validate as of `time` instead of the current time
+    pub fn time(mut self, time: SystemTime) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    /// Size limits of the token, or of the detached jws
+    ///
+    /// The limits of a compact token apply to all of it, claims included.
+    pub fn options(mut self, options: DecodeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Check the registered claims of a claim set
+    pub fn validate(&self, claims: &Map<String, Value>) -> Result<(), ClaimError> {
+        for claim in &self.required {
+            if !claims.contains_key(claim) {
+                return Err(ClaimError::Missing(claim.clone()));
+            }
+        }
+
+        let issued_at = match check_numeric_dates(claims, self.time, self.leeway, self.max_age) {
+            Ok(issued_at) => issued_at,
+            Err(TimeViolation::Malformed(claim)) => {
+                return Err(ClaimError::Malformed(claim.to_owned()))
+            }
+            Err(TimeViolation::Expired) => return Err(ClaimError::Expired),
+            Err(TimeViolation::NotYetValid) => return Err(ClaimError::NotYetValid),
+            Err(TimeViolation::IssuedInFuture | TimeViolation::TooOld) => {
+                return Err(ClaimError::IssuedAt)
+            }
+        };
+        if !issued_at && self.max_age.is_some() {
+            return Err(ClaimError::Missing("iat".to_owned()));
+        }
+
+        let issuer = string(claims, "iss")?;
+        if !self.issuers.is_empty() {
+            match issuer {
+                None => return Err(ClaimError::Missing("iss".to_owned())),
+                Some(issuer) if !self.issuers.iter().any(|i| i == issuer) => {
+                    return Err(ClaimError::Issuer)
+                }
+                _ => {}
+            }
+        }
+
+        let subject = string(claims, "sub")?;
+        if let Some(expected) = &self.subject {
+            match subject {
+                None => return Err(ClaimError::Missing("sub".to_owned())),
+                Some(subject) if subject != expected => return Err(ClaimError::Subject),
+                _ => {}
+            }
+        }
+
+        match (audiences(claims)?, self.audiences.is_empty()) {
+            (None, false) => return Err(ClaimError::Missing("aud".to_owned())),
+            (Some(_), true) => return Err(ClaimError::Audience),
+            (Some(audiences), false) => {
+                if !audiences
+                    .iter()
+                    .any(|a| self.audiences.iter().any(|b| a == b))
+                {
+                    return Err(ClaimError::Audience);
+                }
+            }
+            (None, true) => {}
+        }
+
+        string(claims, "jti")?;
+        Ok(())
+    }
+}
+
+fn string<'a>(claims: &'a Map<String, Value>, claim: &str) -> Result<Option<&'a str>, ClaimError> {
+    match claims.get(claim) {
+        None => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value)),
+        Some(_) => Err(ClaimError::Malformed(claim.to_owned())),
+    }
+}
+
+/// `aud` as a single string or an array of strings
+fn audiences(claims: &Map<String, Value>) -> Result<Option<Vec<&str>>, ClaimError> {
+    let malformed = || ClaimError::Malformed("aud".to_owned());
+    match claims.get("aud") {
+        None => Ok(None),
+        Some(Value::String(audience)) => Ok(Some(vec![audience])),
+        Some(Value::Array(audiences)) => audiences
+            .iter()
+            .map(|a| a.as_str().ok_or_else(malformed))
+            .collect::<Result<_, _>>()
+            .map(Some),
+        Some(_) => Err(malformed()),
+    }
+}
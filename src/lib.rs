@@ -4,6 +4,7 @@
 //!
 //! # Example with writer:
 //! ```
+//! # #[cfg(feature = "openssl")] {
 //! use openssl::pkey::PKey;
 //! use openssl::rsa::Rsa;
 //! use openssl::{hash::MessageDigest};
@@ -47,10 +48,12 @@
 //!     verified_headers.get("custom").unwrap().as_str().unwrap(),
 //!     "custom_value"
 //! );
+//! # }
 //! ```
 //!
 //! # Simple example:
 //! ```
+//! # #[cfg(feature = "openssl")] {
 //! use openssl::pkey::PKey;
 //! use openssl::rsa::Rsa;
 //! use openssl::{hash::MessageDigest};
@@ -88,26 +91,150 @@
 //!     verified_headers.get("custom").unwrap().as_str().unwrap(),
 //!     "custom_value"
 //! );
+//! # }
 //! ```
+//!
+//! # no_std:
+//! The default `std` feature adds the `std::io::Read`/`Write` entry points and the [`jwt`],
+//! [`policy`], [`batch`] and [`replay`] modules. The default `openssl` feature, which implies
+//! `std`, adds the [`openssl`] keys and the [`digest`], [`jwe`], [`jwks`] and [`remote`] modules
+//! built on them; leave it out to use `std` with the `rustcrypto` keys only. Without `std` the
+//! crate is `no_std` with `alloc`: [`SerializeJwsWriter`] and [`DeserializeJwsWriter`] are fed
+//! with [`ByteSink::update`] and signers implement [`ByteSink`] instead of `Write`.
+//!
+//! # Optional features:
+//! - `tower`: [`tower::VerifyJwsLayer`] verifying request bodies against a signature header and
+//!   [`tower::SignJwsLayer`] signing response bodies
+//! - `reqwest`: [`reqwest::JwsMiddleware`] signing requests and verifying responses of a
+//!   [reqwest-middleware](https://crates.io/crates/reqwest-middleware) client
+//! - `pkcs11`: [`pkcs11::Pkcs11SigningKey`] signing with keys kept in a PKCS#11 token
+//! - `rayon`: [`batch::BatchVerifier`] verifying jobs in parallel
+//! - `simd`: SIMD accelerated base64url encoding of streamed payloads
+//! - `rustcrypto`: [`rustcrypto::RustCryptoSigningKey`] and [`rustcrypto::RustCryptoVerificationKey`],
+//!   pure Rust keys created from JWKs
+//! - `wasm`: [`wasm::sign`] and [`wasm::verify`] exported to JavaScript with
+//!   [wasm-bindgen](https://crates.io/crates/wasm-bindgen)
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod batch;
+pub mod borrowed;
+mod chunked;
 pub mod decode;
+#[cfg(feature = "openssl")]
+pub mod digest;
 pub mod encode;
-
+#[cfg(feature = "openssl")]
+pub mod jwe;
+#[cfg(feature = "openssl")]
+pub mod jwks;
+#[cfg(feature = "std")]
+pub mod jwt;
+#[cfg(feature = "openssl")]
 pub mod openssl;
+#[cfg(feature = "pkcs11")]
+pub mod pkcs11;
+#[cfg(feature = "std")]
+pub mod policy;
+#[cfg(feature = "openssl")]
+pub mod remote;
+#[cfg(feature = "std")]
+pub mod replay;
+#[cfg(feature = "reqwest")]
+pub mod reqwest;
+#[cfg(feature = "rustcrypto")]
+pub mod rustcrypto;
+#[cfg(feature = "std")]
+mod time;
+#[cfg(feature = "tower")]
+pub mod tower;
+pub mod verification;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
+use alloc::{string::String, sync::Arc, vec::Vec};
 use anyhow::Result;
 use serde_json::{value::Value, Map};
+#[cfg(feature = "std")]
 use std::io::Read;
-use std::io::Write;
 
-pub use crate::decode::{deserialize, deserialize_selector, DeserializeJwsWriter};
-pub use crate::encode::{serialize, SerializeJwsWriter};
+pub use crate::borrowed::{DetachedJwsRef, SignatureBuf};
+pub use crate::decode::{
+    decode_header, DecodeOptions, DeserializeJwsWriter, DuplicateHeaderMember,
+};
+#[cfg(feature = "std")]
+pub use crate::decode::{
+    deserialize, deserialize_selector, deserialize_selector_with_options, deserialize_with_key,
+    deserialize_with_policy, deserialize_with_resolver, deserialize_with_resolver_and_policy,
+};
+pub use crate::encode::SerializeJwsWriter;
+#[cfg(feature = "std")]
+pub use crate::encode::{serialize, serialize_with_key};
+#[cfg(feature = "std")]
+pub use crate::policy::{PolicyViolation, VerificationPolicy};
+pub use crate::verification::{FailureReason, Verification, VerificationError};
 
 pub type JwsHeader = Map<String, Value>;
 
+/// Conventional name of the HTTP header carrying a detached jws
+pub const JWS_SIGNATURE_HEADER: &str = "x-jws-signature";
+
+/// Receives the signing input of a [`Sign`] or [`Verify`] piece by piece
+///
+/// With the `std` feature every [`std::io::Write`] is a `ByteSink`; without it `Vec<u8>` is.
+///
+/// # Examples
+///
+/// ```
+/// use anyhow::Result;
+/// use detached_jws::{ByteSink, Sign};
+///
+/// /// A signer for a device without `std::io`
+/// #[derive(Default)]
+/// struct Checksum(u32);
+///
+/// impl ByteSink for Checksum {
+///     fn update(&mut self, data: &[u8]) -> Result<()> {
+///         self.0 = data.iter().fold(self.0, |acc, b| acc.rotate_left(5) ^ u32::from(*b));
+///         Ok(())
+///     }
+/// }
+///
+/// impl Sign for Checksum {
+///     fn get_sign(&self) -> Result<Vec<u8>> {
+///         Ok(self.0.to_be_bytes().to_vec())
+///     }
+/// }
+/// ```
+pub trait ByteSink {
+    fn update(&mut self, data: &[u8]) -> Result<()>;
+}
+
+#[cfg(feature = "std")]
+impl<W> ByteSink for W
+where
+    W: std::io::Write + ?Sized,
+{
+    fn update(&mut self, data: &[u8]) -> Result<()> {
+        Ok(self.write_all(data)?)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl ByteSink for Vec<u8> {
+    fn update(&mut self, data: &[u8]) -> Result<()> {
+        self.extend_from_slice(data);
+        Ok(())
+    }
+}
+
 /// A signature signer
-pub trait Sign: Write {
+pub trait Sign: ByteSink {
     fn get_sign(&self) -> Result<Vec<u8>>;
 
+    #[cfg(feature = "std")]
     fn form_detached_jws(
         self,
         algorithm: String,
@@ -122,9 +249,22 @@ pub trait Sign: Write {
 }
 
 /// A signature verifier
-pub trait Verify: Write {
+pub trait Verify: ByteSink {
     fn verify(&self, signature: &[u8]) -> Result<bool>;
 
+    /// Verify the signature and report why it was rejected
+    ///
+    /// The default implementation maps `false` from [`Verify::verify`] to
+    /// [`FailureReason::Mismatch`] and an error to [`FailureReason::Backend`].
+    fn check(&self, signature: &[u8]) -> core::result::Result<(), FailureReason> {
+        match self.verify(signature) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(FailureReason::Mismatch),
+            Err(e) => Err(FailureReason::Backend(e)),
+        }
+    }
+
+    #[cfg(feature = "std")]
     fn verify_jws_detached(
         self,
         jws: &impl AsRef<[u8]>,
@@ -136,3 +276,54 @@ pub trait Verify: Write {
         deserialize(jws, payload, self)
     }
 }
+
+/// A reusable signing key
+///
+/// Keys are `Send + Sync` so one key can be shared between threads (e.g. in an `Arc`)
+/// while every message gets a fresh [`Sign`] from [`SigningKey::signer`].
+pub trait SigningKey: Send + Sync {
+    type Signer<'a>: Sign
+    where
+        Self: 'a;
+
+    /// The `alg` header value of signatures made with this key
+    fn algorithm(&self) -> &str;
+
+    fn signer(&self) -> Result<Self::Signer<'_>>;
+}
+
+/// A reusable verification key
+///
+/// Keys are `Send + Sync` so one key can be shared between threads (e.g. in an `Arc`)
+/// while every message gets a fresh [`Verify`] from [`VerificationKey::verifier`].
+pub trait VerificationKey: Send + Sync {
+    type Verifier<'a>: Verify
+    where
+        Self: 'a;
+
+    /// The only `alg` header value accepted by this key
+    fn algorithm(&self) -> &str;
+
+    fn verifier(&self) -> Result<Self::Verifier<'_>>;
+}
+
+/// Finds the [`VerificationKey`] for a protected header, e.g. by its `kid`
+///
+/// Implemented for closures `Fn(&JwsHeader) -> Option<Arc<K>>`.
+pub trait KeyResolver: Send + Sync {
+    type Key: VerificationKey;
+
+    fn resolve(&self, header: &JwsHeader) -> Result<Arc<Self::Key>>;
+}
+
+impl<F, K> KeyResolver for F
+where
+    F: Fn(&JwsHeader) -> Option<Arc<K>> + Send + Sync,
+    K: VerificationKey,
+{
+    type Key = K;
+
+    fn resolve(&self, header: &JwsHeader) -> Result<Arc<K>> {
+        self(header).ok_or_else(|| anyhow::anyhow!("verification key is not found"))
+    }
+}
@@ -1,9 +1,23 @@
 //! [openssl](https://crates.io/crates/openssl) implementations for [`Verify`] and [`Sign`]
 
-use anyhow::Result;
-use openssl::sign::{Signer, Verifier};
+use anyhow::{bail, Context, Result};
+use openssl::{
+    bn::BigNum,
+    ec::{EcGroup, EcKey},
+    ecdsa::EcdsaSig,
+    hash::MessageDigest,
+    md::Md,
+    memcmp,
+    nid::Nid,
+    pkey::{HasPublic, Id, PKey, PKeyRef, Private, Public},
+    pkey_ctx::PkeyCtx,
+    rsa::{Padding, Rsa},
+    sign::{RsaPssSaltlen, Signer, Verifier},
+};
+use serde_json::Value;
+use std::io::Write;
 
-use crate::{Sign, Verify};
+use crate::{FailureReason, JwsHeader, Sign, SigningKey, VerificationKey, Verify};
 
 impl<'a> Verify for Verifier<'a> {
     fn verify(&self, signature: &[u8]) -> Result<bool> {
@@ -16,3 +30,464 @@ impl<'a> Sign for Signer<'a> {
         Ok(self.sign_to_vec()?)
     }
 }
+
+/// Smallest RSA key RFC 7518 allows for `RS*` and `PS*`
+pub(crate) const MIN_RSA_BITS: usize = 2048;
+
+/// Parameters of a JWS `alg` value
+#[derive(Clone, Copy)]
+enum Algorithm {
+    Hmac(MessageDigest),
+    Rsa(MessageDigest, Padding),
+    /// Digest and the byte length of each of `r` and `s`
+    Ecdsa(MessageDigest, usize),
+}
+
+impl Algorithm {
+    fn from_name(name: &str) -> Result<Self> {
+        Ok(match name {
+            "HS256" => Algorithm::Hmac(MessageDigest::sha256()),
+            "HS384" => Algorithm::Hmac(MessageDigest::sha384()),
+            "HS512" => Algorithm::Hmac(MessageDigest::sha512()),
+            "RS256" => Algorithm::Rsa(MessageDigest::sha256(), Padding::PKCS1),
+            "RS384" => Algorithm::Rsa(MessageDigest::sha384(), Padding::PKCS1),
+            "RS512" => Algorithm::Rsa(MessageDigest::sha512(), Padding::PKCS1),
+            "PS256" => Algorithm::Rsa(MessageDigest::sha256(), Padding::PKCS1_PSS),
+            "PS384" => Algorithm::Rsa(MessageDigest::sha384(), Padding::PKCS1_PSS),
+            "PS512" => Algorithm::Rsa(MessageDigest::sha512(), Padding::PKCS1_PSS),
+            "ES256" => Algorithm::Ecdsa(MessageDigest::sha256(), 32),
+            "ES384" => Algorithm::Ecdsa(MessageDigest::sha384(), 48),
+            "ES512" => Algorithm::Ecdsa(MessageDigest::sha512(), 66),
+            _ => bail!("unsupported algorithm `{}`", name),
+        })
+    }
+
+    fn digest(&self) -> MessageDigest {
+        match *self {
+            Algorithm::Hmac(digest) | Algorithm::Rsa(digest, _) | Algorithm::Ecdsa(digest, _) => {
+                digest
+            }
+        }
+    }
+
+    /// Check that `key` is of the type and, for ECDSA, on the curve required by `name`
+    ///
+    /// RSA keys must have at least [`MIN_RSA_BITS`] bits.
+    fn check_key<T: HasPublic>(&self, name: &str, key: &PKeyRef<T>) -> Result<()> {
+        let suits = match *self {
+            Algorithm::Hmac(_) => false,
+            Algorithm::Rsa(..) => {
+                let rsa = matches!(key.id(), Id::RSA | Id::RSA_PSS);
+                if rsa && (key.bits() as usize) < MIN_RSA_BITS {
+                    bail!("RSA key is shorter than {} bits", MIN_RSA_BITS)
+                }
+                rsa
+            }
+            Algorithm::Ecdsa(_, len) => {
+                let curve = match len {
+                    32 => Nid::X9_62_PRIME256V1,
+                    48 => Nid::SECP384R1,
+                    _ => Nid::SECP521R1,
+                };
+                key.id() == Id::EC && key.ec_key()?.group().curve_name() == Some(curve)
+            }
+        };
+        if !suits {
+            bail!("key type does not suit `{}`", name)
+        }
+        Ok(())
+    }
+
+    /// Check that an `HS*` secret is at least as long as the hash output, as RFC 7518 §3.2
+    /// requires
+    fn check_secret(&self, name: &str, secret: &[u8]) -> Result<()> {
+        let len = self.digest().size();
+        if secret.len() < len {
+            bail!("`{}` secret must be at least {} bytes", name, len)
+        }
+        Ok(())
+    }
+
+    /// Convert an openssl signature to the JWS form
+    fn jws_signature(&self, signature: Vec<u8>) -> Result<Vec<u8>> {
+        match *self {
+            Algorithm::Ecdsa(_, len) => {
+                let signature = EcdsaSig::from_der(&signature)?;
+                let mut raw = signature.r().to_vec_padded(len as i32)?;
+                raw.extend(signature.s().to_vec_padded(len as i32)?);
+                Ok(raw)
+            }
+            _ => Ok(signature),
+        }
+    }
+}
+
+/// The hash function of a JWS `alg` value
+pub(crate) fn message_digest(algorithm: &str) -> Result<MessageDigest> {
+    Ok(Algorithm::from_name(algorithm)?.digest())
+}
+
+/// An openssl private or secret key bound to a JWS algorithm
+///
+/// Implements [`SigningKey`], so one key can be shared between threads and
+/// spawns a fresh [`OpensslSigner`] per message.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+/// use openssl::{pkey::PKey, rsa::Rsa};
+/// use serde_json::Map;
+/// use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+///
+/// let keypair = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+///
+/// let signing_key = Arc::new(OpensslSigningKey::new("PS256", keypair.clone()).unwrap());
+/// let verification_key = OpensslVerificationKey::new("PS256", &keypair).unwrap();
+///
+/// let payload = vec![0, 1, 2, 3, 4, 5, 6];
+///
+/// let jws = detached_jws::serialize_with_key(
+///     Map::new(),
+///     &mut payload.as_slice(),
+///     signing_key.as_ref(),
+/// )
+/// .unwrap();
+///
+/// detached_jws::deserialize_with_key(&jws, &mut payload.as_slice(), &verification_key).unwrap();
+/// ```
+pub struct OpensslSigningKey {
+    name: String,
+    algorithm: Algorithm,
+    key: PKey<Private>,
+}
+
+impl OpensslSigningKey {
+    /// Bind a private key to an `RS*`, `PS*` or `ES*` algorithm
+    ///
+    /// Fails unless the key type, and for `ES*` the curve, suits the algorithm, and for
+    /// `RS*` and `PS*` unless the key has at least 2048 bits.
+    pub fn new(algorithm: &str, key: PKey<Private>) -> Result<Self> {
+        let params = Algorithm::from_name(algorithm)?;
+        if let Algorithm::Hmac(_) = params {
+            bail!("use OpensslSigningKey::hmac for `{}`", algorithm)
+        }
+        params.check_key(algorithm, &key)?;
+
+        Ok(Self {
+            name: algorithm.to_owned(),
+            algorithm: params,
+            key,
+        })
+    }
+
+    /// Create an `HS*` key from a shared secret
+    ///
+    /// The secret must be at least as long as the hash output, e.g. 32 bytes for `HS256`.
+    pub fn hmac(algorithm: &str, secret: &[u8]) -> Result<Self> {
+        let params = Algorithm::from_name(algorithm)?;
+        if !matches!(params, Algorithm::Hmac(_)) {
+            bail!("`{}` is not an HMAC algorithm", algorithm)
+        }
+        params.check_secret(algorithm, secret)?;
+
+        Ok(Self {
+            name: algorithm.to_owned(),
+            algorithm: params,
+            key: PKey::hmac(secret)?,
+        })
+    }
+
+    /// Sign the precomputed digest of a signing input, not possible with `HS*` keys
+    pub(crate) fn sign_digest(&self, digest: &[u8]) -> Result<Vec<u8>> {
+        if digest.len() != self.algorithm.digest().size() {
+            bail!(
+                "`{}` digest must be {} bytes",
+                self.name,
+                self.algorithm.digest().size()
+            )
+        }
+
+        let md = Md::from_nid(self.algorithm.digest().type_()).context("unsupported digest")?;
+
+        let mut ctx = PkeyCtx::new(&self.key)?;
+        ctx.sign_init()?;
+        ctx.set_signature_md(md)?;
+        match self.algorithm {
+            Algorithm::Hmac(_) => bail!("`{}` can not sign a digest", self.name),
+            Algorithm::Rsa(_, padding) => {
+                ctx.set_rsa_padding(padding)?;
+                if padding == Padding::PKCS1_PSS {
+                    ctx.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+                }
+            }
+            Algorithm::Ecdsa(..) => {}
+        }
+
+        let mut signature = Vec::new();
+        ctx.sign_to_vec(digest, &mut signature)?;
+
+        self.algorithm.jws_signature(signature)
+    }
+}
+
+impl SigningKey for OpensslSigningKey {
+    type Signer<'a> = OpensslSigner<'a>;
+
+    fn algorithm(&self) -> &str {
+        &self.name
+    }
+
+    fn signer(&self) -> Result<OpensslSigner<'_>> {
+        let mut signer = Signer::new(self.algorithm.digest(), &self.key)?;
+        if let Algorithm::Rsa(_, padding) = self.algorithm {
+            signer.set_rsa_padding(padding)?;
+            if padding == Padding::PKCS1_PSS {
+                signer.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+            }
+        }
+
+        Ok(OpensslSigner {
+            inner: signer,
+            algorithm: self.algorithm,
+        })
+    }
+}
+
+/// A per-message [`Sign`] created by [`OpensslSigningKey`]
+///
+/// ECDSA signatures are converted from DER to the fixed size `r || s` form required by JWS.
+pub struct OpensslSigner<'a> {
+    inner: Signer<'a>,
+    algorithm: Algorithm,
+}
+
+impl<'a> Sign for OpensslSigner<'a> {
+    fn get_sign(&self) -> Result<Vec<u8>> {
+        self.algorithm.jws_signature(self.inner.sign_to_vec()?)
+    }
+}
+
+impl<'a> Write for OpensslSigner<'a> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+enum VerificationMaterial {
+    Public(PKey<Public>),
+    Secret(PKey<Private>),
+}
+
+/// An openssl public or secret key bound to a JWS algorithm
+///
+/// Implements [`VerificationKey`], so one key can be shared between threads and
+/// spawns a fresh [`OpensslVerifier`] per message.
+pub struct OpensslVerificationKey {
+    name: String,
+    algorithm: Algorithm,
+    key: VerificationMaterial,
+}
+
+impl OpensslVerificationKey {
+    /// Bind the public part of a key to an `RS*`, `PS*` or `ES*` algorithm
+    ///
+    /// Fails unless the key type, and for `ES*` the curve, suits the algorithm, and for
+    /// `RS*` and `PS*` unless the key has at least 2048 bits.
+    pub fn new<T: HasPublic>(algorithm: &str, key: &PKeyRef<T>) -> Result<Self> {
+        let params = Algorithm::from_name(algorithm)?;
+        if let Algorithm::Hmac(_) = params {
+            bail!("use OpensslVerificationKey::hmac for `{}`", algorithm)
+        }
+        params.check_key(algorithm, key)?;
+
+        Ok(Self {
+            name: algorithm.to_owned(),
+            algorithm: params,
+            key: VerificationMaterial::Public(PKey::public_key_from_der(
+                &key.public_key_to_der()?,
+            )?),
+        })
+    }
+
+    /// Create an `HS*` key from a shared secret
+    ///
+    /// The secret must be at least as long as the hash output, e.g. 32 bytes for `HS256`.
+    pub fn hmac(algorithm: &str, secret: &[u8]) -> Result<Self> {
+        let params = Algorithm::from_name(algorithm)?;
+        if !matches!(params, Algorithm::Hmac(_)) {
+            bail!("`{}` is not an HMAC algorithm", algorithm)
+        }
+        params.check_secret(algorithm, secret)?;
+
+        Ok(Self {
+            name: algorithm.to_owned(),
+            algorithm: params,
+            key: VerificationMaterial::Secret(PKey::hmac(secret)?),
+        })
+    }
+
+    /// Create a key for `algorithm` from a JSON Web Key ([RFC 7517](https://tools.ietf.org/html/rfc7517))
+    ///
+    /// Supports `RSA`, `EC` (`P-256`, `P-384`, `P-521`) and `oct` keys; the key type must suit
+    /// the algorithm and the `alg` member, when present, must equal it.
+    pub fn from_jwk(algorithm: &str, jwk: &JwsHeader) -> Result<Self> {
+        if let Some(alg) = jwk.get("alg") {
+            if alg != algorithm {
+                bail!("JWK is for `{}`, not `{}`", alg, algorithm)
+            }
+        }
+
+        let member = |name: &str| -> Result<Vec<u8>> {
+            let value = jwk
+                .get(name)
+                .and_then(Value::as_str)
+                .with_context(|| format!("JWK has no `{}`", name))?;
+            base64::decode_config(value, base64::URL_SAFE_NO_PAD)
+                .with_context(|| format!("wrong JWK `{}` format", name))
+        };
+
+        match (
+            jwk.get("kty").and_then(Value::as_str),
+            Algorithm::from_name(algorithm)?,
+        ) {
+            (Some("oct"), Algorithm::Hmac(_)) => Self::hmac(algorithm, &member("k")?),
+            (Some("RSA"), Algorithm::Rsa(..)) => {
+                let rsa = Rsa::from_public_components(
+                    BigNum::from_slice(&member("n")?)?,
+                    BigNum::from_slice(&member("e")?)?,
+                )?;
+                let key = PKey::from_rsa(rsa)?;
+                Self::new(algorithm, &key)
+            }
+            (Some("EC"), Algorithm::Ecdsa(_, len)) => {
+                let curve = match (jwk.get("crv").and_then(Value::as_str), len) {
+                    (Some("P-256"), 32) => Nid::X9_62_PRIME256V1,
+                    (Some("P-384"), 48) => Nid::SECP384R1,
+                    (Some("P-521"), 66) => Nid::SECP521R1,
+                    (crv, _) => bail!("JWK curve {:?} does not suit `{}`", crv, algorithm),
+                };
+                let group = EcGroup::from_curve_name(curve)?;
+                let x = BigNum::from_slice(&member("x")?)?;
+                let y = BigNum::from_slice(&member("y")?)?;
+                let key =
+                    PKey::from_ec_key(EcKey::from_public_key_affine_coordinates(&group, &x, &y)?)?;
+                Self::new(algorithm, &key)
+            }
+            (kty, _) => bail!("JWK type {:?} does not suit `{}`", kty, algorithm),
+        }
+    }
+}
+
+impl VerificationKey for OpensslVerificationKey {
+    type Verifier<'a> = OpensslVerifier<'a>;
+
+    fn algorithm(&self) -> &str {
+        &self.name
+    }
+
+    fn verifier(&self) -> Result<OpensslVerifier<'_>> {
+        let digest = self.algorithm.digest();
+
+        let inner = match self.key {
+            VerificationMaterial::Secret(ref key) => {
+                OpensslVerifierInner::Hmac(Signer::new(digest, key)?)
+            }
+            VerificationMaterial::Public(ref key) => {
+                let mut verifier = Verifier::new(digest, key)?;
+                if let Algorithm::Rsa(_, padding) = self.algorithm {
+                    verifier.set_rsa_padding(padding)?;
+                    if padding == Padding::PKCS1_PSS {
+                        verifier.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+                    }
+                }
+                OpensslVerifierInner::Verifier(verifier)
+            }
+        };
+
+        let signature_len = match (&self.key, self.algorithm) {
+            (_, Algorithm::Hmac(digest)) => digest.size(),
+            (_, Algorithm::Ecdsa(_, len)) => 2 * len,
+            (VerificationMaterial::Public(key), _) => key.size(),
+            (VerificationMaterial::Secret(key), _) => key.size(),
+        };
+
+        Ok(OpensslVerifier {
+            inner,
+            algorithm: self.algorithm,
+            signature_len,
+        })
+    }
+}
+
+enum OpensslVerifierInner<'a> {
+    Verifier(Verifier<'a>),
+    Hmac(Signer<'a>),
+}
+
+/// A per-message [`Verify`] created by [`OpensslVerificationKey`]
+pub struct OpensslVerifier<'a> {
+    inner: OpensslVerifierInner<'a>,
+    algorithm: Algorithm,
+    signature_len: usize,
+}
+
+impl<'a> Verify for OpensslVerifier<'a> {
+    fn verify(&self, signature: &[u8]) -> Result<bool> {
+        match self.check(signature) {
+            Ok(()) => Ok(true),
+            Err(FailureReason::Backend(e)) => Err(e),
+            Err(_) => Ok(false),
+        }
+    }
+
+    fn check(&self, signature: &[u8]) -> std::result::Result<(), FailureReason> {
+        if signature.len() != self.signature_len {
+            return Err(FailureReason::WrongLength {
+                expected: self.signature_len,
+                actual: signature.len(),
+            });
+        }
+
+        let verified = match (&self.inner, self.algorithm) {
+            (OpensslVerifierInner::Hmac(signer), _) => {
+                // large enough for HS512, keeping the check off the heap
+                let mut expected = [0; 64];
+                signer
+                    .sign(&mut expected)
+                    .map(|len| memcmp::eq(&expected[..len], signature))
+            }
+            (OpensslVerifierInner::Verifier(verifier), Algorithm::Ecdsa(_, len)) => {
+                BigNum::from_slice(&signature[..len])
+                    .and_then(|r| Ok((r, BigNum::from_slice(&signature[len..])?)))
+                    .and_then(|(r, s)| EcdsaSig::from_private_components(r, s))
+                    .and_then(|signature| signature.to_der())
+                    .and_then(|der| verifier.verify(&der))
+            }
+            (OpensslVerifierInner::Verifier(verifier), _) => verifier.verify(signature),
+        };
+
+        match verified {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(FailureReason::Mismatch),
+            Err(e) => Err(FailureReason::Backend(e.into())),
+        }
+    }
+}
+
+impl<'a> Write for OpensslVerifier<'a> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.inner {
+            OpensslVerifierInner::Verifier(ref mut verifier) => verifier.write(buf),
+            OpensslVerifierInner::Hmac(ref mut signer) => signer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
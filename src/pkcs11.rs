@@ -0,0 +1,289 @@
+//! [PKCS#11](https://crates.io/crates/cryptoki) implementation of [`SigningKey`] for keys kept in an HSM
+//!
+//! The signing input is hashed locally with openssl and only the digest is sent to the token,
+//! so a streamed payload never holds the session. Supports `RS*` (`CKM_RSA_PKCS`),
+//! `PS*` (`CKM_RSA_PKCS_PSS`) and `ES*` (`CKM_ECDSA`).
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use cryptoki::context::{CInitializeArgs, CInitializeFlags, Pkcs11};
+//! use cryptoki::session::UserType;
+//! use cryptoki::types::AuthPin;
+//! use detached_jws::pkcs11::Pkcs11SigningKey;
+//! use serde_json::Map;
+//!
+//! let pkcs11 = Pkcs11::new("/usr/lib/softhsm/libsofthsm2.so").unwrap();
+//! pkcs11
+//!     .initialize(CInitializeArgs::new(CInitializeFlags::OS_LOCKING_OK))
+//!     .unwrap();
+//!
+//! let slot = pkcs11.get_slots_with_token().unwrap()[0];
+//! let session = pkcs11.open_ro_session(slot).unwrap();
+//! session
+//!     .login(UserType::User, Some(&AuthPin::new("1234".into())))
+//!     .unwrap();
+//!
+//! let key = Pkcs11SigningKey::from_label("PS256", session, "signing-key").unwrap();
+//!
+//! let jws = detached_jws::serialize_with_key(Map::new(), &mut &b"payload"[..], &key).unwrap();
+//! ```
+
+use anyhow::{bail, Context, Result};
+use cryptoki::{
+    mechanism::{
+        rsa::{PkcsMgfType, PkcsPssParams},
+        Mechanism, MechanismType,
+    },
+    object::{Attribute, AttributeType, KeyType, ObjectClass, ObjectHandle},
+    session::Session,
+    types::Ulong,
+};
+use openssl::hash::{Hasher, MessageDigest};
+use std::{convert::TryFrom, io::Write, sync::Mutex};
+
+use crate::{openssl::MIN_RSA_BITS, Sign, SigningKey};
+
+/// DER encoded `DigestInfo` prefixes prepended to the digest for `CKM_RSA_PKCS`
+static SHA256_DIGEST_INFO: &[u8] = &[
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+static SHA384_DIGEST_INFO: &[u8] = &[
+    0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02, 0x05,
+    0x00, 0x04, 0x30,
+];
+static SHA512_DIGEST_INFO: &[u8] = &[
+    0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03, 0x05,
+    0x00, 0x04, 0x40,
+];
+
+/// DER encoded curve OIDs found in `CKA_EC_PARAMS`
+static P256_PARAMS: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+static P384_PARAMS: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22];
+static P521_PARAMS: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x23];
+
+/// Parameters of a JWS `alg` value
+#[derive(Clone, Copy)]
+enum Algorithm {
+    Pkcs1(MessageDigest, &'static [u8]),
+    Pss(MessageDigest, MechanismType, PkcsMgfType),
+    /// Digest and the byte length of each of `r` and `s`
+    Ecdsa(MessageDigest, usize),
+}
+
+impl Algorithm {
+    fn from_name(name: &str) -> Result<Self> {
+        Ok(match name {
+            "RS256" => Algorithm::Pkcs1(MessageDigest::sha256(), SHA256_DIGEST_INFO),
+            "RS384" => Algorithm::Pkcs1(MessageDigest::sha384(), SHA384_DIGEST_INFO),
+            "RS512" => Algorithm::Pkcs1(MessageDigest::sha512(), SHA512_DIGEST_INFO),
+            "PS256" => Algorithm::Pss(
+                MessageDigest::sha256(),
+                MechanismType::SHA256,
+                PkcsMgfType::MGF1_SHA256,
+            ),
+            "PS384" => Algorithm::Pss(
+                MessageDigest::sha384(),
+                MechanismType::SHA384,
+                PkcsMgfType::MGF1_SHA384,
+            ),
+            "PS512" => Algorithm::Pss(
+                MessageDigest::sha512(),
+                MechanismType::SHA512,
+                PkcsMgfType::MGF1_SHA512,
+            ),
+            "ES256" => Algorithm::Ecdsa(MessageDigest::sha256(), 32),
+            "ES384" => Algorithm::Ecdsa(MessageDigest::sha384(), 48),
+            "ES512" => Algorithm::Ecdsa(MessageDigest::sha512(), 66),
+            _ => bail!("unsupported algorithm `{}`", name),
+        })
+    }
+
+    fn digest(&self) -> MessageDigest {
+        match *self {
+            Algorithm::Pkcs1(digest, _)
+            | Algorithm::Pss(digest, ..)
+            | Algorithm::Ecdsa(digest, _) => digest,
+        }
+    }
+
+    /// Check that `key` is of the type and, for ECDSA, on the curve required by `name`
+    ///
+    /// RSA keys must have at least [`MIN_RSA_BITS`] bits, read from `CKA_MODULUS_BITS` or
+    /// `CKA_MODULUS`; a key whose size can not be read is rejected.
+    fn check_key(&self, name: &str, session: &Session, key: ObjectHandle) -> Result<()> {
+        let attributes = session.get_attributes(
+            key,
+            &[
+                AttributeType::KeyType,
+                AttributeType::EcParams,
+                AttributeType::ModulusBits,
+                AttributeType::Modulus,
+            ],
+        )?;
+
+        let mut key_type = None;
+        let mut ec_params = None;
+        let mut bits = None;
+        for attribute in &attributes {
+            match attribute {
+                Attribute::KeyType(value) => key_type = Some(*value),
+                Attribute::EcParams(params) => ec_params = Some(params.as_slice()),
+                Attribute::ModulusBits(value) => bits = Some((*value).into()),
+                Attribute::Modulus(modulus) if bits.is_none() => bits = Some(modulus_bits(modulus)),
+                _ => {}
+            }
+        }
+
+        let suits = match *self {
+            Algorithm::Pkcs1(..) | Algorithm::Pss(..) => {
+                if key_type == Some(KeyType::RSA) {
+                    match bits {
+                        Some(bits) if bits >= MIN_RSA_BITS => {}
+                        Some(_) => bail!("RSA key is shorter than {} bits", MIN_RSA_BITS),
+                        None => bail!("RSA key size can not be read"),
+                    }
+                }
+                key_type == Some(KeyType::RSA)
+            }
+            Algorithm::Ecdsa(_, len) => {
+                let curve = match len {
+                    32 => P256_PARAMS,
+                    48 => P384_PARAMS,
+                    _ => P521_PARAMS,
+                };
+                key_type == Some(KeyType::EC) && ec_params == Some(curve)
+            }
+        };
+        if !suits {
+            bail!("key type does not suit `{}`", name)
+        }
+        Ok(())
+    }
+}
+
+/// Bit length of a big-endian `CKA_MODULUS`
+fn modulus_bits(modulus: &[u8]) -> usize {
+    match modulus.iter().position(|b| *b != 0) {
+        Some(first) => (modulus.len() - first) * 8 - modulus[first].leading_zeros() as usize,
+        None => 0,
+    }
+}
+
+/// A private key on a PKCS#11 token bound to a JWS algorithm
+///
+/// The session must be logged in. It is shared by all signers of the key and locked
+/// only while the token signs a digest.
+pub struct Pkcs11SigningKey {
+    name: String,
+    algorithm: Algorithm,
+    session: Mutex<Session>,
+    key: ObjectHandle,
+}
+
+impl Pkcs11SigningKey {
+    /// Bind the private key `key` to an `RS*`, `PS*` or `ES*` algorithm
+    ///
+    /// The key is rejected unless its `CKA_KEY_TYPE`, and `CKA_EC_PARAMS` for `ES*`, suit the
+    /// algorithm.
+    pub fn new(algorithm: &str, session: Session, key: ObjectHandle) -> Result<Self> {
+        let name = algorithm;
+        let algorithm = Algorithm::from_name(name)?;
+        algorithm.check_key(name, &session, key)?;
+
+        Ok(Self {
+            name: name.to_owned(),
+            algorithm,
+            session: Mutex::new(session),
+            key,
+        })
+    }
+
+    /// Find the private key by its `CKA_LABEL`
+    pub fn from_label(algorithm: &str, session: Session, label: &str) -> Result<Self> {
+        let key = session
+            .find_objects(&[
+                Attribute::Class(ObjectClass::PRIVATE_KEY),
+                Attribute::Label(label.as_bytes().to_vec()),
+            ])?
+            .into_iter()
+            .next()
+            .with_context(|| format!("private key `{}` is not found", label))?;
+
+        Self::new(algorithm, session, key)
+    }
+
+    fn sign_digest(&self, digest: &[u8]) -> Result<Vec<u8>> {
+        let session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+
+        match self.algorithm {
+            Algorithm::Pkcs1(_, digest_info) => {
+                let mut data = digest_info.to_vec();
+                data.extend_from_slice(digest);
+                Ok(session.sign(&Mechanism::RsaPkcs, self.key, &data)?)
+            }
+            Algorithm::Pss(_, hash_alg, mgf) => {
+                let params = PkcsPssParams {
+                    hash_alg,
+                    mgf,
+                    s_len: Ulong::try_from(digest.len())?,
+                };
+                Ok(session.sign(&Mechanism::RsaPkcsPss(params), self.key, digest)?)
+            }
+            Algorithm::Ecdsa(_, len) => {
+                let signature = session.sign(&Mechanism::Ecdsa, self.key, digest)?;
+                ecdsa_signature(signature, len)
+            }
+        }
+    }
+}
+
+/// Check that a PKCS#11 `r || s` signature has the fixed size required by JWS
+///
+/// `CKM_ECDSA` pads `r` and `s` to the order of the curve, so any other length means the key
+/// is not on the curve of the algorithm.
+fn ecdsa_signature(signature: Vec<u8>, len: usize) -> Result<Vec<u8>> {
+    if signature.len() != 2 * len {
+        bail!("unexpected ECDSA signature length {}", signature.len())
+    }
+    Ok(signature)
+}
+
+impl SigningKey for Pkcs11SigningKey {
+    type Signer<'a> = Pkcs11Signer<'a>;
+
+    fn algorithm(&self) -> &str {
+        &self.name
+    }
+
+    fn signer(&self) -> Result<Pkcs11Signer<'_>> {
+        Ok(Pkcs11Signer {
+            key: self,
+            hasher: Hasher::new(self.algorithm.digest())?,
+        })
+    }
+}
+
+/// A per-message [`Sign`] created by [`Pkcs11SigningKey`]
+pub struct Pkcs11Signer<'a> {
+    key: &'a Pkcs11SigningKey,
+    hasher: Hasher,
+}
+
+impl<'a> Sign for Pkcs11Signer<'a> {
+    fn get_sign(&self) -> Result<Vec<u8>> {
+        let digest = self.hasher.clone().finish()?;
+        self.key.sign_digest(&digest)
+    }
+}
+
+impl<'a> Write for Pkcs11Signer<'a> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.hasher.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.hasher.flush()
+    }
+}
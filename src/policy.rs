@@ -0,0 +1,224 @@
+//! Reusable verification settings for a detached jws
+//!
+//! A [`VerificationPolicy`] gathers what a partner's jws must satisfy besides a valid
+//! signature: the accepted algorithms, required header members and their values, the `crit`
+//! extensions understood, size limits and the clock leeway for time header members. It is
+//! checked against the protected header before the signature is verified.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(feature = "openssl")] {
+//! use std::time::{Duration, SystemTime, UNIX_EPOCH};
+//! use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+//! use detached_jws::{PolicyViolation, VerificationPolicy};
+//! use serde_json::{json, Map};
+//!
+//! let iat = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+//! let mut header = Map::new();
+//! header.insert("typ".to_owned(), json!("JOSE"));
+//! header.insert("iss".to_owned(), json!("partner"));
+//! header.insert("iat".to_owned(), json!(iat));
+//! header.insert("crit".to_owned(), json!(["iat"]));
+//!
+//! let key = OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+//! let jws = detached_jws::serialize_with_key(header, &mut &b"payload"[..], &key).unwrap();
+//!
+//! let policy = VerificationPolicy::new()
+//!     .algorithm("HS256")
+//!     .expect("typ", "JOSE")
+//!     .expect("iss", "partner")
+//!     .require("iat")
+//!     .critical("iat")
+//!     .leeway(Duration::from_secs(60));
+//!
+//! let key = OpensslVerificationKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+//! let header =
+//!     detached_jws::deserialize_with_policy(&jws, &mut &b"payload"[..], &key, &policy).unwrap();
+//! assert_eq!(header["iss"], "partner");
+//!
+//! let policy = policy.expect("iss", "another partner");
+//! let err = detached_jws::deserialize_with_policy(&jws, &mut &b"payload"[..], &key, &policy)
+//!     .unwrap_err();
+//! assert!(err.is::<PolicyViolation>());
+//! # }
+//! ```
+
+use serde_json::{Map, Value};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use crate::time::{check_numeric_dates, TimeViolation};
+use crate::{DecodeOptions, JwsHeader};
+
+/// What the protected header of a detached jws must satisfy
+///
+/// Each builder call narrows the policy. Even an empty one rejects `crit` extensions it
+/// does not accept and checks the `exp`, `nbf` and `iat` header members as NumericDates.
+#[derive(Debug, Clone, Default)]
+pub struct VerificationPolicy {
+    algorithms: Vec<String>,
+    required: Vec<String>,
+    expected: Map<String, Value>,
+    critical: Vec<String>,
+    options: DecodeOptions,
+    leeway: Duration,
+    time: Option<SystemTime>,
+}
+
+impl VerificationPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept `alg`; may be called for several algorithms
+    ///
+    /// Without any, the algorithm is only checked against the verification key.
+    pub fn algorithm(mut self, algorithm: impl Into<String>) -> Self {
+        self.algorithms.push(algorithm.into());
+        self
+    }
+
+    /// Require the header member `name` to be present
+    pub fn require(mut self, name: impl Into<String>) -> Self {
+        let name = name.into();
+        if !self.required.contains(&name) {
+            self.required.push(name);
+        }
+        self
+    }
+
+    /// Require the header member `name` to equal `value`, e.g. `typ` or `iss`
+    pub fn expect(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.expected.insert(name.into(), value.into());
+        self
+    }
+
+    /// Accept `name` in the `crit` header; a jws with any other `crit` entry is rejected
+    pub fn critical(mut self, name: impl Into<String>) -> Self {
+        self.critical.push(name.into());
+        self
+    }
+
+    /// Size limits of the encoded jws
+    pub fn options(mut self, options: DecodeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Tolerated clock skew for the `exp`, `nbf` and `iat` header members
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /// Check time header members as of `time` instead of the current time
+    pub fn time(mut self, time: SystemTime) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    pub(crate) fn decode_options(&self) -> &DecodeOptions {
+        &self.options
+    }
+
+    /// Check a protected header against the policy
+    pub fn check(&self, header: &JwsHeader) -> Result<(), PolicyViolation> {
+        if !self.algorithms.is_empty() {
+            match header.get("alg") {
+                Some(Value::String(alg)) if self.algorithms.contains(alg) => {}
+                Some(Value::String(alg)) => return Err(PolicyViolation::Algorithm(alg.clone())),
+                _ => return Err(PolicyViolation::Missing("alg".to_owned())),
+            }
+        }
+
+        for name in &self.required {
+            if !header.contains_key(name) {
+                return Err(PolicyViolation::Missing(name.clone()));
+            }
+        }
+        for (name, expected) in &self.expected {
+            match header.get(name) {
+                None => return Err(PolicyViolation::Missing(name.clone())),
+                Some(value) if value != expected => {
+                    return Err(PolicyViolation::Unexpected(name.clone()))
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(crit) = header.get("crit") {
+            let names = match crit {
+                Value::Array(names) if !names.is_empty() => names,
+                _ => return Err(PolicyViolation::Malformed("crit".to_owned())),
+            };
+            for name in names {
+                let name = name
+                    .as_str()
+                    .ok_or_else(|| PolicyViolation::Malformed("crit".to_owned()))?;
+                // RFC 7515 Section 4.1.11: listed extensions must be present and understood
+                if !self.critical.iter().any(|c| c == name) || !header.contains_key(name) {
+                    return Err(PolicyViolation::Critical(name.to_owned()));
+                }
+            }
+        }
+
+        self.check_time(header)
+    }
+
+    fn check_time(&self, header: &JwsHeader) -> Result<(), PolicyViolation> {
+        match check_numeric_dates(header, self.time, self.leeway, None) {
+            Ok(_) => Ok(()),
+            Err(TimeViolation::Malformed(name)) => Err(PolicyViolation::Malformed(name.to_owned())),
+            Err(TimeViolation::Expired) => Err(PolicyViolation::Expired),
+            Err(TimeViolation::NotYetValid) => Err(PolicyViolation::NotYetValid),
+            Err(TimeViolation::IssuedInFuture | TimeViolation::TooOld) => {
+                Err(PolicyViolation::IssuedInFuture)
+            }
+        }
+    }
+}
+
+/// The protected header does not satisfy a [`VerificationPolicy`]
+///
+/// Returned wrapped in [`anyhow::Error`] and can be recovered with `downcast_ref`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyViolation {
+    /// `alg` is not one of the accepted algorithms
+    Algorithm(String),
+    /// A required header member is absent
+    Missing(String),
+    /// A header member does not have the expected value
+    Unexpected(String),
+    /// A header member has the wrong JSON type
+    Malformed(String),
+    /// A `crit` extension is not accepted or not present
+    Critical(String),
+    /// The `exp` header member has passed
+    Expired,
+    /// The `nbf` header member has not been reached
+    NotYetValid,
+    /// The `iat` header member is in the future
+    IssuedInFuture,
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyViolation::Algorithm(alg) => write!(f, "algorithm `{}` is not accepted", alg),
+            PolicyViolation::Missing(name) => write!(f, "jws header `{}` is missing", name),
+            PolicyViolation::Unexpected(name) => {
+                write!(f, "jws header `{}` has an unexpected value", name)
+            }
+            PolicyViolation::Malformed(name) => write!(f, "wrong jws header `{}` format", name),
+            PolicyViolation::Critical(name) => {
+                write!(f, "critical jws header `{}` is not supported", name)
+            }
+            PolicyViolation::Expired => f.write_str("jws has expired"),
+            PolicyViolation::NotYetValid => f.write_str("jws is not valid yet"),
+            PolicyViolation::IssuedInFuture => f.write_str("jws is issued in the future"),
+        }
+    }
+}
+
+impl std::error::Error for PolicyViolation {}
@@ -0,0 +1,250 @@
+//! Signing with remote services such as a KMS or an out-of-process HSM
+//!
+//! A [`RemoteSigner`] never sees the streamed payload: the crate hashes the JWS signing input
+//! itself and hands over the digest (or the whole input while it is short enough) to be signed
+//! asynchronously, e.g. by an RPC.
+//!
+//! # Examples
+//!
+//! ```
+//! use detached_jws::remote::{self, InMemoryKms};
+//! use detached_jws::openssl::OpensslVerificationKey;
+//! use openssl::{pkey::PKey, rsa::Rsa};
+//! use serde_json::Map;
+//!
+//! let keypair = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+//! let kms = InMemoryKms::new("PS256", keypair.clone()).unwrap();
+//!
+//! let payload = vec![0, 1, 2, 3, 4, 5, 6];
+//!
+//! # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+//! let jws = remote::serialize_remote(Map::new(), &mut payload.as_slice(), &kms)
+//!     .await
+//!     .unwrap();
+//! # let key = OpensslVerificationKey::new("PS256", &keypair).unwrap();
+//! # detached_jws::deserialize_with_key(&jws, &mut payload.as_slice(), &key).unwrap();
+//! # });
+//! ```
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use openssl::hash::Hasher;
+use openssl::pkey::{PKey, Private};
+use std::io::{Read, Write};
+
+use crate::{
+    chunked::ChunkedEncoder,
+    encode::encode_header,
+    openssl::{message_digest, OpensslSigningKey},
+    JwsHeader, Sign, SigningKey,
+};
+
+static DOT_ARRAY: &[u8] = ".".as_bytes();
+
+/// What a [`RemoteSigner`] is asked to sign
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigningInput<'a> {
+    /// The whole JWS signing input, when not longer than [`RemoteSigner::max_message_len`]
+    Message(&'a [u8]),
+    /// The digest of the JWS signing input by the hash function of the `alg`,
+    /// e.g. SHA-256 for `PS256`
+    Digest(&'a [u8]),
+}
+
+/// A signer living outside the process, e.g. a KMS key
+///
+/// Asymmetric signers usually accept a [`SigningInput::Digest`]; `HS*` signers need the whole
+/// input and have to raise [`max_message_len`](Self::max_message_len) accordingly, otherwise
+/// [`RemoteSerializeJwsWriter::new`] fails.
+///
+/// Implementations use the [`async_trait`] attribute, which keeps the trait usable as
+/// `dyn RemoteSigner`.
+#[async_trait]
+pub trait RemoteSigner: Send + Sync {
+    /// The `alg` header value of signatures made by this signer
+    fn algorithm(&self) -> &str;
+
+    /// Longest signing input sent as [`SigningInput::Message`], zero by default
+    fn max_message_len(&self) -> usize {
+        0
+    }
+
+    /// Sign `input`, returning the signature in its JWS form (`r || s` for ECDSA)
+    async fn sign(&self, input: SigningInput<'_>) -> Result<Vec<u8>>;
+}
+
+/// Serialize to detached jws signed by a [`RemoteSigner`]
+pub async fn serialize_remote(
+    header: JwsHeader,
+    payload: &mut impl Read,
+    signer: &(impl RemoteSigner + ?Sized),
+) -> Result<Vec<u8>> {
+    let mut writer = RemoteSerializeJwsWriter::new(Vec::new(), header, signer)?;
+    std::io::copy(payload, &mut writer)?;
+    writer.finish().await
+}
+
+/// A `Write` implementation serialize to detached jws signed by a [`RemoteSigner`]
+///
+/// Written payload is only hashed; the signer is called once by [`finish`](Self::finish).
+pub struct RemoteSerializeJwsWriter<'s, W, R: ?Sized> {
+    delegate: W,
+    signer: &'s R,
+    encoder: ChunkedEncoder<InputHasher>,
+}
+
+impl<'s, W, R> RemoteSerializeJwsWriter<'s, W, R>
+where
+    W: Write,
+    R: RemoteSigner + ?Sized,
+{
+    /// Fails for an `HS*` signer without a [`max_message_len`](RemoteSigner::max_message_len),
+    /// as a digest cannot be signed with HMAC
+    pub fn new(mut writer: W, header: JwsHeader, signer: &'s R) -> Result<Self> {
+        if is_hmac(signer.algorithm()) && signer.max_message_len() == 0 {
+            bail!(
+                "remote `{}` signer must accept whole messages",
+                signer.algorithm()
+            )
+        }
+
+        let encoded_header = encode_header(signer.algorithm().to_owned(), header)?;
+
+        let mut input = InputHasher {
+            hasher: Hasher::new(message_digest(signer.algorithm())?)?,
+            message: Some(Vec::new()),
+            max_message_len: signer.max_message_len(),
+        };
+        input.write_all(&encoded_header)?;
+        input.write_all(DOT_ARRAY)?;
+
+        writer.write_all(&encoded_header)?;
+        writer.write_all(DOT_ARRAY)?;
+        writer.write_all(DOT_ARRAY)?;
+
+        Ok(Self {
+            delegate: writer,
+            signer,
+            encoder: ChunkedEncoder::new(input),
+        })
+    }
+
+    pub async fn finish(mut self) -> Result<W> {
+        let mut input = self.encoder.finish()?;
+        let digest = input.hasher.finish()?;
+
+        let signature = match input.message {
+            Some(ref message) => self.signer.sign(SigningInput::Message(message)).await?,
+            None if is_hmac(self.signer.algorithm()) => bail!(
+                "signing input exceeds {} bytes of the remote `{}` signer",
+                self.signer.max_message_len(),
+                self.signer.algorithm()
+            ),
+            None => self.signer.sign(SigningInput::Digest(&digest)).await?,
+        };
+
+        self.delegate
+            .write_all(base64::encode_config(signature, base64::URL_SAFE_NO_PAD).as_bytes())?;
+
+        Ok(self.delegate)
+    }
+}
+
+impl<'s, W, R: ?Sized> Write for RemoteSerializeJwsWriter<'s, W, R> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.encoder.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.encoder.flush()
+    }
+}
+
+fn is_hmac(algorithm: &str) -> bool {
+    algorithm.starts_with("HS")
+}
+
+/// Hashes the signing input, keeping it whole while it is short enough
+struct InputHasher {
+    hasher: Hasher,
+    message: Option<Vec<u8>>,
+    max_message_len: usize,
+}
+
+impl Write for InputHasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.hasher.update(buf)?;
+
+        if let Some(ref mut message) = self.message {
+            if message.len() + buf.len() > self.max_message_len {
+                self.message = None;
+            } else {
+                message.extend_from_slice(buf);
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A fake KMS signing with an in-memory openssl key, meant for tests
+///
+/// Accepts digests for `RS*`, `PS*` and `ES*` keys and whole messages up to
+/// [`max_message_len`](Self::max_message_len) bytes (unlimited for `HS*` keys).
+pub struct InMemoryKms {
+    key: OpensslSigningKey,
+    max_message_len: usize,
+}
+
+impl InMemoryKms {
+    /// A key for an `RS*`, `PS*` or `ES*` algorithm, always signing digests
+    pub fn new(algorithm: &str, key: PKey<Private>) -> Result<Self> {
+        Ok(Self {
+            key: OpensslSigningKey::new(algorithm, key)?,
+            max_message_len: 0,
+        })
+    }
+
+    /// A key for an `HS*` algorithm, always signing whole messages
+    pub fn hmac(algorithm: &str, secret: &[u8]) -> Result<Self> {
+        Ok(Self {
+            key: OpensslSigningKey::hmac(algorithm, secret)?,
+            max_message_len: usize::MAX,
+        })
+    }
+
+    /// Accept whole messages up to `len` bytes
+    pub fn max_message_len(mut self, len: usize) -> Self {
+        self.max_message_len = len;
+        self
+    }
+}
+
+#[async_trait]
+impl RemoteSigner for InMemoryKms {
+    fn algorithm(&self) -> &str {
+        self.key.algorithm()
+    }
+
+    fn max_message_len(&self) -> usize {
+        self.max_message_len
+    }
+
+    async fn sign(&self, input: SigningInput<'_>) -> Result<Vec<u8>> {
+        match input {
+            SigningInput::Message(message) if message.len() > self.max_message_len => {
+                bail!("message exceeds {} bytes", self.max_message_len)
+            }
+            SigningInput::Message(message) => {
+                let mut signer = self.key.signer()?;
+                signer.write_all(message)?;
+                signer.get_sign()
+            }
+            SigningInput::Digest(digest) => self.key.sign_digest(digest),
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! Rejecting a detached jws that has already been accepted
+//!
+//! A valid signature stays valid for the same body, so a captured request can be sent again.
+//! [`DeserializeJwsWriter::finish_with_replay_cache`] records every verified jws in a
+//! [`ReplayCache`] and fails with [`ReplayDetected`] when it was seen before.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(feature = "openssl")] {
+//! use std::io::Write;
+//! use std::time::Duration;
+//! use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+//! use detached_jws::replay::{MemoryReplayCache, ReplayDetected};
+//! use detached_jws::{DecodeOptions, DeserializeJwsWriter};
+//! use serde_json::Map;
+//!
+//! let key = OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+//! let jws = detached_jws::serialize_with_key(Map::new(), &mut &b"payload"[..], &key).unwrap();
+//!
+//! let key = OpensslVerificationKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+//! let cache = MemoryReplayCache::new(10_000, Duration::from_secs(300)).unwrap();
+//!
+//! let verify = || {
+//!     let mut writer =
+//!         DeserializeJwsWriter::with_key(&jws, &key, &DecodeOptions::default()).unwrap();
+//!     writer.write_all(b"payload").unwrap();
+//!     writer.finish_with_replay_cache(&cache)
+//! };
+//!
+//! assert!(verify().is_ok());
+//! assert!(verify().unwrap_err().is::<ReplayDetected>());
+//! # }
+//! ```
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+#[cfg(doc)]
+use crate::DeserializeJwsWriter;
+use crate::JwsHeader;
+
+/// Identifies a verified jws in a [`ReplayCache`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ReplayKey {
+    /// The `jti` header, scoped by the `iss` and `kid` headers when present
+    Jti {
+        iss: Option<String>,
+        kid: Option<String>,
+        jti: String,
+    },
+    /// SHA-256 of the signature, for a jws without `jti`
+    Signature([u8; 32]),
+}
+
+impl ReplayKey {
+    /// The `jti` header when it is a string, otherwise the hash of `signature`
+    ///
+    /// A `jti` only has to be unique per issuer, so it is keyed together with the `iss` and
+    /// `kid` headers: partners or keys sharing one cache do not reject each other's tokens.
+    ///
+    /// An ECDSA signature is hashed with `s` in the lower half of the curve order, so the
+    /// flipped `(r, n - s)`, which verifies just as well, gets the same key.
+    pub fn new(header: &JwsHeader, signature: &[u8]) -> Result<Self> {
+        let member = |name| header.get(name).and_then(Value::as_str).map(str::to_owned);
+
+        Ok(match member("jti") {
+            Some(jti) => ReplayKey::Jti {
+                iss: member("iss"),
+                kid: member("kid"),
+                jti,
+            },
+            None => ReplayKey::Signature(Sha256::digest(low_s(header, signature)).into()),
+        })
+    }
+}
+
+/// Order of the P-256 group, big-endian
+const P256_ORDER: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xbc, 0xe6, 0xfa, 0xad, 0xa7, 0x17, 0x9e, 0x84, 0xf3, 0xb9, 0xca, 0xc2, 0xfc, 0x63, 0x25, 0x51,
+];
+
+/// Order of the P-384 group, big-endian
+const P384_ORDER: [u8; 48] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xc7, 0x63, 0x4d, 0x81, 0xf4, 0x37, 0x2d, 0xdf,
+    0x58, 0x1a, 0x0d, 0xb2, 0x48, 0xb0, 0xa7, 0x7a, 0xec, 0xec, 0x19, 0x6a, 0xcc, 0xc5, 0x29, 0x73,
+];
+
+/// Order of the P-521 group, big-endian
+const P521_ORDER: [u8; 66] = [
+    0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xfa, 0x51, 0x86, 0x87, 0x83, 0xbf, 0x2f, 0x96, 0x6b, 0x7f, 0xcc, 0x01, 0x48, 0xf7, 0x09,
+    0xa5, 0xd0, 0x3b, 0xb5, 0xc9, 0xb8, 0x89, 0x9c, 0x47, 0xae, 0xbb, 0x6f, 0xb7, 0x1e, 0x91, 0x38,
+    0x64, 0x09,
+];
+
+/// An `ES*` signature `r || s` with `s` replaced by `n - s` when above half the order `n`
+fn low_s<'s>(header: &JwsHeader, signature: &'s [u8]) -> Cow<'s, [u8]> {
+    let order: &[u8] = match header.get("alg").and_then(Value::as_str) {
+        Some("ES256") => &P256_ORDER,
+        Some("ES384") => &P384_ORDER,
+        Some("ES512") => &P521_ORDER,
+        _ => return Cow::Borrowed(signature),
+    };
+    if signature.len() != 2 * order.len() {
+        return Cow::Borrowed(signature);
+    }
+    let (r, s) = signature.split_at(order.len());
+
+    // with `n` odd, `s` is above `n / 2` exactly when it is above `n - s`; equal length
+    // big-endian numbers compare as byte strings
+    match checked_sub(order, s) {
+        Some(flipped) if s > &flipped[..] => {
+            let mut normalized = r.to_vec();
+            normalized.extend_from_slice(&flipped);
+            Cow::Owned(normalized)
+        }
+        _ => Cow::Borrowed(signature),
+    }
+}
+
+/// `a - b` of equal length big-endian numbers, `None` when `b` is greater
+fn checked_sub(a: &[u8], b: &[u8]) -> Option<Vec<u8>> {
+    let mut difference = vec![0; a.len()];
+    let mut borrow = false;
+    for i in (0..a.len()).rev() {
+        let (digit, under) = a[i].overflowing_sub(b[i]);
+        let (digit, under_borrow) = digit.overflowing_sub(borrow as u8);
+        difference[i] = digit;
+        borrow = under || under_borrow;
+    }
+    (!borrow).then_some(difference)
+}
+
+impl fmt::Display for ReplayKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayKey::Jti { iss, kid, jti } => {
+                write!(f, "jti `{}`", jti)?;
+                if let Some(iss) = iss {
+                    write!(f, " of issuer `{}`", iss)?;
+                }
+                if let Some(kid) = kid {
+                    write!(f, " for key `{}`", kid)?;
+                }
+                Ok(())
+            }
+            ReplayKey::Signature(hash) => {
+                f.write_str("signature ")?;
+                hash.iter().try_for_each(|b| write!(f, "{:02x}", b))
+            }
+        }
+    }
+}
+
+/// Remembers the verified jws for a while, e.g. in memory or a shared store
+pub trait ReplayCache: Send + Sync {
+    /// Record `key` as seen; returns `false` if it is still remembered from an earlier call
+    ///
+    /// Must check and record atomically, so concurrent replays are not both accepted.
+    fn insert(&self, key: ReplayKey) -> Result<bool>;
+}
+
+/// The jws has already been accepted
+///
+/// Returned wrapped in [`anyhow::Error`] and can be recovered with `downcast_ref`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayDetected(pub ReplayKey);
+
+impl fmt::Display for ReplayDetected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "jws replay detected: {} has already been accepted",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ReplayDetected {}
+
+/// An in-process [`ReplayCache`] remembering keys for a fixed time to live
+///
+/// When `capacity` keys are remembered the least recently used one is evicted early, which
+/// lets it be replayed again. A replay attempt counts as a use, so a jws that is being
+/// replayed stays remembered for its whole TTL while untouched keys make room; size the cache
+/// for the expected rate of requests times the TTL.
+pub struct MemoryReplayCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    keys: HashMap<ReplayKey, Entry>,
+    /// Keys by insertion, which with a fixed TTL is also expiry order
+    inserted: BTreeMap<u64, ReplayKey>,
+    /// Keys by their last insertion or lookup, least recent first
+    used: BTreeMap<u64, ReplayKey>,
+    /// Source of the `inserted` and `used` positions
+    clock: u64,
+}
+
+struct Entry {
+    /// `None` for a TTL reaching past what [`Instant`] can represent
+    expires_at: Option<Instant>,
+    inserted: u64,
+    used: u64,
+}
+
+impl Entries {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn remove(&mut self, key: &ReplayKey) {
+        if let Some(entry) = self.keys.remove(key) {
+            self.inserted.remove(&entry.inserted);
+            self.used.remove(&entry.used);
+        }
+    }
+}
+
+impl MemoryReplayCache {
+    /// Create a cache remembering up to `capacity` keys, which must not be zero
+    ///
+    /// A `ttl` too long to represent, such as [`Duration::MAX`], remembers keys until they
+    /// are evicted.
+    pub fn new(capacity: usize, ttl: Duration) -> Result<Self> {
+        if capacity == 0 {
+            bail!("replay cache capacity must not be zero")
+        }
+
+        Ok(Self {
+            capacity,
+            ttl,
+            entries: Mutex::new(Entries::default()),
+        })
+    }
+
+    /// Number of keys currently remembered
+    pub fn len(&self) -> usize {
+        self.lock().keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl ReplayCache for MemoryReplayCache {
+    fn insert(&self, key: ReplayKey) -> Result<bool> {
+        let now = Instant::now();
+        let mut guard = self.lock();
+        let entries = &mut *guard;
+
+        while let Some((_, oldest)) = entries.inserted.first_key_value() {
+            if !matches!(entries.keys[oldest].expires_at, Some(at) if at <= now) {
+                break;
+            }
+            let oldest = oldest.clone();
+            entries.remove(&oldest);
+        }
+
+        let tick = entries.tick();
+        if let Some(entry) = entries.keys.get_mut(&key) {
+            entries.used.remove(&entry.used);
+            entry.used = tick;
+            entries.used.insert(tick, key);
+            return Ok(false);
+        }
+        if entries.keys.len() == self.capacity {
+            if let Some((_, least_recent)) = entries.used.pop_first() {
+                entries.remove(&least_recent);
+            }
+        }
+
+        let entry = Entry {
+            expires_at: now.checked_add(self.ttl),
+            inserted: tick,
+            used: tick,
+        };
+        entries.keys.insert(key.clone(), entry);
+        entries.inserted.insert(tick, key.clone());
+        entries.used.insert(tick, key);
+        Ok(true)
+    }
+}
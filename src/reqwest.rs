@@ -0,0 +1,243 @@
+//! [reqwest-middleware](https://crates.io/crates/reqwest-middleware) integration signing
+//! request bodies and verifying response bodies
+//!
+//! # Example:
+//! ```
+//! use std::sync::Arc;
+//! use serde_json::{json, Map};
+//! use detached_jws::{
+//!     openssl::{OpensslSigningKey, OpensslVerificationKey},
+//!     reqwest::{HostPolicy, JwsMiddleware},
+//!     JwsHeader,
+//! };
+//!
+//! let signing_key = Arc::new(OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap());
+//! let verification_key = Arc::new(OpensslVerificationKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap());
+//!
+//! let mut header = Map::new();
+//! header.insert("kid".to_owned(), json!("key-1"));
+//!
+//! let middleware = JwsMiddleware::new().host(
+//!     "aspsp.example.com",
+//!     HostPolicy::new()
+//!         .sign(signing_key, header)
+//!         .verify(move |_: &JwsHeader| Some(verification_key.clone())),
+//! );
+//!
+//! let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+//!     .with(middleware)
+//!     .build();
+//! ```
+
+use ::reqwest::{Request, Response, ResponseBuilderExt};
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use bytes::BytesMut;
+use http::{header::HeaderName, Extensions, HeaderValue};
+use reqwest_middleware::{Middleware, Next, Result};
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    deserialize_with_resolver, serialize_with_key, JwsHeader, KeyResolver, SigningKey,
+    JWS_SIGNATURE_HEADER,
+};
+
+type SignFn = dyn Fn(&[u8]) -> anyhow::Result<Vec<u8>> + Send + Sync;
+type VerifyFn = dyn Fn(&[u8], &[u8]) -> anyhow::Result<JwsHeader> + Send + Sync;
+
+/// How requests to and responses from one host are signed and verified
+#[derive(Clone, Default)]
+pub struct HostPolicy {
+    sign: Option<Arc<SignFn>>,
+    verify: Option<Arc<VerifyFn>>,
+}
+
+impl HostPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sign request bodies with `key`, adding `header` members to the protected header
+    pub fn sign<K>(mut self, key: Arc<K>, header: JwsHeader) -> Self
+    where
+        K: SigningKey + 'static,
+    {
+        self.sign = Some(Arc::new(move |body| {
+            serialize_with_key(header.clone(), &mut &body[..], key.as_ref())
+        }));
+        self
+    }
+
+    /// Require responses to carry a detached jws verified with the key `resolver` finds
+    ///
+    /// The verified [`JwsHeader`] is added to the response extensions.
+    pub fn verify<R>(mut self, resolver: R) -> Self
+    where
+        R: KeyResolver + 'static,
+    {
+        self.verify = Some(Arc::new(move |jws, body| {
+            deserialize_with_resolver(&jws, &mut &body[..], &resolver)
+        }));
+        self
+    }
+}
+
+/// A [`Middleware`] applying a [`HostPolicy`] by request host and port
+///
+/// Requests to hosts without a policy pass through unchanged. Streaming request bodies
+/// can not be signed and fail the request.
+#[derive(Clone)]
+pub struct JwsMiddleware {
+    header_name: HeaderName,
+    max_body_len: usize,
+    /// Policies by lowercased host and port, `None` for the default port of the scheme
+    hosts: HashMap<(String, Option<u16>), HostPolicy>,
+}
+
+impl JwsMiddleware {
+    pub fn new() -> Self {
+        Self {
+            header_name: HeaderName::from_static(JWS_SIGNATURE_HEADER),
+            max_body_len: 2 * 1024 * 1024,
+            hosts: HashMap::new(),
+        }
+    }
+
+    /// Use `name` instead of [`JWS_SIGNATURE_HEADER`] for requests and responses
+    pub fn header_name(mut self, name: HeaderName) -> Self {
+        self.header_name = name;
+        self
+    }
+
+    /// Fail verified responses with bodies longer than `len` bytes, 2 MiB by default
+    pub fn max_body_len(mut self, len: usize) -> Self {
+        self.max_body_len = len;
+        self
+    }
+
+    /// Apply `policy` to requests whose URL host and port equal `host`
+    ///
+    /// A `host` with a port, e.g. `api.example.com:8443`, matches requests to that port only;
+    /// one without, e.g. `api.example.com`, matches requests to the default port of their
+    /// scheme only. Other ports of the same host need their own policy.
+    pub fn host(mut self, host: &str, policy: HostPolicy) -> Self {
+        let host = host.to_ascii_lowercase();
+        let key = match host.rsplit_once(':') {
+            // a bare IPv6 address has no port
+            Some((name, port)) if !name.contains(':') || name.ends_with(']') => {
+                match port.parse() {
+                    Ok(port) => (name.to_owned(), Some(port)),
+                    Err(_) => (host, None),
+                }
+            }
+            _ => (host, None),
+        };
+        self.hosts.insert(key, policy);
+        self
+    }
+
+    /// The policy for a request URL, by its explicit or default port
+    fn policy(&self, url: &::reqwest::Url) -> Option<HostPolicy> {
+        let host = url.host_str()?.to_ascii_lowercase();
+
+        if let Some(port) = url.port_or_known_default() {
+            if let Some(policy) = self.hosts.get(&(host.clone(), Some(port))) {
+                return Some(policy.clone());
+            }
+        }
+        match url.port() {
+            Some(_) => None,
+            None => self.hosts.get(&(host, None)).cloned(),
+        }
+    }
+
+    fn sign_request(&self, policy: &HostPolicy, request: &mut Request) -> anyhow::Result<()> {
+        let sign = match policy.sign {
+            Some(ref sign) => sign,
+            None => return Ok(()),
+        };
+
+        let body = match request.body() {
+            Some(body) => body
+                .as_bytes()
+                .context("streaming request body can not be signed")?,
+            None => &[],
+        };
+
+        let jws = HeaderValue::from_bytes(&sign(body)?)?;
+        request.headers_mut().insert(self.header_name.clone(), jws);
+
+        Ok(())
+    }
+
+    async fn verify_response(
+        &self,
+        policy: &HostPolicy,
+        mut response: Response,
+    ) -> Result<Response> {
+        let verify = match policy.verify {
+            Some(ref verify) => verify,
+            None => return Ok(response),
+        };
+
+        let url = response.url().clone();
+        let status = response.status();
+        let version = response.version();
+        let headers = std::mem::take(response.headers_mut());
+        let extensions = std::mem::take(response.extensions_mut());
+
+        let jws = headers
+            .get(&self.header_name)
+            .ok_or_else(|| anyhow!("response has no `{}` header", self.header_name))?;
+
+        let mut body = BytesMut::new();
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > self.max_body_len {
+                return Err(anyhow!("response body exceeds {} bytes", self.max_body_len).into());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        let body = body.freeze();
+
+        let header = verify(jws.as_bytes(), &body)?;
+
+        let mut verified = http::Response::builder()
+            .status(status)
+            .version(version)
+            .url(url)
+            .body(body)
+            .map_err(anyhow::Error::from)?;
+        *verified.headers_mut() = headers;
+        verified.extensions_mut().extend(extensions);
+        verified.extensions_mut().insert(header);
+
+        Ok(verified.into())
+    }
+}
+
+impl Default for JwsMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Middleware for JwsMiddleware {
+    async fn handle(
+        &self,
+        mut request: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let policy = match self.policy(request.url()) {
+            Some(policy) => policy,
+            None => return next.run(request, extensions).await,
+        };
+
+        self.sign_request(&policy, &mut request)?;
+
+        let response = next.run(request, extensions).await?;
+
+        self.verify_response(&policy, response).await
+    }
+}
@@ -0,0 +1,685 @@
+//! Pure Rust implementations for [`Verify`] and [`Sign`] from the
+//! [RustCrypto](https://github.com/RustCrypto) crates
+//!
+//! Needs neither `std` nor a C library, so it also builds for `wasm32-unknown-unknown` with the
+//! `wasm` feature. Supports the `HS*`, `RS*`, `PS*` and `ES*` algorithms; keys are created from
+//! JSON Web Keys. `RS*`, `PS*` and `ES512` signing draw from the operating system random source
+//! through [getrandom](https://docs.rs/getrandom/0.2) under `std` (`crypto.getRandomValues`
+//! under `wasm`); without either, pass a random source to
+//! [`RustCryptoSigningKey::signer_with_rng`].
+//!
+//! # RSA signing
+//!
+//! The [rsa](https://crates.io/crates/rsa) crate's private key operations are not constant
+//! time and are affected by the Marvin attack
+//! ([RUSTSEC-2023-0071](https://rustsec.org/advisories/RUSTSEC-2023-0071)): an attacker timing
+//! many `RS*`/`PS*` signatures made by a [`RustCryptoSigningKey`], e.g. over a network, may
+//! recover the private key. Verification only uses the public key and is not affected. Sign
+//! with [`openssl`](crate::openssl) or an HSM where signing timings are observable.
+//!
+//! # Examples
+//!
+//! ```
+//! use detached_jws::rustcrypto::{RustCryptoSigningKey, RustCryptoVerificationKey};
+//! use detached_jws::{DeserializeJwsWriter, SerializeJwsWriter, SigningKey};
+//! use serde_json::{json, Map};
+//! use std::io::Write;
+//!
+//! let jwk = json!({
+//!     "kty": "EC",
+//!     "crv": "P-256",
+//!     "x": "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU",
+//!     "y": "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0",
+//!     "d": "jpsQnnGQmL-YBIffH1136cspYG6-0iY7X1fCE9-E9LI"
+//! });
+//! let jwk = jwk.as_object().unwrap();
+//!
+//! let signing_key = RustCryptoSigningKey::from_jwk("ES256", jwk).unwrap();
+//! let verification_key = RustCryptoVerificationKey::from_jwk("ES256", jwk).unwrap();
+//!
+//! let mut writer = SerializeJwsWriter::new(
+//!     Vec::new(),
+//!     signing_key.algorithm().to_owned(),
+//!     Map::new(),
+//!     signing_key.signer().unwrap(),
+//! )
+//! .unwrap();
+//! writer.write_all(&[0, 1, 2, 3]).unwrap();
+//! let jws = writer.finish().unwrap();
+//!
+//! let mut writer =
+//!     DeserializeJwsWriter::with_key(&jws, &verification_key, &Default::default()).unwrap();
+//! writer.write_all(&[0, 1, 2, 3]).unwrap();
+//! writer.finish().unwrap();
+//! ```
+
+use alloc::{borrow::ToOwned, boxed::Box, format, string::String, vec, vec::Vec};
+use anyhow::{bail, Context, Error, Result};
+use core::cell::RefCell;
+use hmac::{Hmac, Mac};
+use p256::ecdsa::signature::hazmat::{PrehashSigner, PrehashVerifier, RandomizedPrehashSigner};
+use rand_core::CryptoRngCore;
+#[cfg(any(feature = "std", feature = "wasm"))]
+use rand_core::OsRng;
+use rsa::{traits::PublicKeyParts, BigUint, Pkcs1v15Sign, Pss, RsaPrivateKey, RsaPublicKey};
+use serde_json::Value;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+use crate::{ByteSink, FailureReason, JwsHeader, Sign, SigningKey, VerificationKey, Verify};
+
+/// Smallest RSA key RFC 7518 allows for `RS*` and `PS*`
+const MIN_RSA_BITS: usize = 2048;
+
+#[derive(Clone, Copy)]
+enum Hash {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Hash {
+    /// Byte length of the digest
+    fn size(self) -> usize {
+        match self {
+            Hash::Sha256 => 32,
+            Hash::Sha384 => 48,
+            Hash::Sha512 => 64,
+        }
+    }
+}
+
+/// Parameters of a JWS `alg` value
+#[derive(Clone, Copy)]
+enum Algorithm {
+    Hmac(Hash),
+    Rsa(Hash, RsaPadding),
+    /// Digest and the byte length of each of `r` and `s`
+    Ecdsa(Hash, usize),
+}
+
+#[derive(Clone, Copy)]
+enum RsaPadding {
+    Pkcs1,
+    Pss,
+}
+
+impl Algorithm {
+    fn from_name(name: &str) -> Result<Self> {
+        Ok(match name {
+            "HS256" => Algorithm::Hmac(Hash::Sha256),
+            "HS384" => Algorithm::Hmac(Hash::Sha384),
+            "HS512" => Algorithm::Hmac(Hash::Sha512),
+            "RS256" => Algorithm::Rsa(Hash::Sha256, RsaPadding::Pkcs1),
+            "RS384" => Algorithm::Rsa(Hash::Sha384, RsaPadding::Pkcs1),
+            "RS512" => Algorithm::Rsa(Hash::Sha512, RsaPadding::Pkcs1),
+            "PS256" => Algorithm::Rsa(Hash::Sha256, RsaPadding::Pss),
+            "PS384" => Algorithm::Rsa(Hash::Sha384, RsaPadding::Pss),
+            "PS512" => Algorithm::Rsa(Hash::Sha512, RsaPadding::Pss),
+            "ES256" => Algorithm::Ecdsa(Hash::Sha256, 32),
+            "ES384" => Algorithm::Ecdsa(Hash::Sha384, 48),
+            "ES512" => Algorithm::Ecdsa(Hash::Sha512, 66),
+            _ => bail!("unsupported algorithm `{}`", name),
+        })
+    }
+
+    fn hash(&self) -> Hash {
+        match *self {
+            Algorithm::Hmac(hash) | Algorithm::Rsa(hash, _) | Algorithm::Ecdsa(hash, _) => hash,
+        }
+    }
+}
+
+/// The running hash or MAC of a signing input
+#[derive(Clone)]
+enum Hasher {
+    Sha256(Sha256),
+    Sha384(Sha384),
+    Sha512(Sha512),
+    HmacSha256(Hmac<Sha256>),
+    HmacSha384(Hmac<Sha384>),
+    HmacSha512(Hmac<Sha512>),
+}
+
+impl Hasher {
+    fn digest(hash: Hash) -> Self {
+        match hash {
+            Hash::Sha256 => Hasher::Sha256(Sha256::new()),
+            Hash::Sha384 => Hasher::Sha384(Sha384::new()),
+            Hash::Sha512 => Hasher::Sha512(Sha512::new()),
+        }
+    }
+
+    fn hmac(hash: Hash, secret: &[u8]) -> Result<Self> {
+        Ok(match hash {
+            Hash::Sha256 => Hasher::HmacSha256(Hmac::new_from_slice(secret).map_err(Error::msg)?),
+            Hash::Sha384 => Hasher::HmacSha384(Hmac::new_from_slice(secret).map_err(Error::msg)?),
+            Hash::Sha512 => Hasher::HmacSha512(Hmac::new_from_slice(secret).map_err(Error::msg)?),
+        })
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Sha384(h) => h.update(data),
+            Hasher::Sha512(h) => h.update(data),
+            Hasher::HmacSha256(h) => h.update(data),
+            Hasher::HmacSha384(h) => h.update(data),
+            Hasher::HmacSha512(h) => h.update(data),
+        }
+    }
+
+    /// The digest or MAC of everything written so far
+    fn finalize(&self) -> Vec<u8> {
+        match self.clone() {
+            Hasher::Sha256(h) => h.finalize().to_vec(),
+            Hasher::Sha384(h) => h.finalize().to_vec(),
+            Hasher::Sha512(h) => h.finalize().to_vec(),
+            Hasher::HmacSha256(h) => h.finalize().into_bytes().to_vec(),
+            Hasher::HmacSha384(h) => h.finalize().into_bytes().to_vec(),
+            Hasher::HmacSha512(h) => h.finalize().into_bytes().to_vec(),
+        }
+    }
+
+    /// Compare the MAC in constant time
+    fn verify_mac(&self, tag: &[u8]) -> bool {
+        match self.clone() {
+            Hasher::HmacSha256(h) => h.verify_slice(tag).is_ok(),
+            Hasher::HmacSha384(h) => h.verify_slice(tag).is_ok(),
+            Hasher::HmacSha512(h) => h.verify_slice(tag).is_ok(),
+            _ => false,
+        }
+    }
+}
+
+fn pkcs1(hash: Hash) -> Pkcs1v15Sign {
+    match hash {
+        Hash::Sha256 => Pkcs1v15Sign::new::<Sha256>(),
+        Hash::Sha384 => Pkcs1v15Sign::new::<Sha384>(),
+        Hash::Sha512 => Pkcs1v15Sign::new::<Sha512>(),
+    }
+}
+
+fn pss(hash: Hash) -> Pss {
+    match hash {
+        Hash::Sha256 => Pss::new::<Sha256>(),
+        Hash::Sha384 => Pss::new::<Sha384>(),
+        Hash::Sha512 => Pss::new::<Sha512>(),
+    }
+}
+
+/// Members of a JSON Web Key ([RFC 7517](https://tools.ietf.org/html/rfc7517)) for `algorithm`
+struct Jwk<'a> {
+    jwk: &'a JwsHeader,
+    kty: Option<&'a str>,
+    algorithm: Algorithm,
+}
+
+impl<'a> Jwk<'a> {
+    fn new(algorithm: &str, jwk: &'a JwsHeader) -> Result<Self> {
+        if let Some(alg) = jwk.get("alg") {
+            if alg != algorithm {
+                bail!("JWK is for `{}`, not `{}`", alg, algorithm)
+            }
+        }
+
+        Ok(Self {
+            jwk,
+            kty: jwk.get("kty").and_then(Value::as_str),
+            algorithm: Algorithm::from_name(algorithm)?,
+        })
+    }
+
+    fn member(&self, name: &str) -> Result<Vec<u8>> {
+        let value = self
+            .jwk
+            .get(name)
+            .and_then(Value::as_str)
+            .with_context(|| format!("JWK has no `{}`", name))?;
+        base64::decode_config(value, base64::URL_SAFE_NO_PAD)
+            .map_err(Error::msg)
+            .with_context(|| format!("wrong JWK `{}` format", name))
+    }
+
+    fn number(&self, name: &str) -> Result<BigUint> {
+        Ok(BigUint::from_bytes_be(&self.member(name)?))
+    }
+
+    fn rsa_public(&self) -> Result<RsaPublicKey> {
+        let key = RsaPublicKey::new(self.number("n")?, self.number("e")?).map_err(Error::msg)?;
+        check_rsa_size(&key)?;
+        Ok(key)
+    }
+
+    fn curve(&self, name: &str) -> Result<()> {
+        let len = match self.algorithm {
+            Algorithm::Ecdsa(_, len) => len,
+            _ => 0,
+        };
+        match (self.jwk.get("crv").and_then(Value::as_str), len) {
+            (Some("P-256"), 32) | (Some("P-384"), 48) | (Some("P-521"), 66) => Ok(()),
+            (crv, _) => bail!("JWK curve {:?} does not suit `{}`", crv, name),
+        }
+    }
+
+    /// The uncompressed SEC1 point of an `EC` key
+    fn ec_point(&self) -> Result<Vec<u8>> {
+        let mut point = vec![0x04];
+        point.extend(self.member("x")?);
+        point.extend(self.member("y")?);
+        Ok(point)
+    }
+}
+
+fn check_rsa_size(key: &impl PublicKeyParts) -> Result<()> {
+    if key.n().bits() < MIN_RSA_BITS {
+        bail!("RSA key is shorter than {} bits", MIN_RSA_BITS)
+    }
+    Ok(())
+}
+
+/// Check that an `HS*` secret is at least as long as the hash output, as RFC 7518 §3.2 requires
+fn check_secret(name: &str, hash: Hash, secret: &[u8]) -> Result<()> {
+    if secret.len() < hash.size() {
+        bail!("`{}` secret must be at least {} bytes", name, hash.size())
+    }
+    Ok(())
+}
+
+enum SigningMaterial {
+    Secret(Vec<u8>),
+    Rsa(Box<RsaPrivateKey>),
+    P256(p256::ecdsa::SigningKey),
+    P384(p384::ecdsa::SigningKey),
+    P521(p521::ecdsa::SigningKey),
+}
+
+/// A private or secret key bound to a JWS algorithm
+///
+/// Implements [`SigningKey`], so one key can be shared between threads and
+/// spawns a fresh [`RustCryptoSigner`] per message.
+pub struct RustCryptoSigningKey {
+    name: String,
+    algorithm: Algorithm,
+    key: SigningMaterial,
+}
+
+impl RustCryptoSigningKey {
+    /// Create an `HS*` key from a shared secret
+    ///
+    /// The secret must be at least as long as the hash output, e.g. 32 bytes for `HS256`.
+    pub fn hmac(algorithm: &str, secret: &[u8]) -> Result<Self> {
+        let params = Algorithm::from_name(algorithm)?;
+        if !matches!(params, Algorithm::Hmac(_)) {
+            bail!("`{}` is not an HMAC algorithm", algorithm)
+        }
+        check_secret(algorithm, params.hash(), secret)?;
+
+        Ok(Self {
+            name: algorithm.to_owned(),
+            algorithm: params,
+            key: SigningMaterial::Secret(secret.to_vec()),
+        })
+    }
+
+    /// Create a key for `algorithm` from a private JSON Web Key
+    ///
+    /// Supports `RSA`, `EC` (`P-256`, `P-384`, `P-521`) and `oct` keys; the key type must suit
+    /// the algorithm, `RSA` keys need at least 2048 bits and the `alg` member, when present,
+    /// must equal it. See the [module documentation](self#rsa-signing) before signing with an
+    /// `RSA` key.
+    pub fn from_jwk(algorithm: &str, jwk: &JwsHeader) -> Result<Self> {
+        let jwk = Jwk::new(algorithm, jwk)?;
+
+        let key = match (jwk.kty, jwk.algorithm) {
+            (Some("oct"), Algorithm::Hmac(_)) => return Self::hmac(algorithm, &jwk.member("k")?),
+            (Some("RSA"), Algorithm::Rsa(..)) => {
+                let key = RsaPrivateKey::from_components(
+                    jwk.number("n")?,
+                    jwk.number("e")?,
+                    jwk.number("d")?,
+                    vec![jwk.number("p")?, jwk.number("q")?],
+                )
+                .map_err(Error::msg)?;
+                key.validate().map_err(Error::msg)?;
+                check_rsa_size(&key)?;
+                SigningMaterial::Rsa(Box::new(key))
+            }
+            (Some("EC"), Algorithm::Ecdsa(_, len)) => {
+                jwk.curve(algorithm)?;
+                let d = jwk.member("d")?;
+                let key = match len {
+                    32 => SigningMaterial::P256(
+                        p256::ecdsa::SigningKey::from_slice(&d).map_err(Error::msg)?,
+                    ),
+                    48 => SigningMaterial::P384(
+                        p384::ecdsa::SigningKey::from_slice(&d).map_err(Error::msg)?,
+                    ),
+                    _ => SigningMaterial::P521(
+                        p521::ecdsa::SigningKey::from_slice(&d).map_err(Error::msg)?,
+                    ),
+                };
+                if VerificationMaterial::public(&key).map(|k| k.ec_point()) != Some(jwk.ec_point()?)
+                {
+                    bail!("JWK `d` does not match `x` and `y`")
+                }
+                key
+            }
+            (kty, _) => bail!("JWK type {:?} does not suit `{}`", kty, algorithm),
+        };
+
+        Ok(Self {
+            name: algorithm.to_owned(),
+            algorithm: jwk.algorithm,
+            key,
+        })
+    }
+
+    /// Create a signer drawing from `rng` instead of the operating system random source
+    ///
+    /// Needed for `RS*`, `PS*` and `ES512` signing without `std` or `wasm`; `ES256` and `ES384`
+    /// signatures are deterministic and do not use it.
+    pub fn signer_with_rng<'a>(
+        &'a self,
+        rng: &'a mut (dyn CryptoRngCore + Send),
+    ) -> Result<RustCryptoSigner<'a>> {
+        let mut signer = self.signer()?;
+        signer.rng = Some(RefCell::new(rng));
+        Ok(signer)
+    }
+
+    fn sign_digest(
+        &self,
+        digest: &[u8],
+        rng: Option<&mut (dyn CryptoRngCore + Send)>,
+    ) -> Result<Vec<u8>> {
+        Ok(match (&self.key, self.algorithm) {
+            (SigningMaterial::Rsa(key), Algorithm::Rsa(hash, RsaPadding::Pkcs1)) => {
+                with_rng(rng, |mut rng| {
+                    key.sign_with_rng(&mut rng, pkcs1(hash), digest)
+                        .map_err(Error::msg)
+                })?
+            }
+            (SigningMaterial::Rsa(key), Algorithm::Rsa(hash, RsaPadding::Pss)) => {
+                with_rng(rng, |mut rng| {
+                    key.sign_with_rng(&mut rng, pss(hash), digest)
+                        .map_err(Error::msg)
+                })?
+            }
+            (SigningMaterial::P256(key), _) => {
+                let signature: p256::ecdsa::Signature =
+                    key.sign_prehash(digest).map_err(Error::msg)?;
+                signature.to_bytes().to_vec()
+            }
+            (SigningMaterial::P384(key), _) => {
+                let signature: p384::ecdsa::Signature =
+                    key.sign_prehash(digest).map_err(Error::msg)?;
+                signature.to_bytes().to_vec()
+            }
+            (SigningMaterial::P521(key), _) => {
+                // p521 0.13 signs prehashes with a random nonce, `PrehashSigner` included
+                let signature: p521::ecdsa::Signature = with_rng(rng, |mut rng| {
+                    key.sign_prehash_with_rng(&mut rng, digest)
+                        .map_err(Error::msg)
+                })?;
+                signature.to_bytes().to_vec()
+            }
+            _ => bail!("`{}` can not sign a digest", self.name),
+        })
+    }
+}
+
+/// Run `sign` with the caller's random source, or the operating system one where available
+fn with_rng<T>(
+    rng: Option<&mut (dyn CryptoRngCore + Send)>,
+    sign: impl FnOnce(&mut dyn CryptoRngCore) -> Result<T>,
+) -> Result<T> {
+    match rng {
+        Some(rng) => sign(rng),
+        #[cfg(any(feature = "std", feature = "wasm"))]
+        None => sign(&mut OsRng),
+        #[cfg(not(any(feature = "std", feature = "wasm")))]
+        None => bail!("signing needs a random source, see `RustCryptoSigningKey::signer_with_rng`"),
+    }
+}
+
+impl SigningKey for RustCryptoSigningKey {
+    type Signer<'a> = RustCryptoSigner<'a>;
+
+    fn algorithm(&self) -> &str {
+        &self.name
+    }
+
+    fn signer(&self) -> Result<RustCryptoSigner<'_>> {
+        let hasher = match self.key {
+            SigningMaterial::Secret(ref secret) => Hasher::hmac(self.algorithm.hash(), secret)?,
+            _ => Hasher::digest(self.algorithm.hash()),
+        };
+
+        Ok(RustCryptoSigner {
+            key: self,
+            hasher,
+            rng: None,
+        })
+    }
+}
+
+/// A per-message [`Sign`] created by [`RustCryptoSigningKey`]
+pub struct RustCryptoSigner<'a> {
+    key: &'a RustCryptoSigningKey,
+    hasher: Hasher,
+    rng: Option<RefCell<&'a mut (dyn CryptoRngCore + Send)>>,
+}
+
+impl<'a> Sign for RustCryptoSigner<'a> {
+    fn get_sign(&self) -> Result<Vec<u8>> {
+        match self.key.key {
+            SigningMaterial::Secret(_) => Ok(self.hasher.finalize()),
+            _ => match &self.rng {
+                Some(rng) => {
+                    let mut rng = rng.borrow_mut();
+                    let rng: &mut (dyn CryptoRngCore + Send) = &mut **rng;
+                    self.key.sign_digest(&self.hasher.finalize(), Some(rng))
+                }
+                None => self.key.sign_digest(&self.hasher.finalize(), None),
+            },
+        }
+    }
+}
+
+impl<'a> ByteSink for RustCryptoSigner<'a> {
+    fn update(&mut self, data: &[u8]) -> Result<()> {
+        self.hasher.update(data);
+        Ok(())
+    }
+}
+
+enum VerificationMaterial {
+    Secret(Vec<u8>),
+    Rsa(RsaPublicKey),
+    P256(p256::ecdsa::VerifyingKey),
+    P384(p384::ecdsa::VerifyingKey),
+    P521(p521::ecdsa::VerifyingKey),
+}
+
+/// A public or secret key bound to a JWS algorithm
+///
+/// Implements [`VerificationKey`], so one key can be shared between threads and
+/// spawns a fresh [`RustCryptoVerifier`] per message.
+pub struct RustCryptoVerificationKey {
+    name: String,
+    algorithm: Algorithm,
+    key: VerificationMaterial,
+}
+
+impl RustCryptoVerificationKey {
+    /// Create an `HS*` key from a shared secret
+    ///
+    /// The secret must be at least as long as the hash output, e.g. 32 bytes for `HS256`.
+    pub fn hmac(algorithm: &str, secret: &[u8]) -> Result<Self> {
+        let params = Algorithm::from_name(algorithm)?;
+        if !matches!(params, Algorithm::Hmac(_)) {
+            bail!("`{}` is not an HMAC algorithm", algorithm)
+        }
+        check_secret(algorithm, params.hash(), secret)?;
+
+        Ok(Self {
+            name: algorithm.to_owned(),
+            algorithm: params,
+            key: VerificationMaterial::Secret(secret.to_vec()),
+        })
+    }
+
+    /// Create a key for `algorithm` from a public (or private) JSON Web Key
+    ///
+    /// Supports `RSA`, `EC` (`P-256`, `P-384`, `P-521`) and `oct` keys; the key type must suit
+    /// the algorithm, `RSA` keys need at least 2048 bits and the `alg` member, when present,
+    /// must equal it.
+    pub fn from_jwk(algorithm: &str, jwk: &JwsHeader) -> Result<Self> {
+        let jwk = Jwk::new(algorithm, jwk)?;
+
+        let key = match (jwk.kty, jwk.algorithm) {
+            (Some("oct"), Algorithm::Hmac(_)) => return Self::hmac(algorithm, &jwk.member("k")?),
+            (Some("RSA"), Algorithm::Rsa(..)) => VerificationMaterial::Rsa(jwk.rsa_public()?),
+            (Some("EC"), Algorithm::Ecdsa(_, len)) => {
+                jwk.curve(algorithm)?;
+                let point = jwk.ec_point()?;
+                match len {
+                    32 => VerificationMaterial::P256(
+                        p256::ecdsa::VerifyingKey::from_sec1_bytes(&point).map_err(Error::msg)?,
+                    ),
+                    48 => VerificationMaterial::P384(
+                        p384::ecdsa::VerifyingKey::from_sec1_bytes(&point).map_err(Error::msg)?,
+                    ),
+                    _ => VerificationMaterial::P521(
+                        p521::ecdsa::VerifyingKey::from_sec1_bytes(&point).map_err(Error::msg)?,
+                    ),
+                }
+            }
+            (kty, _) => bail!("JWK type {:?} does not suit `{}`", kty, algorithm),
+        };
+
+        Ok(Self {
+            name: algorithm.to_owned(),
+            algorithm: jwk.algorithm,
+            key,
+        })
+    }
+}
+
+impl VerificationMaterial {
+    /// The public part of an `EC` signing key
+    fn public(key: &SigningMaterial) -> Option<Self> {
+        match key {
+            SigningMaterial::P256(key) => Some(VerificationMaterial::P256(*key.verifying_key())),
+            SigningMaterial::P384(key) => Some(VerificationMaterial::P384(*key.verifying_key())),
+            SigningMaterial::P521(key) => Some(VerificationMaterial::P521(key.into())),
+            _ => None,
+        }
+    }
+
+    /// The uncompressed SEC1 point of an `EC` key
+    fn ec_point(&self) -> Vec<u8> {
+        match self {
+            VerificationMaterial::P256(key) => key.to_encoded_point(false).as_bytes().to_vec(),
+            VerificationMaterial::P384(key) => key.to_encoded_point(false).as_bytes().to_vec(),
+            VerificationMaterial::P521(key) => key.to_encoded_point(false).as_bytes().to_vec(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl VerificationKey for RustCryptoVerificationKey {
+    type Verifier<'a> = RustCryptoVerifier<'a>;
+
+    fn algorithm(&self) -> &str {
+        &self.name
+    }
+
+    fn verifier(&self) -> Result<RustCryptoVerifier<'_>> {
+        let hasher = match self.key {
+            VerificationMaterial::Secret(ref secret) => {
+                Hasher::hmac(self.algorithm.hash(), secret)?
+            }
+            _ => Hasher::digest(self.algorithm.hash()),
+        };
+
+        Ok(RustCryptoVerifier { key: self, hasher })
+    }
+}
+
+/// A per-message [`Verify`] created by [`RustCryptoVerificationKey`]
+pub struct RustCryptoVerifier<'a> {
+    key: &'a RustCryptoVerificationKey,
+    hasher: Hasher,
+}
+
+impl<'a> RustCryptoVerifier<'a> {
+    fn signature_len(&self) -> usize {
+        match (&self.key.key, self.key.algorithm) {
+            (_, Algorithm::Ecdsa(_, len)) => 2 * len,
+            (VerificationMaterial::Rsa(key), _) => key.size(),
+            (_, Algorithm::Hmac(Hash::Sha256)) => 32,
+            (_, Algorithm::Hmac(Hash::Sha384)) => 48,
+            _ => 64,
+        }
+    }
+}
+
+impl<'a> Verify for RustCryptoVerifier<'a> {
+    fn verify(&self, signature: &[u8]) -> Result<bool> {
+        match self.check(signature) {
+            Ok(()) => Ok(true),
+            Err(FailureReason::Backend(e)) => Err(e),
+            Err(_) => Ok(false),
+        }
+    }
+
+    fn check(&self, signature: &[u8]) -> core::result::Result<(), FailureReason> {
+        let expected = self.signature_len();
+        if signature.len() != expected {
+            return Err(FailureReason::WrongLength {
+                expected,
+                actual: signature.len(),
+            });
+        }
+
+        let verified = match (&self.key.key, self.key.algorithm) {
+            (VerificationMaterial::Secret(_), _) => self.hasher.verify_mac(signature),
+            (VerificationMaterial::Rsa(key), Algorithm::Rsa(hash, padding)) => {
+                let digest = self.hasher.finalize();
+                match padding {
+                    RsaPadding::Pkcs1 => key.verify(pkcs1(hash), &digest, signature).is_ok(),
+                    RsaPadding::Pss => key.verify(pss(hash), &digest, signature).is_ok(),
+                }
+            }
+            (VerificationMaterial::P256(key), _) => p256::ecdsa::Signature::from_slice(signature)
+                .and_then(|s| key.verify_prehash(&self.hasher.finalize(), &s))
+                .is_ok(),
+            (VerificationMaterial::P384(key), _) => p384::ecdsa::Signature::from_slice(signature)
+                .and_then(|s| key.verify_prehash(&self.hasher.finalize(), &s))
+                .is_ok(),
+            (VerificationMaterial::P521(key), _) => p521::ecdsa::Signature::from_slice(signature)
+                .and_then(|s| key.verify_prehash(&self.hasher.finalize(), &s))
+                .is_ok(),
+            _ => {
+                return Err(FailureReason::Backend(anyhow::anyhow!(
+                    "key does not suit `{}`",
+                    self.key.name
+                )))
+            }
+        };
+
+        if verified {
+            Ok(())
+        } else {
+            Err(FailureReason::Mismatch)
+        }
+    }
+}
+
+impl<'a> ByteSink for RustCryptoVerifier<'a> {
+    fn update(&mut self, data: &[u8]) -> Result<()> {
+        self.hasher.update(data);
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+//! NumericDate checks shared by [`VerificationPolicy`](crate::VerificationPolicy) for header
+//! members and [`Validator`](crate::jwt::Validator) for claims
+
+use serde_json::{Map, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A failed `exp`, `nbf` or `iat` check
+pub(crate) enum TimeViolation {
+    /// The member is not a number
+    Malformed(&'static str),
+    Expired,
+    NotYetValid,
+    IssuedInFuture,
+    /// `iat` is older than the maximum age
+    TooOld,
+}
+
+/// Check the `exp`, `nbf` and `iat` members as of `time`, or the current time
+///
+/// `leeway` is the tolerated clock skew and an `iat` older than `max_age` is rejected. Returns
+/// whether `iat` is present.
+pub(crate) fn check_numeric_dates(
+    members: &Map<String, Value>,
+    time: Option<SystemTime>,
+    leeway: Duration,
+    max_age: Option<Duration>,
+) -> Result<bool, TimeViolation> {
+    let now = time
+        .unwrap_or_else(SystemTime::now)
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |now| now.as_secs_f64());
+    let leeway = leeway.as_secs_f64();
+
+    if let Some(exp) = numeric_date(members, "exp")? {
+        if now - leeway >= exp {
+            return Err(TimeViolation::Expired);
+        }
+    }
+    if let Some(nbf) = numeric_date(members, "nbf")? {
+        if now + leeway < nbf {
+            return Err(TimeViolation::NotYetValid);
+        }
+    }
+    match numeric_date(members, "iat")? {
+        Some(iat) if iat > now + leeway => Err(TimeViolation::IssuedInFuture),
+        Some(iat) if max_age.is_some_and(|max_age| now - leeway > iat + max_age.as_secs_f64()) => {
+            Err(TimeViolation::TooOld)
+        }
+        iat => Ok(iat.is_some()),
+    }
+}
+
+fn numeric_date(
+    members: &Map<String, Value>,
+    name: &'static str,
+) -> Result<Option<f64>, TimeViolation> {
+    match members.get(name) {
+        None => Ok(None),
+        Some(value) => value
+            .as_f64()
+            .map(Some)
+            .ok_or(TimeViolation::Malformed(name)),
+    }
+}
@@ -0,0 +1,698 @@
+//! [tower](https://crates.io/crates/tower) middleware for detached jws over HTTP
+//!
+//! [`VerifyJwsLayer`] verifies the detached jws in a request header against the request body
+//! before the request reaches the inner service, [`SignJwsLayer`] adds a detached jws header
+//! (or trailer) to response bodies.
+//!
+//! # Example with axum:
+//! ```
+//! use std::sync::Arc;
+//! use axum::{routing::post, Extension, Router};
+//! use detached_jws::{openssl::OpensslVerificationKey, tower::VerifyJwsLayer, JwsHeader};
+//!
+//! let key = Arc::new(OpensslVerificationKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap());
+//!
+//! let app: Router = Router::new()
+//!     .route(
+//!         "/payments",
+//!         post(|Extension(header): Extension<JwsHeader>, body: String| async move {
+//!             format!("{:?} signed {}", header.get("kid"), body)
+//!         }),
+//!     )
+//!     .layer(VerifyJwsLayer::new(move |_: &JwsHeader| Some(key.clone())));
+//! ```
+//!
+//! # Signing responses with axum:
+//! ```
+//! use std::sync::Arc;
+//! use axum::{routing::get, Router};
+//! use serde_json::json;
+//! use detached_jws::{openssl::OpensslSigningKey, tower::SignJwsLayer};
+//!
+//! let key = Arc::new(OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap());
+//!
+//! let app: Router = Router::new()
+//!     .route(
+//!         "/accounts",
+//!         get(|| async { "accounts" })
+//!             .layer(SignJwsLayer::new(key.clone()).header_member("kid", json!("key-1"))),
+//!     )
+//!     .route(
+//!         "/statements",
+//!         get(|| async { "statements" })
+//!             .layer(SignJwsLayer::new(key).issued_at().trailers()),
+//!     );
+//! ```
+
+use anyhow::{anyhow, Error};
+use bytes::{Buf, Bytes, BytesMut};
+use http::{
+    header::{self, HeaderName, HeaderValue},
+    HeaderMap, Request, Response, StatusCode,
+};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::BodyExt;
+use serde_json::Value;
+use std::{
+    fmt,
+    future::Future,
+    io::Write,
+    marker::PhantomData,
+    pin::{pin, Pin},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::decode::{key_selector, DecodedJws};
+use crate::{
+    DecodeOptions, DeserializeJwsWriter, JwsHeader, KeyResolver, SerializeJwsWriter, SigningKey,
+    VerificationKey, JWS_SIGNATURE_HEADER,
+};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Why [`VerifyJwsLayer`] rejected a request
+#[derive(Debug)]
+pub enum Rejection {
+    /// The request has no signature header
+    MissingSignature,
+    /// The signature header is not a well formed detached jws
+    MalformedSignature(Error),
+    /// The body could not be read or exceeds the configured limit
+    Body(Error),
+    /// No key was found for the header or the signature does not match the body
+    Unauthorized(Error),
+}
+
+impl Rejection {
+    /// `401 Unauthorized` for [`Rejection::Unauthorized`], `400 Bad Request` otherwise
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Rejection::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::MissingSignature => f.write_str("missing signature"),
+            Rejection::MalformedSignature(_) => f.write_str("malformed signature"),
+            Rejection::Body(_) => f.write_str("unreadable body"),
+            Rejection::Unauthorized(_) => f.write_str("invalid signature"),
+        }
+    }
+}
+
+type RejectionHandler = dyn Fn(&Rejection) -> (StatusCode, String) + Send + Sync;
+
+/// A [`Layer`] verifying the detached jws of request bodies
+///
+/// The body is streamed into the verifier as it arrives. Verified requests are passed on
+/// with the buffered body and the verified [`JwsHeader`] in their extensions;
+/// others are answered with the status and text of [`Rejection`].
+pub struct VerifyJwsLayer<R> {
+    resolver: Arc<R>,
+    header_name: HeaderName,
+    options: DecodeOptions,
+    max_body_len: usize,
+    rejection: Arc<RejectionHandler>,
+}
+
+impl<R> VerifyJwsLayer<R>
+where
+    R: KeyResolver,
+{
+    pub fn new(resolver: R) -> Self {
+        Self {
+            resolver: Arc::new(resolver),
+            header_name: HeaderName::from_static(JWS_SIGNATURE_HEADER),
+            options: DecodeOptions::default(),
+            max_body_len: 2 * 1024 * 1024,
+            rejection: Arc::new(|r| (r.status(), r.to_string())),
+        }
+    }
+
+    /// Read the detached jws from `name` instead of [`JWS_SIGNATURE_HEADER`]
+    pub fn header_name(mut self, name: HeaderName) -> Self {
+        self.header_name = name;
+        self
+    }
+
+    pub fn decode_options(mut self, options: DecodeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Reject bodies longer than `len` bytes, 2 MiB by default
+    pub fn max_body_len(mut self, len: usize) -> Self {
+        self.max_body_len = len;
+        self
+    }
+
+    /// Build the status and text of rejection responses
+    pub fn rejection<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&Rejection) -> (StatusCode, String) + Send + Sync + 'static,
+    {
+        self.rejection = Arc::new(handler);
+        self
+    }
+}
+
+impl<R> Clone for VerifyJwsLayer<R> {
+    fn clone(&self) -> Self {
+        Self {
+            resolver: Arc::clone(&self.resolver),
+            header_name: self.header_name.clone(),
+            options: self.options.clone(),
+            max_body_len: self.max_body_len,
+            rejection: Arc::clone(&self.rejection),
+        }
+    }
+}
+
+impl<S, R> Layer<S> for VerifyJwsLayer<R> {
+    type Service = VerifyJws<S, R>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        VerifyJws {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The [`Service`] created by [`VerifyJwsLayer`]
+pub struct VerifyJws<S, R> {
+    inner: S,
+    config: VerifyJwsLayer<R>,
+}
+
+impl<S: Clone, R> Clone for VerifyJws<S, R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<S, R, B, ResBody> Service<Request<B>> for VerifyJws<S, R>
+where
+    S: Service<Request<B>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    R: KeyResolver + 'static,
+    for<'k> <R::Key as VerificationKey>::Verifier<'k>: Send,
+    B: Body + From<Bytes> + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    ResBody: From<String>,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        // the ready service goes into the future, the fresh clone waits for the next poll_ready
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+
+            match verify_body(&config, &parts.headers, body).await {
+                Ok((header, body)) => {
+                    parts.extensions.insert(header);
+                    inner.call(Request::from_parts(parts, B::from(body))).await
+                }
+                Err(rejection) => {
+                    let (status, text) = (config.rejection)(&rejection);
+                    let mut response = Response::new(ResBody::from(text));
+                    *response.status_mut() = status;
+                    Ok(response)
+                }
+            }
+        })
+    }
+}
+
+async fn verify_body<R, B>(
+    config: &VerifyJwsLayer<R>,
+    headers: &HeaderMap,
+    body: B,
+) -> Result<(JwsHeader, Bytes), Rejection>
+where
+    R: KeyResolver,
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    let jws = headers
+        .get(&config.header_name)
+        .ok_or(Rejection::MissingSignature)?
+        .as_bytes();
+
+    let decoded = DecodedJws::new(&jws, &config.options).map_err(Rejection::MalformedSignature)?;
+
+    let key = config
+        .resolver
+        .resolve(&decoded.header)
+        .map_err(Rejection::Unauthorized)?;
+    if decoded.header.get("alg").and_then(|v| v.as_str()) != Some(key.algorithm()) {
+        return Err(Rejection::Unauthorized(anyhow!(
+            "algorithm does not match the key"
+        )));
+    }
+
+    let mut writer = key_selector(key.as_ref())
+        .and_then(|selector| DeserializeJwsWriter::with_decoded(decoded, selector))
+        .map_err(Rejection::MalformedSignature)?;
+
+    let mut buffer = BytesMut::new();
+    let mut body = pin!(body);
+
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|e| Rejection::Body(anyhow!(e.into())))?;
+        if let Ok(mut data) = frame.into_data() {
+            if buffer.len() + data.remaining() > config.max_body_len {
+                return Err(Rejection::Body(anyhow!(
+                    "body exceeds {} bytes",
+                    config.max_body_len
+                )));
+            }
+            while data.has_remaining() {
+                let chunk = data.chunk();
+                writer
+                    .write_all(chunk)
+                    .map_err(|e| Rejection::Unauthorized(e.into()))?;
+                buffer.extend_from_slice(chunk);
+                let len = chunk.len();
+                data.advance(len);
+            }
+        }
+    }
+
+    let header = writer.finish().map_err(Rejection::Unauthorized)?;
+
+    Ok((header, buffer.freeze()))
+}
+
+/// Why [`SignJwsLayer`] answered `500 Internal Server Error` instead of the inner response
+#[derive(Debug, Clone)]
+pub struct SigningError(pub Arc<Error>);
+
+impl fmt::Display for SigningError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "response could not be signed: {}", self.0)
+    }
+}
+
+impl std::error::Error for SigningError {}
+
+/// A [`Layer`] signing response bodies with a detached jws
+///
+/// By default the body is streamed into the signer and buffered, and the detached jws
+/// is sent in a header. With [`trailers`](SignJwsLayer::trailers) each frame is streamed
+/// into the signer and passed on without buffering, and the detached jws follows as an
+/// HTTP trailer.
+///
+/// A body that can not be signed, e.g. one longer than
+/// [`max_body_len`](SignJwsLayer::max_body_len), is answered with an empty
+/// `500 Internal Server Error`. The status and headers of the inner response are dropped as
+/// they describe the unsent body; the cause is left in the response extensions as a
+/// [`SigningError`] for an outer layer to log.
+///
+/// Apply one layer per route to give routes their own header members.
+pub struct SignJwsLayer<K> {
+    key: Arc<K>,
+    header_name: HeaderName,
+    members: JwsHeader,
+    issued_at: bool,
+    trailers: bool,
+    max_body_len: usize,
+}
+
+impl<K> SignJwsLayer<K>
+where
+    K: SigningKey,
+{
+    pub fn new(key: Arc<K>) -> Self {
+        Self {
+            key,
+            header_name: HeaderName::from_static(JWS_SIGNATURE_HEADER),
+            members: JwsHeader::new(),
+            issued_at: false,
+            trailers: false,
+            max_body_len: 2 * 1024 * 1024,
+        }
+    }
+
+    /// Send the detached jws in `name` instead of [`JWS_SIGNATURE_HEADER`]
+    pub fn header_name(mut self, name: HeaderName) -> Self {
+        self.header_name = name;
+        self
+    }
+
+    /// Add a member, e.g. `kid`, to the protected header of every response
+    pub fn header_member(mut self, name: &str, value: Value) -> Self {
+        self.members.insert(name.to_owned(), value);
+        self
+    }
+
+    /// Add the signing time as `iat` to the protected header
+    pub fn issued_at(mut self) -> Self {
+        self.issued_at = true;
+        self
+    }
+
+    /// Send the detached jws as an HTTP trailer instead of a header
+    pub fn trailers(mut self) -> Self {
+        self.trailers = true;
+        self
+    }
+
+    /// Answer `500 Internal Server Error` for bodies longer than `len` bytes, 2 MiB by default
+    ///
+    /// In [`trailers`](Self::trailers) mode the status is already sent, so a longer body is
+    /// cut off with an error instead.
+    pub fn max_body_len(mut self, len: usize) -> Self {
+        self.max_body_len = len;
+        self
+    }
+
+    fn header(&self) -> JwsHeader {
+        let mut header = self.members.clone();
+        if self.issued_at {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            header.insert("iat".to_owned(), Value::from(now));
+        }
+        header
+    }
+}
+
+impl<K> Clone for SignJwsLayer<K> {
+    fn clone(&self) -> Self {
+        Self {
+            key: Arc::clone(&self.key),
+            header_name: self.header_name.clone(),
+            members: self.members.clone(),
+            issued_at: self.issued_at,
+            trailers: self.trailers,
+            max_body_len: self.max_body_len,
+        }
+    }
+}
+
+impl<S, K> Layer<S> for SignJwsLayer<K> {
+    type Service = SignJws<S, K>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SignJws {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The [`Service`] created by [`SignJwsLayer`]
+pub struct SignJws<S, K> {
+    inner: S,
+    config: SignJwsLayer<K>,
+}
+
+impl<S: Clone, K> Clone for SignJws<S, K> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<S, K, ReqBody, B> Service<Request<ReqBody>> for SignJws<S, K>
+where
+    S: Service<Request<ReqBody>, Response = Response<B>> + Clone + Send + 'static,
+    S::Future: Send,
+    K: SigningKey + 'static,
+    for<'k> K::Signer<'k>: Send,
+    ReqBody: Send + 'static,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response<SignedBody<B>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        // the ready service goes into the future, the fresh clone waits for the next poll_ready
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let (mut parts, body) = inner.call(request).await?.into_parts();
+
+            if config.trailers {
+                let name = config.header_name.clone();
+                parts.headers.remove(header::CONTENT_LENGTH);
+                parts
+                    .headers
+                    .insert(header::TRAILER, HeaderValue::from_name(name.clone()));
+
+                return Ok(Response::from_parts(
+                    parts,
+                    SignedBody::trailers(config, body, name),
+                ));
+            }
+
+            match sign_body(&config, body).await {
+                Ok((jws, body)) => {
+                    parts.headers.insert(config.header_name.clone(), jws);
+                    Ok(Response::from_parts(parts, SignedBody::full(body)))
+                }
+                Err(err) => {
+                    let mut response = Response::new(SignedBody::full(Bytes::new()));
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    response
+                        .extensions_mut()
+                        .insert(SigningError(Arc::new(err)));
+                    Ok(response)
+                }
+            }
+        })
+    }
+}
+
+async fn sign_body<K, B>(config: &SignJwsLayer<K>, body: B) -> anyhow::Result<(HeaderValue, Bytes)>
+where
+    K: SigningKey,
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    let key = config.key.as_ref();
+    let mut writer = SerializeJwsWriter::new(
+        Vec::new(),
+        key.algorithm().to_owned(),
+        config.header(),
+        key.signer()?,
+    )?;
+
+    let mut buffer = BytesMut::new();
+    let mut body = pin!(body);
+
+    while let Some(frame) = body.frame().await {
+        if let Ok(mut data) = frame.map_err(|e| anyhow!(e.into()))?.into_data() {
+            if buffer.len() + data.remaining() > config.max_body_len {
+                anyhow::bail!("body exceeds {} bytes", config.max_body_len)
+            }
+            while data.has_remaining() {
+                let chunk = data.chunk();
+                writer.write_all(chunk)?;
+                buffer.extend_from_slice(chunk);
+                let len = chunk.len();
+                data.advance(len);
+            }
+        }
+    }
+
+    let jws = HeaderValue::from_bytes(&writer.finish()?)?;
+
+    Ok((jws, buffer.freeze()))
+}
+
+/// Signs the frames of a body as they are passed on and resolves to the detached jws
+///
+/// Only polled by [`SignedBody`]; after each data frame it puts the frame in `slot` and
+/// yields once so the frame is sent before the next one is read.
+type SigningFuture = Pin<Box<dyn Future<Output = anyhow::Result<Vec<u8>>> + Send>>;
+
+/// A response body produced by [`SignJwsLayer`]
+pub struct SignedBody<B> {
+    state: SignedBodyState,
+    _body: PhantomData<fn() -> B>,
+}
+
+enum SignedBodyState {
+    Full(Option<Bytes>),
+    Trailers {
+        signing: Option<SigningFuture>,
+        slot: Arc<Mutex<Option<Bytes>>>,
+        header_name: HeaderName,
+    },
+}
+
+impl<B> SignedBody<B> {
+    fn full(body: Bytes) -> Self {
+        Self {
+            state: SignedBodyState::Full(Some(body).filter(|b| !b.is_empty())),
+            _body: PhantomData,
+        }
+    }
+
+    fn trailers<K>(config: SignJwsLayer<K>, inner: B, header_name: HeaderName) -> Self
+    where
+        K: SigningKey + 'static,
+        for<'k> K::Signer<'k>: Send,
+        B: Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<BoxError>,
+    {
+        let slot = Arc::new(Mutex::new(None));
+        let produced = Arc::clone(&slot);
+
+        let signing = async move {
+            let key = config.key.as_ref();
+            let mut writer = SerializeJwsWriter::new(
+                Vec::new(),
+                key.algorithm().to_owned(),
+                config.header(),
+                key.signer()?,
+            )?;
+
+            let mut len = 0;
+            let mut inner = pin!(inner);
+            // trailers of the inner body are replaced by the signature
+            loop {
+                let data = match inner.frame().await {
+                    Some(frame) => match frame.map_err(|e| anyhow!(e.into()))?.into_data() {
+                        Ok(mut data) => data.copy_to_bytes(data.remaining()),
+                        Err(_) => continue,
+                    },
+                    None => break,
+                };
+                len += data.len();
+                if len > config.max_body_len {
+                    anyhow::bail!("body exceeds {} bytes", config.max_body_len)
+                }
+                writer.write_all(&data)?;
+                *produced.lock().unwrap() = Some(data);
+                YieldOnce(false).await;
+            }
+
+            writer.finish()
+        };
+
+        Self {
+            state: SignedBodyState::Trailers {
+                signing: Some(Box::pin(signing)),
+                slot,
+                header_name,
+            },
+            _body: PhantomData,
+        }
+    }
+}
+
+/// Returns `Pending` once, for [`SignedBody`] to send the produced frame
+///
+/// The task is woken first so that a caller which does not take the frame still polls again.
+struct YieldOnce(bool);
+
+impl Future for YieldOnce {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+impl<B> Body for SignedBody<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let (signing, slot, header_name) = match self.get_mut().state {
+            SignedBodyState::Full(ref mut body) => {
+                return Poll::Ready(body.take().map(|b| Ok(Frame::data(b))))
+            }
+            SignedBodyState::Trailers {
+                ref mut signing,
+                ref slot,
+                ref header_name,
+            } => (signing, slot, header_name),
+        };
+
+        let future = match signing {
+            Some(future) => future,
+            None => return Poll::Ready(None),
+        };
+
+        match future.as_mut().poll(cx) {
+            Poll::Pending => match slot.lock().unwrap().take() {
+                Some(data) => Poll::Ready(Some(Ok(Frame::data(data)))),
+                None => Poll::Pending,
+            },
+            Poll::Ready(result) => {
+                *signing = None;
+                let jws = result.map_err(BoxError::from)?;
+                let mut trailers = HeaderMap::new();
+                trailers.insert(header_name.clone(), HeaderValue::from_bytes(&jws)?);
+                Poll::Ready(Some(Ok(Frame::trailers(trailers))))
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self.state {
+            SignedBodyState::Full(ref body) => body.is_none(),
+            SignedBodyState::Trailers { ref signing, .. } => signing.is_none(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.state {
+            SignedBodyState::Full(Some(ref body)) => SizeHint::with_exact(body.len() as u64),
+            SignedBodyState::Full(None) => SizeHint::with_exact(0),
+            // no exact size, which would let the server send a content length and drop trailers
+            SignedBodyState::Trailers { .. } => SizeHint::new(),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Detailed outcome of signature verification
+
+use alloc::{borrow::ToOwned, string::String};
+use core::fmt;
+
+use crate::JwsHeader;
+
+/// Why a signature was rejected
+#[derive(Debug)]
+pub enum FailureReason {
+    /// The signature does not have the length produced by the algorithm and key
+    WrongLength { expected: usize, actual: usize },
+    /// The signature does not match the header and payload
+    Mismatch,
+    /// The verification backend failed before reaching a decision
+    Backend(anyhow::Error),
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureReason::WrongLength { expected, actual } => write!(
+                f,
+                "wrong signature length {}, expected {}",
+                actual, expected
+            ),
+            FailureReason::Mismatch => f.write_str("incorrect signature"),
+            FailureReason::Backend(e) => write!(f, "signature verification failed: {}", e),
+        }
+    }
+}
+
+/// A successfully verified detached jws
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verification {
+    /// The `alg` header the signature was verified with
+    pub algorithm: Option<String>,
+    /// The `kid` header of the key that matched
+    pub key_id: Option<String>,
+    /// The verified protected header
+    pub header: JwsHeader,
+}
+
+impl Verification {
+    pub(crate) fn new(header: JwsHeader) -> Self {
+        Self {
+            algorithm: header_str(&header, "alg"),
+            key_id: header_str(&header, "kid"),
+            header,
+        }
+    }
+}
+
+/// A detached jws whose signature was rejected
+///
+/// Returned wrapped in [`anyhow::Error`] and can be recovered with `downcast_ref`.
+#[derive(Debug)]
+pub struct VerificationError {
+    /// The `alg` header the signature was checked with
+    pub algorithm: Option<String>,
+    /// The `kid` header of the key that was selected
+    pub key_id: Option<String>,
+    pub reason: FailureReason,
+}
+
+impl VerificationError {
+    pub(crate) fn new(header: &JwsHeader, reason: FailureReason) -> Self {
+        Self {
+            algorithm: header_str(header, "alg"),
+            key_id: header_str(header, "kid"),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.reason.fmt(f)
+    }
+}
+
+impl core::error::Error for VerificationError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self.reason {
+            FailureReason::Backend(ref e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+fn header_str(header: &JwsHeader, name: &str) -> Option<String> {
+    header.get(name).and_then(|v| v.as_str()).map(str::to_owned)
+}
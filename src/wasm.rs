@@ -0,0 +1,88 @@
+//! [wasm-bindgen](https://crates.io/crates/wasm-bindgen) exports for JavaScript
+//!
+//! Build for `wasm32-unknown-unknown` with `default-features = false, features = ["wasm"]`;
+//! keys are JWK objects handled by [`crate::rustcrypto`] and payloads are `Uint8Array`s.
+//! Errors are thrown as JavaScript `Error`s.
+//!
+//! ```js
+//! import { sign, verify } from "detached_jws";
+//!
+//! const jwk = { kty: "oct", k: "AyM1SysPpbyDfgZld3umj1qzKObwVMkoqQ-EstJQLr_T-1qS0gZH75aKtMN3Yj0iPS4hcgUuTwjAzZr1Z9CAow" };
+//! const payload = new TextEncoder().encode("payload");
+//!
+//! const jws = sign("HS256", jwk, { kid: "hmac" }, payload);
+//! const header = verify("HS256", jwk, jws, payload); // { alg: "HS256", kid: "hmac" }
+//! ```
+
+use alloc::{format, string::String, vec::Vec};
+use anyhow::{Context, Error, Result};
+use js_sys::{Object, JSON};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    rustcrypto::{RustCryptoSigningKey, RustCryptoVerificationKey},
+    ByteSink, DecodeOptions, DeserializeJwsWriter, JwsHeader, SerializeJwsWriter, SigningKey,
+};
+
+/// Sign `payload` with a private JWK and return the detached jws
+///
+/// `header` holds extra protected header members; `alg` is set to `algorithm`.
+#[wasm_bindgen]
+pub fn sign(
+    algorithm: &str,
+    jwk: &Object,
+    header: Option<Object>,
+    payload: &[u8],
+) -> Result<String, JsError> {
+    let run = || -> Result<String> {
+        let key = RustCryptoSigningKey::from_jwk(algorithm, &from_js(jwk)?)?;
+        let header = match header {
+            Some(ref header) => from_js(header)?,
+            None => JwsHeader::new(),
+        };
+
+        let mut writer =
+            SerializeJwsWriter::new(Vec::new(), algorithm.into(), header, key.signer()?)?;
+        writer.update(payload)?;
+
+        String::from_utf8(writer.finish()?).map_err(Error::msg)
+    };
+
+    run().map_err(to_js)
+}
+
+/// Verify a detached jws over `payload` with a public JWK and return its protected header
+///
+/// The jws is rejected unless its `alg` header equals `algorithm`.
+#[wasm_bindgen]
+pub fn verify(
+    algorithm: &str,
+    jwk: &Object,
+    jws: &str,
+    payload: &[u8],
+) -> Result<JsValue, JsError> {
+    let run = || -> Result<String> {
+        let key = RustCryptoVerificationKey::from_jwk(algorithm, &from_js(jwk)?)?;
+
+        let mut writer = DeserializeJwsWriter::with_key(&jws, &key, &DecodeOptions::default())?;
+        writer.update(payload)?;
+
+        Ok(serde_json::to_string(&writer.finish()?)?)
+    };
+
+    let header = run().map_err(to_js)?;
+    JSON::parse(&header).map_err(|_| JsError::new("header is not valid JSON"))
+}
+
+/// Read a JavaScript object as a JSON object
+fn from_js(value: &Object) -> Result<JwsHeader> {
+    let json = JSON::stringify(value)
+        .ok()
+        .and_then(|json| json.as_string())
+        .context("object can not be converted to JSON")?;
+    serde_json::from_str(&json).context("object is not a JSON object")
+}
+
+fn to_js(e: Error) -> JsError {
+    JsError::new(&format!("{:#}", e))
+}
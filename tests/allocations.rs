@@ -0,0 +1,95 @@
+//! Counts heap allocations of the borrowed decode path with a global allocator
+
+use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+use detached_jws::{DecodeOptions, DetachedJwsRef, VerificationKey};
+use serde_json::{json, Map};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+#[derive(serde::Deserialize)]
+struct Selected<'a> {
+    kid: &'a str,
+    alg: &'a str,
+}
+
+#[test]
+fn borrowed_decode_does_not_allocate() {
+    let mut header = Map::new();
+    header.insert("kid".to_owned(), json!("key-1"));
+    let key = OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+    let jws = detached_jws::serialize_with_key(header, &mut &b"payload"[..], &key).unwrap();
+    let options = DecodeOptions::default();
+
+    let ((kid, alg, signature_len), count) = allocations(|| {
+        let view = DetachedJwsRef::parse(&jws, &options).unwrap();
+        let mut buf = [0; 256];
+        let Selected { kid, alg } = view.header_as(&mut buf).unwrap();
+        let signature = view.signature().unwrap();
+        (kid == "key-1", alg == "HS256", signature.len())
+    });
+
+    assert!(kid && alg);
+    assert_eq!(signature_len, 32);
+    assert_eq!(count, 0);
+
+    let (header, count) = allocations(|| detached_jws::decode_header(&jws, &options).unwrap());
+    assert_eq!(header["kid"], "key-1");
+    assert!(count > 0);
+}
+
+#[test]
+fn borrowed_verify_does_not_allocate() {
+    let payload = vec![7u8; 100_000];
+    let mut header = Map::new();
+    header.insert("kid".to_owned(), json!("key-1"));
+    let key = OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+    let jws = detached_jws::serialize_with_key(header, &mut payload.as_slice(), &key).unwrap();
+    let key =
+        OpensslVerificationKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+    let options = DecodeOptions::default();
+
+    for len in [0, 5, 3 * 1024, 100_000] {
+        let jws = detached_jws::serialize_with_key(
+            Map::new(),
+            &mut &payload[..len],
+            &OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap(),
+        )
+        .unwrap();
+        let view = DetachedJwsRef::parse(&jws, &options).unwrap();
+        let (result, count) = allocations(|| view.verify_with_key(&mut &payload[..len], &key));
+        result.unwrap();
+        assert_eq!(count, 0, "{} byte payload", len);
+    }
+
+    let view = DetachedJwsRef::parse(&jws, &options).unwrap();
+    let verifier = key.verifier().unwrap();
+    let (result, count) = allocations(|| view.verify(&mut payload.as_slice(), verifier));
+    result.unwrap();
+    assert_eq!(count, 0);
+}
@@ -0,0 +1,133 @@
+//! Signature examples of RFC 7515 Appendix A and RFC 7520 Section 4
+//!
+//! The vectors in `data/jws-vectors.json` are the compact serializations from the RFCs
+//! together with their private keys as JWKs; the payload is detached before verification.
+
+use std::io::Write;
+
+use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+use detached_jws::{DecodeOptions, JwsHeader, SerializeJwsWriter, SigningKey, VerificationKey};
+use openssl::bn::BigNum;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Deserialize)]
+struct Vector {
+    name: String,
+    alg: String,
+    /// Whether signing the same input again yields the same signature
+    deterministic: bool,
+    jwk: JwsHeader,
+    jws: String,
+    /// Payload of a jws already published with detached content
+    payload: Option<String>,
+}
+
+impl Vector {
+    /// The jws with its payload segment emptied, and the payload itself
+    fn detach(&self) -> (String, Vec<u8>) {
+        let segments: Vec<&str> = self.jws.split('.').collect();
+        assert_eq!(segments.len(), 3, "{}", self.name);
+
+        let payload = self.payload.as_deref().unwrap_or(segments[1]);
+        let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).unwrap();
+
+        (format!("{}..{}", segments[0], segments[2]), payload)
+    }
+
+    fn signing_key(&self) -> OpensslSigningKey {
+        let member = |name: &str| {
+            let value = self.jwk[name].as_str().unwrap();
+            base64::decode_config(value, base64::URL_SAFE_NO_PAD).unwrap()
+        };
+        let number = |name: &str| BigNum::from_slice(&member(name)).unwrap();
+
+        match self.jwk["kty"].as_str() {
+            Some("oct") => OpensslSigningKey::hmac(&self.alg, &member("k")).unwrap(),
+            Some("RSA") => {
+                let rsa = Rsa::from_private_components(
+                    number("n"),
+                    number("e"),
+                    number("d"),
+                    number("p"),
+                    number("q"),
+                    number("dp"),
+                    number("dq"),
+                    number("qi"),
+                )
+                .unwrap();
+                OpensslSigningKey::new(&self.alg, PKey::from_rsa(rsa).unwrap()).unwrap()
+            }
+            kty => panic!("{}: no deterministic signing for {:?}", self.name, kty),
+        }
+    }
+}
+
+fn vectors() -> Vec<Vector> {
+    serde_json::from_str(include_str!("data/jws-vectors.json")).unwrap()
+}
+
+#[test]
+fn verify_rfc_examples() {
+    for vector in vectors() {
+        let (jws, payload) = vector.detach();
+        let key = OpensslVerificationKey::from_jwk(&vector.alg, &vector.jwk).unwrap();
+
+        let header =
+            detached_jws::deserialize(&jws, &mut payload.as_slice(), key.verifier().unwrap())
+                .unwrap_or_else(|e| panic!("{}: {:?}", vector.name, e));
+        assert_eq!(header["alg"], Value::String(vector.alg.clone()));
+
+        detached_jws::deserialize_with_key(&jws, &mut payload.as_slice(), &key).unwrap();
+
+        let mut tampered = payload.clone();
+        tampered[0] ^= 1;
+        assert!(
+            detached_jws::deserialize_with_key(&jws, &mut tampered.as_slice(), &key).is_err(),
+            "{}",
+            vector.name
+        );
+    }
+}
+
+#[test]
+fn reproduce_deterministic_rfc_examples() {
+    for vector in vectors().into_iter().filter(|v| v.deterministic) {
+        let (jws, payload) = vector.detach();
+        let key = vector.signing_key();
+        let encoded_header = jws.split('.').next().unwrap();
+
+        // the published header bytes, whitespace included
+        let mut writer = SerializeJwsWriter::with_encoded_header(
+            Vec::new(),
+            encoded_header.as_bytes(),
+            key.signer().unwrap(),
+        )
+        .unwrap();
+        writer.write_all(&payload).unwrap();
+        assert_eq!(
+            String::from_utf8(writer.finish().unwrap()).unwrap(),
+            jws,
+            "{}",
+            vector.name
+        );
+
+        // headers published in compact form with sorted members are produced by `serialize`
+        let header = detached_jws::decode_header(&jws, &DecodeOptions::default()).unwrap();
+        let decoded = base64::decode_config(encoded_header, base64::URL_SAFE_NO_PAD).unwrap();
+        if serde_json::to_vec(&header).unwrap() == decoded {
+            let mut header = header;
+            header.remove("alg");
+            let serialized =
+                detached_jws::serialize_with_key(header, &mut payload.as_slice(), &key).unwrap();
+            assert_eq!(
+                String::from_utf8(serialized).unwrap(),
+                jws,
+                "{}",
+                vector.name
+            );
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Encryption examples of RFC 7520 Section 5 and round trips of every supported algorithm
+//!
+//! The vectors in `data/jwe-vectors.json` are the compact serializations from the RFC together
+//! with the recipients' private keys as JWKs; the ciphertext is detached before decryption.
+
+use std::io::Write;
+
+use detached_jws::jwe::{
+    self, DecryptJweWriter, DecryptVerifyWriter, EncryptJweWriter, JweDecryptionKey,
+    JweEncryptionKey, SignEncryptWriter,
+};
+use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+use detached_jws::{DecodeOptions, DeserializeJwsWriter, JwsHeader, SigningKey, VerificationError};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use serde::Deserialize;
+use serde_json::{json, Map};
+
+/// Figure 72 of RFC 7520
+const PLAINTEXT: &str = "You can trust us to stick with you through thick and \
+    thin\u{2013}to the bitter end. And you can trust us to keep any secret of yours\u{2013}\
+    closer than you keep it yourself. But you cannot trust us to let you face trouble alone, \
+    and go off without a word. We are your friends, Frodo.";
+
+#[derive(Deserialize)]
+struct Vector {
+    name: String,
+    alg: String,
+    jwk: JwsHeader,
+    jwe: String,
+}
+
+impl Vector {
+    /// The jwe with its ciphertext segment emptied, and the ciphertext itself
+    fn detach(&self) -> (String, Vec<u8>) {
+        let segments: Vec<&str> = self.jwe.split('.').collect();
+        let ciphertext = base64::decode_config(segments[3], base64::URL_SAFE_NO_PAD).unwrap();
+
+        (
+            format!(
+                "{}.{}.{}..{}",
+                segments[0], segments[1], segments[2], segments[4]
+            ),
+            ciphertext,
+        )
+    }
+}
+
+fn vectors() -> Vec<Vector> {
+    serde_json::from_str(include_str!("data/jwe-vectors.json")).unwrap()
+}
+
+fn payload() -> Vec<u8> {
+    (0..100_000u32).map(|i| (i % 251) as u8).collect()
+}
+
+fn ec_key(curve: Nid) -> PKey<Private> {
+    let group = EcGroup::from_curve_name(curve).unwrap();
+    PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+}
+
+/// A key pair for `alg`
+fn keys(alg: &str) -> (JweEncryptionKey, JweDecryptionKey) {
+    let key = match alg {
+        "A256KW" => {
+            let secret = [3; 32];
+            return (
+                JweEncryptionKey::secret(alg, &secret).unwrap(),
+                JweDecryptionKey::secret(alg, &secret).unwrap(),
+            );
+        }
+        "RSA-OAEP" => PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap(),
+        _ => ec_key(Nid::X9_62_PRIME256V1),
+    };
+
+    (
+        JweEncryptionKey::new(alg, &key).unwrap(),
+        JweDecryptionKey::new(alg, key).unwrap(),
+    )
+}
+
+fn encrypt_chunked(
+    enc: &str,
+    header: JwsHeader,
+    payload: &[u8],
+    key: &JweEncryptionKey,
+) -> (Vec<u8>, Vec<u8>) {
+    let mut writer = EncryptJweWriter::new(Vec::new(), enc, header, key).unwrap();
+    for chunk in payload.chunks(7_000) {
+        writer.write_all(chunk).unwrap();
+    }
+    writer.finish().unwrap()
+}
+
+#[test]
+fn decrypt_rfc_examples() {
+    for vector in vectors() {
+        let (jwe, ciphertext) = vector.detach();
+        let key = JweDecryptionKey::from_jwk(&vector.alg, &vector.jwk)
+            .unwrap_or_else(|e| panic!("{}: {:?}", vector.name, e));
+
+        let (header, plaintext) = jwe::decrypt(&jwe, &mut ciphertext.as_slice(), &key)
+            .unwrap_or_else(|e| panic!("{}: {:?}", vector.name, e));
+        assert_eq!(header["alg"], vector.alg.as_str());
+        assert_eq!(String::from_utf8(plaintext).unwrap(), PLAINTEXT);
+
+        let mut tampered = ciphertext.clone();
+        tampered[0] ^= 1;
+        assert!(
+            jwe::decrypt(&jwe, &mut tampered.as_slice(), &key).is_err(),
+            "{}",
+            vector.name
+        );
+        assert!(
+            jwe::decrypt(&jwe, &mut &ciphertext[..ciphertext.len() - 16], &key).is_err(),
+            "{}",
+            vector.name
+        );
+    }
+}
+
+#[test]
+fn encrypt_to_rfc_example_keys() {
+    for vector in vectors() {
+        let key = JweEncryptionKey::from_jwk(&vector.alg, &vector.jwk).unwrap();
+        let (jwe, ciphertext) =
+            jwe::encrypt("A256GCM", Map::new(), &mut PLAINTEXT.as_bytes(), &key).unwrap();
+
+        let key = JweDecryptionKey::from_jwk(&vector.alg, &vector.jwk).unwrap();
+        let (_, plaintext) = jwe::decrypt(&jwe, &mut ciphertext.as_slice(), &key).unwrap();
+        assert_eq!(plaintext, PLAINTEXT.as_bytes(), "{}", vector.name);
+    }
+}
+
+#[test]
+fn round_trip() {
+    let payload = payload();
+
+    for alg in ["RSA-OAEP", "ECDH-ES", "A256KW"] {
+        let (encryption_key, decryption_key) = keys(alg);
+        assert_eq!(encryption_key.algorithm(), alg);
+
+        for enc in ["A256GCM", "A128CBC-HS256"] {
+            let mut header = Map::new();
+            header.insert("kid".to_owned(), json!("key-1"));
+
+            let (jwe, ciphertext) = encrypt_chunked(enc, header, &payload, &encryption_key);
+            assert_eq!(jwe.split(|b| *b == b'.').nth(3), Some(&b""[..]));
+            assert_ne!(&ciphertext[..100], &payload[..100]);
+
+            let mut writer = DecryptJweWriter::new(&jwe, &decryption_key, Vec::new()).unwrap();
+            assert_eq!(writer.header()["enc"], enc);
+            for chunk in ciphertext.chunks(5_000) {
+                writer.write_all(chunk).unwrap();
+            }
+            let (header, plaintext) = writer.finish().unwrap();
+            assert_eq!(header["alg"], alg);
+            assert_eq!(header["kid"], "key-1");
+            assert_eq!(plaintext, payload, "{} {}", alg, enc);
+            assert!(writer.finish().is_err());
+
+            let mut tampered = ciphertext.clone();
+            *tampered.last_mut().unwrap() ^= 1;
+            assert!(
+                jwe::decrypt(&jwe, &mut tampered.as_slice(), &decryption_key).is_err(),
+                "{} {}",
+                alg,
+                enc
+            );
+        }
+    }
+}
+
+#[test]
+fn ecdh_es_curves_and_party_info() {
+    for curve in [Nid::X9_62_PRIME256V1, Nid::SECP384R1, Nid::SECP521R1] {
+        let key = ec_key(curve);
+        let encryption_key = JweEncryptionKey::new("ECDH-ES", &key).unwrap();
+        let decryption_key = JweDecryptionKey::new("ECDH-ES", key).unwrap();
+
+        let mut header = Map::new();
+        header.insert("apu".to_owned(), json!("QWxpY2U"));
+        header.insert("apv".to_owned(), json!("Qm9i"));
+        let (jwe, ciphertext) = jwe::encrypt(
+            "A128CBC-HS256",
+            header,
+            &mut &b"payload"[..],
+            &encryption_key,
+        )
+        .unwrap();
+
+        let (header, plaintext) =
+            jwe::decrypt(&jwe, &mut ciphertext.as_slice(), &decryption_key).unwrap();
+        assert_eq!(header["epk"]["kty"], "EC");
+        assert_eq!(plaintext, b"payload");
+    }
+}
+
+#[test]
+fn sign_then_encrypt() {
+    let payload = payload();
+    let sender = ec_key(Nid::X9_62_PRIME256V1);
+    let signing_key = OpensslSigningKey::new("ES256", sender.clone()).unwrap();
+    let verification_key = OpensslVerificationKey::new("ES256", &sender).unwrap();
+    let (encryption_key, decryption_key) = keys("RSA-OAEP");
+
+    let mut writer = SignEncryptWriter::new(
+        Vec::new(),
+        "A256GCM",
+        Map::new(),
+        &encryption_key,
+        "ES256".to_owned(),
+        Map::new(),
+        signing_key.signer().unwrap(),
+    )
+    .unwrap();
+    for chunk in payload.chunks(7_001) {
+        writer.write_all(chunk).unwrap();
+    }
+    let (jwe, ciphertext) = writer.finish().unwrap();
+
+    // the plaintext is the compact jws with the payload attached
+    let (header, jws) = jwe::decrypt(&jwe, &mut &ciphertext[..], &decryption_key).unwrap();
+    assert_eq!(header["cty"], "JOSE");
+    let segments: Vec<&[u8]> = jws.split(|b| *b == b'.').collect();
+    assert_eq!(
+        segments[1],
+        base64::encode_config(&payload, base64::URL_SAFE_NO_PAD).as_bytes()
+    );
+    let detached = [segments[0], b"", segments[2]].join(&b'.');
+    let mut verifier =
+        DeserializeJwsWriter::with_key(&detached, &verification_key, &DecodeOptions::default())
+            .unwrap();
+    verifier.write_all(&payload).unwrap();
+    verifier.finish().unwrap();
+
+    // the nested jws is held in memory up to `max_token_len`
+    let options = DecodeOptions {
+        max_token_len: 256 * 1024,
+        ..DecodeOptions::default()
+    };
+    let verify = |ciphertext: &[u8], chunk_len: usize, key: &OpensslVerificationKey| {
+        let mut writer =
+            DecryptVerifyWriter::with_options(&jwe, &decryption_key, key, Vec::new(), &options)
+                .unwrap();
+        for chunk in ciphertext.chunks(chunk_len) {
+            writer.write_all(chunk)?;
+        }
+        writer.finish()
+    };
+
+    for chunk_len in [1, 5, 7_000, ciphertext.len()] {
+        let (header, decrypted) = verify(&ciphertext, chunk_len, &verification_key).unwrap();
+        assert_eq!(header["alg"], "ES256");
+        assert_eq!(decrypted, payload);
+    }
+
+    assert!(verify(
+        &ciphertext[..ciphertext.len() - 1],
+        7_000,
+        &verification_key
+    )
+    .is_err());
+
+    let other = OpensslVerificationKey::new("ES256", &ec_key(Nid::X9_62_PRIME256V1)).unwrap();
+    let err = verify(&ciphertext, 7_000, &other).unwrap_err();
+    assert!(err.downcast_ref::<VerificationError>().is_some());
+
+    let mut writer =
+        DecryptVerifyWriter::new(&jwe, &decryption_key, &verification_key, Vec::new()).unwrap();
+    let err = writer.write_all(&ciphertext).unwrap_err();
+    assert!(err.to_string().contains("jws exceeds"));
+
+    // tampered ciphertext is only reported by `finish`, whatever it decrypts to
+    for enc in ["A256GCM", "A128CBC-HS256"] {
+        let mut writer = SignEncryptWriter::new(
+            Vec::new(),
+            enc,
+            Map::new(),
+            &encryption_key,
+            "ES256".to_owned(),
+            Map::new(),
+            signing_key.signer().unwrap(),
+        )
+        .unwrap();
+        writer.write_all(b"payload").unwrap();
+        let (jwe, ciphertext) = writer.finish().unwrap();
+
+        for bit in 0..ciphertext.len() * 8 {
+            let mut tampered = ciphertext.clone();
+            tampered[bit / 8] ^= 1 << (bit % 8);
+
+            let mut writer =
+                DecryptVerifyWriter::new(&jwe, &decryption_key, &verification_key, Vec::new())
+                    .unwrap();
+            for chunk in tampered.chunks(5) {
+                writer.write_all(chunk).unwrap();
+            }
+            let err = writer.finish().unwrap_err();
+            assert_eq!(err.to_string(), "jwe authentication tag mismatch");
+        }
+    }
+
+    // a jwe without a nested jws
+    let (jwe, _) = encrypt_chunked("A256GCM", Map::new(), &payload, &encryption_key);
+    assert!(
+        DecryptVerifyWriter::new(&jwe, &decryption_key, &verification_key, Vec::new()).is_err()
+    );
+}
+
+#[test]
+fn rejected_input() {
+    let (encryption_key, decryption_key) = keys("A256KW");
+    let (jwe, ciphertext) =
+        jwe::encrypt("A256GCM", Map::new(), &mut &b"payload"[..], &encryption_key).unwrap();
+    let jwe = String::from_utf8(jwe).unwrap();
+
+    // another key encryption key fails to unwrap the content key
+    let other = JweDecryptionKey::secret("A256KW", &[4; 32]).unwrap();
+    assert!(jwe::decrypt(&jwe, &mut ciphertext.as_slice(), &other).is_err());
+
+    // a key for another algorithm
+    let (_, rsa) = keys("RSA-OAEP");
+    let err = DecryptJweWriter::new(&jwe, &rsa, Vec::new()).err().unwrap();
+    assert!(err.to_string().contains("does not match"));
+
+    let segments: Vec<&str> = jwe.split('.').collect();
+    let attached = format!(
+        "{}.{}.{}.{}.{}",
+        segments[0],
+        segments[1],
+        segments[2],
+        base64::encode_config(&ciphertext, base64::URL_SAFE_NO_PAD),
+        segments[4]
+    );
+    assert!(DecryptJweWriter::new(&attached, &decryption_key, Vec::new()).is_err());
+    assert!(DecryptJweWriter::new(&segments[..4].join("."), &decryption_key, Vec::new()).is_err());
+
+    // the protected header is authenticated
+    let header = base64::encode_config(
+        r#"{"alg":"A256KW","enc":"A256GCM","kid":"x"}"#,
+        base64::URL_SAFE_NO_PAD,
+    );
+    let forged = format!("{}.{}", header, segments[1..].join("."));
+    assert!(jwe::decrypt(&forged, &mut ciphertext.as_slice(), &decryption_key).is_err());
+
+    let mut zip = Map::new();
+    zip.insert("zip".to_owned(), json!("DEF"));
+    assert!(EncryptJweWriter::new(Vec::new(), "A256GCM", zip, &encryption_key).is_err());
+    assert!(EncryptJweWriter::new(Vec::new(), "A128GCM", Map::new(), &encryption_key).is_err());
+
+    assert!(JweEncryptionKey::secret("A256KW", &[0; 16]).is_err());
+    assert!(JweEncryptionKey::secret("RSA-OAEP", &[0; 32]).is_err());
+    assert!(JweEncryptionKey::new("RSA-OAEP", &ec_key(Nid::X9_62_PRIME256V1)).is_err());
+
+    // short RSA keys and unsupported curves are rejected up front
+    let short = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+    assert!(JweEncryptionKey::new("RSA-OAEP", &short).is_err());
+    assert!(JweDecryptionKey::new("RSA-OAEP", short).is_err());
+    let secp256k1 = ec_key(Nid::SECP256K1);
+    assert!(JweEncryptionKey::new("ECDH-ES", &secp256k1).is_err());
+    assert!(JweDecryptionKey::new("ECDH-ES", secp256k1).is_err());
+}
+
+#[test]
+fn ecdh_es_rejects_foreign_epk() {
+    let (encryption_key, _) = keys("ECDH-ES");
+    let (jwe, ciphertext) =
+        jwe::encrypt("A256GCM", Map::new(), &mut &b"payload"[..], &encryption_key).unwrap();
+
+    let p384 = JweDecryptionKey::new("ECDH-ES", ec_key(Nid::SECP384R1)).unwrap();
+    let err = jwe::decrypt(&jwe, &mut ciphertext.as_slice(), &p384).unwrap_err();
+    assert!(err.to_string().contains("curve"));
+
+    let (_, other) = keys("ECDH-ES");
+    assert!(jwe::decrypt(&jwe, &mut ciphertext.as_slice(), &other).is_err());
+}
@@ -0,0 +1,436 @@
+use anyhow::Result;
+use detached_jws::jwks::{JwksClient, JwksResolver, JwksResponse};
+use detached_jws::openssl::OpensslSigningKey;
+use openssl::bn::BigNumContext;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use serde_json::{json, Map, Value};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Default)]
+struct ServerState {
+    jwks: Value,
+    cache_control: Option<String>,
+    hits: usize,
+}
+
+/// A stand-in JWKS endpoint on a local port
+struct JwksServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<ServerState>>,
+}
+
+impl JwksServer {
+    fn start(jwks: Value, cache_control: Option<&str>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(ServerState {
+            jwks,
+            cache_control: cache_control.map(str::to_owned),
+            hits: 0,
+        }));
+
+        let shared = Arc::clone(&state);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+
+                let mut state = shared.lock().unwrap();
+                state.hits += 1;
+                let body = state.jwks.to_string();
+                let mut response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+                    body.len()
+                );
+                if let Some(ref cache_control) = state.cache_control {
+                    response.push_str(&format!("Cache-Control: {}\r\n", cache_control));
+                }
+                response.push_str("Connection: close\r\n\r\n");
+                response.push_str(&body);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+
+        Self { addr, state }
+    }
+
+    fn url(&self) -> String {
+        format!("http://{}/jwks.json", self.addr)
+    }
+
+    fn hits(&self) -> usize {
+        self.state.lock().unwrap().hits
+    }
+
+    fn set_jwks(&self, jwks: Value) {
+        self.state.lock().unwrap().jwks = jwks;
+    }
+}
+
+/// A minimal HTTP/1.1 client for `http://` URLs
+struct PlainHttpClient;
+
+impl JwksClient for PlainHttpClient {
+    fn fetch(&self, url: &str) -> Result<JwksResponse> {
+        let rest = url.strip_prefix("http://").unwrap();
+        let (authority, path) = rest.split_at(rest.find('/').unwrap());
+
+        let mut stream = TcpStream::connect(authority)?;
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            path, authority
+        )?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(response[..split].to_vec())?;
+        let cache_control = head.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            match name.eq_ignore_ascii_case("cache-control") {
+                true => Some(value.trim().to_owned()),
+                false => None,
+            }
+        });
+
+        Ok(JwksResponse {
+            body: response[split + 4..].to_vec(),
+            cache_control,
+        })
+    }
+}
+
+/// Fails every fetch after the first
+struct FlakyClient(Mutex<usize>);
+
+impl JwksClient for FlakyClient {
+    fn fetch(&self, url: &str) -> Result<JwksResponse> {
+        let mut calls = self.0.lock().unwrap();
+        *calls += 1;
+        match *calls {
+            1 => PlainHttpClient.fetch(url),
+            _ => anyhow::bail!("connection refused"),
+        }
+    }
+}
+
+fn b64(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn rsa_jwk(kid: &str, key: &PKey<Private>) -> Value {
+    let rsa = key.rsa().unwrap();
+    json!({
+        "kty": "RSA",
+        "kid": kid,
+        "use": "sig",
+        "n": b64(&rsa.n().to_vec()),
+        "e": b64(&rsa.e().to_vec()),
+    })
+}
+
+fn ec_jwk(kid: &str, key: &PKey<Private>) -> Value {
+    let ec = key.ec_key().unwrap();
+    let mut x = openssl::bn::BigNum::new().unwrap();
+    let mut y = openssl::bn::BigNum::new().unwrap();
+    ec.public_key()
+        .affine_coordinates(
+            ec.group(),
+            &mut x,
+            &mut y,
+            &mut BigNumContext::new().unwrap(),
+        )
+        .unwrap();
+    json!({
+        "kty": "EC",
+        "kid": kid,
+        "alg": "ES256",
+        "crv": "P-256",
+        "x": b64(&x.to_vec_padded(32).unwrap()),
+        "y": b64(&y.to_vec_padded(32).unwrap()),
+    })
+}
+
+fn sign(kid: &str, key: &OpensslSigningKey, payload: &[u8]) -> Vec<u8> {
+    let mut header = Map::new();
+    header.insert("kid".to_owned(), json!(kid));
+    detached_jws::serialize_with_key(header, &mut &payload[..], key).unwrap()
+}
+
+fn rsa_key() -> PKey<Private> {
+    PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap()
+}
+
+#[test]
+fn resolve_jwks_keys() {
+    let rsa = rsa_key();
+    let ec = PKey::from_ec_key(
+        EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap(),
+    )
+    .unwrap();
+
+    let server = JwksServer::start(
+        json!({ "keys": [rsa_jwk("rsa", &rsa), ec_jwk("ec", &ec)] }),
+        None,
+    );
+    let resolver = JwksResolver::new(server.url(), PlainHttpClient);
+
+    let payload = b"payload";
+
+    for alg in ["RS256", "PS512"].iter() {
+        let key = OpensslSigningKey::new(alg, rsa.clone()).unwrap();
+        let jws = sign("rsa", &key, payload);
+        detached_jws::deserialize_with_resolver(&jws, &mut &payload[..], &resolver).unwrap();
+    }
+
+    let key = OpensslSigningKey::new("ES256", ec.clone()).unwrap();
+    let jws = sign("ec", &key, payload);
+    detached_jws::deserialize_with_resolver(&jws, &mut &payload[..], &resolver).unwrap();
+
+    let key = OpensslSigningKey::new("RS256", rsa_key()).unwrap();
+    let jws = sign("ec", &key, payload);
+    assert!(detached_jws::deserialize_with_resolver(&jws, &mut &payload[..], &resolver).is_err());
+
+    assert_eq!(server.hits(), 1);
+}
+
+#[test]
+fn refresh_on_unknown_kid() {
+    let old = rsa_key();
+    let new = rsa_key();
+
+    let server = JwksServer::start(
+        json!({ "keys": [rsa_jwk("old", &old)] }),
+        Some("max-age=3600"),
+    );
+    let resolver =
+        JwksResolver::new(server.url(), PlainHttpClient).min_refresh_interval(Duration::ZERO);
+
+    let payload = b"payload";
+    let old_jws = sign(
+        "old",
+        &OpensslSigningKey::new("RS256", old).unwrap(),
+        payload,
+    );
+    let new_jws = sign(
+        "new",
+        &OpensslSigningKey::new("RS256", new.clone()).unwrap(),
+        payload,
+    );
+
+    detached_jws::deserialize_with_resolver(&old_jws, &mut &payload[..], &resolver).unwrap();
+    assert!(
+        detached_jws::deserialize_with_resolver(&new_jws, &mut &payload[..], &resolver).is_err()
+    );
+    assert_eq!(server.hits(), 2);
+
+    server.set_jwks(json!({ "keys": [rsa_jwk("new", &new)] }));
+
+    detached_jws::deserialize_with_resolver(&new_jws, &mut &payload[..], &resolver).unwrap();
+    detached_jws::deserialize_with_resolver(&new_jws, &mut &payload[..], &resolver).unwrap();
+    assert_eq!(server.hits(), 3);
+}
+
+#[test]
+fn rate_limit_refresh() {
+    let key = rsa_key();
+
+    let server = JwksServer::start(json!({ "keys": [] }), None);
+    let resolver = JwksResolver::new(server.url(), PlainHttpClient)
+        .min_refresh_interval(Duration::from_secs(3600));
+
+    let payload = b"payload";
+    let jws = sign(
+        "key",
+        &OpensslSigningKey::new("RS256", key).unwrap(),
+        payload,
+    );
+
+    for _ in 0..3 {
+        assert!(
+            detached_jws::deserialize_with_resolver(&jws, &mut &payload[..], &resolver).is_err()
+        );
+    }
+    assert_eq!(server.hits(), 1);
+}
+
+#[test]
+fn huge_refresh_interval_never_elapses() {
+    let key = rsa_key();
+
+    let server = JwksServer::start(json!({ "keys": [] }), Some("no-cache"));
+    let resolver =
+        JwksResolver::new(server.url(), PlainHttpClient).min_refresh_interval(Duration::MAX);
+
+    let payload = b"payload";
+    let jws = sign(
+        "key",
+        &OpensslSigningKey::new("RS256", key).unwrap(),
+        payload,
+    );
+
+    for _ in 0..3 {
+        assert!(
+            detached_jws::deserialize_with_resolver(&jws, &mut &payload[..], &resolver).is_err()
+        );
+    }
+    assert_eq!(server.hits(), 1);
+}
+
+#[test]
+fn honour_cache_control() {
+    let key = rsa_key();
+    let jwks = json!({ "keys": [rsa_jwk("key", &key)] });
+
+    let payload = b"payload";
+    let jws = sign(
+        "key",
+        &OpensslSigningKey::new("RS256", key).unwrap(),
+        payload,
+    );
+
+    let server = JwksServer::start(jwks.clone(), Some("public, max-age=0"));
+    let resolver =
+        JwksResolver::new(server.url(), PlainHttpClient).min_refresh_interval(Duration::ZERO);
+    for _ in 0..3 {
+        detached_jws::deserialize_with_resolver(&jws, &mut &payload[..], &resolver).unwrap();
+    }
+    assert_eq!(server.hits(), 3);
+
+    let server = JwksServer::start(jwks, Some("public, max-age=3600"));
+    let resolver =
+        JwksResolver::new(server.url(), PlainHttpClient).min_refresh_interval(Duration::ZERO);
+    for _ in 0..3 {
+        detached_jws::deserialize_with_resolver(&jws, &mut &payload[..], &resolver).unwrap();
+    }
+    assert_eq!(server.hits(), 1);
+}
+
+#[test]
+fn cap_huge_max_age() {
+    let key = rsa_key();
+    let server = JwksServer::start(
+        json!({ "keys": [rsa_jwk("key", &key)] }),
+        Some("max-age=18446744073709551615"),
+    );
+    let resolver =
+        JwksResolver::new(server.url(), PlainHttpClient).min_refresh_interval(Duration::ZERO);
+
+    let payload = b"payload";
+    let jws = sign(
+        "key",
+        &OpensslSigningKey::new("RS256", key).unwrap(),
+        payload,
+    );
+
+    for _ in 0..3 {
+        detached_jws::deserialize_with_resolver(&jws, &mut &payload[..], &resolver).unwrap();
+    }
+    assert_eq!(server.hits(), 1);
+}
+
+#[test]
+fn keep_keys_when_refresh_fails() {
+    let key = rsa_key();
+    let server = JwksServer::start(json!({ "keys": [rsa_jwk("key", &key)] }), Some("no-cache"));
+    let resolver = JwksResolver::new(server.url(), FlakyClient(Mutex::new(0)))
+        .min_refresh_interval(Duration::ZERO);
+
+    let payload = b"payload";
+    let jws = sign(
+        "key",
+        &OpensslSigningKey::new("RS256", key).unwrap(),
+        payload,
+    );
+
+    for _ in 0..3 {
+        detached_jws::deserialize_with_resolver(&jws, &mut &payload[..], &resolver).unwrap();
+    }
+}
+
+#[test]
+fn resolve_keys_without_kid() {
+    let key = rsa_key();
+    let mut jwk = rsa_jwk("", &key);
+    jwk.as_object_mut().unwrap().remove("kid");
+
+    let server = JwksServer::start(json!({ "keys": [jwk] }), None);
+    let resolver = JwksResolver::new(server.url(), PlainHttpClient);
+
+    let payload = b"payload";
+    let signing_key = OpensslSigningKey::new("RS256", key.clone()).unwrap();
+    let jws =
+        detached_jws::serialize_with_key(Map::new(), &mut &payload[..], &signing_key).unwrap();
+    detached_jws::deserialize_with_resolver(&jws, &mut &payload[..], &resolver).unwrap();
+
+    // a `kid` in the jws still has to match
+    let jws = sign("key", &signing_key, payload);
+    assert!(detached_jws::deserialize_with_resolver(&jws, &mut &payload[..], &resolver).is_err());
+
+    // without `kid` the key must be the only candidate
+    let other = rsa_key();
+    let mut other_jwk = rsa_jwk("", &other);
+    other_jwk.as_object_mut().unwrap().remove("kid");
+    let server = JwksServer::start(json!({ "keys": [rsa_jwk("key", &key), other_jwk] }), None);
+    let resolver = JwksResolver::new(server.url(), PlainHttpClient);
+    let jws =
+        detached_jws::serialize_with_key(Map::new(), &mut &payload[..], &signing_key).unwrap();
+    assert!(detached_jws::deserialize_with_resolver(&jws, &mut &payload[..], &resolver).is_err());
+}
+
+/// Delays every fetch to let concurrent callers pile up
+struct SlowClient;
+
+impl JwksClient for SlowClient {
+    fn fetch(&self, url: &str) -> Result<JwksResponse> {
+        thread::sleep(Duration::from_millis(200));
+        PlainHttpClient.fetch(url)
+    }
+}
+
+#[test]
+fn single_flight_refresh() {
+    let key = rsa_key();
+    let server = JwksServer::start(json!({ "keys": [rsa_jwk("key", &key)] }), None);
+    let resolver =
+        Arc::new(JwksResolver::new(server.url(), SlowClient).min_refresh_interval(Duration::ZERO));
+
+    let payload = b"payload";
+    let jws = Arc::new(sign(
+        "key",
+        &OpensslSigningKey::new("RS256", key).unwrap(),
+        payload,
+    ));
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let resolver = Arc::clone(&resolver);
+            let jws = Arc::clone(&jws);
+            thread::spawn(move || {
+                detached_jws::deserialize_with_resolver(&*jws, &mut &payload[..], &*resolver)
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap().unwrap();
+    }
+
+    assert_eq!(server.hits(), 1);
+}
@@ -0,0 +1,302 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use detached_jws::jwt::{self, ClaimError, Token, Validator};
+use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+use detached_jws::{DecodeOptions, JwsHeader};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Claims {
+    iss: String,
+    aud: Vec<String>,
+    sub: String,
+    exp: u64,
+    iat: u64,
+    jti: String,
+}
+
+fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn claims() -> Claims {
+    Claims {
+        iss: "issuer".to_owned(),
+        aud: vec!["api".to_owned(), "admin".to_owned()],
+        sub: "user-1".to_owned(),
+        exp: 2_000,
+        iat: 1_000,
+        jti: "id-1".to_owned(),
+    }
+}
+
+fn keys() -> (OpensslSigningKey, OpensslVerificationKey) {
+    (
+        OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap(),
+        OpensslVerificationKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap(),
+    )
+}
+
+fn claim_error(err: anyhow::Error) -> ClaimError {
+    err.downcast_ref::<ClaimError>()
+        .unwrap_or_else(|| panic!("{:?}", err))
+        .clone()
+}
+
+#[test]
+fn decode_rfc_7515_example() {
+    #[derive(Deserialize)]
+    struct Vector {
+        jwk: JwsHeader,
+        jws: String,
+    }
+
+    let vectors: Vec<Vector> = serde_json::from_str(include_str!("data/jws-vectors.json")).unwrap();
+    let vector = &vectors[0];
+    let key = OpensslVerificationKey::from_jwk("HS256", &vector.jwk).unwrap();
+
+    let validator = Validator::new().issuer("joe").time(at(1_300_819_000));
+    let token: Token<Map<String, Value>> = jwt::decode(&vector.jws, &key, &validator).unwrap();
+    assert_eq!(token.header["typ"], "JWT");
+    assert_eq!(token.claims["http://example.com/is_root"], true);
+
+    let err = jwt::decode::<Value>(&vector.jws, &key, &validator.time(at(1_300_819_380)));
+    assert_eq!(claim_error(err.unwrap_err()), ClaimError::Expired);
+}
+
+#[test]
+fn round_trip() {
+    let (signing_key, verification_key) = keys();
+    let validator = Validator::new()
+        .issuer("other")
+        .issuer("issuer")
+        .audience("api")
+        .subject("user-1")
+        .require("jti")
+        .max_age(Duration::from_secs(600))
+        .time(at(1_500));
+
+    let mut header = Map::new();
+    header.insert("kid".to_owned(), json!("key-1"));
+    let token = jwt::encode(header.clone(), &claims(), &signing_key).unwrap();
+    assert_eq!(token.split('.').count(), 3);
+
+    let decoded: Token<Claims> = jwt::decode(&token, &verification_key, &validator).unwrap();
+    assert_eq!(decoded.header["kid"], "key-1");
+    assert_eq!(decoded.header["typ"], "JWT");
+    assert_eq!(decoded.claims, claims());
+
+    let (jws, payload) = jwt::encode_detached(header, &claims(), &signing_key).unwrap();
+    assert!(String::from_utf8(jws.clone()).unwrap().contains(".."));
+    let decoded: Token<Claims> =
+        jwt::decode_detached(&jws, &payload, &verification_key, &validator).unwrap();
+    assert_eq!(decoded.claims, claims());
+
+    // tampered claims fail verification before validation
+    let mut tampered = payload.clone();
+    tampered[1] ^= 1;
+    let err = jwt::decode_detached::<Value>(&jws, &tampered, &verification_key, &validator);
+    assert!(err.unwrap_err().downcast_ref::<ClaimError>().is_none());
+
+    let mut typ = Map::new();
+    typ.insert("typ".to_owned(), json!("at+jwt"));
+    let token = jwt::encode(typ, &claims(), &signing_key).unwrap();
+    let decoded = jwt::decode::<Claims>(&token, &verification_key, &validator).unwrap();
+    assert_eq!(decoded.header["typ"], "at+jwt");
+}
+
+#[test]
+fn decode_options() {
+    let (signing_key, verification_key) = keys();
+    let mut claims = json!({ "exp": 2_000, "roles": [] });
+    claims["roles"] = (0..2_000).map(|i| json!(format!("role-{}", i))).collect();
+    let token = jwt::encode(Map::new(), &claims, &signing_key).unwrap();
+    assert!(token.len() > 16 * 1024);
+
+    let validator = Validator::new().time(at(1_000));
+    assert!(jwt::decode::<Value>(&token, &verification_key, &validator).is_err());
+
+    let options = DecodeOptions {
+        max_token_len: 64 * 1024,
+        ..DecodeOptions::default()
+    };
+    let validator = validator.options(options);
+    let decoded: Token<Value> = jwt::decode(&token, &verification_key, &validator).unwrap();
+    assert_eq!(decoded.claims, claims);
+
+    let validator = validator.options(DecodeOptions {
+        max_signature_len: 16,
+        ..DecodeOptions::default()
+    });
+    let (jws, payload) = jwt::encode_detached(Map::new(), &claims, &signing_key).unwrap();
+    assert!(jwt::decode_detached::<Value>(&jws, &payload, &verification_key, &validator).is_err());
+}
+
+#[test]
+fn validation() {
+    let base = json!({
+        "iss": "issuer",
+        "aud": "api",
+        "sub": "user-1",
+        "exp": 2_000,
+        "nbf": 900,
+        "iat": 1_000,
+        "jti": "id-1",
+    });
+    let validator = Validator::new()
+        .issuer("issuer")
+        .audience("api")
+        .subject("user-1")
+        .time(at(1_000));
+    let check = |validator: &Validator, changes: Value| {
+        let mut claims = base.as_object().unwrap().clone();
+        for (name, value) in changes.as_object().unwrap() {
+            match value {
+                Value::Null => claims.remove(name),
+                value => claims.insert(name.clone(), value.clone()),
+            };
+        }
+        validator.validate(&claims)
+    };
+
+    assert_eq!(check(&validator, json!({})), Ok(()));
+    assert_eq!(
+        check(&validator, json!({ "exp": 1_000 })),
+        Err(ClaimError::Expired)
+    );
+    assert_eq!(
+        check(&validator, json!({ "exp": 999.5 })),
+        Err(ClaimError::Expired)
+    );
+    assert_eq!(
+        check(&validator, json!({ "nbf": 1_001 })),
+        Err(ClaimError::NotYetValid)
+    );
+    assert_eq!(
+        check(&validator, json!({ "iat": 1_001 })),
+        Err(ClaimError::IssuedAt)
+    );
+    assert_eq!(
+        check(&validator, json!({ "iss": "other" })),
+        Err(ClaimError::Issuer)
+    );
+    assert_eq!(
+        check(&validator, json!({ "sub": "user-2" })),
+        Err(ClaimError::Subject)
+    );
+    assert_eq!(
+        check(&validator, json!({ "aud": "other" })),
+        Err(ClaimError::Audience)
+    );
+    assert_eq!(
+        check(&validator, json!({ "aud": ["other", "api"] })),
+        Ok(())
+    );
+    assert_eq!(
+        check(&validator, json!({ "aud": ["api", 1] })),
+        Err(ClaimError::Malformed("aud".to_owned()))
+    );
+    assert_eq!(
+        check(&validator, json!({ "exp": "2000" })),
+        Err(ClaimError::Malformed("exp".to_owned()))
+    );
+    assert_eq!(
+        check(&validator, json!({ "jti": 1 })),
+        Err(ClaimError::Malformed("jti".to_owned()))
+    );
+    assert_eq!(
+        check(&validator, json!({ "iss": null })),
+        Err(ClaimError::Missing("iss".to_owned()))
+    );
+
+    // `exp` is required unless made optional
+    let missing_exp = json!({ "exp": null });
+    assert_eq!(
+        check(&validator, missing_exp.clone()),
+        Err(ClaimError::Missing("exp".to_owned()))
+    );
+    assert_eq!(
+        check(&validator.clone().optional("exp"), missing_exp),
+        Ok(())
+    );
+
+    let jti = validator.clone().require("jti");
+    assert_eq!(
+        check(&jti, json!({ "jti": null })),
+        Err(ClaimError::Missing("jti".to_owned()))
+    );
+
+    // an audience restricted token is rejected when no audience is expected
+    let no_audience = Validator::new().time(at(1_000));
+    assert_eq!(check(&no_audience, json!({})), Err(ClaimError::Audience));
+    assert_eq!(check(&no_audience, json!({ "aud": null })), Ok(()));
+
+    let leeway = validator.clone().leeway(Duration::from_secs(30));
+    assert_eq!(
+        check(&leeway, json!({ "exp": 990, "nbf": 1_020, "iat": 1_020 })),
+        Ok(())
+    );
+    assert_eq!(
+        check(&leeway, json!({ "exp": 970 })),
+        Err(ClaimError::Expired)
+    );
+
+    let max_age = validator.clone().max_age(Duration::from_secs(60));
+    assert_eq!(check(&max_age, json!({ "iat": 940 })), Ok(()));
+    assert_eq!(
+        check(&max_age, json!({ "iat": 939 })),
+        Err(ClaimError::IssuedAt)
+    );
+    assert_eq!(
+        check(&max_age, json!({ "iat": null })),
+        Err(ClaimError::Missing("iat".to_owned()))
+    );
+}
+
+#[test]
+fn rejected_input() {
+    let (signing_key, verification_key) = keys();
+    let validator = Validator::new().audience("api").time(at(1_500));
+
+    assert!(jwt::encode(Map::new(), &[1, 2, 3], &signing_key).is_err());
+    assert!(jwt::encode(Map::new(), "claims", &signing_key).is_err());
+
+    let token = jwt::encode(Map::new(), &claims(), &signing_key).unwrap();
+    let segments: Vec<&str> = token.split('.').collect();
+    assert!(
+        jwt::decode::<Claims>(&segments[..2].join("."), &verification_key, &validator).is_err()
+    );
+    assert!(jwt::decode::<Claims>(&format!("{}.", token), &verification_key, &validator).is_err());
+
+    let detached = format!("{}..{}", segments[0], segments[2]);
+    assert!(jwt::decode::<Claims>(&detached, &verification_key, &validator).is_err());
+
+    // a payload that is not a claim set
+    let (jws, payload) = {
+        let jws = detached_jws::serialize_with_key(Map::new(), &mut &b"[1]"[..], &signing_key);
+        (jws.unwrap(), b"[1]".to_vec())
+    };
+    assert!(jwt::decode_detached::<Value>(&jws, &payload, &verification_key, &validator).is_err());
+
+    // claims that do not deserialize into the requested type
+    let err = jwt::decode::<Vec<u8>>(&token, &verification_key, &validator).unwrap_err();
+    assert!(err.downcast_ref::<ClaimError>().is_none());
+
+    let other =
+        OpensslVerificationKey::hmac("HS256", b"another secret of 32 bytes or more").unwrap();
+    assert!(jwt::decode::<Claims>(&token, &other, &validator).is_err());
+
+    let err = jwt::decode::<Claims>(&token, &verification_key, &validator.time(at(2_000)));
+    assert_eq!(claim_error(err.unwrap_err()), ClaimError::Expired);
+
+    // a duplicate `exp` must not let a later, longer expiry win
+    let payload = br#"{"sub":"user-1","exp":1000,"exp":4000000000}"#;
+    let jws = detached_jws::serialize_with_key(Map::new(), &mut &payload[..], &signing_key);
+    let validator = Validator::new().time(at(1_500));
+    let err = jwt::decode_detached::<Value>(&jws.unwrap(), payload, &verification_key, &validator);
+    assert_eq!(
+        claim_error(err.unwrap_err()),
+        ClaimError::Duplicate("exp".to_owned())
+    );
+}
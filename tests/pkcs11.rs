@@ -0,0 +1,229 @@
+//! Runs against SoftHSM with `cargo test --features pkcs11 --test pkcs11` when
+//! `SOFTHSM_MODULE` points to `libsofthsm2.so`, and is skipped otherwise
+//!
+//! The token in the first slot is re-initialized, so never point it to a real HSM.
+
+use cryptoki::context::{CInitializeArgs, CInitializeFlags, Pkcs11};
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, AttributeType, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::types::AuthPin;
+use detached_jws::openssl::OpensslVerificationKey;
+use detached_jws::pkcs11::Pkcs11SigningKey;
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey, EcPoint};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Public};
+use openssl::rsa::Rsa;
+use serde_json::{json, Map};
+use std::sync::Arc;
+use std::thread;
+
+const SO_PIN: &str = "abcdef654321";
+const USER_PIN: &str = "fedcba123456";
+
+fn session(pkcs11: &Pkcs11) -> Session {
+    let session = pkcs11
+        .open_rw_session(pkcs11.get_slots_with_token().unwrap()[0])
+        .unwrap();
+    session
+        .login(UserType::User, Some(&AuthPin::new(USER_PIN.into())))
+        .unwrap();
+    session
+}
+
+fn init_token(module: &str) -> Pkcs11 {
+    let pkcs11 = Pkcs11::new(module).unwrap();
+    pkcs11
+        .initialize(CInitializeArgs::new(CInitializeFlags::OS_LOCKING_OK))
+        .unwrap();
+
+    let slot = pkcs11.get_slots_with_token().unwrap()[0];
+    let so_pin = AuthPin::new(SO_PIN.into());
+    pkcs11.init_token(slot, &so_pin, "detached-jws").unwrap();
+
+    let session = pkcs11.open_rw_session(slot).unwrap();
+    session.login(UserType::So, Some(&so_pin)).unwrap();
+    session.init_pin(&AuthPin::new(USER_PIN.into())).unwrap();
+
+    pkcs11
+}
+
+fn private_template(label: &str) -> Vec<Attribute> {
+    vec![
+        Attribute::Token(true),
+        Attribute::Private(true),
+        Attribute::Sensitive(true),
+        Attribute::Sign(true),
+        Attribute::Label(label.as_bytes().to_vec()),
+    ]
+}
+
+fn generate_rsa(session: &Session, label: &str, bits: u64) -> PKey<Public> {
+    let (public, _) = session
+        .generate_key_pair(
+            &Mechanism::RsaPkcsKeyPairGen,
+            &[
+                Attribute::Token(true),
+                Attribute::Verify(true),
+                Attribute::ModulusBits(bits.into()),
+                Attribute::PublicExponent(vec![0x01, 0x00, 0x01]),
+            ],
+            &private_template(label),
+        )
+        .unwrap();
+
+    let attributes = session
+        .get_attributes(
+            public,
+            &[AttributeType::Modulus, AttributeType::PublicExponent],
+        )
+        .unwrap();
+    let (n, e) = match attributes.as_slice() {
+        [Attribute::Modulus(n), Attribute::PublicExponent(e)] => (n, e),
+        _ => panic!("unexpected attributes"),
+    };
+
+    let rsa = Rsa::from_public_components(
+        BigNum::from_slice(n).unwrap(),
+        BigNum::from_slice(e).unwrap(),
+    )
+    .unwrap();
+    PKey::from_rsa(rsa).unwrap()
+}
+
+fn generate_ec(session: &Session, label: &str, curve: Nid, oid: &[u8]) -> PKey<Public> {
+    let (public, _): (ObjectHandle, ObjectHandle) = session
+        .generate_key_pair(
+            &Mechanism::EccKeyPairGen,
+            &[
+                Attribute::Token(true),
+                Attribute::Verify(true),
+                Attribute::EcParams(oid.to_vec()),
+            ],
+            &private_template(label),
+        )
+        .unwrap();
+
+    let point = match session
+        .get_attributes(public, &[AttributeType::EcPoint])
+        .unwrap()
+        .as_slice()
+    {
+        [Attribute::EcPoint(point)] => point.clone(),
+        _ => panic!("unexpected attributes"),
+    };
+
+    // CKA_EC_POINT is a DER OCTET STRING wrapping the uncompressed point
+    let skip = match point[1] {
+        len if len & 0x80 != 0 => 2 + (len & 0x7f) as usize,
+        _ => 2,
+    };
+
+    let group = EcGroup::from_curve_name(curve).unwrap();
+    let point =
+        EcPoint::from_bytes(&group, &point[skip..], &mut BigNumContext::new().unwrap()).unwrap();
+    PKey::from_ec_key(EcKey::from_public_key(&group, &point).unwrap()).unwrap()
+}
+
+#[test]
+fn sign_with_softhsm() {
+    let module = match std::env::var("SOFTHSM_MODULE") {
+        Ok(module) => module,
+        Err(_) => {
+            eprintln!("SOFTHSM_MODULE is not set, skipping");
+            return;
+        }
+    };
+
+    let pkcs11 = init_token(&module);
+
+    let admin = session(&pkcs11);
+    let keys = [
+        (
+            "rsa",
+            generate_rsa(&admin, "rsa", 2048),
+            vec!["RS256", "RS512", "PS256", "PS384"],
+        ),
+        (
+            "p256",
+            generate_ec(
+                &admin,
+                "p256",
+                Nid::X9_62_PRIME256V1,
+                &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07],
+            ),
+            vec!["ES256"],
+        ),
+        (
+            "p384",
+            generate_ec(
+                &admin,
+                "p384",
+                Nid::SECP384R1,
+                &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22],
+            ),
+            vec!["ES384"],
+        ),
+        (
+            "p521",
+            generate_ec(
+                &admin,
+                "p521",
+                Nid::SECP521R1,
+                &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x23],
+            ),
+            vec!["ES512"],
+        ),
+    ];
+
+    let payload = vec![9u8; 50_000];
+
+    for (label, public, algorithms) in keys.iter() {
+        for alg in algorithms {
+            let signing_key =
+                Arc::new(Pkcs11SigningKey::from_label(alg, session(&pkcs11), label).unwrap());
+            let verification_key = OpensslVerificationKey::new(alg, public).unwrap();
+
+            let handles: Vec<_> = (0..4)
+                .map(|i| {
+                    let signing_key = Arc::clone(&signing_key);
+                    let payload = payload.clone();
+                    thread::spawn(move || {
+                        let mut header = Map::new();
+                        header.insert("kid".to_owned(), json!(i));
+                        detached_jws::serialize_with_key(
+                            header,
+                            &mut payload.as_slice(),
+                            signing_key.as_ref(),
+                        )
+                        .unwrap()
+                    })
+                })
+                .collect();
+
+            for handle in handles {
+                let jws = handle.join().unwrap();
+                detached_jws::deserialize_with_key(
+                    &jws,
+                    &mut payload.as_slice(),
+                    &verification_key,
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    assert!(Pkcs11SigningKey::from_label("ES256", session(&pkcs11), "missing").is_err());
+    assert!(Pkcs11SigningKey::from_label("HS256", session(&pkcs11), "rsa").is_err());
+
+    // the key type and curve must suit the algorithm
+    assert!(Pkcs11SigningKey::from_label("ES256", session(&pkcs11), "rsa").is_err());
+    assert!(Pkcs11SigningKey::from_label("PS256", session(&pkcs11), "p256").is_err());
+    assert!(Pkcs11SigningKey::from_label("ES512", session(&pkcs11), "p256").is_err());
+    assert!(Pkcs11SigningKey::from_label("ES256", session(&pkcs11), "p384").is_err());
+
+    // as must the RSA key size
+    generate_rsa(&admin, "short", 1024);
+    assert!(Pkcs11SigningKey::from_label("RS256", session(&pkcs11), "short").is_err());
+}
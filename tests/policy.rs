@@ -0,0 +1,189 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+use detached_jws::{
+    DecodeOptions, DeserializeJwsWriter, JwsHeader, PolicyViolation, VerificationPolicy,
+};
+use serde_json::{json, Value};
+
+fn sign(header: Value) -> Vec<u8> {
+    let header: JwsHeader = serde_json::from_value(header).unwrap();
+    let key = OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+    detached_jws::serialize_with_key(header, &mut &b"payload"[..], &key).unwrap()
+}
+
+fn key() -> OpensslVerificationKey {
+    OpensslVerificationKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap()
+}
+
+fn violation(jws: &[u8], policy: &VerificationPolicy) -> PolicyViolation {
+    let err = detached_jws::deserialize_with_policy(&jws, &mut &b"payload"[..], &key(), policy)
+        .unwrap_err();
+    err.downcast_ref::<PolicyViolation>()
+        .unwrap_or_else(|| panic!("{:?}", err))
+        .clone()
+}
+
+fn partner_policy() -> VerificationPolicy {
+    VerificationPolicy::new()
+        .algorithm("HS256")
+        .algorithm("PS256")
+        .expect("typ", "JOSE")
+        .expect("iss", "partner")
+        .require("kid")
+        .critical("iat")
+        .leeway(Duration::from_secs(30))
+        .time(UNIX_EPOCH + Duration::from_secs(1_000))
+}
+
+#[test]
+fn accepted_header() {
+    let jws = sign(json!({
+        "typ": "JOSE",
+        "iss": "partner",
+        "kid": "key-1",
+        "iat": 1_020,
+        "crit": ["iat"],
+    }));
+    let policy = partner_policy();
+
+    let header =
+        detached_jws::deserialize_with_policy(&jws, &mut &b"payload"[..], &key(), &policy).unwrap();
+    assert_eq!(header["kid"], "key-1");
+
+    let key = key();
+    let mut writer = DeserializeJwsWriter::with_policy(&jws, &key, &policy).unwrap();
+    writer.write_all(b"payload").unwrap();
+    writer.finish().unwrap();
+
+    // the signature is still verified
+    assert!(
+        detached_jws::deserialize_with_policy(&jws, &mut &b"tampered"[..], &key, &policy).is_err()
+    );
+
+    let resolver = |h: &JwsHeader| {
+        assert_eq!(h["iss"], "partner");
+        Some(Arc::new(self::key()))
+    };
+    detached_jws::deserialize_with_resolver_and_policy(
+        &jws,
+        &mut &b"payload"[..],
+        &resolver,
+        &policy,
+    )
+    .unwrap();
+}
+
+#[test]
+fn rejected_headers() {
+    let policy = partner_policy();
+    let check = |changes: Value| {
+        let mut header = json!({ "typ": "JOSE", "iss": "partner", "kid": "key-1" });
+        for (name, value) in changes.as_object().unwrap() {
+            match value {
+                Value::Null => header.as_object_mut().unwrap().remove(name),
+                value => header
+                    .as_object_mut()
+                    .unwrap()
+                    .insert(name.clone(), value.clone()),
+            };
+        }
+        violation(&sign(header), &policy)
+    };
+
+    assert_eq!(
+        check(json!({ "typ": "JWT" })),
+        PolicyViolation::Unexpected("typ".to_owned())
+    );
+    assert_eq!(
+        check(json!({ "iss": null })),
+        PolicyViolation::Missing("iss".to_owned())
+    );
+    assert_eq!(
+        check(json!({ "kid": null })),
+        PolicyViolation::Missing("kid".to_owned())
+    );
+    assert_eq!(
+        check(json!({ "crit": ["b64"], "b64": false })),
+        PolicyViolation::Critical("b64".to_owned())
+    );
+    assert_eq!(
+        check(json!({ "crit": ["iat"] })),
+        PolicyViolation::Critical("iat".to_owned())
+    );
+    assert_eq!(
+        check(json!({ "crit": [] })),
+        PolicyViolation::Malformed("crit".to_owned())
+    );
+    assert_eq!(
+        check(json!({ "crit": "iat", "iat": 1_000 })),
+        PolicyViolation::Malformed("crit".to_owned())
+    );
+    assert_eq!(check(json!({ "exp": 970 })), PolicyViolation::Expired);
+    assert_eq!(check(json!({ "nbf": 1_031 })), PolicyViolation::NotYetValid);
+    assert_eq!(
+        check(json!({ "iat": 1_031 })),
+        PolicyViolation::IssuedInFuture
+    );
+    assert_eq!(
+        check(json!({ "iat": "1000" })),
+        PolicyViolation::Malformed("iat".to_owned())
+    );
+
+    let jws = sign(json!({ "typ": "JOSE", "iss": "partner", "kid": "key-1" }));
+    assert_eq!(
+        violation(&jws, &VerificationPolicy::new().algorithm("ES256")),
+        PolicyViolation::Algorithm("HS256".to_owned())
+    );
+    assert_eq!(
+        violation(&jws, &VerificationPolicy::new().expect("iss", "other")),
+        PolicyViolation::Unexpected("iss".to_owned())
+    );
+
+    // no resolver lookup for a header the policy rejects
+    let resolver = |_: &JwsHeader| -> Option<Arc<OpensslVerificationKey>> {
+        panic!("resolved a rejected header")
+    };
+    let err = detached_jws::deserialize_with_resolver_and_policy(
+        &jws,
+        &mut &b"payload"[..],
+        &resolver,
+        &VerificationPolicy::new().algorithm("ES256"),
+    )
+    .unwrap_err();
+    assert!(err.is::<PolicyViolation>());
+}
+
+#[test]
+fn size_limits() {
+    let jws = sign(json!({ "kid": "key-1" }));
+    let policy = VerificationPolicy::new().options(DecodeOptions {
+        max_token_len: 16,
+        ..DecodeOptions::default()
+    });
+
+    let err = detached_jws::deserialize_with_policy(&jws, &mut &b"payload"[..], &key(), &policy)
+        .unwrap_err();
+    assert!(err.to_string().contains("exceeds"), "{}", err);
+    assert!(DeserializeJwsWriter::with_policy(&jws, &key(), &policy).is_err());
+}
+
+#[test]
+fn empty_policy() {
+    let policy = VerificationPolicy::new();
+
+    let jws = sign(json!({ "custom": true }));
+    detached_jws::deserialize_with_policy(&jws, &mut &b"payload"[..], &key(), &policy).unwrap();
+
+    let jws = sign(json!({ "crit": ["custom"], "custom": true }));
+    assert_eq!(
+        violation(&jws, &policy),
+        PolicyViolation::Critical("custom".to_owned())
+    );
+    assert_eq!(
+        violation(&sign(json!({ "exp": 1 })), &policy),
+        PolicyViolation::Expired
+    );
+}
@@ -0,0 +1,106 @@
+//! Properties of serialization over random headers, payloads and write patterns
+//!
+//! The `fuzz` directory holds the matching cargo-fuzz targets for untrusted input.
+
+use std::io::Write;
+
+use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+use detached_jws::{
+    DecodeOptions, DeserializeJwsWriter, JwsHeader, SerializeJwsWriter, SigningKey,
+};
+use proptest::collection::{hash_map, vec};
+use proptest::prelude::*;
+use serde_json::Value;
+
+/// JSON values without floats, which do not round-trip exactly
+fn json_value() -> impl Strategy<Value = Value> {
+    let leaf = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::from),
+        any::<i64>().prop_map(Value::from),
+        any::<u64>().prop_map(Value::from),
+        ".*".prop_map(Value::from),
+    ];
+    leaf.prop_recursive(4, 32, 4, |inner| {
+        prop_oneof![
+            vec(inner.clone(), 0..4).prop_map(Value::from),
+            hash_map(".*", inner, 0..4).prop_map(|m| Value::Object(m.into_iter().collect())),
+        ]
+    })
+}
+
+fn json_header() -> impl Strategy<Value = JwsHeader> {
+    hash_map(".*", json_value(), 0..8).prop_map(|m| m.into_iter().collect())
+}
+
+fn write_chunked(writer: &mut impl Write, payload: &[u8], splits: &[usize]) {
+    let mut rest = payload;
+    for split in splits.iter().cycle() {
+        if rest.is_empty() {
+            break;
+        }
+        let (chunk, tail) = rest.split_at((*split).min(rest.len()));
+        writer.write_all(chunk).unwrap();
+        rest = tail;
+    }
+    writer.write_all(rest).unwrap();
+}
+
+fn keys() -> (OpensslSigningKey, OpensslVerificationKey) {
+    (
+        OpensslSigningKey::hmac("HS256", b"a property test secret of 32 bytes").unwrap(),
+        OpensslVerificationKey::hmac("HS256", b"a property test secret of 32 bytes").unwrap(),
+    )
+}
+
+proptest! {
+    #[test]
+    fn serialize_then_deserialize(header in json_header(), payload in vec(any::<u8>(), 0..4096)) {
+        let (signing_key, key) = keys();
+
+        let jws = detached_jws::serialize_with_key(header.clone(), &mut payload.as_slice(), &signing_key)
+            .unwrap();
+        let verified = detached_jws::deserialize_with_key(&jws, &mut payload.as_slice(), &key).unwrap();
+
+        let mut expected = header;
+        expected.insert("alg".to_owned(), "HS256".into());
+        prop_assert_eq!(verified, expected);
+    }
+
+    #[test]
+    fn chunking_does_not_change_outcome(
+        payload in vec(any::<u8>(), 0..100_000),
+        splits in vec(1..70_000usize, 1..8),
+        tamper in any::<Option<prop::sample::Index>>(),
+    ) {
+        let (signing_key, key) = keys();
+        let options = DecodeOptions::default();
+
+        let whole = detached_jws::serialize_with_key(JwsHeader::new(), &mut payload.as_slice(), &signing_key)
+            .unwrap();
+
+        let mut writer = SerializeJwsWriter::new(
+            Vec::new(),
+            "HS256".to_owned(),
+            JwsHeader::new(),
+            signing_key.signer().unwrap(),
+        )
+        .unwrap();
+        write_chunked(&mut writer, &payload, &splits);
+        prop_assert_eq!(writer.finish().unwrap(), whole.clone());
+
+        let mut payload = payload;
+        if let (Some(index), false) = (tamper.as_ref(), payload.is_empty()) {
+            let at = index.index(payload.len());
+            payload[at] ^= 1;
+        }
+
+        let expected = detached_jws::deserialize_with_key(&whole, &mut payload.as_slice(), &key);
+        let mut writer = DeserializeJwsWriter::with_key(&whole, &key, &options).unwrap();
+        write_chunked(&mut writer, &payload, &splits);
+        let actual = writer.finish();
+
+        prop_assert_eq!(expected.is_ok(), actual.is_ok());
+        prop_assert_eq!(actual.is_ok(), tamper.is_none() || payload.is_empty());
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use detached_jws::openssl::OpensslVerificationKey;
+use detached_jws::remote::{
+    self, InMemoryKms, RemoteSerializeJwsWriter, RemoteSigner, SigningInput,
+};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use serde_json::{json, Map};
+use std::io::Write;
+use std::sync::Mutex;
+
+/// Records what the wrapped KMS was asked to sign
+struct Recording {
+    kms: InMemoryKms,
+    inputs: Mutex<Vec<(bool, Vec<u8>)>>,
+}
+
+#[async_trait]
+impl RemoteSigner for Recording {
+    fn algorithm(&self) -> &str {
+        self.kms.algorithm()
+    }
+
+    fn max_message_len(&self) -> usize {
+        RemoteSigner::max_message_len(&self.kms)
+    }
+
+    async fn sign(&self, input: SigningInput<'_>) -> Result<Vec<u8>> {
+        let recorded = match input {
+            SigningInput::Message(message) => (true, message.to_vec()),
+            SigningInput::Digest(digest) => (false, digest.to_vec()),
+        };
+        self.inputs.lock().unwrap().push(recorded);
+        self.kms.sign(input).await
+    }
+}
+
+struct Unavailable;
+
+#[async_trait]
+impl RemoteSigner for Unavailable {
+    fn algorithm(&self) -> &str {
+        "ES256"
+    }
+
+    async fn sign(&self, _: SigningInput<'_>) -> Result<Vec<u8>> {
+        anyhow::bail!("service unavailable")
+    }
+}
+
+fn ec_key(curve: Nid) -> PKey<Private> {
+    PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(curve).unwrap()).unwrap()).unwrap()
+}
+
+fn signing_input(jws: &[u8], payload: &[u8]) -> Vec<u8> {
+    let header = jws.split(|b| *b == b'.').next().unwrap();
+    let mut input = header.to_vec();
+    input.push(b'.');
+    input.extend(base64::encode_config(payload, base64::URL_SAFE_NO_PAD).as_bytes());
+    input
+}
+
+#[tokio::test]
+async fn sign_digest_remotely() {
+    let rsa = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let keys = [
+        ("RS256", rsa.clone()),
+        ("PS384", rsa),
+        ("ES256", ec_key(Nid::X9_62_PRIME256V1)),
+        ("ES512", ec_key(Nid::SECP521R1)),
+    ];
+
+    let payload = vec![3u8; 10_000];
+
+    for (alg, key) in keys.iter() {
+        let kms = InMemoryKms::new(alg, key.clone()).unwrap();
+
+        let mut header = Map::new();
+        header.insert("kid".to_owned(), json!("kms-key"));
+        let jws = remote::serialize_remote(header, &mut payload.as_slice(), &kms)
+            .await
+            .unwrap();
+
+        let verification_key = OpensslVerificationKey::new(alg, key).unwrap();
+        let header =
+            detached_jws::deserialize_with_key(&jws, &mut payload.as_slice(), &verification_key)
+                .unwrap();
+        assert_eq!(header["kid"], "kms-key");
+    }
+}
+
+#[tokio::test]
+async fn choose_message_or_digest() {
+    let signer = Recording {
+        kms: InMemoryKms::new(
+            "PS256",
+            PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap(),
+        )
+        .unwrap()
+        .max_message_len(4096),
+        inputs: Mutex::new(Vec::new()),
+    };
+
+    let small = vec![1u8; 100];
+    let large = vec![2u8; 10_000];
+
+    let mut writer = RemoteSerializeJwsWriter::new(Vec::new(), Map::new(), &signer).unwrap();
+    for chunk in small.chunks(7) {
+        writer.write_all(chunk).unwrap();
+    }
+    let small_jws = writer.finish().await.unwrap();
+
+    let large_jws = remote::serialize_remote(Map::new(), &mut large.as_slice(), &signer)
+        .await
+        .unwrap();
+
+    let inputs = signer.inputs.lock().unwrap();
+    assert_eq!(inputs[0], (true, signing_input(&small_jws, &small)));
+    assert_eq!(
+        inputs[1],
+        (
+            false,
+            hash(MessageDigest::sha256(), &signing_input(&large_jws, &large))
+                .unwrap()
+                .to_vec()
+        )
+    );
+}
+
+#[tokio::test]
+async fn sign_hmac_message_remotely() {
+    let kms = InMemoryKms::hmac("HS512", &[7; 64]).unwrap();
+    let payload = vec![5u8; 100_000];
+
+    let jws = remote::serialize_remote(Map::new(), &mut payload.as_slice(), &kms)
+        .await
+        .unwrap();
+
+    let key = OpensslVerificationKey::hmac("HS512", &[7; 64]).unwrap();
+    detached_jws::deserialize_with_key(&jws, &mut payload.as_slice(), &key).unwrap();
+
+    let kms = InMemoryKms::hmac("HS256", b"a shared secret of 32 bytes or more")
+        .unwrap()
+        .max_message_len(16);
+    assert!(
+        remote::serialize_remote(Map::new(), &mut payload.as_slice(), &kms)
+            .await
+            .is_err()
+    );
+
+    let kms = InMemoryKms::hmac("HS256", b"a shared secret of 32 bytes or more")
+        .unwrap()
+        .max_message_len(0);
+    let error = RemoteSerializeJwsWriter::new(Vec::new(), Map::new(), &kms)
+        .err()
+        .unwrap();
+    assert!(
+        error.to_string().contains("must accept whole messages"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn sign_with_dyn_signer() {
+    let key = ec_key(Nid::X9_62_PRIME256V1);
+    let signers: Vec<Box<dyn RemoteSigner>> = vec![
+        Box::new(InMemoryKms::new("ES256", key.clone()).unwrap()),
+        Box::new(InMemoryKms::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap()),
+    ];
+
+    for signer in signers.iter() {
+        remote::serialize_remote(Map::new(), &mut &b"payload"[..], signer.as_ref())
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn remote_failure() {
+    let error = remote::serialize_remote(Map::new(), &mut &b"payload"[..], &Unavailable)
+        .await
+        .unwrap_err();
+    assert_eq!(error.to_string(), "service unavailable");
+}
@@ -0,0 +1,213 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+use detached_jws::replay::{MemoryReplayCache, ReplayCache, ReplayDetected, ReplayKey};
+use detached_jws::{DecodeOptions, DeserializeJwsWriter};
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use serde_json::{json, Map};
+
+fn sign(header: Map<String, serde_json::Value>, payload: &[u8]) -> Vec<u8> {
+    let key = OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+    detached_jws::serialize_with_key(header, &mut &payload[..], &key).unwrap()
+}
+
+fn verify(jws: &[u8], payload: &[u8], cache: &dyn ReplayCache) -> anyhow::Result<()> {
+    let key =
+        OpensslVerificationKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+    let mut writer = DeserializeJwsWriter::with_key(&jws, &key, &DecodeOptions::default())?;
+    writer.write_all(payload)?;
+    writer.finish_with_replay_cache(cache).map(|_| ())
+}
+
+#[test]
+fn replay_is_detected() {
+    let cache = MemoryReplayCache::new(100, Duration::from_secs(60)).unwrap();
+
+    let jws = sign(Map::new(), b"payload");
+    verify(&jws, b"payload", &cache).unwrap();
+    let err = verify(&jws, b"payload", &cache).unwrap_err();
+    match err.downcast_ref::<ReplayDetected>() {
+        Some(ReplayDetected(ReplayKey::Signature(_))) => {}
+        _ => panic!("{:?}", err),
+    }
+
+    // the same jti is a replay even with another payload
+    let mut header = Map::new();
+    header.insert("jti".to_owned(), json!("id-1"));
+    verify(&sign(header.clone(), b"first"), b"first", &cache).unwrap();
+    let err = verify(&sign(header, b"second"), b"second", &cache).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<ReplayDetected>(),
+        Some(&ReplayDetected(ReplayKey::Jti {
+            iss: None,
+            kid: None,
+            jti: "id-1".to_owned(),
+        }))
+    );
+    assert_eq!(
+        err.to_string(),
+        "jws replay detected: jti `id-1` has already been accepted"
+    );
+}
+
+#[test]
+fn jti_is_scoped_by_issuer_and_key() {
+    let cache = MemoryReplayCache::new(100, Duration::from_secs(60)).unwrap();
+
+    let header = |iss: &str, kid: &str| {
+        let mut header = Map::new();
+        header.insert("jti".to_owned(), json!("id-1"));
+        header.insert("iss".to_owned(), json!(iss));
+        header.insert("kid".to_owned(), json!(kid));
+        header
+    };
+
+    verify(&sign(header("a", "1"), b"payload"), b"payload", &cache).unwrap();
+    verify(&sign(header("b", "1"), b"payload"), b"payload", &cache).unwrap();
+    verify(&sign(header("a", "2"), b"payload"), b"payload", &cache).unwrap();
+
+    let err = verify(&sign(header("a", "1"), b"other"), b"other", &cache).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "jws replay detected: jti `id-1` of issuer `a` for key `1` has already been accepted"
+    );
+}
+
+#[test]
+fn rejected_signature_is_not_recorded() {
+    let cache = MemoryReplayCache::new(100, Duration::from_secs(60)).unwrap();
+
+    let jws = sign(Map::new(), b"payload");
+    let err = verify(&jws, b"tampered", &cache).unwrap_err();
+    assert!(err.downcast_ref::<ReplayDetected>().is_none());
+    assert!(cache.is_empty());
+
+    verify(&jws, b"payload", &cache).unwrap();
+}
+
+#[test]
+fn flipped_ecdsa_signature_is_a_replay() {
+    for (alg, curve, len) in [
+        ("ES256", Nid::X9_62_PRIME256V1, 32),
+        ("ES384", Nid::SECP384R1, 48),
+        ("ES512", Nid::SECP521R1, 66),
+    ] {
+        let group = EcGroup::from_curve_name(curve).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let signing_key = OpensslSigningKey::new(alg, key.clone()).unwrap();
+        let verification_key = OpensslVerificationKey::new(alg, &key).unwrap();
+        let cache = MemoryReplayCache::new(100, Duration::from_secs(60)).unwrap();
+
+        let verify = |jws: &[u8]| {
+            let mut writer =
+                DeserializeJwsWriter::with_key(&jws, &verification_key, &DecodeOptions::default())?;
+            writer.write_all(b"payload")?;
+            writer.finish_with_replay_cache(&cache)
+        };
+
+        let jws = detached_jws::serialize_with_key(Map::new(), &mut &b"payload"[..], &signing_key)
+            .unwrap();
+        verify(&jws).unwrap();
+
+        // (r, n - s) is an equally valid signature of the same payload
+        let jws = String::from_utf8(jws).unwrap();
+        let (header, signature) = jws.split_once("..").unwrap();
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).unwrap();
+        let mut order = BigNum::new().unwrap();
+        group
+            .order(&mut order, &mut BigNumContext::new().unwrap())
+            .unwrap();
+        let mut flipped = BigNum::new().unwrap();
+        flipped
+            .checked_sub(&order, &BigNum::from_slice(&signature[len..]).unwrap())
+            .unwrap();
+        let mut signature = signature[..len].to_vec();
+        signature.extend_from_slice(&flipped.to_vec_padded(len as i32).unwrap());
+        let flipped = format!(
+            "{}..{}",
+            header,
+            base64::encode_config(&signature, base64::URL_SAFE_NO_PAD)
+        );
+
+        let err = verify(flipped.as_bytes()).unwrap_err();
+        assert!(err.is::<ReplayDetected>(), "{} {:?}", alg, err);
+        assert_eq!(cache.len(), 1);
+    }
+}
+
+#[test]
+fn zero_capacity_is_rejected() {
+    assert!(MemoryReplayCache::new(0, Duration::from_secs(60)).is_err());
+}
+
+#[test]
+fn entries_expire() {
+    let cache = MemoryReplayCache::new(100, Duration::from_millis(100)).unwrap();
+    let key = || ReplayKey::Jti {
+        iss: None,
+        kid: None,
+        jti: "id".to_owned(),
+    };
+
+    assert!(cache.insert(key()).unwrap());
+    assert!(!cache.insert(key()).unwrap());
+    thread::sleep(Duration::from_millis(150));
+    assert!(cache.insert(key()).unwrap());
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn unbounded_ttl_never_expires() {
+    let cache = MemoryReplayCache::new(2, Duration::MAX).unwrap();
+    let key = |i: u8| ReplayKey::Signature([i; 32]);
+
+    assert!(cache.insert(key(1)).unwrap());
+    assert!(!cache.insert(key(1)).unwrap());
+    assert!(cache.insert(key(2)).unwrap());
+    assert!(cache.insert(key(3)).unwrap());
+    assert!(cache.insert(key(1)).unwrap());
+}
+
+#[test]
+fn least_recently_used_entry_is_evicted_at_capacity() {
+    let cache = MemoryReplayCache::new(2, Duration::from_secs(60)).unwrap();
+    let key = |i: u8| ReplayKey::Signature([i; 32]);
+
+    assert!(cache.insert(key(1)).unwrap());
+    assert!(cache.insert(key(2)).unwrap());
+    // the replay of 1 makes 2 the least recently used
+    assert!(!cache.insert(key(1)).unwrap());
+    assert!(cache.insert(key(3)).unwrap());
+    assert_eq!(cache.len(), 2);
+
+    assert!(!cache.insert(key(1)).unwrap());
+    assert!(cache.insert(key(2)).unwrap());
+    assert!(!cache.insert(key(1)).unwrap());
+    assert!(cache.insert(key(3)).unwrap());
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn concurrent_replays_accept_once() {
+    let cache = Arc::new(MemoryReplayCache::new(100, Duration::from_secs(60)).unwrap());
+    let jws = Arc::new(sign(Map::new(), b"payload"));
+
+    let accepted = (0..8)
+        .map(|_| {
+            let cache = cache.clone();
+            let jws = jws.clone();
+            thread::spawn(move || verify(&jws, b"payload", &*cache).is_ok())
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .filter(|accepted| *accepted)
+        .count();
+    assert_eq!(accepted, 1);
+}
@@ -0,0 +1,190 @@
+use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+use detached_jws::reqwest::{HostPolicy, JwsMiddleware};
+use detached_jws::JwsHeader;
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+use serde_json::{json, Map};
+use std::sync::Arc;
+use wiremock::{matchers::method, Mock, MockServer, Request, Respond, ResponseTemplate};
+
+const CLIENT_SECRET: &[u8] = b"the client secret of 32 bytes or more";
+const SERVER_SECRET: &[u8] = b"the server secret of 32 bytes or more";
+
+/// Verifies signed requests and answers with a signed echo of the body
+struct SignedEcho {
+    server_secret: &'static [u8],
+}
+
+impl Respond for SignedEcho {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let key = OpensslVerificationKey::hmac("HS256", CLIENT_SECRET).unwrap();
+        let verified = request
+            .headers
+            .get("x-jws-signature")
+            .map(|jws| {
+                detached_jws::deserialize_with_key(
+                    &jws.as_bytes(),
+                    &mut request.body.as_slice(),
+                    &key,
+                )
+            })
+            .and_then(Result::ok);
+
+        let body = match verified {
+            Some(header) => format!(
+                "{} {}",
+                header["kid"],
+                String::from_utf8_lossy(&request.body)
+            ),
+            None => "unsigned".to_owned(),
+        };
+
+        let key = OpensslSigningKey::hmac("HS256", self.server_secret).unwrap();
+        let mut header = Map::new();
+        header.insert("kid".to_owned(), json!("server-key"));
+        let jws = detached_jws::serialize_with_key(header, &mut body.as_bytes(), &key).unwrap();
+
+        ResponseTemplate::new(200)
+            .insert_header("x-jws-signature", String::from_utf8(jws).unwrap().as_str())
+            .set_body_string(body)
+    }
+}
+
+async fn server(server_secret: &'static [u8]) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(SignedEcho { server_secret })
+        .mount(&server)
+        .await;
+    server
+}
+
+fn client(host: &str) -> ClientWithMiddleware {
+    client_with_limit(host, 2 * 1024 * 1024)
+}
+
+fn client_with_limit(host: &str, max_body_len: usize) -> ClientWithMiddleware {
+    let signing_key = Arc::new(OpensslSigningKey::hmac("HS256", CLIENT_SECRET).unwrap());
+    let verification_key = Arc::new(OpensslVerificationKey::hmac("HS256", SERVER_SECRET).unwrap());
+
+    let mut header = Map::new();
+    header.insert("kid".to_owned(), json!("client-key"));
+
+    let middleware = JwsMiddleware::new().max_body_len(max_body_len).host(
+        host,
+        HostPolicy::new()
+            .sign(signing_key, header)
+            .verify(move |h: &JwsHeader| match h.get("kid") {
+                Some(kid) if kid == "server-key" => Some(Arc::clone(&verification_key)),
+                _ => None,
+            }),
+    );
+
+    ClientBuilder::new(reqwest::Client::new())
+        .with(middleware)
+        .build()
+}
+
+#[tokio::test]
+async fn sign_request_and_verify_response() {
+    let server = server(SERVER_SECRET).await;
+
+    let response = client(&server.address().to_string())
+        .post(server.uri())
+        .body("payload")
+        .send()
+        .await
+        .unwrap();
+
+    let header = response.extensions().get::<JwsHeader>().unwrap().clone();
+    assert_eq!(header["kid"], "server-key");
+    assert_eq!(response.url().as_str(), format!("{}/", server.uri()));
+    assert_eq!(response.text().await.unwrap(), r#""client-key" payload"#);
+}
+
+#[tokio::test]
+async fn reject_invalid_response_signature() {
+    let server = server(b"another server secret of 32 bytes").await;
+
+    let result = client(&server.address().to_string())
+        .post(server.uri())
+        .body("payload")
+        .send()
+        .await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn pass_through_other_hosts() {
+    let server = server(b"another server secret of 32 bytes").await;
+
+    let response = client("example.com")
+        .post(server.uri())
+        .body("payload")
+        .send()
+        .await
+        .unwrap();
+
+    assert!(response.extensions().get::<JwsHeader>().is_none());
+    assert_eq!(response.text().await.unwrap(), "unsigned");
+}
+
+#[tokio::test]
+async fn pass_through_other_ports() {
+    let server = server(b"another server secret of 32 bytes").await;
+
+    // the policy covers another port, or the default one, of the same host
+    for host in ["127.0.0.1:1", "127.0.0.1"] {
+        let response = client(host)
+            .post(server.uri())
+            .body("payload")
+            .send()
+            .await
+            .unwrap();
+
+        assert!(response.extensions().get::<JwsHeader>().is_none());
+        assert_eq!(response.text().await.unwrap(), "unsigned");
+    }
+}
+
+#[tokio::test]
+async fn limit_response_body() {
+    let server = server(SERVER_SECRET).await;
+
+    // the echo is `"client-key" payload`, 20 bytes
+    let err = client_with_limit(&server.address().to_string(), 19)
+        .post(server.uri())
+        .body("payload")
+        .send()
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("exceeds 19 bytes"), "{}", err);
+
+    client_with_limit(&server.address().to_string(), 20)
+        .post(server.uri())
+        .body("payload")
+        .send()
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn reject_unsigned_response() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("unsigned"))
+        .mount(&server)
+        .await;
+
+    let err = client(&server.address().to_string())
+        .post(server.uri())
+        .body("payload")
+        .send()
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("has no `x-jws-signature` header"),
+        "{}",
+        err
+    );
+}
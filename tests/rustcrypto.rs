@@ -0,0 +1,251 @@
+use std::io::Write;
+
+use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+use detached_jws::rustcrypto::{RustCryptoSigningKey, RustCryptoVerificationKey};
+use detached_jws::{JwsHeader, SerializeJwsWriter, SigningKey, VerificationKey};
+use openssl::bn::{BigNum, BigNumContext, BigNumRef};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+#[derive(Deserialize)]
+struct Vector {
+    name: String,
+    alg: String,
+    deterministic: bool,
+    jwk: JwsHeader,
+    jws: String,
+    payload: Option<String>,
+}
+
+impl Vector {
+    fn detach(&self) -> (String, Vec<u8>) {
+        let segments: Vec<&str> = self.jws.split('.').collect();
+        let payload = self.payload.as_deref().unwrap_or(segments[1]);
+        let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).unwrap();
+
+        (format!("{}..{}", segments[0], segments[2]), payload)
+    }
+}
+
+/// RFC vectors of the algorithms implemented by the backend
+fn vectors() -> Vec<Vector> {
+    serde_json::from_str(include_str!("data/jws-vectors.json")).unwrap()
+}
+
+fn encode(value: &[u8]) -> Value {
+    Value::String(base64::encode_config(value, base64::URL_SAFE_NO_PAD))
+}
+
+fn number(value: &BigNumRef) -> Value {
+    encode(&value.to_vec())
+}
+
+/// An openssl key pair for `alg` with its private JWK
+fn keypair(alg: &str) -> (OpensslSigningKey, OpensslVerificationKey, JwsHeader) {
+    let (key, jwk) = match &alg[..2] {
+        "HS" => {
+            let secret: Vec<u8> = (0..64).collect();
+            let jwk = json!({ "kty": "oct", "k": encode(&secret) });
+            return (
+                OpensslSigningKey::hmac(alg, &secret).unwrap(),
+                OpensslVerificationKey::hmac(alg, &secret).unwrap(),
+                jwk.as_object().unwrap().clone(),
+            );
+        }
+        "RS" | "PS" => {
+            let rsa = Rsa::generate(2048).unwrap();
+            let jwk = json!({
+                "kty": "RSA",
+                "n": number(rsa.n()),
+                "e": number(rsa.e()),
+                "d": number(rsa.d()),
+                "p": number(rsa.p().unwrap()),
+                "q": number(rsa.q().unwrap()),
+            });
+            (PKey::from_rsa(rsa).unwrap(), jwk)
+        }
+        _ => {
+            let (curve, crv, len) = match alg {
+                "ES256" => (Nid::X9_62_PRIME256V1, "P-256", 32),
+                "ES384" => (Nid::SECP384R1, "P-384", 48),
+                _ => (Nid::SECP521R1, "P-521", 66),
+            };
+            let group = EcGroup::from_curve_name(curve).unwrap();
+            let ec = EcKey::generate(&group).unwrap();
+            let (mut x, mut y) = (BigNum::new().unwrap(), BigNum::new().unwrap());
+            ec.public_key()
+                .affine_coordinates(&group, &mut x, &mut y, &mut BigNumContext::new().unwrap())
+                .unwrap();
+            let padded = |n: &BigNumRef| encode(&n.to_vec_padded(len).unwrap());
+            let jwk = json!({
+                "kty": "EC",
+                "crv": crv,
+                "x": padded(&x),
+                "y": padded(&y),
+                "d": padded(ec.private_key()),
+            });
+            (PKey::from_ec_key(ec).unwrap(), jwk)
+        }
+    };
+
+    (
+        OpensslSigningKey::new(alg, key.clone()).unwrap(),
+        OpensslVerificationKey::new(alg, &key).unwrap(),
+        jwk.as_object().unwrap().clone(),
+    )
+}
+
+#[test]
+fn verify_rfc_examples() {
+    for vector in vectors() {
+        let (jws, payload) = vector.detach();
+        let key = RustCryptoVerificationKey::from_jwk(&vector.alg, &vector.jwk)
+            .unwrap_or_else(|e| panic!("{}: {:?}", vector.name, e));
+
+        detached_jws::deserialize_with_key(&jws, &mut payload.as_slice(), &key)
+            .unwrap_or_else(|e| panic!("{}: {:?}", vector.name, e));
+
+        let mut tampered = payload.clone();
+        tampered[0] ^= 1;
+        assert!(
+            detached_jws::deserialize_with_key(&jws, &mut tampered.as_slice(), &key).is_err(),
+            "{}",
+            vector.name
+        );
+    }
+}
+
+#[test]
+fn reproduce_deterministic_rfc_examples() {
+    for vector in vectors().into_iter().filter(|v| v.deterministic) {
+        let (jws, payload) = vector.detach();
+        let key = RustCryptoSigningKey::from_jwk(&vector.alg, &vector.jwk).unwrap();
+
+        let mut writer = SerializeJwsWriter::with_encoded_header(
+            Vec::new(),
+            jws.split('.').next().unwrap().as_bytes(),
+            key.signer().unwrap(),
+        )
+        .unwrap();
+        writer.write_all(&payload).unwrap();
+        assert_eq!(
+            String::from_utf8(writer.finish().unwrap()).unwrap(),
+            jws,
+            "{}",
+            vector.name
+        );
+    }
+}
+
+#[test]
+fn interoperate_with_openssl() {
+    let payload: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+
+    for alg in [
+        "HS256", "HS384", "HS512", "RS256", "RS512", "PS256", "PS384", "ES256", "ES384", "ES512",
+    ] {
+        let (openssl_signing, openssl_verification, jwk) = keypair(alg);
+        let signing_key = RustCryptoSigningKey::from_jwk(alg, &jwk).unwrap();
+        let verification_key = RustCryptoVerificationKey::from_jwk(alg, &jwk).unwrap();
+        assert_eq!(verification_key.algorithm(), alg);
+
+        let jws =
+            detached_jws::serialize_with_key(Map::new(), &mut payload.as_slice(), &signing_key)
+                .unwrap();
+        detached_jws::deserialize_with_key(&jws, &mut payload.as_slice(), &openssl_verification)
+            .unwrap_or_else(|e| panic!("{}: {:?}", alg, e));
+
+        let jws =
+            detached_jws::serialize_with_key(Map::new(), &mut payload.as_slice(), &openssl_signing)
+                .unwrap();
+        detached_jws::deserialize_with_key(&jws, &mut payload.as_slice(), &verification_key)
+            .unwrap_or_else(|e| panic!("{}: {:?}", alg, e));
+        assert!(
+            detached_jws::deserialize_with_key(&jws, &mut &payload[1..], &verification_key)
+                .is_err(),
+            "{}",
+            alg
+        );
+    }
+}
+
+#[test]
+fn unsuitable_jwk() {
+    let (_, _, jwk) = keypair("ES256");
+
+    assert!(RustCryptoVerificationKey::from_jwk("RS256", &jwk).is_err());
+    assert!(RustCryptoVerificationKey::from_jwk("ES384", &jwk).is_err());
+    assert!(RustCryptoVerificationKey::from_jwk("ES512", &jwk).is_err());
+
+    let mut with_alg = jwk.clone();
+    with_alg.insert("alg".to_owned(), json!("ES384"));
+    assert!(RustCryptoVerificationKey::from_jwk("ES256", &with_alg).is_err());
+
+    let (_, _, other) = keypair("ES256");
+    let mut mismatched = jwk.clone();
+    mismatched.insert("d".to_owned(), other["d"].clone());
+    assert!(RustCryptoSigningKey::from_jwk("ES256", &mismatched).is_err());
+
+    let mut public = jwk;
+    public.remove("d");
+    assert!(RustCryptoSigningKey::from_jwk("ES256", &public).is_err());
+    assert!(RustCryptoVerificationKey::from_jwk("ES256", &public).is_ok());
+
+    let rsa = Rsa::generate(1024).unwrap();
+    let short = json!({
+        "kty": "RSA",
+        "n": number(rsa.n()),
+        "e": number(rsa.e()),
+        "d": number(rsa.d()),
+        "p": number(rsa.p().unwrap()),
+        "q": number(rsa.q().unwrap()),
+    });
+    let short = short.as_object().unwrap();
+    assert!(RustCryptoSigningKey::from_jwk("RS256", short).is_err());
+    assert!(RustCryptoVerificationKey::from_jwk("PS256", short).is_err());
+
+    for secret in [&[][..], &[7; 31]] {
+        assert!(RustCryptoSigningKey::hmac("HS256", secret).is_err());
+        assert!(RustCryptoVerificationKey::hmac("HS256", secret).is_err());
+        let oct = json!({ "kty": "oct", "k": encode(secret) });
+        assert!(RustCryptoSigningKey::from_jwk("HS256", oct.as_object().unwrap()).is_err());
+    }
+    assert!(RustCryptoVerificationKey::hmac("HS256", &[7; 32]).is_ok());
+    assert!(RustCryptoVerificationKey::hmac("HS384", &[7; 32]).is_err());
+}
+
+#[test]
+fn sign_with_caller_rng() {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let payload = b"caller supplied randomness";
+    let sign = |key: &RustCryptoSigningKey, seed| {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut writer = SerializeJwsWriter::new(
+            Vec::new(),
+            key.algorithm().to_owned(),
+            Map::new(),
+            key.signer_with_rng(&mut rng).unwrap(),
+        )
+        .unwrap();
+        writer.write_all(payload).unwrap();
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    };
+
+    for alg in ["RS256", "PS256", "ES512"] {
+        let (_, openssl_verification, jwk) = keypair(alg);
+        let key = RustCryptoSigningKey::from_jwk(alg, &jwk).unwrap();
+
+        let jws = sign(&key, 1);
+        detached_jws::deserialize_with_key(&jws, &mut &payload[..], &openssl_verification)
+            .unwrap_or_else(|e| panic!("{}: {:?}", alg, e));
+        assert_eq!(jws, sign(&key, 1), "{}", alg);
+        if alg != "RS256" {
+            assert_ne!(jws, sign(&key, 2), "{}", alg);
+        }
+    }
+}
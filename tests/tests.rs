@@ -3,7 +3,13 @@ extern crate detached_jws;
 extern crate lazy_static;
 
 use anyhow::Result;
-use detached_jws::{Sign, Verify};
+use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+use detached_jws::{
+    ByteSink, DecodeOptions, DeserializeJwsWriter, DuplicateHeaderMember, FailureReason,
+    SerializeJwsWriter, Sign, SigningKey, VerificationError, VerificationKey, Verify,
+};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::nid::Nid;
 use openssl::pkey::PKey;
 use openssl::rsa::Rsa;
 use openssl::{hash::MessageDigest, pkey::Private};
@@ -12,7 +18,8 @@ use openssl::{
     sign::{Signer, Verifier},
 };
 use serde_json::{json, Map, Value};
-use std::{io::Write, vec};
+use std::sync::Arc;
+use std::{io::Write, thread, vec};
 
 type JwsHeader = Map<String, Value>;
 
@@ -180,3 +187,828 @@ fn selector_openssl() {
         detached_jws::deserialize_selector(&jws, &mut payload.as_slice(), selector).unwrap();
     }
 }
+
+#[test]
+fn decode_options_limits() {
+    let payload = vec![0, 1, 2, 3, 4, 5, 6];
+
+    let mut header = Map::new();
+    header.insert("nested".to_owned(), json!([[[["deep"]]]]));
+
+    let jws = detached_jws::serialize(
+        "test_algorithm".to_owned(),
+        header,
+        &mut payload.as_slice(),
+        DummySigner::default(),
+    )
+    .unwrap();
+
+    let deserialize = |options: DecodeOptions| {
+        detached_jws::deserialize_selector_with_options(
+            &jws,
+            &mut payload.as_slice(),
+            |_| Some(DummyVerifier::default()),
+            &options,
+        )
+    };
+
+    deserialize(DecodeOptions::default()).unwrap();
+
+    let err = deserialize(DecodeOptions {
+        max_token_len: jws.len() - 1,
+        ..DecodeOptions::default()
+    })
+    .unwrap_err();
+    assert!(err.to_string().contains("jws exceeds"));
+
+    let err = deserialize(DecodeOptions {
+        max_header_len: 16,
+        ..DecodeOptions::default()
+    })
+    .unwrap_err();
+    assert!(err.to_string().contains("header exceeds"));
+
+    let err = deserialize(DecodeOptions {
+        max_header_depth: 4,
+        ..DecodeOptions::default()
+    })
+    .unwrap_err();
+    assert!(err.to_string().contains("nesting depth"));
+
+    let err = deserialize(DecodeOptions {
+        max_signature_len: 16,
+        ..DecodeOptions::default()
+    })
+    .unwrap_err();
+    assert!(err.to_string().contains("signature exceeds"));
+}
+
+#[test]
+fn entry_points_apply_default_limits() {
+    let payload = vec![0, 1, 2, 3, 4, 5, 6];
+
+    // e.g. a long `x5c` certificate chain
+    let mut header = Map::new();
+    header.insert("x5c".to_owned(), json!(vec!["A".repeat(4096); 8]));
+
+    let jws = detached_jws::serialize(
+        "test_algorithm".to_owned(),
+        header,
+        &mut payload.as_slice(),
+        DummySigner::default(),
+    )
+    .unwrap();
+    assert!(jws.len() > DecodeOptions::default().max_token_len);
+
+    let err = detached_jws::deserialize(&jws, &mut payload.as_slice(), DummyVerifier::default())
+        .unwrap_err();
+    assert!(err.to_string().contains("jws exceeds"));
+    let err = detached_jws::deserialize_selector(&jws, &mut payload.as_slice(), |_| {
+        Some(DummyVerifier::default())
+    })
+    .unwrap_err();
+    assert!(err.to_string().contains("jws exceeds"));
+    assert!(DeserializeJwsWriter::new(&jws, |_| Some(DummyVerifier::default())).is_err());
+
+    // trusted input may opt out
+    detached_jws::deserialize_selector_with_options(
+        &jws,
+        &mut payload.as_slice(),
+        |_| Some(DummyVerifier::default()),
+        &DecodeOptions::unlimited(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn duplicate_header_member() {
+    let payload = vec![0, 1, 2, 3, 4, 5, 6];
+
+    let encoded_header =
+        base64::encode_config(r#"{"alg":"RS256","alg":"none"}"#, base64::URL_SAFE_NO_PAD);
+    let signing_input = format!(
+        "{}.{}",
+        encoded_header,
+        base64::encode_config(&payload, base64::URL_SAFE_NO_PAD)
+    );
+    let jws = format!(
+        "{}..{}",
+        encoded_header,
+        base64::encode_config(&signing_input, base64::URL_SAFE_NO_PAD)
+    );
+
+    let err = detached_jws::deserialize(&jws, &mut payload.as_slice(), DummyVerifier::default())
+        .unwrap_err();
+
+    assert_eq!(
+        err.downcast_ref::<DuplicateHeaderMember>(),
+        Some(&DuplicateHeaderMember("alg".to_owned()))
+    );
+}
+
+#[test]
+fn preserve_header_bytes() {
+    let payload = vec![0, 1, 2, 3, 4, 5, 6];
+    let header_json = br#"{ "kid": "1", "alg": "test_algorithm" }"#;
+    let encoded_header = base64::encode_config(header_json, base64::URL_SAFE_NO_PAD);
+
+    let mut writer =
+        SerializeJwsWriter::with_header_json(Vec::new(), header_json, DummySigner::default())
+            .unwrap();
+    writer.write_all(&payload).unwrap();
+    let jws = writer.finish().unwrap();
+
+    let mut writer = SerializeJwsWriter::with_encoded_header(
+        Vec::new(),
+        encoded_header.as_bytes(),
+        DummySigner::default(),
+    )
+    .unwrap();
+    writer.write_all(&payload).unwrap();
+    assert_eq!(writer.finish().unwrap(), jws);
+
+    let mut writer = DeserializeJwsWriter::new(&jws, |_| Some(DummyVerifier::default())).unwrap();
+    writer.write_all(&payload).unwrap();
+    let verified_headers = writer.finish().unwrap();
+
+    assert_eq!(writer.encoded_header(), encoded_header.as_bytes());
+    assert_eq!(verified_headers.get("kid").unwrap().as_str().unwrap(), "1");
+}
+
+#[test]
+fn pre_serialized_header_requires_alg() {
+    assert!(SerializeJwsWriter::with_header_json(
+        Vec::new(),
+        br#"{"kid":"1"}"#,
+        DummySigner::default()
+    )
+    .is_err());
+    assert!(SerializeJwsWriter::with_encoded_header(
+        Vec::new(),
+        b"not base64!",
+        DummySigner::default()
+    )
+    .is_err());
+}
+
+#[test]
+fn openssl_keys() {
+    let rsa = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let ec = PKey::from_ec_key(
+        EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap(),
+    )
+    .unwrap();
+
+    let keys = [
+        (
+            OpensslSigningKey::new("RS256", rsa.clone()).unwrap(),
+            OpensslVerificationKey::new("RS256", &rsa).unwrap(),
+        ),
+        (
+            OpensslSigningKey::new("PS384", rsa.clone()).unwrap(),
+            OpensslVerificationKey::new("PS384", &rsa).unwrap(),
+        ),
+        (
+            OpensslSigningKey::new("ES256", ec.clone()).unwrap(),
+            OpensslVerificationKey::new("ES256", &ec).unwrap(),
+        ),
+        (
+            OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap(),
+            OpensslVerificationKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap(),
+        ),
+    ];
+
+    let payload = vec![0, 1, 2, 3, 4, 5, 6];
+
+    for (signing_key, verification_key) in keys.iter() {
+        let jws =
+            detached_jws::serialize_with_key(Map::new(), &mut payload.as_slice(), signing_key)
+                .unwrap();
+
+        let verified_headers =
+            detached_jws::deserialize_with_key(&jws, &mut payload.as_slice(), verification_key)
+                .unwrap();
+        assert_eq!(
+            verified_headers.get("alg").unwrap().as_str().unwrap(),
+            signing_key.algorithm()
+        );
+
+        assert!(
+            detached_jws::deserialize_with_key(&jws, &mut [7].as_ref(), verification_key).is_err()
+        );
+    }
+
+    let es256 = &keys[2].1;
+    assert_eq!(
+        detached_jws::serialize_with_key(Map::new(), &mut payload.as_slice(), &keys[2].0)
+            .unwrap()
+            .rsplit(|b| *b == b'.')
+            .next()
+            .unwrap()
+            .len(),
+        86
+    );
+
+    let jws =
+        detached_jws::serialize_with_key(Map::new(), &mut payload.as_slice(), &keys[0].0).unwrap();
+    assert!(detached_jws::deserialize_with_key(&jws, &mut payload.as_slice(), es256).is_err());
+
+    // the key type and curve must suit the algorithm
+    assert!(OpensslSigningKey::new("ES256", rsa.clone()).is_err());
+    assert!(OpensslSigningKey::new("ES384", ec.clone()).is_err());
+    assert!(OpensslSigningKey::new("PS256", ec.clone()).is_err());
+    assert!(OpensslVerificationKey::new("ES256", &rsa).is_err());
+    assert!(OpensslVerificationKey::new("ES512", &ec).is_err());
+    assert!(OpensslVerificationKey::new("RS256", &ec).is_err());
+
+    // as must the RSA key size
+    let short = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+    assert!(OpensslSigningKey::new("RS256", short.clone()).is_err());
+    assert!(OpensslVerificationKey::new("PS256", &short).is_err());
+
+    // and an HMAC secret must be as long as the hash output
+    for secret in [&[][..], &[7; 31]] {
+        assert!(OpensslSigningKey::hmac("HS256", secret).is_err());
+        assert!(OpensslVerificationKey::hmac("HS256", secret).is_err());
+        let jwk =
+            json!({ "kty": "oct", "k": base64::encode_config(secret, base64::URL_SAFE_NO_PAD) });
+        assert!(OpensslVerificationKey::from_jwk("HS256", jwk.as_object().unwrap()).is_err());
+    }
+    assert!(OpensslSigningKey::hmac("HS256", &[7; 32]).is_ok());
+    assert!(OpensslSigningKey::hmac("HS512", &[7; 32]).is_err());
+}
+
+#[test]
+fn shared_keys_across_threads() {
+    let rsa = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+    let signing_key = Arc::new(OpensslSigningKey::new("PS256", rsa.clone()).unwrap());
+    let verification_key = Arc::new(OpensslVerificationKey::new("PS256", &rsa).unwrap());
+
+    let handles: Vec<_> = (0..4u8)
+        .map(|i| {
+            let signing_key = Arc::clone(&signing_key);
+            let verification_key = Arc::clone(&verification_key);
+            thread::spawn(move || {
+                let payload = vec![i; 32];
+                let jws = detached_jws::serialize_with_key(
+                    Map::new(),
+                    &mut payload.as_slice(),
+                    signing_key.as_ref(),
+                )
+                .unwrap();
+                detached_jws::deserialize_with_key(
+                    &jws,
+                    &mut payload.as_slice(),
+                    verification_key.as_ref(),
+                )
+                .unwrap();
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn verification_outcome() {
+    let keypair = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let signing_key = OpensslSigningKey::new("RS256", keypair.clone()).unwrap();
+    let verification_key = OpensslVerificationKey::new("RS256", &keypair).unwrap();
+
+    let mut header = Map::new();
+    header.insert("kid".to_owned(), json!("key-1"));
+
+    let payload = vec![0, 1, 2, 3, 4, 5, 6];
+
+    let jws =
+        detached_jws::serialize_with_key(header, &mut payload.as_slice(), &signing_key).unwrap();
+
+    let verify = |jws: &[u8], payload: &[u8]| {
+        let mut writer =
+            DeserializeJwsWriter::new(&jws, |_| Some(verification_key.verifier().unwrap()))
+                .unwrap();
+        writer.write_all(payload).unwrap();
+        writer.finish_verification()
+    };
+
+    let verification = verify(&jws, &payload).unwrap();
+    assert_eq!(verification.algorithm.as_deref(), Some("RS256"));
+    assert_eq!(verification.key_id.as_deref(), Some("key-1"));
+
+    let err = verify(&jws, &[7]).unwrap_err();
+    let err = err.downcast_ref::<VerificationError>().unwrap();
+    assert_eq!(err.key_id.as_deref(), Some("key-1"));
+    assert!(matches!(err.reason, FailureReason::Mismatch));
+
+    let dot = jws.iter().rposition(|b| *b == b'.').unwrap();
+    let signature = base64::decode_config(&jws[dot + 1..], base64::URL_SAFE_NO_PAD).unwrap();
+    let mut truncated = jws[..=dot].to_vec();
+    truncated.extend(base64::encode_config(&signature[..253], base64::URL_SAFE_NO_PAD).bytes());
+    let err = verify(&truncated, &payload).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<VerificationError>().unwrap().reason,
+        FailureReason::WrongLength {
+            expected: 256,
+            actual: 253
+        }
+    ));
+}
+
+#[test]
+fn verification_backend_error() {
+    struct FailingVerifier;
+
+    impl Verify for FailingVerifier {
+        fn verify(&self, _signature: &[u8]) -> Result<bool> {
+            anyhow::bail!("device unavailable")
+        }
+    }
+
+    impl Write for FailingVerifier {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let payload = vec![0, 1, 2, 3, 4, 5, 6];
+
+    let jws = detached_jws::serialize(
+        "test_algorithm".to_owned(),
+        Map::new(),
+        &mut payload.as_slice(),
+        DummySigner::default(),
+    )
+    .unwrap();
+
+    let err = detached_jws::deserialize(&jws, &mut payload.as_slice(), FailingVerifier)
+        .unwrap_err()
+        .downcast::<VerificationError>()
+        .unwrap();
+
+    assert_eq!(err.algorithm.as_deref(), Some("test_algorithm"));
+    assert!(matches!(err.reason, FailureReason::Backend(_)));
+}
+
+#[test]
+fn resolve_key_by_kid() {
+    let keys: Vec<_> = ["key-1", "key-2"]
+        .iter()
+        .map(|kid| {
+            (
+                kid.to_string(),
+                OpensslSigningKey::hmac("HS256", kid.repeat(8).as_bytes()).unwrap(),
+                Arc::new(OpensslVerificationKey::hmac("HS256", kid.repeat(8).as_bytes()).unwrap()),
+            )
+        })
+        .collect();
+
+    let resolver = |h: &JwsHeader| {
+        let kid = h.get("kid")?.as_str()?;
+        keys.iter()
+            .find(|(id, _, _)| id == kid)
+            .map(|(_, _, key)| Arc::clone(key))
+    };
+
+    let payload = vec![0, 1, 2, 3, 4, 5, 6];
+
+    for (kid, signing_key, _) in keys.iter() {
+        let mut header = Map::new();
+        header.insert("kid".to_owned(), json!(kid));
+
+        let jws =
+            detached_jws::serialize_with_key(header, &mut payload.as_slice(), signing_key).unwrap();
+
+        detached_jws::deserialize_with_resolver(&jws, &mut payload.as_slice(), &resolver).unwrap();
+    }
+
+    let mut header = Map::new();
+    header.insert("kid".to_owned(), json!("key-3"));
+    let jws =
+        detached_jws::serialize_with_key(header, &mut payload.as_slice(), &keys[0].1).unwrap();
+
+    assert!(
+        detached_jws::deserialize_with_resolver(&jws, &mut payload.as_slice(), &resolver).is_err()
+    );
+}
+
+#[test]
+fn sign_content_digest() {
+    use detached_jws::digest::{self, ContentDigest, DigestAlgorithm, DigestMismatch};
+
+    let payload = vec![7u8; 100_000];
+    let signing_key =
+        OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+    let verification_key =
+        OpensslVerificationKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+
+    for algorithm in [DigestAlgorithm::Sha256, DigestAlgorithm::Sha512] {
+        let stored = ContentDigest::compute(algorithm, &mut payload.as_slice()).unwrap();
+        assert_eq!(stored.to_string().parse::<ContentDigest>().unwrap(), stored);
+
+        let precomputed = ContentDigest::new(algorithm, stored.value().to_vec()).unwrap();
+        let mut header = Map::new();
+        header.insert("kid".to_owned(), json!("key-1"));
+        let jws = digest::serialize_digest_with_key(header, &precomputed, &signing_key).unwrap();
+
+        let header =
+            digest::deserialize_digest_with_key(&jws, &mut payload.as_slice(), &verification_key)
+                .unwrap();
+        assert_eq!(header["kid"], "key-1");
+        assert_eq!(header[digest::CONTENT_DIGEST_MEMBER], stored.to_string());
+        assert_eq!(header["crit"], json!([digest::CONTENT_DIGEST_MEMBER]));
+
+        // the signed payload is empty, but a plain verification must not accept it
+        assert!(
+            detached_jws::deserialize_with_key(&jws, &mut &b""[..], &verification_key).is_err()
+        );
+        assert!(detached_jws::deserialize(
+            &jws,
+            &mut &b""[..],
+            verification_key.verifier().unwrap()
+        )
+        .is_err());
+        let view = detached_jws::DetachedJwsRef::parse(&jws, &Default::default()).unwrap();
+        assert!(view
+            .verify_with_key(&mut &b""[..], &verification_key)
+            .is_err());
+        assert!(view
+            .verify(&mut &b""[..], verification_key.verifier().unwrap())
+            .is_err());
+
+        let mut writer =
+            digest::DeserializeDigestWriter::with_key(&jws, &verification_key).unwrap();
+        assert_eq!(writer.digest(), &stored);
+        for chunk in payload.chunks(333) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let error =
+            digest::deserialize_digest_with_key(&jws, &mut &payload[1..], &verification_key)
+                .unwrap_err();
+        let mismatch = error.downcast_ref::<DigestMismatch>().unwrap();
+        assert_eq!(mismatch.expected, stored);
+
+        let other_key =
+            OpensslVerificationKey::hmac("HS256", b"another secret of 32 bytes or more").unwrap();
+        assert!(
+            digest::deserialize_digest_with_key(&jws, &mut payload.as_slice(), &other_key).is_err()
+        );
+    }
+
+    let stored = ContentDigest::compute(DigestAlgorithm::Sha256, &mut payload.as_slice()).unwrap();
+    let mut header = Map::new();
+    header.insert("x5c".to_owned(), json!(vec!["A".repeat(4096); 8]));
+    let jws = digest::serialize_digest_with_key(header, &stored, &signing_key).unwrap();
+    let selector = |_: &Map<String, Value>| Some(verification_key.verifier().unwrap());
+    assert!(digest::DeserializeDigestWriter::new(&jws, selector).is_err());
+    let unlimited = DecodeOptions::unlimited();
+    let writer = digest::DeserializeDigestWriter::with_options(&jws, selector, &unlimited);
+    assert_eq!(writer.unwrap().digest(), &stored);
+
+    let mut header = Map::new();
+    header.insert("crit".to_owned(), json!(["b64"]));
+    header.insert("b64".to_owned(), json!(true));
+    let jws = digest::serialize_digest_with_key(header, &stored, &signing_key).unwrap();
+    let header = detached_jws::decode_header(&jws, &Default::default()).unwrap();
+    assert_eq!(
+        header["crit"],
+        json!(["b64", digest::CONTENT_DIGEST_MEMBER])
+    );
+
+    let mut header = Map::new();
+    header.insert("crit".to_owned(), json!("b64"));
+    assert!(digest::serialize_digest_with_key(header, &stored, &signing_key).is_err());
+
+    assert!(ContentDigest::new(DigestAlgorithm::Sha256, vec![0; 20]).is_err());
+    assert!("md5=:AAAA:".parse::<ContentDigest>().is_err());
+    let parsed: ContentDigest =
+        "unixsum=:MA==:, sha-256=:RK/0qy18MlBSVnWgjwz6lZEWjP/lF5HF9bvEF8FabDg=:"
+            .parse()
+            .unwrap();
+    assert_eq!(parsed.algorithm(), DigestAlgorithm::Sha256);
+
+    let jws = detached_jws::serialize_with_key(Map::new(), &mut payload.as_slice(), &signing_key)
+        .unwrap();
+    assert!(
+        digest::deserialize_digest_with_key(&jws, &mut payload.as_slice(), &verification_key)
+            .is_err()
+    );
+}
+
+#[test]
+fn batch_verification() {
+    use detached_jws::batch::BatchVerifier;
+
+    let keys: Vec<_> = ["key-1", "key-2"]
+        .iter()
+        .map(|kid| {
+            let secret = kid.repeat(8);
+            (
+                kid.to_string(),
+                OpensslSigningKey::hmac("HS256", secret.as_bytes()).unwrap(),
+                Arc::new(OpensslVerificationKey::hmac("HS256", secret.as_bytes()).unwrap()),
+            )
+        })
+        .collect();
+
+    let jobs: Vec<_> = (0..100u8)
+        .map(|i| {
+            let (kid, signing_key, _) = &keys[i as usize % 2];
+            let mut header = Map::new();
+            header.insert("kid".to_owned(), json!(kid));
+            header.insert("n".to_owned(), json!(i));
+            let payload = vec![i; 64];
+            let jws =
+                detached_jws::serialize_with_key(header, &mut payload.as_slice(), signing_key)
+                    .unwrap();
+            match i % 7 {
+                0 => (jws, vec![i; 63]),
+                _ => (jws, payload),
+            }
+        })
+        .collect();
+
+    let verifier = BatchVerifier::new(|h: &JwsHeader| {
+        let kid = h.get("kid")?.as_str()?;
+        keys.iter()
+            .find(|(id, _, _)| id == kid)
+            .map(|(_, _, key)| Arc::clone(key))
+    })
+    .chunk_len(16);
+
+    let results: Vec<_> = verifier
+        .verify(jobs.iter().map(|(jws, payload)| (jws, payload.as_slice())))
+        .collect();
+
+    assert_eq!(results.len(), 100);
+    for (i, result) in results.iter().enumerate() {
+        match i % 7 {
+            0 => assert!(result.is_err()),
+            _ => assert_eq!(result.as_ref().unwrap()["n"], json!(i)),
+        }
+    }
+}
+
+#[test]
+fn borrowed_view() {
+    use detached_jws::DetachedJwsRef;
+    use std::borrow::Cow;
+
+    #[derive(serde::Deserialize)]
+    struct Selected<'a> {
+        #[serde(borrow)]
+        kid: Cow<'a, str>,
+        alg: &'a str,
+    }
+
+    let keypair = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let signing_key = OpensslSigningKey::new("PS256", keypair.clone()).unwrap();
+    let key = OpensslVerificationKey::new("PS256", &keypair).unwrap();
+
+    let mut header = Map::new();
+    header.insert("kid".to_owned(), json!("key\"1"));
+    header.insert("large".to_owned(), json!("x".repeat(4000)));
+    let payload = vec![0, 1, 2, 3, 4, 5, 6];
+    let jws =
+        detached_jws::serialize_with_key(header, &mut payload.as_slice(), &signing_key).unwrap();
+
+    let view = DetachedJwsRef::parse(&jws, &DecodeOptions::default()).unwrap();
+    assert!(jws.starts_with(view.encoded_header()));
+
+    let mut small = [0; 16];
+    assert!(view.header_as::<Selected>(&mut small).is_err());
+
+    let mut buf = vec![0; view.header_buffer_len()];
+    let selected: Selected = view.header_as(&mut buf).unwrap();
+    assert_eq!(selected.kid, "key\"1");
+    assert_eq!(selected.alg, "PS256");
+    assert_eq!(view.header().unwrap()["kid"], "key\"1");
+
+    assert_eq!(view.signature().unwrap().len(), 256);
+    view.verify_with_key(&mut payload.as_slice(), &key).unwrap();
+
+    let error = view.verify_with_key(&mut &payload[1..], &key).unwrap_err();
+    let error = error.downcast_ref::<VerificationError>().unwrap();
+    assert!(matches!(error.reason, FailureReason::Mismatch));
+
+    let other = OpensslVerificationKey::new("RS256", &keypair).unwrap();
+    assert!(view
+        .verify_with_key(&mut payload.as_slice(), &other)
+        .is_err());
+
+    let options = DecodeOptions {
+        max_signature_len: 128,
+        ..DecodeOptions::default()
+    };
+    assert!(DetachedJwsRef::parse(&jws, &options).is_err());
+    assert!(DetachedJwsRef::parse(b"no-dots", &DecodeOptions::default()).is_err());
+}
+
+/// An HS256 jws over a protected header serialized by hand
+fn hmac_jws(header_json: &str, payload: &[u8]) -> Vec<u8> {
+    let encoded_header = base64::encode_config(header_json, base64::URL_SAFE_NO_PAD);
+    let key = OpensslSigningKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+    let mut signer = key.signer().unwrap();
+    signer.write_all(encoded_header.as_bytes()).unwrap();
+    signer.write_all(b".").unwrap();
+    signer
+        .write_all(base64::encode_config(payload, base64::URL_SAFE_NO_PAD).as_bytes())
+        .unwrap();
+
+    format!(
+        "{}..{}",
+        encoded_header,
+        base64::encode_config(signer.get_sign().unwrap(), base64::URL_SAFE_NO_PAD)
+    )
+    .into_bytes()
+}
+
+#[test]
+fn borrowed_view_checks_header_like_writer() {
+    use detached_jws::DetachedJwsRef;
+
+    let payload = b"payload";
+    let key =
+        OpensslVerificationKey::hmac("HS256", b"a shared secret of 32 bytes or more").unwrap();
+    let options = DecodeOptions::default();
+    let writer = |jws: &[u8]| {
+        let mut writer = DeserializeJwsWriter::with_key(&jws, &key, &options)?;
+        writer.write_all(payload)?;
+        writer.finish()
+    };
+
+    let jws = hmac_jws(r#"{"alg":"HS256","kid":"a","kid":"b"}"#, payload);
+    let view = DetachedJwsRef::parse(&jws, &options).unwrap();
+    let duplicate = Some(&DuplicateHeaderMember("kid".to_owned()));
+    for err in [
+        writer(&jws).unwrap_err(),
+        view.verify_with_key(&mut &payload[..], &key).unwrap_err(),
+        view.verify(&mut &payload[..], key.verifier().unwrap())
+            .unwrap_err(),
+    ] {
+        assert_eq!(err.downcast_ref::<DuplicateHeaderMember>(), duplicate);
+    }
+
+    // `alg` written with a JSON escape is compared unescaped
+    let jws = hmac_jws(r#"{"alg":"\u0048S256","kid":"a"}"#, payload);
+    assert_eq!(writer(&jws).unwrap()["alg"], "HS256");
+    let view = DetachedJwsRef::parse(&jws, &options).unwrap();
+    view.verify_with_key(&mut &payload[..], &key).unwrap();
+    view.verify(&mut &payload[..], key.verifier().unwrap())
+        .unwrap();
+}
+
+#[test]
+fn chunked_payload_encoding() {
+    let payload: Vec<u8> = (0..200_000u32).map(|i| (i * 31 % 251) as u8).collect();
+    let expected = base64::encode_config(&payload, base64::URL_SAFE_NO_PAD);
+
+    for sizes in [
+        &[1][..],
+        &[7, 49_152, 2],
+        &[49_151, 1, 100_000],
+        &[200_000],
+        &[8192],
+    ] {
+        let mut writer = SerializeJwsWriter::new(
+            Vec::new(),
+            "test_algorithm".to_owned(),
+            Map::new(),
+            DummySigner::default(),
+        )
+        .unwrap();
+
+        let mut rest = payload.as_slice();
+        for size in sizes.iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (chunk, tail) = rest.split_at((*size).min(rest.len()));
+            writer.write_all(chunk).unwrap();
+            rest = tail;
+        }
+        let jws = writer.finish().unwrap();
+
+        let signature = jws.rsplit(|b| *b == b'.').next().unwrap();
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).unwrap();
+        let encoded_payload = signature.rsplit(|b| *b == b'.').next().unwrap();
+        assert_eq!(encoded_payload, expected.as_bytes());
+
+        // the dummy signature holds the whole signing input
+        let options = DecodeOptions {
+            max_token_len: usize::MAX,
+            max_signature_len: usize::MAX,
+            ..DecodeOptions::default()
+        };
+        detached_jws::deserialize_selector_with_options(
+            &jws,
+            &mut payload.as_slice(),
+            |_| Some(DummyVerifier::default()),
+            &options,
+        )
+        .unwrap();
+    }
+}
+
+#[test]
+fn flush_encodes_whole_quanta() {
+    /// Shares the signing input received so far
+    #[derive(Clone, Default)]
+    struct SharedSigner(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Sign for SharedSigner {
+        fn get_sign(&self) -> Result<Vec<u8>> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+    }
+
+    impl Write for SharedSigner {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let signer = SharedSigner::default();
+    let mut writer = SerializeJwsWriter::new(
+        Vec::new(),
+        "test_algorithm".to_owned(),
+        Map::new(),
+        signer.clone(),
+    )
+    .unwrap();
+
+    writer.write_all(&[0, 1, 2, 3, 4]).unwrap();
+    assert!(signer.0.lock().unwrap().ends_with(b"."));
+    writer.flush().unwrap();
+    assert!(signer.0.lock().unwrap().ends_with(b".AAEC"));
+
+    writer.write_all(&[5, 6]).unwrap();
+    writer.flush().unwrap();
+    assert!(signer.0.lock().unwrap().ends_with(b".AAECAwQF"));
+
+    writer.write_all(&[7]).unwrap();
+    writer.finish().unwrap();
+    let expected = base64::encode_config([0, 1, 2, 3, 4, 5, 6, 7], base64::URL_SAFE_NO_PAD);
+    assert!(signer.0.lock().unwrap().ends_with(expected.as_bytes()));
+}
+
+/// Signer and verifier implementing only [`ByteSink`], as on targets without `std::io`
+#[derive(Default)]
+pub struct SinkOnlySigner(Vec<u8>);
+
+impl ByteSink for SinkOnlySigner {
+    fn update(&mut self, data: &[u8]) -> Result<()> {
+        self.0.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+impl Sign for SinkOnlySigner {
+    fn get_sign(&self) -> Result<Vec<u8>> {
+        Ok(self.0.clone())
+    }
+}
+
+impl Verify for SinkOnlySigner {
+    fn verify(&self, signature: &[u8]) -> Result<bool> {
+        Ok(signature.eq(&self.0))
+    }
+}
+
+#[test]
+fn byte_sink_signer() {
+    let mut header = Map::new();
+    header.insert("custom".to_owned(), json!("custom_value"));
+
+    let mut writer = SerializeJwsWriter::new(
+        Vec::new(),
+        "test_algorithm".to_owned(),
+        header,
+        SinkOnlySigner::default(),
+    )
+    .unwrap();
+    writer.update(&[0, 1, 2, 3]).unwrap();
+    writer.update(&[4, 5, 6]).unwrap();
+    let jws = writer.finish().unwrap();
+
+    let mut writer = DeserializeJwsWriter::new(&jws, |_| Some(SinkOnlySigner::default())).unwrap();
+    writer.update(&[0, 1, 2, 3, 4, 5, 6]).unwrap();
+    let verified_headers = writer.finish().unwrap();
+    assert_eq!(verified_headers["custom"], json!("custom_value"));
+
+    let mut writer = DeserializeJwsWriter::new(&jws, |_| Some(SinkOnlySigner::default())).unwrap();
+    writer.update(&[0, 1, 2, 3, 4, 5]).unwrap();
+    assert!(writer.finish().is_err());
+}
@@ -0,0 +1,302 @@
+use axum::{
+    body::Body,
+    routing::{get, post},
+    Extension, Router,
+};
+use detached_jws::openssl::{OpensslSigningKey, OpensslVerificationKey};
+use detached_jws::tower::{Rejection, SignJwsLayer, SigningError, VerifyJwsLayer};
+use detached_jws::{JwsHeader, KeyResolver};
+use http::{header::HeaderName, Request, StatusCode};
+use http_body_util::BodyExt;
+use serde_json::{json, Map};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+const SECRET: &[u8] = b"a shared secret of 32 bytes or more";
+
+struct Keys(Arc<OpensslVerificationKey>);
+
+impl KeyResolver for Keys {
+    type Key = OpensslVerificationKey;
+
+    fn resolve(&self, header: &JwsHeader) -> anyhow::Result<Arc<OpensslVerificationKey>> {
+        match header.get("kid") {
+            Some(kid) if kid == "key-1" => Ok(Arc::clone(&self.0)),
+            _ => anyhow::bail!("unknown key"),
+        }
+    }
+}
+
+fn app(layer: VerifyJwsLayer<Keys>) -> Router {
+    Router::new()
+        .route(
+            "/",
+            post(
+                |Extension(header): Extension<JwsHeader>, body: String| async move {
+                    format!("{} {}", header.get("kid").unwrap().as_str().unwrap(), body)
+                },
+            ),
+        )
+        .layer(layer)
+}
+
+fn verify_layer() -> VerifyJwsLayer<Keys> {
+    let key = OpensslVerificationKey::hmac("HS256", SECRET).unwrap();
+    VerifyJwsLayer::new(Keys(Arc::new(key)))
+}
+
+fn sign(kid: &str, payload: &[u8]) -> String {
+    let mut header = Map::new();
+    header.insert("kid".to_owned(), json!(kid));
+
+    let key = OpensslSigningKey::hmac("HS256", SECRET).unwrap();
+    let jws = detached_jws::serialize_with_key(header, &mut &payload[..], &key).unwrap();
+
+    String::from_utf8(jws).unwrap()
+}
+
+async fn send(router: Router, jws: Option<&str>, body: &'static str) -> (StatusCode, String) {
+    let mut request = Request::post("/");
+    if let Some(jws) = jws {
+        request = request.header("x-jws-signature", jws);
+    }
+
+    let response = router
+        .oneshot(request.body(Body::from(body)).unwrap())
+        .await
+        .unwrap();
+
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn verified_request() {
+    let jws = sign("key-1", b"payload");
+
+    let (status, body) = send(app(verify_layer()), Some(&jws), "payload").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "key-1 payload");
+}
+
+#[tokio::test]
+async fn rejected_requests() {
+    let jws = sign("key-1", b"payload");
+
+    let (status, body) = send(app(verify_layer()), None, "payload").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body, "missing signature");
+
+    let (status, _) = send(app(verify_layer()), Some("not a jws"), "payload").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, body) = send(app(verify_layer()), Some(&jws), "tampered").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body, "invalid signature");
+
+    let unknown = sign("key-2", b"payload");
+    let (status, _) = send(app(verify_layer()), Some(&unknown), "payload").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(app(verify_layer().max_body_len(4)), Some(&jws), "payload").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn configured_layer() {
+    let jws = sign("key-1", b"payload");
+
+    let layer = verify_layer()
+        .header_name(HeaderName::from_static("x-signature"))
+        .rejection(|r| match r {
+            Rejection::Unauthorized(_) => (StatusCode::FORBIDDEN, "denied".to_owned()),
+            _ => (StatusCode::UNPROCESSABLE_ENTITY, r.to_string()),
+        });
+
+    let (status, body) = send(app(layer.clone()), Some(&jws), "payload").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body, "missing signature");
+
+    let response = app(layer)
+        .oneshot(
+            Request::post("/")
+                .header("x-signature", &jws)
+                .body(Body::from("tampered"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+fn signing_app() -> Router {
+    let key = Arc::new(OpensslSigningKey::hmac("HS256", SECRET).unwrap());
+
+    Router::new()
+        .route(
+            "/header",
+            get(|| async { "payload" })
+                .layer(SignJwsLayer::new(Arc::clone(&key)).header_member("kid", json!("key-1"))),
+        )
+        .route(
+            "/trailers",
+            get(|| async { "payload" }).layer(
+                SignJwsLayer::new(key)
+                    .header_name(HeaderName::from_static("x-signature"))
+                    .issued_at()
+                    .trailers(),
+            ),
+        )
+}
+
+#[tokio::test]
+async fn signed_response_header() {
+    let response = signing_app()
+        .oneshot(Request::get("/header").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let jws = response.headers()["x-jws-signature"].as_bytes().to_vec();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body.as_ref(), b"payload");
+
+    let key = OpensslVerificationKey::hmac("HS256", SECRET).unwrap();
+    let header = detached_jws::deserialize_with_key(&jws, &mut body.as_ref(), &key).unwrap();
+    assert_eq!(header.get("kid").unwrap(), "key-1");
+    assert!(header.get("iat").is_none());
+}
+
+#[tokio::test]
+async fn signed_response_trailer() {
+    let response = signing_app()
+        .oneshot(Request::get("/trailers").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(response.headers()["trailer"], "x-signature");
+    assert!(response.headers().get("x-jws-signature").is_none());
+
+    let collected = response.into_body().collect().await.unwrap();
+    let jws = collected.trailers().unwrap()["x-signature"]
+        .as_bytes()
+        .to_vec();
+    let body = collected.to_bytes();
+    assert_eq!(body.as_ref(), b"payload");
+
+    let key = OpensslVerificationKey::hmac("HS256", SECRET).unwrap();
+    let header = detached_jws::deserialize_with_key(&jws, &mut body.as_ref(), &key).unwrap();
+    assert!(header.get("iat").unwrap().is_u64());
+}
+
+#[tokio::test]
+async fn signed_response_body_limit() {
+    let key = Arc::new(OpensslSigningKey::hmac("HS256", SECRET).unwrap());
+    let app: Router = Router::new().route(
+        "/header",
+        get(|| async { ([("x-kept", "no")], "payload") })
+            .layer(SignJwsLayer::new(key).max_body_len(4)),
+    );
+    let response = app
+        .oneshot(Request::get("/header").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(response.headers().get("x-kept").is_none());
+    let err = response.extensions().get::<SigningError>().unwrap();
+    assert!(err.to_string().contains("body exceeds 4 bytes"));
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(body.is_empty());
+}
+
+/// Sends `frames`, then never ends unless `end` is set
+#[derive(Clone)]
+struct FramedBody {
+    frames: std::collections::VecDeque<bytes::Bytes>,
+    end: bool,
+}
+
+impl http_body::Body for FramedBody {
+    type Data = bytes::Bytes;
+    type Error = std::convert::Infallible;
+
+    fn poll_frame(
+        mut self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<http_body::Frame<bytes::Bytes>, Self::Error>>> {
+        match self.frames.pop_front() {
+            Some(data) => std::task::Poll::Ready(Some(Ok(http_body::Frame::data(data)))),
+            None if self.end => std::task::Poll::Ready(None),
+            None => std::task::Poll::Pending,
+        }
+    }
+}
+
+async fn signed_trailers(
+    frames: &[&'static str],
+    end: bool,
+    max_body_len: usize,
+) -> detached_jws::tower::SignedBody<FramedBody> {
+    let key = Arc::new(OpensslSigningKey::hmac("HS256", SECRET).unwrap());
+    let layer = SignJwsLayer::new(key).trailers().max_body_len(max_body_len);
+    let frames = frames
+        .iter()
+        .map(|f| bytes::Bytes::from_static(f.as_bytes()));
+    let body = FramedBody {
+        frames: frames.collect(),
+        end,
+    };
+
+    let service = tower::service_fn(move |_: Request<()>| {
+        let body = body.clone();
+        async move { Ok::<_, std::convert::Infallible>(http::Response::new(body)) }
+    });
+    let response = tower::Layer::layer(&layer, service)
+        .oneshot(Request::new(()))
+        .await
+        .unwrap();
+    response.into_body()
+}
+
+#[tokio::test]
+async fn signed_trailer_streams_frames() {
+    // frames are passed on before the body ends
+    let mut body = signed_trailers(&["first"], false, 1024).await;
+    let frame = body.frame().await.unwrap().unwrap();
+    assert_eq!(frame.into_data().unwrap().as_ref(), b"first");
+
+    let collected = signed_trailers(&["pay", "lo", "ad"], true, 1024)
+        .await
+        .collect()
+        .await
+        .unwrap();
+    let jws = collected.trailers().unwrap()["x-jws-signature"]
+        .as_bytes()
+        .to_vec();
+    let body = collected.to_bytes();
+    assert_eq!(body.as_ref(), b"payload");
+
+    let key = OpensslVerificationKey::hmac("HS256", SECRET).unwrap();
+    detached_jws::deserialize_with_key(&jws, &mut body.as_ref(), &key).unwrap();
+}
+
+#[tokio::test]
+async fn signed_trailer_body_limit() {
+    let mut body = signed_trailers(&["pay", "lo", "ad"], true, 5).await;
+
+    assert_eq!(
+        body.frame().await.unwrap().unwrap().into_data().unwrap(),
+        "pay"
+    );
+    assert_eq!(
+        body.frame().await.unwrap().unwrap().into_data().unwrap(),
+        "lo"
+    );
+    let err = body.frame().await.unwrap().unwrap_err();
+    assert!(err.to_string().contains("body exceeds 5 bytes"));
+    assert!(body.frame().await.is_none());
+}
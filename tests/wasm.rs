@@ -0,0 +1,83 @@
+//! Run under Node with
+//! `cargo test --target wasm32-unknown-unknown --no-default-features --features wasm --test wasm`;
+//! `.cargo/config.toml` sets `wasm-bindgen-test-runner` as the runner.
+#![cfg(target_arch = "wasm32")]
+
+use detached_jws::wasm::{sign, verify};
+use detached_jws::JwsHeader;
+use js_sys::{Object, Reflect, JSON};
+use serde::Deserialize;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[derive(Deserialize)]
+struct Vector {
+    name: String,
+    alg: String,
+    jwk: JwsHeader,
+    jws: String,
+    payload: Option<String>,
+}
+
+/// RFC 7515 and RFC 7520 vectors, all of which the pure Rust backend verifies
+fn vectors() -> Vec<Vector> {
+    serde_json::from_str(include_str!("data/jws-vectors.json")).unwrap()
+}
+
+fn object(value: &impl serde::Serialize) -> Object {
+    JSON::parse(&serde_json::to_string(value).unwrap())
+        .unwrap()
+        .into()
+}
+
+fn member(object: &JsValue, name: &str) -> Option<String> {
+    Reflect::get(object, &JsValue::from_str(name))
+        .unwrap()
+        .as_string()
+}
+
+#[wasm_bindgen_test]
+fn verify_rfc_examples() {
+    for vector in vectors() {
+        let segments: Vec<&str> = vector.jws.split('.').collect();
+        let jws = format!("{}..{}", segments[0], segments[2]);
+        let payload = vector.payload.as_deref().unwrap_or(segments[1]);
+        let mut payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).unwrap();
+
+        let jwk = object(&vector.jwk);
+        let header = verify(&vector.alg, &jwk, &jws, &payload)
+            .unwrap_or_else(|_| panic!("{} is not verified", vector.name));
+        assert_eq!(member(&header, "alg"), Some(vector.alg.clone()));
+
+        payload[0] ^= 1;
+        assert!(verify(&vector.alg, &jwk, &jws, &payload).is_err());
+    }
+}
+
+#[wasm_bindgen_test]
+fn sign_and_verify() {
+    let payload: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    let header = object(&serde_json::json!({ "kid": "key-1" }));
+
+    for vector in vectors() {
+        let jwk = object(&vector.jwk);
+
+        let jws = sign(&vector.alg, &jwk, Some(header.clone()), &payload).unwrap();
+        assert!(jws.contains(".."));
+
+        let verified = verify(&vector.alg, &jwk, &jws, &payload).unwrap();
+        assert_eq!(member(&verified, "kid").as_deref(), Some("key-1"));
+
+        assert!(verify(&vector.alg, &jwk, &jws, &payload[1..]).is_err());
+    }
+}
+
+#[wasm_bindgen_test]
+fn reject_other_algorithm() {
+    let vector = vectors().into_iter().find(|v| v.alg == "HS256").unwrap();
+    let jwk = object(&vector.jwk);
+
+    let jws = sign("HS256", &jwk, None, b"payload").unwrap();
+    assert!(verify("HS384", &jwk, &jws, b"payload").is_err());
+    assert!(sign("none", &jwk, None, b"payload").is_err());
+}